@@ -4,24 +4,38 @@
 
 use agent_client_protocol::{self as acp, TextContent};
 use async_trait::async_trait;
-use futures::{StreamExt, pin_mut};
+use futures::{Stream, StreamExt};
 use log::{debug, error, info, warn};
 use serde_json::json;
 use std::collections::HashMap;
 use std::path::PathBuf;
+use std::pin::Pin;
 use std::sync::Arc;
 use tokio::sync::{RwLock, mpsc, oneshot};
 use tokio_util::sync::CancellationToken;
-use uuid::Uuid;
 
-use crate::chat::Chat;
+use crate::chat::chat_mode::ChatMode;
+use crate::chat::chat_permission::{GrantScope, ToolCallAnswer};
+use crate::chat::chat_todo::{TodoItem, TodoStatus};
+use crate::chat::{Chat, EChatState, SessionStore, StreamedChatResponse};
 use crate::config::Config;
 use crate::mcp::get_config_tools;
+use crate::model::catalog::ModelCatalog;
+use crate::model::param::{ContentPart, ModelMessage, SamplingParams};
 
 /// 会话更新发送器
 pub type SessionUpdateSender =
     mpsc::UnboundedSender<(acp::SessionNotification, oneshot::Sender<()>)>;
 
+/// 工具调用权限请求，由连接转发给客户端并返回用户的选择
+pub type PermissionRequest = (
+    acp::RequestPermissionRequest,
+    oneshot::Sender<acp::Result<acp::RequestPermissionResponse>>,
+);
+
+/// 工具调用权限请求发送器
+pub type PermissionRequestSender = mpsc::UnboundedSender<PermissionRequest>;
+
 /// 会话数据
 #[derive(Clone)]
 #[allow(dead_code)]
@@ -31,10 +45,28 @@ struct SessionData {
     chat: Chat,
 }
 
+/// 查找会话的完整 ID，加载会话时客户端可能只给出了 ID 前缀
+fn resolve_session_id<V>(
+    map: &HashMap<acp::SessionId, V>,
+    session_id: &acp::SessionId,
+) -> Option<acp::SessionId> {
+    if map.contains_key(session_id) {
+        return Some(session_id.clone());
+    }
+    let mut matches = map
+        .keys()
+        .filter(|id| id.0.starts_with(session_id.0.as_ref()));
+    match (matches.next(), matches.next()) {
+        (Some(id), None) => Some(id.clone()),
+        _ => None,
+    }
+}
+
 /// ACP Agent 实现
 pub struct AcpAgent {
     sessions: Arc<RwLock<HashMap<acp::SessionId, SessionData>>>,
     session_update_tx: SessionUpdateSender,
+    permission_tx: PermissionRequestSender,
     config: Config,
     agent_info: acp::Implementation,
     cancels: Arc<RwLock<HashMap<acp::SessionId, CancellationToken>>>,
@@ -47,10 +79,12 @@ impl AcpAgent {
         server_version: String,
         config: Config,
         session_update_tx: SessionUpdateSender,
+        permission_tx: PermissionRequestSender,
     ) -> Self {
         Self {
             sessions: Arc::new(RwLock::new(HashMap::new())),
             session_update_tx,
+            permission_tx,
             config,
             agent_info: acp::Implementation::new(server_name, server_version)
                 .title(Some("Agent CLI".to_string())),
@@ -65,9 +99,31 @@ impl AcpAgent {
         chat
     }
 
-    /// 生成会话 ID
-    fn generate_session_id(&self) -> acp::SessionId {
-        acp::SessionId::new(Uuid::new_v4().to_string())
+    /// 把恢复的对话记录发送给客户端
+    async fn replay_history(&self, session_id: &acp::SessionId, messages: &[ModelMessage]) {
+        for msg in messages {
+            let chunk = |text: &str| {
+                acp::ContentChunk::new(acp::ContentBlock::Text(TextContent::new(text.to_string())))
+            };
+            let mut updates = Vec::new();
+            match msg.role.as_ref() {
+                "user" if !msg.content.is_empty() => {
+                    updates.push(acp::SessionUpdate::UserMessageChunk(chunk(&msg.content)));
+                }
+                "assistant" => {
+                    if !msg.think.is_empty() {
+                        updates.push(acp::SessionUpdate::AgentThoughtChunk(chunk(&msg.think)));
+                    }
+                    if !msg.content.is_empty() {
+                        updates.push(acp::SessionUpdate::AgentMessageChunk(chunk(&msg.content)));
+                    }
+                }
+                _ => {}
+            }
+            for update in updates {
+                let _ = self.send_session_update(session_id.clone(), update).await;
+            }
+        }
     }
 
     /// 处理提示并发送更新
@@ -76,17 +132,51 @@ impl AcpAgent {
         session_id: acp::SessionId,
         content_blocks: Vec<acp::ContentBlock>,
     ) -> acp::Result<acp::PromptResponse> {
-        // 提取文本内容
+        // 提取文本内容，图片和嵌入的资源作为附件
         let mut full_prompt = String::new();
-        for block in &content_blocks {
-            if let acp::ContentBlock::Text(text_content) = block {
-                full_prompt.push_str(&text_content.text);
-                full_prompt.push('\n');
+        let mut parts = Vec::new();
+        for block in content_blocks {
+            match block {
+                acp::ContentBlock::Text(text_content) => {
+                    full_prompt.push_str(&text_content.text);
+                    full_prompt.push('\n');
+                }
+                acp::ContentBlock::Image(image) => parts.push(ContentPart::Image {
+                    mime_type: image.mime_type,
+                    data: image.data,
+                }),
+                acp::ContentBlock::Resource(resource) => match resource.resource {
+                    acp::EmbeddedResourceResource::TextResourceContents(res) => {
+                        full_prompt.push_str(&format!("[文件: {}]\n{}\n", res.uri, res.text));
+                    }
+                    acp::EmbeddedResourceResource::BlobResourceContents(res) => {
+                        let mime_type = res
+                            .mime_type
+                            .unwrap_or("application/octet-stream".to_string());
+                        parts.push(if mime_type.starts_with("image/") {
+                            ContentPart::Image {
+                                mime_type,
+                                data: res.blob,
+                            }
+                        } else {
+                            ContentPart::File {
+                                name: res.uri,
+                                mime_type,
+                                data: res.blob,
+                            }
+                        });
+                    }
+                    _ => {}
+                },
+                acp::ContentBlock::ResourceLink(link) => {
+                    full_prompt.push_str(&format!("[文件: {}]\n", link.uri));
+                }
+                _ => {}
             }
         }
         full_prompt = full_prompt.trim().to_string();
 
-        if full_prompt.is_empty() {
+        if full_prompt.is_empty() && parts.is_empty() {
             return Err(acp::Error::invalid_params());
         }
 
@@ -98,8 +188,10 @@ impl AcpAgent {
 
         // 获取会话并处理流式响应
         let mut sessions = self.sessions.write().await;
+        let full_id =
+            resolve_session_id(&sessions, &session_id).ok_or_else(acp::Error::invalid_params)?;
         let session = sessions
-            .get_mut(&session_id)
+            .get_mut(&full_id)
             .ok_or_else(|| acp::Error::invalid_params())?;
         {
             self.cancels
@@ -109,104 +201,259 @@ impl AcpAgent {
         }
 
         // 使用流式处理
-        let stream = session.chat.stream_chat(&full_prompt);
-        pin_mut!(stream);
+        let message = ModelMessage::user(full_prompt).with_parts(parts);
+        let mut stream: Pin<Box<dyn Stream<Item = anyhow::Result<StreamedChatResponse>> + '_>> =
+            Box::pin(
+                session
+                    .chat
+                    .stream_chat_message(message, SamplingParams::default()),
+            );
 
         // 处理流式响应
         let mut current_text = String::new();
 
-        while let Some(result) = stream.next().await {
-            info!("{:?}", result);
-            match result {
-                Ok(response) => {
-                    match response {
-                        crate::chat::StreamedChatResponse::Text(text) => {
-                            current_text.push_str(&text);
-
-                            // 发送流式文本更新
-                            let _ = self
-                                .send_session_update(
-                                    session_id.clone(),
-                                    acp::SessionUpdate::AgentMessageChunk(acp::ContentChunk::new(
-                                        acp::ContentBlock::Text(TextContent::new(text)),
-                                    )),
-                                )
-                                .await;
-                        }
-                        crate::chat::StreamedChatResponse::ToolCall(call) => {
-                            debug!("工具调用: {:?}", call);
-                            let _ = self
-                                .send_session_update(
-                                    session_id.clone(),
-                                    acp::SessionUpdate::ToolCall(acp::ToolCall::new(
-                                        call.id,
-                                        call.function.name,
-                                    )),
-                                )
-                                .await;
-                        }
-                        crate::chat::StreamedChatResponse::ToolResponse(msg) => {
-                            debug!("工具执行结果: {:?}", msg);
-                            let _ = self
-                                .send_session_update(
-                                    session_id.clone(),
-                                    acp::SessionUpdate::ToolCallUpdate(acp::ToolCallUpdate::new(
-                                        msg.tool_call_id.to_string(),
-                                        acp::ToolCallUpdateFields::new()
-                                            .content(vec![acp::ToolCallContent::Content(
-                                                acp::Content::new(acp::ContentBlock::Text(
-                                                    acp::TextContent::new(msg.content.to_string()),
-                                                )),
-                                            )])
-                                            .title(msg.name),
-                                    )),
-                                )
-                                .await;
-                        }
-                        crate::chat::StreamedChatResponse::Reasoning(text) => {
-                            debug!("推理内容: {}", text);
-
-                            // 推理内容可以作为注释发送
-                            let _ = self
-                                .send_session_update(
-                                    session_id.clone(),
-                                    acp::SessionUpdate::AgentThoughtChunk(acp::ContentChunk::new(
-                                        acp::ContentBlock::Text(TextContent::new(text)),
-                                    )),
-                                )
-                                .await;
-                        }
-                        crate::chat::StreamedChatResponse::TokenUsage(usage) => {
-                            info!("Token 使用: {:?}", usage);
-                        }
-                        crate::chat::StreamedChatResponse::End => {
-                            info!("流处理完成");
+        loop {
+            while let Some(result) = stream.next().await {
+                info!("{:?}", result);
+                match result {
+                    Ok(response) => {
+                        match response {
+                            crate::chat::StreamedChatResponse::Text(text) => {
+                                current_text.push_str(&text);
+
+                                // 发送流式文本更新
+                                let _ = self
+                                    .send_session_update(
+                                        session_id.clone(),
+                                        acp::SessionUpdate::AgentMessageChunk(
+                                            acp::ContentChunk::new(acp::ContentBlock::Text(
+                                                TextContent::new(text),
+                                            )),
+                                        ),
+                                    )
+                                    .await;
+                            }
+                            crate::chat::StreamedChatResponse::ToolCall(call) => {
+                                debug!("工具调用: {:?}", call);
+                                let _ = self
+                                    .send_session_update(
+                                        session_id.clone(),
+                                        acp::SessionUpdate::ToolCall(acp::ToolCall::new(
+                                            call.id,
+                                            call.function.name,
+                                        )),
+                                    )
+                                    .await;
+                            }
+                            crate::chat::StreamedChatResponse::ToolResponse(msg) => {
+                                debug!("工具执行结果: {:?}", msg);
+                                let _ = self
+                                    .send_session_update(
+                                        session_id.clone(),
+                                        acp::SessionUpdate::ToolCallUpdate(
+                                            acp::ToolCallUpdate::new(
+                                                msg.tool_call_id.to_string(),
+                                                acp::ToolCallUpdateFields::new()
+                                                    .content(vec![acp::ToolCallContent::Content(
+                                                        acp::Content::new(acp::ContentBlock::Text(
+                                                            acp::TextContent::new(
+                                                                msg.content.to_string(),
+                                                            ),
+                                                        )),
+                                                    )])
+                                                    .title(msg.name),
+                                            )
+                                            // 输出被截断时在 _meta 中附带截断信息
+                                            .meta(
+                                                msg.truncation.and_then(|truncation| {
+                                                    let mut meta = acp::Meta::new();
+                                                    meta.insert(
+                                                        "truncation".into(),
+                                                        serde_json::to_value(truncation).ok()?,
+                                                    );
+                                                    Some(meta)
+                                                }),
+                                            ),
+                                        ),
+                                    )
+                                    .await;
+                            }
+                            crate::chat::StreamedChatResponse::Reasoning(text) => {
+                                debug!("推理内容: {}", text);
+
+                                // 推理内容可以作为注释发送
+                                let _ = self
+                                    .send_session_update(
+                                        session_id.clone(),
+                                        acp::SessionUpdate::AgentThoughtChunk(
+                                            acp::ContentChunk::new(acp::ContentBlock::Text(
+                                                TextContent::new(text),
+                                            )),
+                                        ),
+                                    )
+                                    .await;
+                            }
+                            crate::chat::StreamedChatResponse::TokenUsage(usage) => {
+                                info!("Token 使用: {:?}", usage);
+                            }
+                            crate::chat::StreamedChatResponse::Todos(todos) => {
+                                // 每次发送完整的计划，客户端用它替换之前的计划
+                                let _ = self
+                                    .send_session_update(
+                                        session_id.clone(),
+                                        acp::SessionUpdate::Plan(session_plan(&todos)),
+                                    )
+                                    .await;
+                            }
+                            crate::chat::StreamedChatResponse::End => {
+                                info!("流处理完成");
+                            }
                         }
                     }
+                    Err(e) => {
+                        error!("流处理错误: {}", e);
+                        // 发送错误更新
+                        let _ = self
+                            .send_session_update(
+                                session_id.clone(),
+                                acp::SessionUpdate::AgentMessageChunk(acp::ContentChunk::new(
+                                    acp::ContentBlock::Text(acp::TextContent::new(format!(
+                                        "错误: {}",
+                                        e
+                                    ))),
+                                )),
+                            )
+                            .await;
+                        return Err(acp::Error::internal_error());
+                    }
                 }
-                Err(e) => {
-                    error!("流处理错误: {}", e);
-                    // 发送错误更新
-                    let _ = self
-                        .send_session_update(
-                            session_id.clone(),
-                            acp::SessionUpdate::AgentMessageChunk(acp::ContentChunk::new(
-                                acp::ContentBlock::Text(acp::TextContent::new(format!(
-                                    "错误: {}",
-                                    e
-                                ))),
-                            )),
-                        )
-                        .await;
-                    return Err(acp::Error::internal_error());
-                }
             }
+
+            drop(stream);
+
+            // 工具调用等待确认时逐个向客户端请求权限，都答复后继续执行
+            if !self
+                .request_tool_permissions(&session_id, &mut session.chat)
+                .await
+            {
+                break;
+            }
+            stream = Box::pin(session.chat.stream_rechat());
+        }
+
+        // 工具调用暂停时说明原因，用户发送新的消息即可继续
+        if let Some(reason) = session.chat.tool_loop_pause() {
+            let _ = self
+                .send_session_update(
+                    session_id.clone(),
+                    acp::SessionUpdate::AgentMessageChunk(acp::ContentChunk::new(
+                        acp::ContentBlock::Text(acp::TextContent::new(format!(
+                            "\n\n{}，已暂停。发送新的消息继续",
+                            reason
+                        ))),
+                    )),
+                )
+                .await;
+            return Ok(acp::PromptResponse::new(acp::StopReason::MaxTurnRequests));
         }
 
         // 返回响应
         Ok(acp::PromptResponse::new(acp::StopReason::EndTurn))
     }
 
+    /// 逐个请求等待确认的工具调用的权限，返回是否有调用需要继续执行
+    async fn request_tool_permissions(&self, session_id: &acp::SessionId, chat: &mut Chat) -> bool {
+        while let Some((call, decision)) = chat.pending_confirmations().into_iter().next() {
+            let mut meta = acp::Meta::new();
+            meta.insert("decision".into(), json!(decision.to_string()));
+            let request = acp::RequestPermissionRequest::new(
+                session_id.clone(),
+                acp::ToolCallUpdate::new(
+                    call.id.clone(),
+                    acp::ToolCallUpdateFields::new()
+                        .title(call.function.name.clone())
+                        .raw_input(serde_json::from_str(&call.function.arguments).ok()),
+                ),
+                vec![
+                    acp::PermissionOption::new(
+                        "allow_once",
+                        "允许",
+                        acp::PermissionOptionKind::AllowOnce,
+                    ),
+                    acp::PermissionOption::new(
+                        "allow_session",
+                        "本会话总是允许该工具",
+                        acp::PermissionOptionKind::AllowAlways,
+                    ),
+                    acp::PermissionOption::new(
+                        "allow_session_exact",
+                        "本会话总是允许相同参数的调用",
+                        acp::PermissionOptionKind::AllowAlways,
+                    ),
+                    acp::PermissionOption::new(
+                        "allow_project",
+                        "本项目总是允许相同参数的调用",
+                        acp::PermissionOptionKind::AllowAlways,
+                    ),
+                    acp::PermissionOption::new(
+                        "allow_project_tool",
+                        "本项目总是允许该工具",
+                        acp::PermissionOptionKind::AllowAlways,
+                    ),
+                    acp::PermissionOption::new(
+                        "reject_once",
+                        "拒绝",
+                        acp::PermissionOptionKind::RejectOnce,
+                    ),
+                ],
+            )
+            .meta(meta);
+
+            let (tx, rx) = oneshot::channel();
+            let response = match self.permission_tx.send((request, tx)) {
+                Ok(()) => rx.await.map_err(|_| acp::Error::internal_error()).flatten(),
+                Err(_) => Err(acp::Error::internal_error()),
+            };
+            let answer = match response.map(|response| response.outcome) {
+                Ok(acp::RequestPermissionOutcome::Selected(selected)) => {
+                    match selected.option_id.0.as_ref() {
+                        "allow_once" => ToolCallAnswer::Approve,
+                        "allow_session" => ToolCallAnswer::AlwaysAllow {
+                            exact: false,
+                            scope: GrantScope::Session,
+                        },
+                        "allow_session_exact" => ToolCallAnswer::AlwaysAllow {
+                            exact: true,
+                            scope: GrantScope::Session,
+                        },
+                        "allow_project" => ToolCallAnswer::AlwaysAllow {
+                            exact: true,
+                            scope: GrantScope::Project,
+                        },
+                        "allow_project_tool" => ToolCallAnswer::AlwaysAllow {
+                            exact: false,
+                            scope: GrantScope::Project,
+                        },
+                        _ => ToolCallAnswer::Reject(None),
+                    }
+                }
+                // 客户端取消或请求失败时拒绝剩下的所有调用
+                other => {
+                    warn!("工具调用 {} 的权限请求没有结果: {:?}", call.id, other);
+                    if let Err(e) = chat.answer_tool_calls(ToolCallAnswer::Reject(None)) {
+                        error!("拒绝工具调用失败: {}", e);
+                    }
+                    break;
+                }
+            };
+            if let Err(e) = chat.answer_tool_call(&call.id, answer) {
+                error!("答复工具调用 {} 失败: {}", call.id, e);
+                break;
+            }
+        }
+        chat.get_state() == EChatState::WaitingToolUse
+    }
+
     /// 发送会话更新
     async fn send_session_update(
         &self,
@@ -223,6 +470,36 @@ impl AcpAgent {
     }
 }
 
+/// 可以切换的会话模式
+fn session_modes(current: ChatMode) -> acp::SessionModeState {
+    acp::SessionModeState::new(
+        current.id(),
+        ChatMode::ALL
+            .into_iter()
+            .map(|mode| {
+                acp::SessionMode::new(mode.id(), mode.to_string()).description(mode.description())
+            })
+            .collect(),
+    )
+}
+
+/// todo 工具记录的任务列表，作为执行计划发送给客户端
+fn session_plan(todos: &[TodoItem]) -> acp::Plan {
+    acp::Plan::new(
+        todos
+            .iter()
+            .map(|item| {
+                let status = match item.status {
+                    TodoStatus::Pending => acp::PlanEntryStatus::Pending,
+                    TodoStatus::InProgress => acp::PlanEntryStatus::InProgress,
+                    TodoStatus::Done => acp::PlanEntryStatus::Completed,
+                };
+                acp::PlanEntry::new(item.content.clone(), acp::PlanEntryPriority::Medium, status)
+            })
+            .collect(),
+    )
+}
+
 #[async_trait(?Send)]
 impl acp::Agent for AcpAgent {
     async fn initialize(
@@ -233,7 +510,15 @@ impl acp::Agent for AcpAgent {
 
         Ok(acp::InitializeResponse::new(acp::ProtocolVersion::V1)
             .agent_info(self.agent_info.clone())
-            .agent_capabilities(acp::AgentCapabilities::new()))
+            .agent_capabilities(
+                acp::AgentCapabilities::new()
+                    .load_session(true)
+                    .prompt_capabilities(
+                        acp::PromptCapabilities::new()
+                            .image(true)
+                            .embedded_context(true),
+                    ),
+            ))
     }
 
     async fn authenticate(
@@ -249,12 +534,23 @@ impl acp::Agent for AcpAgent {
         &self,
         request: acp::NewSessionRequest,
     ) -> acp::Result<acp::NewSessionResponse> {
-        let session_id = self.generate_session_id();
         let cwd = request.cwd.clone();
+        let mut chat = self.create_chat();
+        // 会话 ID 与保存的会话一致，之后可以通过 load_session 恢复
+        let session_id = acp::SessionId::new(chat.start_session(&cwd));
 
         info!("创建新会话 - ID: {:?}, 工作目录: {:?}", session_id, cwd);
 
-        let chat = self.create_chat();
+        let model = chat.model_name();
+        let token_limit = chat.agent().get_token_limit();
+        // 查询失败时只提供当前模型
+        let mut models = chat.agent().list_models().await.unwrap_or_else(|e| {
+            warn!("获取模型列表失败: {}", e);
+            vec![]
+        });
+        if !models.contains(&model) {
+            models.insert(0, model.clone());
+        }
         let session_data = SessionData {
             id: session_id.clone(),
             cwd: cwd.clone(),
@@ -266,13 +562,18 @@ impl acp::Agent for AcpAgent {
             .await
             .insert(session_id.clone(), session_data);
 
-        let model = self.config.model.clone().unwrap_or("deepseek-chat".into());
-        Ok(acp::NewSessionResponse::new(session_id).modes(None).models(
-            acp::SessionModelState::new(
-                model.clone(),
-                vec![acp::ModelInfo::new(model.clone(), model)],
-            ),
-        ))
+        Ok(acp::NewSessionResponse::new(session_id)
+            .modes(session_modes(ChatMode::default()))
+            .models(acp::SessionModelState::new(
+                model,
+                models
+                    .into_iter()
+                    .map(|m| {
+                        let caps = ModelCatalog::global().lookup(&m, token_limit);
+                        acp::ModelInfo::new(m.clone(), m).description(caps.summary())
+                    })
+                    .collect(),
+            )))
     }
 
     async fn load_session(
@@ -280,9 +581,35 @@ impl acp::Agent for AcpAgent {
         request: acp::LoadSessionRequest,
     ) -> acp::Result<acp::LoadSessionResponse> {
         info!("收到加载会话请求: {:?}", request);
-        // 暂不支持从持久化存储加载会话
-        warn!("load_session 暂不支持从持久化存储加载");
-        Err(acp::Error::method_not_found())
+        let record = SessionStore::local()
+            .load(&request.session_id.0)
+            .map_err(|e| {
+                warn!("加载会话失败: {}", e);
+                acp::Error::invalid_params()
+            })?;
+        self.replay_history(&request.session_id, &record.messages)
+            .await;
+
+        // 按完整 ID 保存，客户端给出的可能只是前缀
+        let session_id = acp::SessionId::new(record.meta.id.clone());
+        let mut chat = self.create_chat();
+        chat.resume_session(record);
+        let modes = session_modes(chat.mode());
+        if !chat.todos().is_empty() {
+            let _ = self
+                .send_session_update(
+                    request.session_id.clone(),
+                    acp::SessionUpdate::Plan(session_plan(chat.todos())),
+                )
+                .await;
+        }
+        let session_data = SessionData {
+            id: session_id.clone(),
+            cwd: request.cwd,
+            chat,
+        };
+        self.sessions.write().await.insert(session_id, session_data);
+        Ok(acp::LoadSessionResponse::new().modes(modes))
     }
 
     async fn prompt(&self, request: acp::PromptRequest) -> acp::Result<acp::PromptResponse> {
@@ -293,7 +620,10 @@ impl acp::Agent for AcpAgent {
 
     async fn cancel(&self, request: acp::CancelNotification) -> acp::Result<()> {
         info!("收到取消请求 - 会话: {:?}", request.session_id);
-        if let Some(token) = self.cancels.read().await.get(&request.session_id) {
+        let cancels = self.cancels.read().await;
+        if let Some(token) = resolve_session_id(&cancels, &request.session_id)
+            .and_then(|session_id| cancels.get(&session_id))
+        {
             token.cancel();
             Ok(())
         } else {
@@ -311,12 +641,13 @@ impl acp::Agent for AcpAgent {
             request.session_id, request.mode_id
         );
 
-        // TODO: 实现模式切换逻辑
-        // let mut sessions = self.sessions.write().await;
-        // if let Some(session) = sessions.get_mut(&request.session_id) {
-        //     // 更新模式
-        // }
-
+        let mode = ChatMode::parse(&request.mode_id.0).ok_or_else(acp::Error::invalid_params)?;
+        let mut sessions = self.sessions.write().await;
+        let session = resolve_session_id(&sessions, &request.session_id)
+            .and_then(|session_id| sessions.get_mut(&session_id))
+            .ok_or_else(acp::Error::invalid_params)?;
+        // 从计划模式切换到执行模式即批准计划，用户的下一条消息开始执行
+        session.chat.set_mode(mode);
         Ok(acp::SetSessionModeResponse::new())
     }
 
@@ -328,6 +659,14 @@ impl acp::Agent for AcpAgent {
             "收到设置会话模型请求 - 会话: {:?}, 模型: {:?}",
             request.session_id, request.model_id
         );
+        let mut sessions = self.sessions.write().await;
+        let session = resolve_session_id(&sessions, &request.session_id)
+            .and_then(|session_id| sessions.get_mut(&session_id))
+            .ok_or_else(acp::Error::invalid_params)?;
+        session.chat.set_model(&request.model_id.0).map_err(|e| {
+            error!("切换模型失败: {}", e);
+            acp::Error::internal_error()
+        })?;
         Ok(acp::SetSessionModelResponse::new())
     }
 
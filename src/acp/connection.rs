@@ -7,13 +7,14 @@ use anyhow::Result;
 use async_trait::async_trait;
 use futures::{SinkExt, StreamExt};
 use log::{debug, error, info, warn};
+use std::rc::Rc;
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::mpsc;
 use tokio::task::LocalSet;
 use tokio_tungstenite::{WebSocketStream, accept_async, tungstenite::Message};
 use tokio_util::compat::{TokioAsyncReadCompatExt, TokioAsyncWriteCompatExt};
 
-use crate::acp::agent_impl::AcpAgent;
+use crate::acp::agent_impl::{AcpAgent, PermissionRequest};
 use crate::config::Config;
 
 /// 连接类型枚举
@@ -68,12 +69,14 @@ impl AcpConnection for StdioConnection {
 
         // 创建会话更新通道
         let (session_update_tx, mut session_update_rx) = mpsc::unbounded_channel();
+        let (permission_tx, permission_rx) = mpsc::unbounded_channel();
 
         let agent = AcpAgent::new(
             self.config.server_name.clone(),
             self.config.server_version.clone(),
             config,
             session_update_tx,
+            permission_tx,
         );
 
         let stdin = tokio::io::stdin();
@@ -95,7 +98,9 @@ impl AcpConnection for StdioConnection {
                 );
 
                 // 克隆 conn 用于后台任务
-                let conn_clone = conn;
+                let conn = Rc::new(conn);
+                let conn_clone = conn.clone();
+                tokio::task::spawn_local(forward_permission_requests(conn, permission_rx));
 
                 // 启动后台任务处理会话通知
                 tokio::task::spawn_local(async move {
@@ -191,9 +196,16 @@ impl WssConnection {
 
         // 创建会话更新通道
         let (session_update_tx, mut session_update_rx) = mpsc::unbounded_channel();
+        let (permission_tx, permission_rx) = mpsc::unbounded_channel();
 
         // 创建 ACP Agent
-        let agent = AcpAgent::new(server_name, server_version, config, session_update_tx);
+        let agent = AcpAgent::new(
+            server_name,
+            server_version,
+            config,
+            session_update_tx,
+            permission_tx,
+        );
 
         // 创建双向通道来桥接 WebSocket 和 ACP
         let (acp_to_ws_tx, mut acp_to_ws_rx) = mpsc::unbounded_channel::<String>();
@@ -274,7 +286,9 @@ impl WssConnection {
         info!("ACP 连接创建成功");
 
         // 克隆 conn 用于后台任务
-        let conn_clone = conn;
+        let conn = Rc::new(conn);
+        let conn_clone = conn.clone();
+        tokio::task::spawn_local(forward_permission_requests(conn, permission_rx));
 
         // 启动后台任务处理会话通知
         let session_task = tokio::task::spawn_local(async move {
@@ -379,6 +393,18 @@ impl AcpConnection for WssConnection {
     }
 }
 
+/// 把工具调用的权限请求转发给客户端，并把用户的选择返回给 Agent
+async fn forward_permission_requests(
+    conn: Rc<acp::AgentSideConnection>,
+    mut permission_rx: mpsc::UnboundedReceiver<PermissionRequest>,
+) {
+    while let Some((request, tx)) = permission_rx.recv().await {
+        info!("请求工具调用权限: {:?}", request.tool_call.tool_call_id);
+        tx.send(conn.request_permission(request).await).ok();
+    }
+    info!("权限请求转发任务结束");
+}
+
 /// 无界发送器写入器，实现 futures::AsyncWrite 接口
 struct UnboundedSenderWriter {
     sender: mpsc::UnboundedSender<String>,
@@ -1,15 +1,41 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
 use futures::{Stream, StreamExt, pin_mut};
 use log::{info, warn};
 
+use crate::chat::chat_compress::{CompressionConfig, Summarizer};
+use crate::chat::chat_mode::ChatMode;
+use crate::chat::chat_permission::{
+    ConfirmReply, PermissionAction, PermissionDecision, PermissionPolicy, ToolCallAnswer,
+};
+use crate::chat::chat_todo::TodoItem;
+use crate::chat::chat_window::ContextPolicy;
 use crate::config::{self, Config};
 use crate::mcp::McpTool;
-use crate::model::param::ModelMessage;
-use crate::prompt;
+use crate::model::AgentModel;
+use crate::model::catalog::{ModelCapabilities, ModelCatalog};
+use crate::model::param::{ModelMessage, SamplingParams, ToolCall};
+use crate::model::registry::{ModelRegistry, ProviderConfig};
+use crate::model::tokenizer::TokenEstimator;
+use crate::prompt::SystemPrompt;
 
+pub mod chat_branch;
+pub mod chat_compress;
+pub mod chat_hooks;
+pub mod chat_loop;
+pub mod chat_mode;
+pub mod chat_permission;
+mod chat_schema;
+pub mod chat_session;
 pub mod chat_state;
 pub mod chat_stream;
+pub mod chat_todo;
 mod chat_tools;
+pub mod chat_window;
 
+pub use chat_branch::Branch;
+pub use chat_session::{SessionMeta, SessionRecord, SessionStore};
 pub use chat_state::ChatState;
 pub use chat_state::EChatState;
 pub use chat_stream::StreamedChatResponse;
@@ -44,16 +70,27 @@ pub use chat_stream::StreamedChatResponse;
 #[derive(Clone)]
 pub struct Chat {
     state: ChatState,
+    provider: ProviderConfig,     // 当前使用的提供方配置
     max_context_num: usize,       // 保存token限制的副本
     auto_compress_threshold: f32, // 自动压缩阈值（token使用比例）
+    compress_trigger_ratio: f32,  // 超过这个比例才执行需要调用模型的压缩策略
+    compression: CompressionConfig,
+    max_tokens: Option<u32>, // 配置中的上下文上限，为空时使用模型上下文窗口
+    session: Option<SessionMeta>, // 持久化的会话，为空时不保存
+    system_prompt: SystemPrompt, // 系统提示词和项目说明文件，清空对话时重新加载
 }
 
+/// 结构化输出校验失败后的最大重新请求次数
+const MAX_SCHEMA_RETRIES: usize = 2;
+
 /// Chat 构建器，用于改进初始化和配置验证
 pub struct ChatBuilder {
     config: Config,
     tools: Vec<McpTool>,
     ask_before_tool_execution: Option<bool>,
     max_context_num: Option<usize>,
+    /// 是否允许通过 task 工具启动子代理
+    subagent: bool,
 }
 
 impl ChatBuilder {
@@ -64,13 +101,21 @@ impl ChatBuilder {
             tools: Vec::new(),
             ask_before_tool_execution: None,
             max_context_num: None,
+            subagent: true,
         }
     }
 
+    /// 不允许启动子代理，用于子代理自己的对话
+    pub fn without_subagent(mut self) -> Self {
+        self.subagent = false;
+        self
+    }
+
     /// 构建 Chat 实例，进行配置验证
     pub fn build(self) -> Result<Chat, String> {
-        // 验证配置
-        if self.config.api_key.is_empty() {
+        // 验证配置，本地提供方不需要密钥
+        let provider = ProviderConfig::from_config(&self.config);
+        if provider.requires_api_key() && provider.api_key.is_empty() {
             return Err("API密钥不能为空".to_string());
         }
 
@@ -85,34 +130,59 @@ impl ChatBuilder {
             return Err("最大对话轮次数必须大于0".to_string());
         }
 
-        let client = crate::client::chat_client::ChatClient::new(
-            self.config.api_key.clone(),
-            self.config
-                .url
-                .clone()
-                .unwrap_or("https://api.deepseek.com".into()),
-            self.config.model.clone().unwrap_or("deepseek-chat".into()),
-            self.tools,
-        );
+        // 根据配置的提供方创建模型后端，模型能力以用户配置为准
+        ModelCatalog::global().set_overrides(self.config.model_capabilities.clone());
+        let agent = ModelRegistry::global()
+            .create(&provider)
+            .map_err(|e| e.to_string())?;
+        let mut client = crate::client::chat_client::ChatClient::new(agent, self.tools);
+        client.set_sampling(self.config.sampling.clone());
 
-        let context = vec![ModelMessage::system(
-            self.config
-                .prompt
-                .map(|p| prompt::build_enhanced_prompt(&p))
-                .unwrap_or_else(|| prompt::get_default_enhanced_prompt()),
-        )];
+        let cwd = std::env::current_dir().unwrap_or_default();
+        let system_prompt = SystemPrompt::from_config(&self.config);
+        let context = vec![ModelMessage::system(system_prompt.build(&cwd))];
 
-        let tokens = client.get_token_limit();
-        let state = ChatState::new(client, context, tokens, ask_before_tool_execution);
+        let tokens = effective_token_limit(client.get_token_limit(), self.config.max_tokens);
+        let estimator = TokenEstimator::for_provider(&provider);
+        let mut state = ChatState::new(
+            client,
+            context,
+            tokens,
+            PermissionPolicy::new(self.config.permissions.clone(), ask_before_tool_execution),
+            estimator,
+        );
+        state.set_context_policy(
+            self.config.context_policy,
+            self.config.auto_compress_threshold,
+        );
+        state.set_max_parallel_tools(self.config.max_parallel_tools);
+        state.set_tool_output(self.config.tool_output.clone());
+        state.set_hooks(self.config.hooks.clone());
+        state.set_tool_loop_limits(self.config.max_tool_try, self.config.max_tool_repeat);
+        state.set_project(&cwd);
+        if self.subagent {
+            state.set_subagent_config(self.config.clone());
+        }
 
         Ok(Chat {
             state,
+            provider,
             max_context_num,
             auto_compress_threshold: self.config.auto_compress_threshold,
+            compress_trigger_ratio: self.config.compress_trigger_ratio,
+            compression: self.config.compression,
+            max_tokens: self.config.max_tokens,
+            session: None,
+            system_prompt,
         })
     }
 }
 
+/// 模型上下文窗口与配置上限取较小值
+fn effective_token_limit(context_window: u32, max_tokens: Option<u32>) -> u32 {
+    max_tokens.map_or(context_window, |max| max.min(context_window))
+}
+
 impl Default for Chat {
     fn default() -> Self {
         Self::new(config::Config::local().unwrap())
@@ -131,7 +201,228 @@ impl Chat {
     }
 
     pub fn get_token_limit(&self) -> u32 {
-        self.state.client.get_token_limit()
+        effective_token_limit(self.state.client.get_token_limit(), self.max_tokens)
+    }
+
+    /// 当前模型的能力
+    pub fn capabilities(&self) -> ModelCapabilities {
+        self.state.client.agent.capabilities()
+    }
+
+    /// 当前使用的模型名称
+    pub fn model_name(&self) -> String {
+        self.state.client.agent.model_name()
+    }
+
+    /// 获取当前模型后端，用于在不持有 Chat 锁的情况下发起请求
+    pub fn agent(&self) -> Arc<dyn AgentModel> {
+        self.state.client.agent.clone()
+    }
+
+    /// 运行时切换模型提供方，上下文保持不变
+    pub fn set_provider(&mut self, provider: &ProviderConfig) -> anyhow::Result<()> {
+        let agent = ModelRegistry::global().create(provider)?;
+        self.state.client.set_agent(agent);
+        self.state.set_max_tokens(
+            self.get_token_limit(),
+            TokenEstimator::for_provider(provider),
+        );
+        // 子代理跟随切换后的模型
+        if let Some(mut config) = self.state.subagent_config().cloned() {
+            provider.apply_to(&mut config);
+            self.state.set_subagent_config(config);
+        }
+        self.provider = provider.clone();
+        Ok(())
+    }
+
+    /// 在当前提供方下切换模型
+    pub fn set_model(&mut self, model: &str) -> anyhow::Result<()> {
+        let mut provider = self.provider.clone();
+        provider.model = Some(model.to_string());
+        self.set_provider(&provider)
+    }
+
+    /// 默认采样参数
+    pub fn sampling(&self) -> &SamplingParams {
+        self.state.client.sampling()
+    }
+
+    /// 修改默认采样参数，对之后的所有请求生效
+    pub fn set_sampling(&mut self, sampling: SamplingParams) {
+        self.state.client.set_sampling(sampling);
+    }
+
+    /// 开启会话持久化，之后每轮对话结束都会保存，返回会话 ID
+    pub fn start_session(&mut self, cwd: &Path) -> String {
+        let meta = SessionMeta::new(cwd);
+        info!("开启会话 {}", meta.id);
+        let id = meta.id.clone();
+        self.state.set_session_id(&id);
+        self.state.set_project(cwd);
+        // 会话的工作目录可能和进程的不同，还没有开始对话时按会话目录加载项目说明
+        if self.state.context().len() <= 1 {
+            self.reload_system_prompt();
+        }
+        self.session = Some(meta);
+        id
+    }
+
+    /// 恢复保存的会话，之后的对话继续保存到该会话
+    pub fn resume_session(&mut self, record: SessionRecord) {
+        info!(
+            "恢复会话 {}，共 {} 条消息",
+            record.meta.id,
+            record.messages.len()
+        );
+        *self.state.context_mut() = record.messages;
+        self.state
+            .set_conversation_turn(record.meta.conversation_turn);
+        self.state.set_branches(record.branches);
+        self.state.set_session_id(&record.meta.id);
+        self.state.set_project(Path::new(&record.meta.cwd));
+        self.state.set_session_grants(record.meta.grants.clone());
+        self.state.set_mode(record.meta.mode);
+        self.state.set_todos(record.meta.todos.clone());
+        self.session = Some(record.meta);
+    }
+
+    /// 对话结束时调用，没有开启持久化的对话删除保存完整工具输出的临时目录
+    ///
+    /// 保存的会话恢复后还会引用这些文件，由 prune_expired 按时间清理
+    pub fn end_session(&self) {
+        if self.session.is_none() {
+            self.state.cleanup_tool_outputs();
+        }
+    }
+
+    /// 当前会话的元数据，未开启持久化时为空
+    pub fn session(&self) -> Option<&SessionMeta> {
+        self.session.as_ref()
+    }
+
+    /// 保存当前会话，失败只记录日志，不影响对话
+    pub fn save_session(&mut self) {
+        let model = self.model_name();
+        let Some(meta) = self.session.as_mut() else {
+            return;
+        };
+        let context = self.state.context();
+        let branches = self.state.branches();
+        // 还没有用户输入时不创建会话文件
+        if !context.iter().any(|msg| msg.role == "user") && branches.is_empty() {
+            return;
+        }
+        meta.grants = self.state.session_grants().to_vec();
+        meta.mode = self.state.mode();
+        meta.todos = self.state.todos().to_vec();
+        meta.update(context, self.state.get_conversation_turn_info(), model);
+        if let Err(e) = SessionStore::local().save(meta, context, branches) {
+            warn!("保存会话 {} 失败: {}", meta.id, e);
+        }
+    }
+
+    /// 回退到指定消息之前，原来的对话保存为分支，返回被丢弃的第一条消息
+    pub fn rewind(&mut self, index: usize) -> anyhow::Result<ModelMessage> {
+        self.ensure_editable()?;
+        let msg = self.state.rewind(index)?;
+        self.state.set_state(EChatState::Idle);
+        self.save_session();
+        Ok(msg)
+    }
+
+    /// 撤销最后一轮对话，返回最后一条用户消息，可以修改后重新发送
+    pub fn undo(&mut self) -> anyhow::Result<ModelMessage> {
+        let Some(index) = self.last_user_index() else {
+            return Err(anyhow::anyhow!("没有可以撤销的对话"));
+        };
+        self.rewind(index)
+    }
+
+    /// 修改指定的用户消息：回退到该消息之前，返回修改后的消息，附件保持不变，
+    /// 发送后从这里重新生成
+    pub fn edit_message(&mut self, index: usize, content: String) -> anyhow::Result<ModelMessage> {
+        if self
+            .context()
+            .get(index)
+            .is_none_or(|msg| msg.role != "user")
+        {
+            return Err(anyhow::anyhow!("第 {} 条消息不是用户消息", index));
+        }
+        let mut msg = self.rewind(index)?;
+        msg.content = content.into();
+        Ok(msg)
+    }
+
+    /// 准备重新生成，之后调用 stream_rechat 从回退的位置继续对话
+    ///
+    /// 指定消息时回退到该消息之前，用户消息会保留下来重新发送；
+    /// 不指定时丢弃最后一轮的回复，最后一条是用户消息或工具结果时不需要丢弃
+    pub fn prepare_regenerate(&mut self, index: Option<usize>) -> anyhow::Result<()> {
+        let index = match index {
+            Some(index) => index,
+            None => {
+                let Some(last) = self.context().last() else {
+                    return Err(anyhow::anyhow!("没有可以重新生成的对话"));
+                };
+                if last.role != "assistant" || last.tool_calls.is_some() {
+                    self.ensure_editable()?;
+                    return Ok(());
+                }
+                match self.last_user_index() {
+                    Some(index) => index + 1,
+                    None => return Err(anyhow::anyhow!("没有可以重新生成的对话")),
+                }
+            }
+        };
+        let msg = self.rewind(index)?;
+        if msg.role == "user" {
+            self.add_message(msg);
+        }
+        Ok(())
+    }
+
+    /// 被替换下来的对话分支
+    pub fn branches(&self) -> &[Branch] {
+        self.state.branches()
+    }
+
+    /// 切换到指定分支，当前对话保存为新的分支
+    pub fn switch_branch(&mut self, id: usize) -> anyhow::Result<()> {
+        self.ensure_editable()?;
+        self.state.switch_branch(id)?;
+        self.state.set_state(EChatState::Idle);
+        self.save_session();
+        Ok(())
+    }
+
+    /// 固定或取消固定指定消息，固定的消息不会被滑动窗口丢弃
+    pub fn pin_message(&mut self, index: usize, pinned: bool) -> anyhow::Result<()> {
+        self.ensure_editable()?;
+        let Some(msg) = self.state.context_mut().get_mut(index) else {
+            return Err(anyhow::anyhow!("没有第 {} 条消息", index));
+        };
+        if msg.role == "system" {
+            return Err(anyhow::anyhow!("系统提示始终保留，不需要固定"));
+        }
+        msg.pinned = pinned;
+        self.save_session();
+        Ok(())
+    }
+
+    fn last_user_index(&self) -> Option<usize> {
+        self.context().iter().rposition(|msg| msg.role == "user")
+    }
+
+    /// 模型输出或压缩期间不能修改对话记录
+    fn ensure_editable(&self) -> anyhow::Result<()> {
+        match self.get_state() {
+            EChatState::Running | EChatState::Compressing => Err(anyhow::anyhow!(
+                "对话进行中，无法修改对话记录，当前状态：{:?}",
+                self.get_state()
+            )),
+            _ => Ok(()),
+        }
     }
 
     pub fn is_running(&self) -> bool {
@@ -149,6 +440,7 @@ impl Chat {
 
     pub fn confirm(&mut self) {
         if self.get_state() == EChatState::WaitingToolConfirm {
+            self.state.finish_tool_confirm();
             self.state.set_state(EChatState::WaitingToolUse);
         } else {
             self.state.set_state(EChatState::Idle);
@@ -171,19 +463,124 @@ impl Chat {
         self.state.is_remain_tool_call()
     }
 
-    // 是否正在等待工具调用确认
+    // 是否正在等待工具调用确认，有调用被权限规则判定为询问时需要确认
     pub fn is_need_tool_confirm(&self) -> bool {
-        self.get_state() != EChatState::WaitingToolUse
-            && self.state.should_tool_confirmation()
-            && self.is_remain_tool_call()
+        if self.get_state() == EChatState::WaitingToolUse {
+            return false;
+        }
+        let mut need_confirm = false;
+        for (call, decision) in self.tool_permissions() {
+            info!("工具调用 {} 的权限: {}", call.function.name, decision);
+            need_confirm |= decision.action == PermissionAction::Ask;
+        }
+        need_confirm
     }
 
-    /// 拒绝工具调用
-    pub fn reject_tool_call(&mut self) {
-        self.state.set_state(EChatState::Idle);
-        for call in self.state.get_tool_calls() {
-            self.add_message(ModelMessage::tool("用户拒绝调用", call));
+    /// 待处理的工具调用及权限规则的判断结果，用于向用户说明匹配的规则
+    pub fn tool_permissions(&self) -> Vec<(ToolCall, PermissionDecision)> {
+        self.state.tool_permissions()
+    }
+
+    /// 等待用户确认的工具调用及权限规则的判断结果
+    pub fn pending_confirmations(&self) -> Vec<(ToolCall, PermissionDecision)> {
+        self.tool_permissions()
+            .into_iter()
+            .filter(|(_, decision)| decision.action == PermissionAction::Ask)
+            .collect()
+    }
+
+    /// 答复一个等待确认的工具调用
+    ///
+    /// 需要确认的调用都答复后进入等待执行状态，由调用方继续 stream_rechat；
+    /// 所有调用都被拒绝且没有附带原因时回到空闲，等待用户的下一次输入
+    pub fn answer_tool_call(&mut self, id: &str, answer: ToolCallAnswer) -> anyhow::Result<()> {
+        if self.get_state() != EChatState::WaitingToolConfirm {
+            anyhow::bail!("没有等待确认的工具调用");
+        }
+        let Some((call, _)) = self
+            .pending_confirmations()
+            .into_iter()
+            .find(|(call, _)| call.id == id)
+        else {
+            anyhow::bail!("工具调用 {} 不在等待确认", id);
+        };
+        info!("工具调用 {} 的答复: {:?}", call.function.name, answer);
+        match answer {
+            ToolCallAnswer::Approve => self.state.approve_tool_call(id),
+            ToolCallAnswer::AlwaysAllow { exact, scope } => {
+                // 保存失败时授权在本次运行中仍然有效
+                if let Err(e) = self.state.grant_tool(&call, exact, scope) {
+                    warn!("保存授权失败: {}", e);
+                }
+                self.state.approve_tool_call(id);
+            }
+            ToolCallAnswer::Reject(reason) => self.state.reject_tool_call(call, reason),
+        }
+        if self.pending_confirmations().is_empty() {
+            let feedback = self.state.finish_tool_confirm();
+            if self.is_remain_tool_call() || feedback {
+                self.state.set_state(EChatState::WaitingToolUse);
+            } else {
+                self.state.set_state(EChatState::Idle);
+            }
         }
+        self.save_session();
+        Ok(())
+    }
+
+    /// 用同一个答复处理所有等待确认的调用
+    pub fn answer_tool_calls(&mut self, answer: ToolCallAnswer) -> anyhow::Result<()> {
+        // 总是允许的答复可能同时免去后面调用的确认，每次重新获取
+        while let Some((call, _)) = self.pending_confirmations().into_iter().next() {
+            self.answer_tool_call(&call.id, answer.clone())?;
+        }
+        Ok(())
+    }
+
+    /// 处理文本前端的确认输入，默认答复第一个等待确认的调用，输入格式见 ConfirmReply::HELP
+    pub fn reply_tool_confirm(&mut self, input: &str) -> anyhow::Result<()> {
+        let Some(reply) = ConfirmReply::parse(input) else {
+            anyhow::bail!("无法识别的答复「{}」，{}", input, ConfirmReply::HELP);
+        };
+        if reply.all {
+            return self.answer_tool_calls(reply.answer);
+        }
+        let Some((call, _)) = self.pending_confirmations().into_iter().next() else {
+            anyhow::bail!("没有等待确认的工具调用");
+        };
+        self.answer_tool_call(&call.id, reply.answer)
+    }
+
+    /// 列出待处理的工具调用及权限判断结果，标出当前需要答复的调用，最后一行是输入格式
+    pub fn describe_tool_confirm(&self) -> String {
+        let current = self
+            .pending_confirmations()
+            .into_iter()
+            .next()
+            .map(|(call, _)| call.id);
+        let mut lines: Vec<String> = self
+            .tool_permissions()
+            .into_iter()
+            .enumerate()
+            .map(|(i, (call, decision))| {
+                let arguments: String = call.function.arguments.chars().take(200).collect();
+                let marker = if current.as_ref() == Some(&call.id) {
+                    " ← 当前"
+                } else {
+                    ""
+                };
+                format!(
+                    "  {}. {} {}: {}{}",
+                    i + 1,
+                    call.function.name,
+                    arguments,
+                    decision,
+                    marker
+                )
+            })
+            .collect();
+        lines.push(ConfirmReply::HELP.to_string());
+        lines.join("\n")
     }
 
     // 用已有的上下文再次发送给模型，用于突然中断的情况
@@ -191,6 +588,8 @@ impl Chat {
         &mut self,
     ) -> impl Stream<Item = Result<StreamedChatResponse, anyhow::Error>> + '_ {
         async_stream::stream! {
+            let mut schema_retries = 0;
+            let mut stop_hook_active = false;
             loop {
                 // 先判断是否超过轮次
                 info!("对话轮次 {} {}", self.state.get_conversation_turn_info(), self.max_context_num);
@@ -198,26 +597,17 @@ impl Chat {
                     info!("超过对话轮次 {} {}", self.state.get_conversation_turn_info(), self.max_context_num);
                     self.state.set_state(EChatState::WaitingTurnConfirm);
                 }
-                // 检查是否需要自动压缩
-                if self.should_auto_compress() {
-                    info!("检测到需要自动压缩，正在执行...");
-                    // 执行自动压缩
-                    let compressed = self.auto_compress_if_needed().await;
-                    if compressed {
-                        info!("自动压缩完成，继续处理聊天");
-                    } else {
-                        warn!("自动压缩失败，继续处理聊天");
-                    }
-                }
                 if self.get_state() == EChatState::Idle || self.get_state() == EChatState::WaitingToolUse {
                     // 如果有工具调用需要确认，退出等待确认
                     if self.is_need_tool_confirm() {
                         warn!("等待工具确认");
                         self.state.set_state(EChatState::WaitingToolConfirm);
+                        self.save_session();
                         return;
                     }
                     self.state.set_state(EChatState::Running);
                     // 处理工具调用
+                    let executed = self.is_remain_tool_call();
                     {
                         let stream = chat_tools::ChatTools::handle_stream_tool(self, self.get_cancel_token());
                         pin_mut!(stream);
@@ -225,6 +615,31 @@ impl Chat {
                             yield res;
                         }
                     }
+                    // 取消时工具结果已经记录，不再发起新的请求
+                    if self.get_cancel_token().is_cancelled() {
+                        info!("对话已取消");
+                        self.state.set_state(EChatState::Idle);
+                        break;
+                    }
+                    // 工具调用陷入循环或达到本轮上限时暂停，等待用户确认是否继续
+                    if executed && let Some(reason) = self.state.check_tool_loop() {
+                        warn!("暂停工具调用: {}", reason);
+                        self.state.set_state(EChatState::WaitingLoopConfirm);
+                        break;
+                    }
+                    // 发送前检查是否需要自动压缩，此时已包含新的用户输入和工具结果
+                    if self.should_auto_compress() {
+                        info!("检测到需要自动压缩，正在执行...");
+                        // 执行自动压缩
+                        let compressed = self.auto_compress_if_needed().await;
+                        if compressed {
+                            info!("自动压缩完成，继续处理聊天");
+                        } else {
+                            warn!("自动压缩失败，继续处理聊天");
+                        }
+                        // 压缩结束后状态会回到空闲
+                        self.state.set_state(EChatState::Running);
+                    }
                     // 处理聊天
                     {
                         // 对话轮数 + 1
@@ -244,6 +659,22 @@ impl Chat {
                     // 无工具调用，退出循环
                     self.state.set_state(EChatState::Idle);
                     if !self.is_remain_tool_call() {
+                        match self.check_structured_output(&mut schema_retries) {
+                            Ok(true) => continue,
+                            Ok(false) => {}
+                            Err(e) => {
+                                yield Err(e);
+                                break;
+                            }
+                        }
+                        // stop 钩子可以阻止结束，把原因作为新的输入让模型继续
+                        let last = self.context().last().map(|msg| msg.content.to_string()).unwrap_or_default();
+                        if let Some(reason) = self.state.hooks().stop(&last, stop_hook_active).await {
+                            info!("stop 钩子要求继续对话: {}", reason);
+                            stop_hook_active = true;
+                            self.add_message(ModelMessage::user(reason));
+                            continue;
+                        }
                         info!("对话结束");
                         break;
                     }
@@ -254,6 +685,7 @@ impl Chat {
                     break;
                 }
             }
+            self.save_session();
             self.state.reset_cancel_token()
         }
     }
@@ -265,7 +697,26 @@ impl Chat {
     where
         'b: 'a,
     {
+        self.chat_message(ModelMessage::user(prompt.to_string()))
+    }
+
+    /// 非流式对话，用户消息可以带图片、文件等附件
+    pub fn chat_message(
+        &mut self,
+        msg: ModelMessage,
+    ) -> impl Stream<Item = Result<StreamedChatResponse, anyhow::Error>> + '_ {
+        self.state
+            .client
+            .set_turn_sampling(SamplingParams::default());
         async_stream::stream! {
+            let mut msg = msg;
+            if let Some(reason) = self.state.hooks().user_prompt_submit(&mut msg).await {
+                yield Err(anyhow::anyhow!("输入被钩子阻止: {}", reason));
+                return;
+            }
+            self.begin_turn();
+            let mut schema_retries = 0;
+            let mut stop_hook_active = false;
             loop {
                 // 先判断是否超过轮次
                 if self.is_over_context_limit() {
@@ -288,17 +739,29 @@ impl Chat {
                     if self.is_need_tool_confirm() {
                         warn!("等待工具确认");
                         self.state.set_state(EChatState::WaitingToolConfirm);
+                        self.save_session();
                         return;
                     }
                     self.state.set_state(EChatState::Running);
                     // 处理工具调用
                     {
-                        let stream = chat_stream::ChatStream::handle_chat(&mut self.state, prompt);
+                        let stream = chat_stream::ChatStream::handle_chat(&mut self.state, msg.clone());
                         pin_mut!(stream);
                         while let Some(res) = stream.next().await {
                             yield res;
                         }
                     }
+                    if self.get_cancel_token().is_cancelled() {
+                        info!("对话已取消");
+                        self.state.set_state(EChatState::Idle);
+                        break;
+                    }
+                    if matches!(
+                        self.get_state(),
+                        EChatState::WaitingLoopConfirm | EChatState::WaitingToolConfirm
+                    ) {
+                        break;
+                    }
                     // 处理聊天
                     {
                         // 对话轮数 + 1
@@ -318,6 +781,22 @@ impl Chat {
                     // 无工具调用，退出循环
                     self.state.set_state(EChatState::Idle);
                     if !self.is_remain_tool_call() {
+                        match self.check_structured_output(&mut schema_retries) {
+                            Ok(true) => continue,
+                            Ok(false) => {}
+                            Err(e) => {
+                                yield Err(e);
+                                break;
+                            }
+                        }
+                        // stop 钩子可以阻止结束，把原因作为新的输入让模型继续
+                        let last = self.context().last().map(|msg| msg.content.to_string()).unwrap_or_default();
+                        if let Some(reason) = self.state.hooks().stop(&last, stop_hook_active).await {
+                            info!("stop 钩子要求继续对话: {}", reason);
+                            stop_hook_active = true;
+                            msg = ModelMessage::user(reason);
+                            continue;
+                        }
                         info!("对话结束");
                         break;
                     }
@@ -330,6 +809,7 @@ impl Chat {
                     break;
                 }
             }
+            self.save_session();
             self.state.reset_cancel_token()
         }
     }
@@ -338,9 +818,31 @@ impl Chat {
         &'a mut self,
         prompt: &'a str,
     ) -> impl Stream<Item = Result<StreamedChatResponse, anyhow::Error>> + 'a {
-        self.state
-            .add_message(ModelMessage::user(prompt.to_string()));
+        self.stream_chat_with(prompt, SamplingParams::default())
+    }
+
+    /// 与 stream_chat 相同，但本轮对话（包括工具调用后的后续请求）使用指定的采样参数，
+    /// 未设置的字段使用默认参数
+    pub fn stream_chat_with<'a>(
+        &'a mut self,
+        prompt: &'a str,
+        sampling: SamplingParams,
+    ) -> impl Stream<Item = Result<StreamedChatResponse, anyhow::Error>> + 'a {
+        self.stream_chat_message(ModelMessage::user(prompt.to_string()), sampling)
+    }
+
+    /// 以完整的用户消息发起流式对话，用于发送图片、文件等附件
+    pub fn stream_chat_message(
+        &mut self,
+        msg: ModelMessage,
+        sampling: SamplingParams,
+    ) -> impl Stream<Item = Result<StreamedChatResponse, anyhow::Error>> + '_ {
+        self.state.client.set_turn_sampling(sampling);
         async_stream::stream! {
+            if let Err(e) = self.submit_prompt(msg).await {
+                yield Err(e);
+                return;
+            }
             let stream = self.stream_rechat();
             pin_mut!(stream);
             while let Some(res) = stream.next().await {
@@ -349,8 +851,124 @@ impl Chat {
         }
     }
 
-    /// 检查是否需要自动压缩
+    /// 执行 user_prompt_submit 钩子后把用户消息加入上下文，被钩子阻止时返回错误
+    pub async fn submit_prompt(&mut self, mut msg: ModelMessage) -> anyhow::Result<()> {
+        if let Some(reason) = self.state.hooks().user_prompt_submit(&mut msg).await {
+            warn!("用户输入被钩子阻止: {}", reason);
+            return Err(anyhow::anyhow!("输入被钩子阻止: {}", reason));
+        }
+        self.begin_turn();
+        self.state.add_message(msg);
+        Ok(())
+    }
+
+    /// 用户开始新的一轮对话，重新统计工具调用，暂停中的循环由新的输入接管
+    fn begin_turn(&mut self) {
+        self.state.reset_tool_loop();
+        if self.get_state() == EChatState::WaitingLoopConfirm {
+            self.state.set_state(EChatState::Idle);
+        }
+    }
+
+    /// 当前的会话模式
+    pub fn mode(&self) -> ChatMode {
+        self.state.mode()
+    }
+
+    /// 切换会话模式，只改变之后的工具调用，不发起对话
+    pub fn set_mode(&mut self, mode: ChatMode) {
+        info!("切换到{}", mode);
+        self.state.set_mode(mode);
+    }
+
+    /// 模型通过 todo 工具记录的任务列表
+    pub fn todos(&self) -> &[TodoItem] {
+        self.state.todos()
+    }
+
+    /// 批准计划：切换到执行模式，返回让模型按计划执行的输入，由调用方作为用户消息发送
+    pub fn approve_plan(&mut self) -> anyhow::Result<String> {
+        if self.mode() != ChatMode::Plan {
+            return Err(anyhow::anyhow!("当前不在计划模式"));
+        }
+        if self.get_state() != EChatState::Idle {
+            return Err(anyhow::anyhow!(
+                "对话不在空闲状态，当前状态：{:?}",
+                self.get_state()
+            ));
+        }
+        self.set_mode(ChatMode::Execute);
+        Ok(chat_mode::APPROVE_PROMPT.to_string())
+    }
+
+    /// 工具调用暂停的原因
+    pub fn tool_loop_pause(&self) -> Option<String> {
+        (self.get_state() == EChatState::WaitingLoopConfirm)
+            .then(|| self.state.tool_loop().pause_reason().map(str::to_string))
+            .flatten()
+    }
+
+    /// 本轮已执行的工具调用批数和上限
+    pub fn tool_loop_info(&self) -> (usize, usize) {
+        let guard = self.state.tool_loop();
+        (guard.batches(), guard.max_batches())
+    }
+
+    /// 答复工具调用的暂停，继续时重新统计，之后由调用方继续 stream_rechat
+    pub fn confirm_tool_loop(&mut self, resume: bool) {
+        if self.get_state() != EChatState::WaitingLoopConfirm {
+            return;
+        }
+        info!("工具调用暂停后{}", if resume { "继续" } else { "停止" });
+        if resume {
+            self.state.reset_tool_loop();
+        }
+        self.state.set_state(EChatState::Idle);
+    }
+
+    /// 校验最后一条回复是否符合本轮要求的 JSON Schema
+    ///
+    /// 符合时把回复规范化为纯 JSON；不符合且还能重试时追加纠正提示并返回 true，
+    /// 重试次数用完时返回错误
+    fn check_structured_output(&mut self, retries: &mut usize) -> anyhow::Result<bool> {
+        let Some(schema) = self.state.client.response_schema() else {
+            return Ok(false);
+        };
+        // 被取消时回复不完整，不做校验
+        if self.get_cancel_token().is_cancelled() {
+            return Ok(false);
+        }
+        let Some(last) = self
+            .state
+            .context_mut()
+            .last_mut()
+            .filter(|msg| msg.role == "assistant")
+        else {
+            return Ok(false);
+        };
+        match chat_schema::validate(&last.content, &schema) {
+            Ok(value) => {
+                last.content = value.to_string().into();
+                Ok(false)
+            }
+            Err(e) if *retries < MAX_SCHEMA_RETRIES => {
+                *retries += 1;
+                warn!("回复不符合 JSON Schema，第 {} 次重新请求: {}", retries, e);
+                self.add_message(ModelMessage::user(format!(
+                    "你的回复不符合要求的 JSON Schema：\n{}\n请只输出一个符合以下 JSON Schema 的 JSON，不要添加其它内容：\n{}",
+                    e, schema
+                )));
+                Ok(true)
+            }
+            Err(e) => Err(anyhow::anyhow!("回复不符合 JSON Schema: {}", e)),
+        }
+    }
+
+    /// 检查是否需要自动压缩，使用滑动窗口策略时不压缩
     pub fn should_auto_compress(&self) -> bool {
+        if self.state.context_policy() == ContextPolicy::SlidingWindow {
+            return false;
+        }
         // 获取max_tokens的值
         let max_tokens = self.get_token_limit();
         info!("限制：{} {}", max_tokens, self.auto_compress_threshold);
@@ -362,7 +980,7 @@ impl Chat {
     pub async fn auto_compress_if_needed(&mut self) -> bool {
         if self.should_auto_compress() {
             info!("Token使用超过阈值，触发自动压缩");
-            return self.compress_conversation().await;
+            return self.run_compression(false).await;
         }
         false
     }
@@ -394,114 +1012,219 @@ impl Chat {
         self.state.context()
     }
 
+    /// 清空对话，只保留重新生成的系统提示词，修改过的项目说明文件在这里重新加载
+    ///
+    /// 开启了持久化时换成新的会话，已保存的会话不会被清空后的对话覆盖
     pub fn clear_context(&mut self) {
         self.state.context_mut().clear();
+        self.state.set_todos(Vec::new());
+        self.state.set_branches(Vec::new());
+        self.reload_system_prompt();
+        if let Some(cwd) = self.session.as_ref().map(|meta| PathBuf::from(&meta.cwd)) {
+            self.start_session(&cwd);
+        }
     }
 
-    /// 主动向模型要求压缩对话，压缩后的对话将取代原对话上下文
-    /// 返回压缩是否成功的布尔值
-    pub async fn compress_conversation(&mut self) -> bool {
-        // 临时保存当前上下文，以便压缩失败时恢复
-        let original_context = self.state.context().clone();
-
-        // 如果上下文为空或只有系统消息，不需要压缩
-        if original_context.len() <= 1 {
-            info!("上下文过短，无需压缩");
-            return true;
+    /// 按当前项目目录重新生成系统提示词，替换上下文中的第一条系统消息
+    fn reload_system_prompt(&mut self) {
+        let prompt = ModelMessage::system(self.system_prompt.build(self.state.project()));
+        let context = self.state.context_mut();
+        match context.first_mut() {
+            Some(msg) if msg.role == "system" => *msg = prompt,
+            _ => context.insert(0, prompt),
         }
+    }
 
-        // 构建压缩prompt
-        let compress_prompt = self.build_compress_prompt();
+    /// 主动压缩对话，依次执行所有配置的压缩策略，压缩后的对话将取代原对话上下文
+    /// 返回压缩是否成功的布尔值
+    pub async fn compress_conversation(&mut self) -> bool {
+        self.run_compression(true).await
+    }
 
-        info!("开始压缩对话，当前上下文长度: {}", original_context.len());
+    /// 对话在副本上进行时，把共享的对话标记为运行中，期间不能开始新的对话或切换模式，
+    /// 结束后用副本替换
+    pub fn begin_run(&mut self) {
+        self.state.set_state(EChatState::Running);
+    }
 
-        // 创建一个临时的压缩消息，包含当前上下文
-        let mut compress_messages = vec![ModelMessage::system(
-            "你是一个对话压缩助手。请将以下对话压缩成简洁的摘要，保留关键信息和上下文，但大幅减少token使用。只返回压缩后的摘要内容，不要添加额外解释。",
-        )];
+    /// 压缩在对话的副本上进行时，把共享的对话标记为正在压缩，期间不接受新的输入和修改
+    pub fn begin_compress(&mut self) {
+        self.state.set_state(EChatState::Compressing);
+    }
 
-        // 添加除了系统消息外的所有消息作为参考
-        for msg in &original_context[1..] {
-            let role = msg.role.as_ref();
-            let content = msg.content.as_ref();
-            let think = if !msg.think.is_empty() {
-                format!(" [思考: {}]", msg.think.as_ref())
-            } else {
-                String::new()
-            };
-            compress_messages.push(ModelMessage::user(format!(
-                "{}: {}{}",
-                role, content, think
-            )));
+    /// 把副本上压缩得到的上下文合并回来，模式、模型等其他设置保留当前的
+    pub fn finish_compress(&mut self, compressed: Option<&Chat>) {
+        if let Some(compressed) = compressed {
+            *self.state.context_mut() = compressed.context().clone();
+            self.state.reset_conversation_turn();
+            self.save_session();
         }
+        self.state.set_state(EChatState::Idle);
+    }
 
-        compress_messages.push(ModelMessage::user(compress_prompt));
+    /// 按配置依次执行压缩策略
+    ///
+    /// 自动压缩时用量降到阈值以下就停止，需要调用模型的策略只在用量仍超过
+    /// compress_trigger_ratio 时执行。总结失败或摘要不合格时改为直接省略旧对话
+    async fn run_compression(&mut self, force: bool) -> bool {
+        let original = self.state.context().clone();
+        // 如果上下文为空或只有系统消息，不需要压缩
+        if original.len() <= 1 {
+            info!("上下文过短，无需压缩");
+            return true;
+        }
+        info!("开始压缩对话，当前上下文长度: {}", original.len());
 
-        // 先获取client的引用，避免后续借用冲突
+        let limit = self.get_token_limit() as f32;
+        let target = (limit * self.auto_compress_threshold) as u32;
+        let trigger = (limit * self.compress_trigger_ratio) as u32;
         let client = self.state.client().clone();
+        let estimator = self.state.estimator().clone();
+        let summarizer = Summarizer::new(&client, &estimator);
 
-        // 使用非流式方式请求压缩
         self.state.set_state(EChatState::Compressing);
-        let stream = client.chat2(compress_messages);
-        pin_mut!(stream);
-
-        let mut compressed_content = String::new();
-        while let Some(res) = stream.next().await {
-            match res {
-                Ok(msg) => {
-                    compressed_content.push_str(&msg.content);
-                }
+        let mut context = original.clone();
+        let mut tokens = self.state.get_current_token_usage();
+        for kind in self.compression.strategies.iter() {
+            if !force && tokens < target {
+                break;
+            }
+            let strategy = kind.build(&self.compression);
+            if !force && strategy.uses_model() && tokens < trigger {
+                info!(
+                    "用量 {} 未达到 {}，跳过压缩策略 {}",
+                    tokens,
+                    trigger,
+                    strategy.name()
+                );
+                continue;
+            }
+            let res = match strategy.compress(&context, &summarizer).await {
+                Ok(res) => res,
                 Err(e) => {
-                    warn!("压缩失败: {}", e);
-                    self.state.set_state(EChatState::Idle);
-                    return false;
+                    warn!("压缩策略 {} 失败，改为省略旧对话: {}", strategy.name(), e);
+                    chat_compress::fallback(&context, &self.compression)
+                }
+            };
+            if res != context {
+                context = res;
+                // 保留下来的消息带有压缩前的用量，需要重新估算
+                for msg in context.iter_mut() {
+                    msg.token_usage = None;
                 }
+                tokens = self.state.estimate_tokens(&context);
             }
+            info!("压缩策略 {} 完成，估算 token: {}", strategy.name(), tokens);
         }
         self.state.set_state(EChatState::Idle);
 
-        if compressed_content.trim().is_empty() {
-            warn!("压缩返回空内容");
+        if context == original {
+            warn!("没有可以压缩的内容");
             return false;
         }
+        *self.state.context_mut() = context;
+
+        // 重置对话轮次计数，因为现在上下文被压缩了
+        self.state.reset_conversation_turn();
+        self.save_session();
+
+        info!("对话压缩成功，新上下文长度: {}", self.state.context().len());
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use async_trait::async_trait;
+    use serde_json::json;
 
-        // 构建新的压缩后上下文
-        let mut new_context = Vec::new();
+    use super::*;
+    use crate::connection::CommonConnectionContent;
+    use crate::model::ModelStream;
+    use crate::model::param::ModelInputParam;
 
-        // 保留系统消息
-        if let Some(system_msg) = original_context.first() {
-            if system_msg.role == "system" {
-                new_context.push(system_msg.clone());
+    /// 第一次请求返回一个工具调用，之后只返回文字
+    #[derive(Debug, Default)]
+    struct ToolCallModel {
+        requests: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl AgentModel for ToolCallModel {
+        async fn chat(
+            &self,
+            _param: ModelInputParam,
+        ) -> Result<Vec<CommonConnectionContent>, anyhow::Error> {
+            if self.requests.fetch_add(1, Ordering::SeqCst) > 0 {
+                return Ok(vec![CommonConnectionContent::Content("完成".into())]);
             }
+            let mut call = ToolCall::new();
+            call.id = "call_1".into();
+            call.function.name = "shell_command".into();
+            call.function.arguments = r#"{"command":"rm -rf build"}"#.into();
+            Ok(vec![CommonConnectionContent::ToolCall(call)])
         }
 
-        // 添加压缩后的摘要作为用户消息
-        new_context.push(ModelMessage::user(format!(
-            "对话历史摘要: {}",
-            compressed_content
-        )));
-
-        // 替换上下文
-        *self.state.context_mut() = new_context;
+        async fn stream_chat(&self, _param: ModelInputParam) -> ModelStream {
+            Box::pin(futures::stream::empty())
+        }
 
-        // 重置对话轮次计数，因为现在上下文被压缩了
-        self.state.reset_conversation_turn();
+        fn get_token_limit(&self) -> u32 {
+            64000
+        }
 
-        info!("对话压缩成功，新上下文长度: {}", self.state.context().len());
-        true
+        fn model_name(&self) -> String {
+            "tool-call-test".into()
+        }
     }
 
-    /// 构建压缩prompt
-    fn build_compress_prompt(&self) -> String {
-        r#"请将上面的对话历史压缩成一个简洁的摘要。要求：
-1. 保留对话的核心主题和关键信息
-2. 保留用户的重要需求和问题
-3. 保留重要的技术细节和决策
-4. 大幅减少冗余的对话轮次
-5. 保持逻辑连贯性
-6. 只返回压缩后的摘要，不要添加任何解释或说明
+    #[tokio::test]
+    async fn test_chat_message_waits_for_tool_confirm() {
+        let model = Arc::new(ToolCallModel::default());
+        let agent = model.clone();
+        ModelRegistry::global().register(
+            "tool-call-test",
+            Arc::new(move |_| agent.clone() as Arc<dyn AgentModel>),
+        );
+        let config: Config = serde_json::from_value(json!({
+            "provider": "tool-call-test",
+            "api_key": "test",
+            "permissions": {"default": "ask"}
+        }))
+        .unwrap();
+        let mut chat = Chat::new(config);
+        let responses: Vec<_> = chat
+            .chat_message(ModelMessage::user("清理构建目录"))
+            .collect()
+            .await;
+        assert!(responses.iter().all(Result::is_ok));
+        assert_eq!(chat.get_state(), EChatState::WaitingToolConfirm);
+        assert_eq!(model.requests.load(Ordering::SeqCst), 1);
+        assert!(chat.context().iter().all(|msg| msg.role != "tool"));
+        assert_eq!(chat.pending_confirmations().len(), 1);
+    }
 
-压缩后的摘要应该足够简洁，但包含所有必要信息，以便继续对话时能理解上下文。"#
-            .to_string()
+    #[test]
+    fn test_clear_context_starts_new_session() {
+        let agent = Arc::new(ToolCallModel::default());
+        ModelRegistry::global().register(
+            "clear-test",
+            Arc::new(move |_| agent.clone() as Arc<dyn AgentModel>),
+        );
+        let config: Config = serde_json::from_value(json!({
+            "provider": "clear-test",
+            "api_key": "test"
+        }))
+        .unwrap();
+        let mut chat = Chat::new(config);
+        let old = chat.start_session(&std::env::temp_dir());
+        chat.state.context_mut().push(ModelMessage::user("你好"));
+        chat.clear_context();
+        let new = chat.session().map(|meta| meta.id.clone()).unwrap();
+        assert_ne!(old, new);
+        assert_eq!(chat.context().len(), 1);
+        assert!(chat.branches().is_empty());
     }
 }
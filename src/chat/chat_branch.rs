@@ -0,0 +1,157 @@
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+use crate::model::param::ModelMessage;
+
+/// 摘要中消息内容最多保留的字符数
+const SUMMARY_MAX_CHARS: usize = 40;
+
+/// 被替换下来的对话分支，保存完整的对话记录，可以随时切换回去
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Branch {
+    pub id: usize,
+    /// 与当时的对话开始不同的位置（消息下标）
+    pub fork_at: usize,
+    pub messages: Vec<ModelMessage>,
+    /// 分支的对话轮次计数
+    #[serde(default)]
+    pub conversation_turn: usize,
+}
+
+impl Branch {
+    /// 一行摘要，显示分叉处的消息
+    pub fn summary(&self) -> String {
+        let first = self
+            .messages
+            .get(self.fork_at)
+            .map(|msg| {
+                let text: String = msg
+                    .content
+                    .trim()
+                    .lines()
+                    .next()
+                    .unwrap_or_default()
+                    .chars()
+                    .take(SUMMARY_MAX_CHARS)
+                    .collect();
+                format!("[{}] {}", msg.role, text)
+            })
+            .unwrap_or_default();
+        format!(
+            "#{} 第 {} 条消息起不同，共 {} 条消息 {}",
+            self.id,
+            self.fork_at,
+            self.messages.len(),
+            first
+        )
+    }
+}
+
+/// 对话分支集合
+#[derive(Debug, Clone, Default)]
+pub struct Branches {
+    list: Vec<Branch>,
+    next_id: usize,
+}
+
+impl Branches {
+    pub fn new(list: Vec<Branch>) -> Self {
+        let next_id = list.iter().map(|b| b.id + 1).max().unwrap_or(0);
+        Self { list, next_id }
+    }
+
+    pub fn list(&self) -> &[Branch] {
+        &self.list
+    }
+
+    /// 回退到指定消息之前，原来的对话保存为分支，返回被丢弃的第一条消息
+    ///
+    /// 只能回退到用户消息或模型回复，工具结果必须跟在对应的调用后面
+    pub fn rewind(
+        &mut self,
+        context: &mut Vec<ModelMessage>,
+        conversation_turn: usize,
+        index: usize,
+    ) -> anyhow::Result<ModelMessage> {
+        let Some(msg) = context.get(index) else {
+            return Err(anyhow!("没有第 {} 条消息", index));
+        };
+        if msg.role != "user" && msg.role != "assistant" {
+            return Err(anyhow!(
+                "第 {} 条消息是 {} 消息，只能回退到用户消息或模型回复",
+                index,
+                msg.role
+            ));
+        }
+        self.push(context.clone(), index, conversation_turn);
+        Ok(context.split_off(index).swap_remove(0))
+    }
+
+    /// 切换到指定分支，当前对话保存为新的分支，返回切换后的对话轮次计数
+    pub fn switch(
+        &mut self,
+        context: &mut Vec<ModelMessage>,
+        conversation_turn: usize,
+        id: usize,
+    ) -> anyhow::Result<usize> {
+        let Some(pos) = self.list.iter().position(|b| b.id == id) else {
+            return Err(anyhow!("分支 #{} 不存在", id));
+        };
+        let branch = self.list.remove(pos);
+        let fork_at = context
+            .iter()
+            .zip(&branch.messages)
+            .take_while(|(a, b)| a == b)
+            .count();
+        let current = std::mem::replace(context, branch.messages);
+        self.push(current, fork_at, conversation_turn);
+        Ok(branch.conversation_turn)
+    }
+
+    fn push(&mut self, messages: Vec<ModelMessage>, fork_at: usize, conversation_turn: usize) {
+        self.list.push(Branch {
+            id: self.next_id,
+            fork_at,
+            messages,
+            conversation_turn,
+        });
+        self.next_id += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::param::ToolCall;
+
+    #[test]
+    fn test_rewind_and_switch() {
+        let call = ToolCall::new();
+        let mut context = vec![
+            ModelMessage::system("系统"),
+            ModelMessage::user("问题"),
+            ModelMessage::assistant("", "", vec![call.clone()]),
+            ModelMessage::tool("结果", call),
+            ModelMessage::assistant("回答", "", vec![]),
+        ];
+        let original = context.clone();
+        let mut branches = Branches::default();
+
+        assert!(branches.rewind(&mut context, 3, 3).is_err());
+        let removed = branches.rewind(&mut context, 3, 2).unwrap();
+        assert_eq!(removed, original[2]);
+        assert_eq!(context, original[..2]);
+        assert_eq!(branches.list()[0].fork_at, 2);
+
+        context.push(ModelMessage::assistant("另一个回答", "", vec![]));
+        let turn = branches.switch(&mut context, 1, 0).unwrap();
+        assert_eq!(turn, 3);
+        assert_eq!(context, original);
+        let branch = &branches.list()[0];
+        assert_eq!(
+            (branch.id, branch.fork_at, branch.messages.len()),
+            (1, 2, 3)
+        );
+        assert!(branches.switch(&mut context, 3, 0).is_err());
+    }
+}
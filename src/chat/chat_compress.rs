@@ -0,0 +1,576 @@
+use std::collections::BTreeSet;
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::client::chat_client::ChatClient;
+use crate::model::param::{ContentPart, ModelMessage};
+use crate::model::tokenizer::TokenEstimator;
+use crate::model::wire;
+
+/// 压缩产生的摘要消息的开头，用于识别之前的摘要
+pub const SUMMARY_PREFIX: &str = "[对话历史摘要]";
+
+/// 整理对话记录时单个工具参数最多保留的字符数
+const TRANSCRIPT_ARGS_MAX_CHARS: usize = 500;
+/// 整理对话记录时单个工具结果最多保留的字符数
+const TRANSCRIPT_RESULT_MAX_CHARS: usize = 2000;
+/// 检查摘要时最多要求保留的文件路径数量
+const MAX_CHECKED_PATHS: usize = 20;
+
+const SUMMARY_SYSTEM_PROMPT: &str = "你是一个对话压缩助手，负责把智能体与用户之间较早的对话整理成摘要，摘要会取代原来的对话继续使用。只返回摘要内容，不要添加额外解释。";
+
+const SUMMARY_PROMPT: &str = r#"请把上面的对话记录压缩成摘要。要求：
+1. 保留用户的目标、需求和约束
+2. 保留做过的决定和原因
+3. 原样保留涉及的文件路径、函数名、命令，以及对它们做过的修改
+4. 保留工具调用得到的关键结果和出现过的错误
+5. 列出还没有完成的事项
+6. 省略寒暄和重复的内容，只返回摘要"#;
+
+const MERGE_PROMPT: &str = r#"上面是按时间顺序排列的几段对话摘要，请把它们合并成一份摘要。要求：
+1. 后面的摘要与前面冲突时以后面的为准
+2. 原样保留涉及的文件路径、函数名、命令
+3. 保留还没有完成的事项
+4. 只返回合并后的摘要"#;
+
+fn keep_turns_default() -> usize {
+    2
+}
+
+fn tool_output_max_chars_default() -> usize {
+    1000
+}
+
+fn max_summaries_default() -> usize {
+    3
+}
+
+fn strategies_default() -> Vec<StrategyKind> {
+    vec![StrategyKind::PruneToolOutputs, StrategyKind::RollingSummary]
+}
+
+/// 对话压缩配置
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CompressionConfig {
+    /// 依次执行的压缩策略，自动压缩时用量降到阈值以下就不再执行后面的策略
+    #[serde(default = "strategies_default")]
+    pub strategies: Vec<StrategyKind>,
+    /// 原样保留的最近对话轮数；只有一轮对话时为保留的最近模型回复数
+    #[serde(default = "keep_turns_default")]
+    pub keep_turns: usize,
+    /// 较早的工具结果超过这个字符数时截短
+    #[serde(default = "tool_output_max_chars_default")]
+    pub tool_output_max_chars: usize,
+    /// 滚动摘要最多保留的摘要条数，超过后合并为一条
+    #[serde(default = "max_summaries_default")]
+    pub max_summaries: usize,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            strategies: strategies_default(),
+            keep_turns: keep_turns_default(),
+            tool_output_max_chars: tool_output_max_chars_default(),
+            max_summaries: max_summaries_default(),
+        }
+    }
+}
+
+/// 内置的压缩策略
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StrategyKind {
+    /// 截短较早的工具结果，不调用模型
+    PruneToolOutputs,
+    /// 原样保留最近几轮对话，其余的总结成一条摘要
+    RecentTurns,
+    /// 分层滚动摘要：只总结新移出的对话，摘要过多时再合并
+    RollingSummary,
+    /// 把全部对话总结成一条摘要
+    FullSummary,
+}
+
+impl StrategyKind {
+    pub fn build(self, config: &CompressionConfig) -> Box<dyn CompressionStrategy> {
+        match self {
+            Self::PruneToolOutputs => Box::new(PruneToolOutputs {
+                keep_turns: config.keep_turns,
+                max_chars: config.tool_output_max_chars,
+            }),
+            Self::RecentTurns => Box::new(RecentTurns {
+                keep_turns: config.keep_turns,
+            }),
+            Self::RollingSummary => Box::new(RollingSummary {
+                keep_turns: config.keep_turns,
+                max_summaries: config.max_summaries.max(1),
+            }),
+            Self::FullSummary => Box::new(RecentTurns { keep_turns: 0 }),
+        }
+    }
+}
+
+/// 压缩策略，输入完整的对话记录，返回压缩后的对话记录
+#[async_trait]
+pub trait CompressionStrategy: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// 是否需要调用模型，这类策略只在用量超过 compress_trigger_ratio 时自动执行
+    fn uses_model(&self) -> bool {
+        false
+    }
+
+    async fn compress(
+        &self,
+        context: &[ModelMessage],
+        summarizer: &Summarizer<'_>,
+    ) -> anyhow::Result<Vec<ModelMessage>>;
+}
+
+/// 截短较早的工具结果
+struct PruneToolOutputs {
+    keep_turns: usize,
+    max_chars: usize,
+}
+
+#[async_trait]
+impl CompressionStrategy for PruneToolOutputs {
+    fn name(&self) -> &'static str {
+        "prune_tool_outputs"
+    }
+
+    async fn compress(
+        &self,
+        context: &[ModelMessage],
+        _summarizer: &Summarizer<'_>,
+    ) -> anyhow::Result<Vec<ModelMessage>> {
+        Ok(prune_tool_outputs(context, self.keep_turns, self.max_chars))
+    }
+}
+
+/// 原样保留最近几轮，其余对话连同之前的摘要重新总结成一条
+struct RecentTurns {
+    keep_turns: usize,
+}
+
+#[async_trait]
+impl CompressionStrategy for RecentTurns {
+    fn name(&self) -> &'static str {
+        "recent_turns"
+    }
+
+    fn uses_model(&self) -> bool {
+        true
+    }
+
+    async fn compress(
+        &self,
+        context: &[ModelMessage],
+        summarizer: &Summarizer<'_>,
+    ) -> anyhow::Result<Vec<ModelMessage>> {
+        let split = Split::find(context, self.keep_turns, false)
+            .ok_or_else(|| anyhow!("没有可以总结的旧对话"))?;
+        let summary = summarizer.summarize(split.old(context)).await?;
+        Ok(split.replace(context, summary_message(&summary)))
+    }
+}
+
+/// 分层滚动摘要
+///
+/// 之前的摘要原样保留，只总结新移出的对话并追加在后面；
+/// 连续的摘要超过 max_summaries 条时合并为一条更高层的摘要
+struct RollingSummary {
+    keep_turns: usize,
+    max_summaries: usize,
+}
+
+#[async_trait]
+impl CompressionStrategy for RollingSummary {
+    fn name(&self) -> &'static str {
+        "rolling_summary"
+    }
+
+    fn uses_model(&self) -> bool {
+        true
+    }
+
+    async fn compress(
+        &self,
+        context: &[ModelMessage],
+        summarizer: &Summarizer<'_>,
+    ) -> anyhow::Result<Vec<ModelMessage>> {
+        let split = Split::find(context, self.keep_turns, true)
+            .ok_or_else(|| anyhow!("没有可以总结的旧对话"))?;
+        let summary = summarizer.summarize(split.old(context)).await?;
+        let mut res = split.replace(context, summary_message(&summary));
+
+        let first = (0..=split.start)
+            .rev()
+            .take_while(|&i| i == split.start || is_summary(&res[i]))
+            .last()
+            .unwrap_or(split.start);
+        if split.start + 1 - first > self.max_summaries {
+            let summaries = &res[first..=split.start];
+            match summarizer.merge(summaries).await {
+                Ok(merged) => {
+                    info!("合并 {} 条摘要", summaries.len());
+                    res.splice(first..=split.start, [summary_message(&merged)]);
+                }
+                Err(e) => warn!("合并摘要失败，保留原来的摘要: {}", e),
+            }
+        }
+        Ok(res)
+    }
+}
+
+/// 调用模型生成摘要，并检查摘要质量
+pub struct Summarizer<'a> {
+    client: &'a ChatClient,
+    estimator: &'a TokenEstimator,
+}
+
+impl<'a> Summarizer<'a> {
+    pub fn new(client: &'a ChatClient, estimator: &'a TokenEstimator) -> Self {
+        Self { client, estimator }
+    }
+
+    /// 总结一段对话
+    pub async fn summarize(&self, messages: &[ModelMessage]) -> anyhow::Result<String> {
+        self.request(messages, SUMMARY_PROMPT).await
+    }
+
+    /// 把几段摘要合并为一段
+    pub async fn merge(&self, summaries: &[ModelMessage]) -> anyhow::Result<String> {
+        self.request(summaries, MERGE_PROMPT).await
+    }
+
+    async fn request(&self, messages: &[ModelMessage], prompt: &str) -> anyhow::Result<String> {
+        let request = vec![
+            ModelMessage::system(SUMMARY_SYSTEM_PROMPT),
+            ModelMessage::user(format!("{}\n\n{}", render_transcript(messages), prompt)),
+        ];
+        let summary = self.client.complete(request).await?;
+        check_summary(summary.trim(), messages, self.estimator)
+            .map_err(|e| anyhow!("摘要不合格: {}", e))?;
+        Ok(summary.trim().to_string())
+    }
+}
+
+/// 一次压缩的划分，[start, end) 是要压缩的旧消息，其余消息原样保留
+#[derive(Debug, PartialEq)]
+struct Split {
+    start: usize,
+    end: usize,
+}
+
+impl Split {
+    /// 找出要压缩的旧消息，系统提示和最近 keep_turns 轮对话原样保留
+    ///
+    /// 只在用户消息或模型回复处切分，工具结果始终跟在对应的调用后面。
+    /// 对话不超过 keep_turns 轮时，在最后一轮内按模型回复切分，保留用户的要求。
+    /// keep_summaries 为 true 时之前的摘要也原样保留
+    fn find(context: &[ModelMessage], keep_turns: usize, keep_summaries: bool) -> Option<Self> {
+        let head = context
+            .iter()
+            .take_while(|msg| msg.role == "system")
+            .count();
+        let turns: Vec<usize> = (head..context.len())
+            .filter(|&i| context[i].role == "user" && !is_summary(&context[i]))
+            .collect();
+        let (start, end) = if turns.len() > keep_turns {
+            let end = match keep_turns {
+                0 => context.len(),
+                n => turns[turns.len() - n],
+            };
+            (head, end)
+        } else {
+            let last = *turns.last()?;
+            let steps: Vec<usize> = (last + 1..context.len())
+                .filter(|&i| context[i].role == "assistant")
+                .collect();
+            if steps.len() <= keep_turns {
+                return None;
+            }
+            (last + 1, steps[steps.len() - keep_turns])
+        };
+        let start = if keep_summaries {
+            (start..end)
+                .find(|&i| !is_summary(&context[i]))
+                .unwrap_or(end)
+        } else {
+            start
+        };
+        (start < end).then_some(Self { start, end })
+    }
+
+    fn old<'a>(&self, context: &'a [ModelMessage]) -> &'a [ModelMessage] {
+        &context[self.start..self.end]
+    }
+
+    fn replace(&self, context: &[ModelMessage], msg: ModelMessage) -> Vec<ModelMessage> {
+        let mut res = context[..self.start].to_vec();
+        res.push(msg);
+        res.extend_from_slice(&context[self.end..]);
+        res
+    }
+}
+
+pub fn is_summary(msg: &ModelMessage) -> bool {
+    msg.role == "user" && msg.content.starts_with(SUMMARY_PREFIX)
+}
+
+fn summary_message(summary: &str) -> ModelMessage {
+    ModelMessage::user(format!("{}\n{}", SUMMARY_PREFIX, summary))
+}
+
+/// 截短最近 keep_turns 轮之前的工具结果，图片等内容换成文字说明
+fn prune_tool_outputs(
+    context: &[ModelMessage],
+    keep_turns: usize,
+    max_chars: usize,
+) -> Vec<ModelMessage> {
+    let end = Split::find(context, keep_turns, false).map_or(0, |split| split.end);
+    let mut res = context.to_vec();
+    for msg in res[..end].iter_mut().filter(|msg| msg.role == "tool") {
+        let len = msg.content.chars().count();
+        if len > max_chars {
+            msg.content = format!(
+                "{}\n[较早的工具输出已截短，原长度 {} 字符]",
+                truncate_middle(&msg.content, max_chars),
+                len
+            )
+            .into();
+        }
+        if !msg.parts.is_empty() {
+            let text: Vec<String> = msg.parts.iter().map(ContentPart::placeholder).collect();
+            msg.content = format!("{}\n{}", msg.content, text.join("\n")).into();
+            msg.parts.clear();
+        }
+    }
+    res
+}
+
+/// 总结失败时的兜底：截短工具结果并直接省略旧对话，只记下涉及的文件路径
+pub fn fallback(context: &[ModelMessage], config: &CompressionConfig) -> Vec<ModelMessage> {
+    let context = prune_tool_outputs(context, config.keep_turns, config.tool_output_max_chars);
+    let Some(split) = Split::find(&context, config.keep_turns, true) else {
+        return context;
+    };
+    let old = split.old(&context);
+    let mut note = format!("更早的 {} 条消息因无法总结已省略。", old.len());
+    let paths = mentioned_paths(old);
+    if !paths.is_empty() {
+        let paths: Vec<&str> = paths.iter().map(String::as_str).collect();
+        note.push_str(&format!("其中涉及的文件: {}", paths.join(", ")));
+    }
+    split.replace(&context, summary_message(&note))
+}
+
+/// 检查摘要质量：不能为空，要比原文短，并保留原文中至少一半的文件路径
+fn check_summary(
+    summary: &str,
+    source: &[ModelMessage],
+    estimator: &TokenEstimator,
+) -> Result<(), String> {
+    if summary.is_empty() {
+        return Err("摘要为空".into());
+    }
+    let source_tokens = estimator.count_messages(source);
+    let summary_tokens = estimator.count_text(summary);
+    if summary_tokens >= source_tokens {
+        return Err(format!(
+            "摘要 ({} token) 没有比原文 ({} token) 更短",
+            summary_tokens, source_tokens
+        ));
+    }
+    let paths = mentioned_paths(source);
+    let kept = paths
+        .iter()
+        .filter(|p| summary.contains(p.as_str()))
+        .count();
+    if kept < paths.len().min(MAX_CHECKED_PATHS).div_ceil(2) {
+        return Err(format!("只保留了 {}/{} 个文件路径", kept, paths.len()));
+    }
+    Ok(())
+}
+
+/// 用户消息、模型回复和工具参数中提到的文件路径，不含工具结果
+fn mentioned_paths(messages: &[ModelMessage]) -> BTreeSet<String> {
+    let mut paths = BTreeSet::new();
+    for msg in messages.iter().filter(|msg| msg.role != "tool") {
+        let args = msg
+            .tool_calls
+            .iter()
+            .flatten()
+            .map(|t| &t.function.arguments);
+        for text in std::iter::once(&msg.content.to_string()).chain(args) {
+            paths.extend(
+                text.split(|c: char| c.is_whitespace() || "\"'`()[]{}<>,;，。：；（）".contains(c))
+                    .map(|word| word.trim_end_matches(['.', ':']))
+                    .filter(|word| is_path(word))
+                    .map(str::to_string),
+            );
+        }
+    }
+    paths
+}
+
+/// 粗略判断是否为文件路径：带目录分隔符，或者有字母组成的扩展名
+fn is_path(word: &str) -> bool {
+    if word.len() < 3 || word.len() > 200 || word.contains("://") {
+        return false;
+    }
+    if word.contains('/') {
+        return word.chars().any(|c| c.is_alphanumeric());
+    }
+    match word.rsplit_once('.') {
+        Some((name, ext)) => {
+            !name.is_empty()
+                && (1..=5).contains(&ext.len())
+                && ext.chars().all(|c| c.is_ascii_alphanumeric())
+                && ext.chars().any(|c| c.is_ascii_alphabetic())
+        }
+        None => false,
+    }
+}
+
+/// 把对话整理成便于总结的文字，保留工具调用的名称、参数和结果
+fn render_transcript(messages: &[ModelMessage]) -> String {
+    let mut out = Vec::new();
+    for msg in messages.iter().filter(|msg| !wire::is_internal(msg)) {
+        match msg.role.as_ref() {
+            "tool" => out.push(format!(
+                "[工具 {} 的结果]\n{}",
+                msg.name,
+                truncate_middle(&msg.content, TRANSCRIPT_RESULT_MAX_CHARS)
+            )),
+            "assistant" => {
+                if !msg.content.is_empty() {
+                    out.push(format!("[助手]\n{}", msg.content));
+                }
+                for tool in msg.tool_calls.iter().flatten() {
+                    out.push(format!(
+                        "[助手调用工具 {}]\n{}",
+                        tool.function.name,
+                        truncate_middle(&tool.function.arguments, TRANSCRIPT_ARGS_MAX_CHARS)
+                    ));
+                }
+            }
+            _ if is_summary(msg) => out.push(msg.content.to_string()),
+            role => {
+                let mut text = msg.content.to_string();
+                for part in msg.parts.iter() {
+                    text.push('\n');
+                    text.push_str(&part.placeholder());
+                }
+                let role = if role == "user" { "用户" } else { "系统" };
+                out.push(format!("[{}]\n{}", role, text));
+            }
+        }
+    }
+    out.join("\n\n")
+}
+
+/// 超过长度时保留开头和结尾，省略中间部分
+fn truncate_middle(text: &str, max_chars: usize) -> String {
+    let len = text.chars().count();
+    if len <= max_chars {
+        return text.to_string();
+    }
+    let head: String = text.chars().take(max_chars / 2).collect();
+    let tail: String = text.chars().skip(len - max_chars / 2).collect();
+    format!(
+        "{}\n…[省略 {} 字符]…\n{}",
+        head,
+        len - head.chars().count() * 2,
+        tail
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::param::ToolCall;
+
+    fn call(args: &str) -> ToolCall {
+        let mut call = ToolCall::new();
+        call.function.name = "read_file".into();
+        call.function.arguments = args.into();
+        call
+    }
+
+    fn context() -> Vec<ModelMessage> {
+        let read = call(r#"{"path": "src/main.rs"}"#);
+        vec![
+            ModelMessage::system("系统"),
+            ModelMessage::user("看看 src/main.rs"),
+            ModelMessage::assistant("", "", vec![read.clone()]),
+            ModelMessage::tool("x".repeat(50), read),
+            ModelMessage::assistant("看完了", "", vec![]),
+            ModelMessage::user("再看看 Cargo.toml"),
+            ModelMessage::assistant("好的", "", vec![]),
+        ]
+    }
+
+    #[test]
+    fn test_split() {
+        let context = context();
+        assert_eq!(
+            Split::find(&context, 1, false),
+            Some(Split { start: 1, end: 5 })
+        );
+        assert_eq!(Split::find(&context, 2, false), None);
+        // 只有一轮时在这一轮内切分，保留用户的要求
+        let single = &context[..5];
+        assert_eq!(
+            Split::find(single, 1, false),
+            Some(Split { start: 2, end: 4 })
+        );
+
+        let mut rolled = Split { start: 1, end: 5 }.replace(&context, summary_message("旧摘要"));
+        rolled.push(ModelMessage::user("第三轮"));
+        assert_eq!(
+            Split::find(&rolled, 1, true),
+            Some(Split { start: 2, end: 4 })
+        );
+        assert_eq!(
+            Split::find(&rolled, 1, false),
+            Some(Split { start: 1, end: 4 })
+        );
+    }
+
+    #[test]
+    fn test_prune_and_fallback() {
+        let context = context();
+        let pruned = prune_tool_outputs(&context, 1, 10);
+        assert!(pruned[3].content.contains("原长度 50 字符"));
+        assert_eq!(pruned[5..], context[5..]);
+
+        let config = CompressionConfig {
+            keep_turns: 1,
+            ..Default::default()
+        };
+        let res = fallback(&context, &config);
+        assert_eq!(res.len(), 4);
+        assert_eq!(res[0], context[0]);
+        assert!(is_summary(&res[1]) && res[1].content.contains("src/main.rs"));
+        assert_eq!(res[2..], context[5..]);
+    }
+
+    #[test]
+    fn test_check_summary() {
+        let estimator = TokenEstimator::Heuristic {
+            ascii: 0.25,
+            other: 1.0,
+        };
+        let context = context();
+        let old = &context[1..5];
+        assert!(check_summary("", old, &estimator).is_err());
+        assert!(check_summary("读了一个文件", old, &estimator).is_err());
+        assert!(check_summary("读了 src/main.rs", old, &estimator).is_ok());
+        assert!(check_summary(&"src/main.rs".repeat(100), old, &estimator).is_err());
+    }
+}
@@ -0,0 +1,407 @@
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::model::param::{ModelMessage, ToolCall};
+
+fn hook_timeout_secs_default() -> u64 {
+    60
+}
+
+/// 生命周期钩子配置，同一事件的钩子按顺序执行
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct HooksConfig {
+    /// 工具调用前，可以阻止调用或改写参数
+    #[serde(default)]
+    pub pre_tool_use: Vec<HookConfig>,
+    /// 工具调用后，可以给结果追加说明
+    #[serde(default)]
+    pub post_tool_use: Vec<HookConfig>,
+    /// 用户提交输入时，可以拒绝输入或追加上下文
+    #[serde(default)]
+    pub user_prompt_submit: Vec<HookConfig>,
+    /// 一轮对话结束时，可以要求模型继续
+    #[serde(default)]
+    pub stop: Vec<HookConfig>,
+}
+
+/// 单个钩子，command 和 builtin 二选一
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct HookConfig {
+    /// 匹配的工具名，多个用 | 分隔，为空或 * 时匹配所有工具，只对工具事件生效
+    #[serde(default)]
+    pub matcher: Option<String>,
+    /// shell 命令，从标准输入读取事件 JSON，向标准输出写入 JSON 结果
+    #[serde(default)]
+    pub command: Option<String>,
+    /// 内置处理器
+    #[serde(default)]
+    pub builtin: Option<BuiltinHook>,
+    /// 命令超时秒数，超时后忽略该钩子
+    #[serde(default = "hook_timeout_secs_default")]
+    pub timeout_secs: u64,
+}
+
+/// 内置的钩子处理器
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BuiltinHook {
+    /// 总是阻止，配合 matcher 禁用指定工具
+    Deny,
+    /// 把事件写入日志
+    Log,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HookEvent {
+    PreToolUse,
+    PostToolUse,
+    UserPromptSubmit,
+    Stop,
+}
+
+impl HookEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::PreToolUse => "pre_tool_use",
+            Self::PostToolUse => "post_tool_use",
+            Self::UserPromptSubmit => "user_prompt_submit",
+            Self::Stop => "stop",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum Decision {
+    Allow,
+    Block,
+}
+
+/// 钩子返回的 JSON，标准输出为空时表示允许
+#[derive(Debug, Default, Deserialize, PartialEq)]
+struct HookOutput {
+    #[serde(default)]
+    decision: Option<Decision>,
+    #[serde(default)]
+    reason: Option<String>,
+    /// 改写后的工具参数，只对 pre_tool_use 生效
+    #[serde(default)]
+    tool_input: Option<Value>,
+    /// 追加到工具结果或用户输入的说明
+    #[serde(default)]
+    additional_context: Option<String>,
+}
+
+/// 同一事件所有钩子的汇总结果
+#[derive(Debug, Default, PartialEq)]
+pub struct HookOutcome {
+    /// 阻止的原因，为空时表示允许
+    pub blocked: Option<String>,
+    /// 改写后的工具参数
+    pub tool_input: Option<Value>,
+    /// 各钩子追加的说明
+    pub context: Vec<String>,
+}
+
+impl HookConfig {
+    fn matches(&self, tool: Option<&str>) -> bool {
+        match (self.matcher.as_deref(), tool) {
+            (None | Some("") | Some("*"), _) | (_, None) => true,
+            (Some(matcher), Some(tool)) => matcher.split('|').any(|name| name.trim() == tool),
+        }
+    }
+
+    async fn run(&self, event: HookEvent, input: &Value) -> anyhow::Result<HookOutput> {
+        if let Some(builtin) = self.builtin {
+            return Ok(match builtin {
+                BuiltinHook::Deny => HookOutput {
+                    decision: Some(Decision::Block),
+                    reason: Some("已被策略禁止".into()),
+                    ..Default::default()
+                },
+                BuiltinHook::Log => {
+                    info!("钩子事件 {}: {}", event.name(), input);
+                    HookOutput::default()
+                }
+            });
+        }
+        let Some(command) = &self.command else {
+            return Err(anyhow!("钩子没有配置 command 或 builtin"));
+        };
+        let mut cmd = if cfg!(windows) {
+            let mut cmd = Command::new("cmd");
+            cmd.arg("/C").arg(command);
+            cmd
+        } else {
+            let mut cmd = Command::new("sh");
+            cmd.arg("-c").arg(command);
+            cmd
+        };
+        let mut child = cmd
+            .env("AGENT_CLI_HOOK_EVENT", event.name())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| anyhow!("启动钩子命令失败: {}", e))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            // 命令不读取标准输入时写入会失败，不影响结果
+            let _ = stdin.write_all(input.to_string().as_bytes()).await;
+        }
+        let output = tokio::time::timeout(
+            Duration::from_secs(self.timeout_secs),
+            child.wait_with_output(),
+        )
+        .await
+        .map_err(|_| anyhow!("钩子命令超时 ({} 秒): {}", self.timeout_secs, command))??;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        match output.status.code() {
+            Some(0) => parse_output(&stdout),
+            // 退出码 2 表示阻止，原因写在标准错误中
+            Some(2) => Ok(HookOutput {
+                decision: Some(Decision::Block),
+                reason: Some(stderr.trim().to_string()),
+                ..Default::default()
+            }),
+            _ => Err(anyhow!(
+                "钩子命令执行失败 ({}): {} {}",
+                output.status,
+                command,
+                stderr.trim()
+            )),
+        }
+    }
+}
+
+fn parse_output(stdout: &str) -> anyhow::Result<HookOutput> {
+    let stdout = stdout.trim();
+    if stdout.is_empty() {
+        return Ok(HookOutput::default());
+    }
+    serde_json::from_str(stdout).map_err(|e| anyhow!("钩子输出不是有效的 JSON: {}", e))
+}
+
+/// 生命周期钩子，在工具调用前后、用户提交输入和一轮对话结束时执行
+#[derive(Debug, Clone, Default)]
+pub struct Hooks {
+    config: HooksConfig,
+    session_id: Option<String>,
+}
+
+impl Hooks {
+    pub fn new(config: HooksConfig) -> Self {
+        Self {
+            config,
+            session_id: None,
+        }
+    }
+
+    /// 开启或恢复会话后，钩子输入中带上会话 ID
+    pub fn set_session(&mut self, id: &str) {
+        self.session_id = Some(id.to_string());
+    }
+
+    fn hooks(&self, event: HookEvent) -> &[HookConfig] {
+        match event {
+            HookEvent::PreToolUse => &self.config.pre_tool_use,
+            HookEvent::PostToolUse => &self.config.post_tool_use,
+            HookEvent::UserPromptSubmit => &self.config.user_prompt_submit,
+            HookEvent::Stop => &self.config.stop,
+        }
+    }
+
+    /// 依次执行事件的钩子，某个钩子阻止后不再执行后面的钩子，执行出错的钩子会被忽略
+    pub async fn run(&self, event: HookEvent, tool: Option<&str>, fields: Value) -> HookOutcome {
+        let mut outcome = HookOutcome::default();
+        let hooks: Vec<&HookConfig> = self
+            .hooks(event)
+            .iter()
+            .filter(|hook| hook.matches(tool))
+            .collect();
+        if hooks.is_empty() {
+            return outcome;
+        }
+        let cwd = std::env::current_dir().unwrap_or_default();
+        let mut input = json!({
+            "event": event.name(),
+            "session_id": self.session_id,
+            "cwd": cwd.to_string_lossy(),
+        });
+        if let (Value::Object(input), Value::Object(fields)) = (&mut input, fields) {
+            input.extend(fields);
+        }
+        for hook in hooks {
+            // 后面的钩子看到前面的钩子改写后的参数
+            if let Some(tool_input) = &outcome.tool_input {
+                input["tool_input"] = tool_input.clone();
+            }
+            let output = match hook.run(event, &input).await {
+                Ok(output) => output,
+                Err(e) => {
+                    warn!("{} 钩子执行失败: {}", event.name(), e);
+                    continue;
+                }
+            };
+            if let Some(context) = output.additional_context {
+                outcome.context.push(context);
+            }
+            if output.decision == Some(Decision::Block) {
+                let reason = output.reason.filter(|r| !r.is_empty());
+                outcome.blocked = Some(reason.unwrap_or_else(|| "被钩子阻止".into()));
+                break;
+            }
+            if event == HookEvent::PreToolUse
+                && let Some(tool_input) = output.tool_input
+            {
+                outcome.tool_input = Some(tool_input);
+            }
+        }
+        outcome
+    }
+
+    /// 工具调用前执行，返回阻止的原因，钩子改写的参数直接写回调用
+    pub async fn pre_tool_use(&self, call: &mut ToolCall) -> Option<String> {
+        let tool_input = serde_json::from_str::<Value>(&call.function.arguments)
+            .unwrap_or_else(|_| Value::String(call.function.arguments.clone()));
+        let outcome = self
+            .run(
+                HookEvent::PreToolUse,
+                Some(&call.function.name),
+                json!({"tool_name": call.function.name, "tool_input": tool_input}),
+            )
+            .await;
+        if let Some(tool_input) = outcome.tool_input {
+            info!(
+                "钩子改写了工具 {} 的参数: {}",
+                call.function.name, tool_input
+            );
+            call.function.arguments = tool_input.to_string();
+        }
+        outcome.blocked
+    }
+
+    /// 工具调用后执行，钩子的说明追加到工具结果后面
+    pub async fn post_tool_use(&self, call: &ToolCall, msg: &mut ModelMessage) {
+        let tool_input = serde_json::from_str::<Value>(&call.function.arguments)
+            .unwrap_or_else(|_| Value::String(call.function.arguments.clone()));
+        let outcome = self
+            .run(
+                HookEvent::PostToolUse,
+                Some(&call.function.name),
+                json!({
+                    "tool_name": call.function.name,
+                    "tool_input": tool_input,
+                    "tool_output": msg.content,
+                }),
+            )
+            .await;
+        for context in outcome.context {
+            msg.add_content(format!("\n\n[hook] {}", context));
+        }
+    }
+
+    /// 用户提交输入时执行，返回阻止的原因，钩子的说明追加到用户消息后面
+    pub async fn user_prompt_submit(&self, msg: &mut ModelMessage) -> Option<String> {
+        let outcome = self
+            .run(
+                HookEvent::UserPromptSubmit,
+                None,
+                json!({"prompt": msg.content}),
+            )
+            .await;
+        for context in outcome.context {
+            msg.add_content(format!("\n\n{}", context));
+        }
+        outcome.blocked
+    }
+
+    /// 一轮对话结束时执行，钩子阻止结束时返回原因，作为新的输入让模型继续
+    ///
+    /// active 表示本轮对话已经因为 stop 钩子继续过，钩子可以据此避免无限循环
+    pub async fn stop(&self, last_message: &str, active: bool) -> Option<String> {
+        self.run(
+            HookEvent::Stop,
+            None,
+            json!({"last_message": last_message, "stop_hook_active": active}),
+        )
+        .await
+        .blocked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_and_parse() {
+        let mut hook = HookConfig {
+            matcher: None,
+            command: None,
+            builtin: Some(BuiltinHook::Deny),
+            timeout_secs: 1,
+        };
+        assert!(hook.matches(Some("filesystem")));
+        hook.matcher = Some("filesystem | shell_command".into());
+        assert!(hook.matches(Some("shell_command")));
+        assert!(!hook.matches(Some("file")));
+        assert!(hook.matches(None));
+
+        assert_eq!(parse_output(" \n").unwrap(), HookOutput::default());
+        assert_eq!(
+            parse_output(r#"{"decision": "block", "reason": "不允许"}"#).unwrap(),
+            HookOutput {
+                decision: Some(Decision::Block),
+                reason: Some("不允许".into()),
+                ..Default::default()
+            }
+        );
+        assert!(parse_output("formatted 3 files").is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_tool_hooks() {
+        let command = |command: &str| HookConfig {
+            matcher: Some("shell_command".into()),
+            command: Some(command.into()),
+            builtin: None,
+            timeout_secs: 5,
+        };
+        let hooks = Hooks::new(HooksConfig {
+            pre_tool_use: vec![
+                command(r#"echo '{"tool_input": {"command": "ls"}}'"#),
+                command(r#"grep -q '"command":"ls"' && echo 'ls 被禁止' >&2 && exit 2"#),
+            ],
+            post_tool_use: vec![
+                command("exit 1"),
+                command(r#"echo '{"additional_context": "已格式化"}'"#),
+            ],
+            ..Default::default()
+        });
+        let mut call = ToolCall::new();
+        call.function.name = "shell_command".into();
+        call.function.arguments = r#"{"command": "rm -rf /"}"#.into();
+
+        assert_eq!(
+            hooks.pre_tool_use(&mut call).await.as_deref(),
+            Some("ls 被禁止")
+        );
+        assert_eq!(call.function.arguments, r#"{"command":"ls"}"#);
+        let mut msg = ModelMessage::tool("结果", call.clone());
+        hooks.post_tool_use(&call, &mut msg).await;
+        assert_eq!(msg.content, "结果\n\n[hook] 已格式化");
+
+        call.function.name = "filesystem".into();
+        assert_eq!(hooks.pre_tool_use(&mut call).await, None);
+    }
+}
@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+
+use log::warn;
+use serde_json::Value;
+
+use crate::model::param::{ModelMessage, ToolCall};
+
+/// 一轮对话中的工具调用记录，限制调用的批数，并发现重复的调用和错误
+///
+/// 相同参数的调用或相同的错误第 repeat_limit 次出现时在结果后面提醒模型，
+/// 之后再出现、或者执行的批数达到 max_batches 时暂停，等待用户确认
+#[derive(Debug, Clone, Default)]
+pub struct ToolLoopGuard {
+    /// 一轮对话中最多执行的批数，为 0 时不限制
+    max_batches: usize,
+    /// 重复多少次时提醒模型，为 0 时不检测
+    repeat_limit: usize,
+    batches: usize,
+    /// 工具名和参数到调用次数
+    calls: HashMap<String, usize>,
+    /// 工具名和错误信息到出现次数
+    errors: HashMap<String, usize>,
+    /// 最近一次暂停的原因
+    pause_reason: Option<String>,
+}
+
+impl ToolLoopGuard {
+    pub fn new(max_batches: usize, repeat_limit: usize) -> Self {
+        Self {
+            max_batches,
+            repeat_limit,
+            ..Self::default()
+        }
+    }
+
+    /// 用户开始新的一轮对话或确认继续后重新计数
+    pub fn reset(&mut self) {
+        *self = Self::new(self.max_batches, self.repeat_limit);
+    }
+
+    pub fn batches(&self) -> usize {
+        self.batches
+    }
+
+    pub fn max_batches(&self) -> usize {
+        self.max_batches
+    }
+
+    pub fn pause_reason(&self) -> Option<&str> {
+        self.pause_reason.as_deref()
+    }
+
+    /// 记录执行完的一批调用，需要提醒模型的结果后面追加说明，需要暂停时返回原因
+    pub fn check(&mut self, calls: &[ToolCall], results: &mut [ModelMessage]) -> Option<String> {
+        self.batches += 1;
+        let mut pause = None;
+        for call in calls {
+            let mut notes = Vec::new();
+            if self.repeat_limit > 0 {
+                let key = format!(
+                    "{}\n{}",
+                    call.function.name,
+                    canonical(&call.function.arguments)
+                );
+                let count = bump(&mut self.calls, key);
+                if count > self.repeat_limit {
+                    pause.get_or_insert(format!(
+                        "工具 {} 已经用相同的参数调用了 {} 次",
+                        call.function.name, count
+                    ));
+                } else if count == self.repeat_limit {
+                    notes.push(format!(
+                        "[循环检测] 这是第 {} 次用相同的参数调用 {}，结果不会有变化。请换一种方法，或者停止并向用户说明遇到的问题。",
+                        count, call.function.name
+                    ));
+                }
+            }
+            let Some(result) = results.iter_mut().find(|msg| msg.tool_call_id == call.id) else {
+                continue;
+            };
+            if self.repeat_limit > 0
+                && let Some(error) = error_message(&result.content)
+            {
+                let count = bump(
+                    &mut self.errors,
+                    format!("{}\n{}", call.function.name, error),
+                );
+                if count > self.repeat_limit {
+                    pause.get_or_insert(format!(
+                        "工具 {} 已经 {} 次返回相同的错误: {}",
+                        call.function.name, count, error
+                    ));
+                } else if count == self.repeat_limit {
+                    notes.push(format!(
+                        "[循环检测] {} 已经 {} 次返回相同的错误，不要再用同样的方式重试。请先查明原因（例如重新读取文件确认当前内容），或者换一种方法。",
+                        call.function.name, count
+                    ));
+                }
+            }
+            if !notes.is_empty() {
+                warn!("工具 {} 重复调用: {}", call.function.name, notes.join(" "));
+                result.content = format!("{}\n\n{}", result.content, notes.join("\n")).into();
+            }
+        }
+        if pause.is_none() && self.max_batches > 0 && self.batches >= self.max_batches {
+            pause = Some(format!(
+                "本轮对话已经执行了 {} 批工具调用，达到上限 max_tool_try",
+                self.batches
+            ));
+        }
+        self.pause_reason = pause.clone();
+        pause
+    }
+}
+
+fn bump(counts: &mut HashMap<String, usize>, key: String) -> usize {
+    let count = counts.entry(key).or_default();
+    *count += 1;
+    *count
+}
+
+/// 参数按 JSON 值规范化，忽略空白和字段顺序的差异
+fn canonical(arguments: &str) -> String {
+    serde_json::from_str::<Value>(arguments)
+        .map(|value| value.to_string())
+        .unwrap_or_else(|_| arguments.to_string())
+}
+
+/// 工具返回的错误信息，结果后面可能追加了钩子的说明，只解析开头的 JSON
+pub fn error_message(content: &str) -> Option<String> {
+    let value = serde_json::Deserializer::from_str(content)
+        .into_iter::<Value>()
+        .next()?
+        .ok()?;
+    if value.get("error") != Some(&Value::Bool(true)) {
+        return None;
+    }
+    Some(
+        value
+            .get("message")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(id: &str, name: &str, arguments: &str) -> ToolCall {
+        let mut call = ToolCall::new();
+        call.id = id.into();
+        call.function.name = name.into();
+        call.function.arguments = arguments.into();
+        call
+    }
+
+    #[test]
+    fn test_repeated_calls() {
+        let mut guard = ToolLoopGuard::new(0, 2);
+        let run = |guard: &mut ToolLoopGuard, arguments: &str| {
+            let calls = vec![call("1", "shell_command", arguments)];
+            let mut results = vec![ModelMessage::tool("ok", calls[0].clone())];
+            let pause = guard.check(&calls, &mut results);
+            (pause, results.remove(0).content.to_string())
+        };
+        assert_eq!(run(&mut guard, r#"{"command": "ls"}"#), (None, "ok".into()));
+        let (pause, content) = run(&mut guard, r#"{"command":"ls"}"#);
+        assert_eq!(pause, None);
+        assert!(content.starts_with("ok\n\n[循环检测] 这是第 2 次"));
+        assert_eq!(run(&mut guard, r#"{"command":"pwd"}"#).0, None);
+        let (pause, _) = run(&mut guard, r#"{"command":"ls"}"#);
+        assert_eq!(
+            pause.as_deref(),
+            Some("工具 shell_command 已经用相同的参数调用了 3 次")
+        );
+        guard.reset();
+        assert_eq!(run(&mut guard, r#"{"command":"ls"}"#).0, None);
+    }
+
+    #[test]
+    fn test_repeated_errors_and_limit() {
+        let mut guard = ToolLoopGuard::new(3, 2);
+        let error = serde_json::json!({"error": true, "message": "没有找到要替换的内容"});
+        for (i, expected) in [None, None, Some("3 次返回相同的错误")].iter().enumerate() {
+            // 参数每次都不同，只有错误相同
+            let calls = vec![call("1", "filesystem", &format!(r#"{{"old": "{}"}}"#, i))];
+            let mut results = vec![ModelMessage::tool(
+                format!("{}\n\n[hook] 已记录", error),
+                calls[0].clone(),
+            )];
+            let pause = guard.check(&calls, &mut results);
+            match expected {
+                Some(text) => assert!(pause.unwrap().contains(text)),
+                None => assert_eq!(pause, None),
+            }
+            assert_eq!(results[0].content.contains("[循环检测]"), i == 1);
+        }
+        assert_eq!(guard.batches(), 3);
+
+        let mut guard = ToolLoopGuard::new(2, 0);
+        let calls = vec![call("1", "shell_command", "{}")];
+        let mut results = vec![ModelMessage::tool("ok", calls[0].clone())];
+        assert_eq!(guard.check(&calls, &mut results), None);
+        assert!(
+            guard
+                .check(&calls, &mut results)
+                .unwrap()
+                .contains("max_tool_try")
+        );
+        assert!(guard.pause_reason().is_some());
+    }
+}
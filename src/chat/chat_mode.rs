@@ -0,0 +1,127 @@
+use std::fmt;
+
+use rmcp::model::Tool;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::mcp::McpManager;
+use crate::model::param::ToolCall;
+
+/// 会话模式
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatMode {
+    /// 正常执行，工具按权限策略调用
+    #[default]
+    Execute,
+    /// 只读的计划模式：修改文件、执行命令的工具被隐藏或拒绝，模型给出计划，用户批准后切换到执行模式
+    Plan,
+}
+
+impl ChatMode {
+    pub const ALL: [ChatMode; 2] = [ChatMode::Execute, ChatMode::Plan];
+
+    pub fn id(&self) -> &'static str {
+        match self {
+            ChatMode::Execute => "execute",
+            ChatMode::Plan => "plan",
+        }
+    }
+
+    pub fn parse(id: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|mode| mode.id() == id)
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            ChatMode::Execute => "按权限规则调用工具，执行修改",
+            ChatMode::Plan => "只能使用只读工具，先给出计划，批准后再执行",
+        }
+    }
+}
+
+impl fmt::Display for ChatMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChatMode::Execute => write!(f, "执行模式"),
+            ChatMode::Plan => write!(f, "计划模式"),
+        }
+    }
+}
+
+/// 计划模式下追加到系统提示词的说明
+pub const PLAN_PROMPT: &str = "\n\n# 计划模式\n当前处于只读的计划模式：只能使用只读的工具查看代码和资料，不能修改文件或执行命令。\
+请先调查清楚，然后给出详细的执行计划，包括要修改的文件、具体步骤、可能的风险和验证方式，再等待用户批准。\
+用户批准后会切换到执行模式，届时再按计划执行。";
+
+/// 用户批准计划后发送给模型的输入
+pub const APPROVE_PROMPT: &str = "计划已批准，现在已切换到执行模式，请按照上面的计划开始执行。";
+
+/// 部分操作只读的内置工具：工具名、区分操作的参数和只读的操作
+const READ_ONLY_OPERATIONS: [(&str, &str, &[&str]); 1] =
+    [("filesystem", "operation", &["read", "list", "check"])];
+
+/// 计划模式下是否向模型提供这个工具：标注为只读的工具，以及有只读操作的内置工具
+pub fn is_visible_in_plan(tool: &Tool) -> bool {
+    tool.annotations
+        .as_ref()
+        .and_then(|annotations| annotations.read_only_hint)
+        .unwrap_or(false)
+        || READ_ONLY_OPERATIONS
+            .iter()
+            .any(|(name, _, _)| tool.name == *name)
+}
+
+/// 计划模式下是否允许这个调用
+pub fn is_read_only_call(call: &ToolCall) -> bool {
+    let name = call.function.name.as_str();
+    if let Some((_, param, operations)) = READ_ONLY_OPERATIONS
+        .iter()
+        .find(|(tool, _, _)| *tool == name)
+    {
+        let args: Value = serde_json::from_str(&call.function.arguments).unwrap_or_default();
+        return args
+            .get(*param)
+            .and_then(Value::as_str)
+            .is_some_and(|operation| operations.contains(&operation));
+    }
+    McpManager::global()
+        .get_tool(name)
+        .is_some_and(|tool| is_visible_in_plan(&tool.get_tool()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(name: &str, arguments: &str) -> ToolCall {
+        let mut call = ToolCall::new();
+        call.function.name = name.into();
+        call.function.arguments = arguments.into();
+        call
+    }
+
+    #[test]
+    fn test_read_only_call() {
+        assert!(is_read_only_call(&call(
+            "filesystem",
+            r#"{"operation":"read","path":"a"}"#
+        )));
+        assert!(is_read_only_call(&call(
+            "filesystem",
+            r#"{"operation":"list","path":"."}"#
+        )));
+        assert!(!is_read_only_call(&call(
+            "filesystem",
+            r#"{"operation":"write","path":"a"}"#
+        )));
+        assert!(!is_read_only_call(&call("filesystem", "{}")));
+        // 没有注册的工具没有只读标注
+        assert!(!is_read_only_call(&call(
+            "shell_command",
+            r#"{"command":"ls"}"#
+        )));
+        assert_eq!(ChatMode::parse("plan"), Some(ChatMode::Plan));
+        assert_eq!(ChatMode::parse("other"), None);
+    }
+}
@@ -0,0 +1,687 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::chat::chat_mode::{self, ChatMode};
+use crate::config::Config;
+use crate::mcp::mcp_manager::McpManager;
+use crate::model::param::ToolCall;
+
+/// 工具调用的处理方式
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PermissionAction {
+    /// 直接执行
+    Allow,
+    /// 不执行，把拒绝原因返回给模型
+    Deny,
+    /// 执行前询问用户
+    Ask,
+}
+
+/// 权限规则，配置的条件都满足时匹配，未配置的条件不限制
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PermissionRule {
+    pub action: PermissionAction,
+    /// 工具名，支持 * 和 ? 通配符
+    #[serde(default)]
+    pub tool: Option<String>,
+    /// 工具所属的 mcp 服务名，支持通配符，内置工具为 internal
+    #[serde(default)]
+    pub server: Option<String>,
+    /// 参数名到通配符模式，参数不是字符串时与其 JSON 文本比较
+    #[serde(default)]
+    pub args: HashMap<String, String>,
+    /// 这些参数必须是当前工作目录下的路径
+    #[serde(default)]
+    pub within_cwd: Vec<String>,
+    /// 规则说明，报告匹配的规则时显示
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+impl PermissionRule {
+    fn matches(&self, call: &ToolCall, server: &str, cwd: &Path) -> bool {
+        if let Some(tool) = &self.tool
+            && !wildcard(tool, &call.function.name)
+        {
+            return false;
+        }
+        if let Some(pattern) = &self.server
+            && !wildcard(pattern, server)
+        {
+            return false;
+        }
+        if self.args.is_empty() && self.within_cwd.is_empty() {
+            return true;
+        }
+        let Ok(Value::Object(args)) = serde_json::from_str::<Value>(&call.function.arguments)
+        else {
+            return false;
+        };
+        let text = |name: &str| {
+            args.get(name).map(|value| match value {
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            })
+        };
+        self.args
+            .iter()
+            .all(|(name, pattern)| text(name).is_some_and(|value| wildcard(pattern, &value)))
+            && self
+                .within_cwd
+                .iter()
+                .all(|name| text(name).is_some_and(|path| is_within(Path::new(&path), cwd)))
+    }
+
+    /// 规则的一行说明，没有配置 description 时列出匹配条件
+    pub fn summary(&self) -> String {
+        if let Some(description) = &self.description {
+            return description.clone();
+        }
+        let mut conditions = Vec::new();
+        if let Some(tool) = &self.tool {
+            conditions.push(format!("工具 {}", tool));
+        }
+        if let Some(server) = &self.server {
+            conditions.push(format!("服务 {}", server));
+        }
+        let mut args: Vec<_> = self.args.iter().collect();
+        args.sort();
+        for (name, pattern) in args {
+            conditions.push(format!("{} 匹配 {}", name, pattern));
+        }
+        for name in &self.within_cwd {
+            conditions.push(format!("{} 在工作目录下", name));
+        }
+        if conditions.is_empty() {
+            "所有工具".into()
+        } else {
+            conditions.join("，")
+        }
+    }
+}
+
+/// 工具调用权限配置
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct PermissionConfig {
+    /// 没有规则匹配时的处理方式，未配置时按 ask_before_tool_execution 询问或允许
+    #[serde(default)]
+    pub default: Option<PermissionAction>,
+    /// 按顺序匹配的规则，第一条匹配的规则生效
+    #[serde(default)]
+    pub rules: Vec<PermissionRule>,
+}
+
+/// 用户“总是允许”的范围
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum GrantScope {
+    /// 本会话，随会话保存
+    Session,
+    /// 当前项目（工作目录），保存在配置目录的 permissions.json
+    Project,
+}
+
+/// 用户授予的“总是允许”，arguments 为空时允许该工具的所有调用，否则只允许参数完全相同的调用
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PermissionGrant {
+    pub tool: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<Value>,
+}
+
+impl PermissionGrant {
+    pub fn new(call: &ToolCall, exact: bool) -> Self {
+        Self {
+            tool: call.function.name.clone(),
+            arguments: exact.then(|| {
+                serde_json::from_str(&call.function.arguments)
+                    .unwrap_or_else(|_| Value::String(call.function.arguments.clone()))
+            }),
+        }
+    }
+
+    fn matches(&self, call: &ToolCall) -> bool {
+        if self.tool != call.function.name {
+            return false;
+        }
+        // 按 JSON 值比较，忽略空白和字段顺序的差异
+        self.arguments.as_ref().is_none_or(|arguments| {
+            serde_json::from_str::<Value>(&call.function.arguments)
+                .unwrap_or_else(|_| Value::String(call.function.arguments.clone()))
+                == *arguments
+        })
+    }
+}
+
+/// 用户对等待确认的工具调用的答复
+#[derive(Debug, Clone, PartialEq)]
+pub enum ToolCallAnswer {
+    /// 允许这一次
+    Approve,
+    /// 总是允许该工具，exact 为 true 时只允许参数完全相同的调用
+    AlwaysAllow { exact: bool, scope: GrantScope },
+    /// 拒绝，原因会返回给模型
+    Reject(Option<String>),
+}
+
+/// 文本前端的确认输入，all 为 true 时答复所有等待确认的调用，否则只答复第一个
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfirmReply {
+    pub answer: ToolCallAnswer,
+    pub all: bool,
+}
+
+impl ConfirmReply {
+    /// 输入格式的说明
+    pub const HELP: &str = "输入 y 执行，n [原因] 拒绝，a 本会话总是允许该工具，s 本会话总是允许相同参数的调用，p 本项目总是允许相同参数的调用，pt 本项目总是允许该工具，all 全部执行，none [原因] 全部拒绝";
+
+    /// 解析用户的输入，无法识别时返回 None
+    pub fn parse(input: &str) -> Option<Self> {
+        let input = input.trim();
+        let (word, rest) = input
+            .split_once(char::is_whitespace)
+            .map_or((input, ""), |(word, rest)| (word, rest.trim()));
+        let reason = (!rest.is_empty()).then(|| rest.to_string());
+        let always = |exact, scope| ToolCallAnswer::AlwaysAllow { exact, scope };
+        let (answer, all) = match word.to_lowercase().as_str() {
+            "y" | "yes" => (ToolCallAnswer::Approve, false),
+            "n" | "no" => (ToolCallAnswer::Reject(reason), false),
+            "a" | "always" => (always(false, GrantScope::Session), false),
+            "s" | "same" => (always(true, GrantScope::Session), false),
+            // 项目授权会一直保存，默认只允许相同参数的调用
+            "p" | "project" => (always(true, GrantScope::Project), false),
+            "pt" | "project-tool" => (always(false, GrantScope::Project), false),
+            "all" => (ToolCallAnswer::Approve, true),
+            "none" => (ToolCallAnswer::Reject(reason), true),
+            _ => return None,
+        };
+        // 只有拒绝可以附带原因
+        if !rest.is_empty() && !matches!(answer, ToolCallAnswer::Reject(_)) {
+            return None;
+        }
+        Some(Self { answer, all })
+    }
+}
+
+/// 判断依据
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecisionSource {
+    /// 匹配的规则序号（从 1 开始）和说明
+    Rule(usize, String),
+    /// 用户授予的“总是允许”
+    Grant(GrantScope),
+    /// 用户确认了这次调用
+    User,
+    /// 没有规则匹配，使用默认处理方式
+    Default,
+    /// 当前的会话模式不允许
+    Mode(ChatMode),
+}
+
+/// 权限判断结果
+#[derive(Debug, Clone, PartialEq)]
+pub struct PermissionDecision {
+    pub action: PermissionAction,
+    pub source: DecisionSource,
+}
+
+impl fmt::Display for PermissionDecision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = match self.action {
+            PermissionAction::Allow => "允许",
+            PermissionAction::Deny => "拒绝",
+            PermissionAction::Ask => "询问",
+        };
+        match &self.source {
+            DecisionSource::Rule(index, summary) => {
+                write!(f, "{}（规则 #{}: {}）", action, index, summary)
+            }
+            DecisionSource::Grant(GrantScope::Session) => write!(f, "{}（本会话已授权）", action),
+            DecisionSource::Grant(GrantScope::Project) => write!(f, "{}（本项目已授权）", action),
+            DecisionSource::User => write!(f, "{}（用户已确认）", action),
+            DecisionSource::Default => write!(f, "{}（默认）", action),
+            DecisionSource::Mode(mode) => write!(f, "{}（{}下只能使用只读工具）", action, mode),
+        }
+    }
+}
+
+/// 工具调用权限策略，所有前端共用，在执行前判断每个调用是允许、拒绝还是询问
+#[derive(Debug, Clone)]
+pub struct PermissionPolicy {
+    rules: Vec<PermissionRule>,
+    default: PermissionAction,
+    /// 本会话的授权
+    session_grants: Vec<PermissionGrant>,
+    /// 当前项目的授权
+    project_grants: Vec<PermissionGrant>,
+    /// 当前项目目录，项目授权按这个目录保存
+    project: PathBuf,
+    /// 会话模式，计划模式下拒绝所有非只读的调用
+    mode: ChatMode,
+}
+
+impl Default for PermissionPolicy {
+    fn default() -> Self {
+        Self {
+            rules: Vec::new(),
+            default: PermissionAction::Allow,
+            session_grants: Vec::new(),
+            project_grants: Vec::new(),
+            project: PathBuf::new(),
+            mode: ChatMode::default(),
+        }
+    }
+}
+
+impl PermissionPolicy {
+    pub fn new(config: PermissionConfig, ask_before_tool_execution: bool) -> Self {
+        let default = config.default.unwrap_or(if ask_before_tool_execution {
+            PermissionAction::Ask
+        } else {
+            PermissionAction::Allow
+        });
+        Self {
+            rules: config.rules,
+            default,
+            ..Self::default()
+        }
+    }
+
+    /// 设置当前项目目录并加载该项目保存的授权
+    pub fn set_project(&mut self, cwd: &Path) {
+        self.project = cwd.to_path_buf();
+        self.project_grants = load_project_grants()
+            .remove(&project_key(cwd))
+            .unwrap_or_default();
+    }
+
+    pub fn project(&self) -> &Path {
+        &self.project
+    }
+
+    pub fn session_grants(&self) -> &[PermissionGrant] {
+        &self.session_grants
+    }
+
+    /// 恢复会话时设置本会话的授权
+    pub fn set_session_grants(&mut self, grants: Vec<PermissionGrant>) {
+        self.session_grants = grants;
+    }
+
+    /// 添加授权，项目授权立即写入文件
+    pub fn grant(&mut self, grant: PermissionGrant, scope: GrantScope) -> anyhow::Result<()> {
+        let grants = match scope {
+            GrantScope::Session => &mut self.session_grants,
+            GrantScope::Project => &mut self.project_grants,
+        };
+        if grants.contains(&grant) {
+            return Ok(());
+        }
+        info!("添加授权 {:?}: {:?}", scope, grant);
+        grants.push(grant);
+        if scope == GrantScope::Project {
+            let mut saved = load_project_grants();
+            saved.insert(project_key(&self.project), self.project_grants.clone());
+            save_project_grants(&saved)?;
+        }
+        Ok(())
+    }
+
+    pub fn mode(&self) -> ChatMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: ChatMode) {
+        self.mode = mode;
+    }
+
+    /// 判断工具调用的处理方式
+    pub fn evaluate(&self, call: &ToolCall) -> PermissionDecision {
+        if self.mode == ChatMode::Plan && !chat_mode::is_read_only_call(call) {
+            return PermissionDecision {
+                action: PermissionAction::Deny,
+                source: DecisionSource::Mode(self.mode),
+            };
+        }
+        let server = McpManager::global()
+            .server_name(&call.function.name)
+            .unwrap_or_default();
+        let cwd = std::env::current_dir().unwrap_or_default();
+        self.evaluate_with(call, &server, &cwd)
+    }
+
+    fn evaluate_with(&self, call: &ToolCall, server: &str, cwd: &Path) -> PermissionDecision {
+        let decision = self
+            .rules
+            .iter()
+            .enumerate()
+            .find(|(_, rule)| rule.matches(call, server, cwd))
+            .map(|(i, rule)| PermissionDecision {
+                action: rule.action,
+                source: DecisionSource::Rule(i + 1, rule.summary()),
+            })
+            .unwrap_or(PermissionDecision {
+                action: self.default,
+                source: DecisionSource::Default,
+            });
+        // 授权只免去询问，不覆盖拒绝的规则
+        if decision.action != PermissionAction::Ask {
+            return decision;
+        }
+        [
+            (&self.session_grants, GrantScope::Session),
+            (&self.project_grants, GrantScope::Project),
+        ]
+        .into_iter()
+        .find(|(grants, _)| grants.iter().any(|grant| grant.matches(call)))
+        .map(|(_, scope)| PermissionDecision {
+            action: PermissionAction::Allow,
+            source: DecisionSource::Grant(scope),
+        })
+        .unwrap_or(decision)
+    }
+}
+
+/// 读取各项目保存的授权，按项目目录索引
+fn load_project_grants() -> HashMap<String, Vec<PermissionGrant>> {
+    fs::read_to_string(grants_file())
+        .ok()
+        .and_then(|text| serde_json::from_str(&text).ok())
+        .unwrap_or_default()
+}
+
+fn save_project_grants(grants: &HashMap<String, Vec<PermissionGrant>>) -> anyhow::Result<()> {
+    let path = grants_file();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, serde_json::to_string_pretty(grants)?)?;
+    Ok(())
+}
+
+fn grants_file() -> PathBuf {
+    Config::get_standard_config_dir().join("permissions.json")
+}
+
+fn project_key(cwd: &Path) -> String {
+    cwd.canonicalize()
+        .unwrap_or_else(|_| cwd.to_path_buf())
+        .to_string_lossy()
+        .to_string()
+}
+
+/// 通配符匹配，* 匹配任意字符串，? 匹配单个字符
+fn wildcard(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // 最近一个 * 的位置和它当时对应的文本位置，匹配失败时回溯
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((sp, st)) = star {
+            p = sp + 1;
+            t = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// 路径是否在 dir 下，解析已存在部分的符号链接后比较
+fn is_within(path: &Path, dir: &Path) -> bool {
+    resolve(&dir.join(path)).starts_with(resolve(dir))
+}
+
+/// 规范化路径：最近的已存在祖先目录解析符号链接，其余还不存在的部分按字面处理 . 和 ..
+///
+/// 要创建的文件还不存在，只按字面规范化的话，经过指向外部的符号链接目录就能绕过 within_cwd
+fn resolve(path: &Path) -> PathBuf {
+    let components: Vec<Component> = path.components().collect();
+    for i in (1..=components.len()).rev() {
+        let existing: PathBuf = components[..i].iter().collect();
+        if let Ok(mut resolved) = existing.canonicalize() {
+            resolved.extend(&components[i..]);
+            return normalize(&resolved);
+        }
+    }
+    normalize(path)
+}
+
+fn normalize(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                result.pop();
+            }
+            other => result.push(other),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wildcard() {
+        assert!(wildcard("git push*", "git push origin main"));
+        assert!(wildcard("*", ""));
+        assert!(wildcard("file?ystem", "filesystem"));
+        assert!(wildcard("*rm *", "cd a && rm -rf b"));
+        assert!(!wildcard("rm *", "git rm a"));
+        assert!(!wildcard("git push*", "git pull"));
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let rule =
+            |action, tool: &str, args: &[(&str, &str)], within_cwd: &[&str]| PermissionRule {
+                action,
+                tool: Some(tool.into()),
+                server: None,
+                args: args
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+                within_cwd: within_cwd.iter().map(|s| s.to_string()).collect(),
+                description: None,
+            };
+        let policy = PermissionPolicy::new(
+            PermissionConfig {
+                default: None,
+                rules: vec![
+                    rule(
+                        PermissionAction::Allow,
+                        "filesystem",
+                        &[("operation", "read")],
+                        &["path"],
+                    ),
+                    rule(
+                        PermissionAction::Ask,
+                        "shell_command",
+                        &[("command", "git push*")],
+                        &[],
+                    ),
+                    rule(
+                        PermissionAction::Deny,
+                        "shell_command",
+                        &[("command", "rm *")],
+                        &[],
+                    ),
+                ],
+            },
+            true,
+        );
+        let call = |name: &str, args: Value| {
+            let mut call = ToolCall::new();
+            call.function.name = name.into();
+            call.function.arguments = args.to_string();
+            call
+        };
+        let cwd = Path::new("/project");
+        let eval = |call: ToolCall| policy.evaluate_with(&call, "internal", cwd);
+
+        let read = eval(call(
+            "filesystem",
+            serde_json::json!({"operation": "read", "path": "src/../main.rs"}),
+        ));
+        assert_eq!(read.action, PermissionAction::Allow);
+        assert_eq!(
+            read.to_string(),
+            "允许（规则 #1: 工具 filesystem，operation 匹配 read，path 在工作目录下）"
+        );
+        let outside = eval(call(
+            "filesystem",
+            serde_json::json!({"operation": "read", "path": "../secret"}),
+        ));
+        assert_eq!(outside.to_string(), "询问（默认）");
+        let push = eval(call(
+            "shell_command",
+            serde_json::json!({"command": "git push origin"}),
+        ));
+        assert_eq!(
+            push.source,
+            DecisionSource::Rule(2, "工具 shell_command，command 匹配 git push*".into())
+        );
+        let rm = eval(call(
+            "shell_command",
+            serde_json::json!({"command": "rm -rf /"}),
+        ));
+        assert_eq!(rm.action, PermissionAction::Deny);
+        let ls = eval(call("shell_command", serde_json::json!({"command": "ls"})));
+        assert_eq!(ls.action, PermissionAction::Ask);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_within_symlink() {
+        let root = std::env::temp_dir().join(format!("agent-cli-within-{}", uuid::Uuid::new_v4()));
+        let cwd = root.join("project");
+        let outside = root.join("outside");
+        fs::create_dir_all(cwd.join("src")).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        std::os::unix::fs::symlink(&outside, cwd.join("link")).unwrap();
+
+        assert!(is_within(Path::new("src/new.rs"), &cwd));
+        assert!(is_within(Path::new("new/dir/../a.rs"), &cwd));
+        assert!(!is_within(Path::new("link/new.rs"), &cwd));
+        assert!(!is_within(Path::new("link/new/a.rs"), &cwd));
+        assert!(!is_within(Path::new("src/../../outside/a.rs"), &cwd));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_grants() {
+        let mut policy = PermissionPolicy::new(
+            PermissionConfig {
+                default: None,
+                rules: vec![PermissionRule {
+                    action: PermissionAction::Deny,
+                    tool: Some("shell_command".into()),
+                    server: None,
+                    args: [("command".to_string(), "rm *".to_string())].into(),
+                    within_cwd: Vec::new(),
+                    description: None,
+                }],
+            },
+            true,
+        );
+        let call = |name: &str, args: &str| {
+            let mut call = ToolCall::new();
+            call.function.name = name.into();
+            call.function.arguments = args.into();
+            call
+        };
+        let cwd = Path::new("/project");
+        let ls = call("shell_command", r#"{"command": "ls"}"#);
+        policy
+            .grant(PermissionGrant::new(&ls, true), GrantScope::Session)
+            .unwrap();
+        let eval = |call: ToolCall| policy.evaluate_with(&call, "internal", cwd);
+        assert_eq!(
+            eval(call("shell_command", r#"{"command":"ls"}"#)).to_string(),
+            "允许（本会话已授权）"
+        );
+        assert_eq!(
+            eval(call("shell_command", r#"{"command":"pwd"}"#)).action,
+            PermissionAction::Ask
+        );
+
+        policy
+            .grant(PermissionGrant::new(&ls, false), GrantScope::Session)
+            .unwrap();
+        let eval = |call: ToolCall| policy.evaluate_with(&call, "internal", cwd);
+        assert_eq!(
+            eval(call("shell_command", r#"{"command":"pwd"}"#)).action,
+            PermissionAction::Allow
+        );
+        // 授权不覆盖拒绝的规则
+        assert_eq!(
+            eval(call("shell_command", r#"{"command":"rm -rf /"}"#)).action,
+            PermissionAction::Deny
+        );
+        assert_eq!(policy.session_grants().len(), 2);
+    }
+
+    #[test]
+    fn test_confirm_reply() {
+        let reply = |input: &str| ConfirmReply::parse(input).map(|r| (r.answer, r.all));
+        assert_eq!(reply("Y"), Some((ToolCallAnswer::Approve, false)));
+        assert_eq!(
+            reply("n 换成只读的命令"),
+            Some((ToolCallAnswer::Reject(Some("换成只读的命令".into())), false))
+        );
+        assert_eq!(
+            reply("s"),
+            Some((
+                ToolCallAnswer::AlwaysAllow {
+                    exact: true,
+                    scope: GrantScope::Session
+                },
+                false
+            ))
+        );
+        assert_eq!(
+            reply("p"),
+            Some((
+                ToolCallAnswer::AlwaysAllow {
+                    exact: true,
+                    scope: GrantScope::Project
+                },
+                false
+            ))
+        );
+        assert_eq!(
+            reply("pt"),
+            Some((
+                ToolCallAnswer::AlwaysAllow {
+                    exact: false,
+                    scope: GrantScope::Project
+                },
+                false
+            ))
+        );
+        assert_eq!(reply("none"), Some((ToolCallAnswer::Reject(None), true)));
+        assert_eq!(reply("all"), Some((ToolCallAnswer::Approve, true)));
+        assert_eq!(reply("y 好的"), None);
+        assert_eq!(reply("maybe"), None);
+    }
+}
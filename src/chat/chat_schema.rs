@@ -0,0 +1,55 @@
+use serde_json::Value;
+
+/// 从回复中取出 JSON，兼容模型用 ```json 代码块包裹的情况
+pub fn extract_json(content: &str) -> &str {
+    let content = content.trim();
+    let Some(rest) = content.strip_prefix("```") else {
+        return content;
+    };
+    // 跳过代码块的语言标记
+    let rest = rest.split_once('\n').map_or("", |(_, body)| body);
+    rest.trim_end().strip_suffix("```").unwrap_or(rest).trim()
+}
+
+/// 校验回复是否符合 Schema，成功时返回解析后的 JSON，失败时返回错误说明
+pub fn validate(content: &str, schema: &Value) -> Result<Value, String> {
+    let value: Value = serde_json::from_str(extract_json(content))
+        .map_err(|e| format!("回复不是合法的 JSON: {}", e))?;
+    let validator =
+        jsonschema::validator_for(schema).map_err(|e| format!("JSON Schema 无效: {}", e))?;
+    let errors: Vec<String> = validator
+        .iter_errors(&value)
+        .map(|e| {
+            let path = e.instance_path.to_string();
+            if path.is_empty() {
+                e.to_string()
+            } else {
+                format!("{}: {}", path, e)
+            }
+        })
+        .collect();
+    if errors.is_empty() {
+        Ok(value)
+    } else {
+        Err(errors.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_validate() {
+        let schema = json!({
+            "type": "object",
+            "properties": {"name": {"type": "string"}, "age": {"type": "integer"}},
+            "required": ["name"],
+        });
+        let value = validate("```json\n{\"name\": \"a\", \"age\": 1}\n```", &schema).unwrap();
+        assert_eq!(value["age"], 1);
+        assert!(validate("{\"age\": \"1\"}", &schema).is_err());
+        assert!(validate("name: a", &schema).is_err());
+    }
+}
@@ -0,0 +1,296 @@
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, anyhow};
+use chrono::Local;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::chat::chat_branch::Branch;
+use crate::chat::chat_mode::ChatMode;
+use crate::chat::chat_permission::PermissionGrant;
+use crate::chat::chat_todo::TodoItem;
+use crate::config::Config;
+use crate::model::param::ModelMessage;
+
+/// 标题最多保留的字符数
+const TITLE_MAX_CHARS: usize = 40;
+
+/// 会话元数据，保存在会话文件的第一行
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SessionMeta {
+    pub id: String,
+    /// 会话标题，取第一条用户消息
+    #[serde(default)]
+    pub title: String,
+    /// 创建会话时的工作目录
+    #[serde(default)]
+    pub cwd: String,
+    /// 最后一次保存时使用的模型
+    #[serde(default)]
+    pub model: String,
+    pub created_at: String,
+    pub updated_at: String,
+    /// 对话轮次计数，恢复会话时继续累计
+    #[serde(default)]
+    pub conversation_turn: usize,
+    #[serde(default)]
+    pub message_count: usize,
+    /// 本会话中用户授予的“总是允许”
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub grants: Vec<PermissionGrant>,
+    /// 会话模式，恢复会话时继续使用
+    #[serde(default, skip_serializing_if = "is_execute")]
+    pub mode: ChatMode,
+    /// 模型记录的任务列表
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub todos: Vec<TodoItem>,
+}
+
+fn is_execute(mode: &ChatMode) -> bool {
+    *mode == ChatMode::Execute
+}
+
+impl SessionMeta {
+    /// 创建新的会话，ID 以创建时间开头，便于按时间排序
+    pub fn new(cwd: &Path) -> Self {
+        let now = Local::now();
+        let suffix = Uuid::new_v4().simple().to_string();
+        let time = now.format("%Y-%m-%d %H:%M:%S").to_string();
+        Self {
+            id: format!("{}-{}", now.format("%Y%m%d-%H%M%S"), &suffix[..6]),
+            title: String::new(),
+            cwd: cwd.to_string_lossy().to_string(),
+            model: String::new(),
+            created_at: time.clone(),
+            updated_at: time,
+            conversation_turn: 0,
+            message_count: 0,
+            grants: Vec::new(),
+            mode: ChatMode::default(),
+            todos: Vec::new(),
+        }
+    }
+
+    /// 保存前更新元数据
+    pub fn update(&mut self, messages: &[ModelMessage], conversation_turn: usize, model: String) {
+        if self.title.is_empty()
+            && let Some(msg) = messages
+                .iter()
+                .find(|msg| msg.role == "user" && !msg.content.trim().is_empty())
+        {
+            let line = msg.content.trim().lines().next().unwrap_or_default();
+            self.title = line.chars().take(TITLE_MAX_CHARS).collect();
+        }
+        self.updated_at = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+        self.model = model;
+        self.conversation_turn = conversation_turn;
+        self.message_count = messages.len();
+    }
+
+    /// 一行摘要，用于列出会话
+    pub fn summary(&self) -> String {
+        format!(
+            "{}  {}  {} 轮  {}",
+            self.id, self.updated_at, self.conversation_turn, self.title
+        )
+    }
+}
+
+/// 保存的会话：元数据、完整的对话记录和被替换下来的分支
+#[derive(Debug, Clone)]
+pub struct SessionRecord {
+    pub meta: SessionMeta,
+    pub messages: Vec<ModelMessage>,
+    pub branches: Vec<Branch>,
+}
+
+/// 会话存储
+///
+/// 每个会话保存为一个 JSONL 文件，第一行是元数据，之后每行一条消息；
+/// 有分支时另存为同名的 .branches.json 文件
+#[derive(Debug, Clone)]
+pub struct SessionStore {
+    dir: PathBuf,
+}
+
+impl SessionStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// 标准配置目录下的会话存储
+    pub fn local() -> Self {
+        Self::new(Config::get_standard_config_dir().join("sessions"))
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.jsonl", id))
+    }
+
+    fn branches_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.branches.json", id))
+    }
+
+    /// 保存整个会话，先写入临时文件再替换，避免中途退出时损坏原有记录
+    pub fn save(
+        &self,
+        meta: &SessionMeta,
+        messages: &[ModelMessage],
+        branches: &[Branch],
+    ) -> anyhow::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let tmp = self.dir.join(format!("{}.jsonl.tmp", meta.id));
+        {
+            let mut file = BufWriter::new(File::create(&tmp)?);
+            writeln!(file, "{}", serde_json::to_string(meta)?)?;
+            for msg in messages {
+                writeln!(file, "{}", serde_json::to_string(msg)?)?;
+            }
+            file.flush()?;
+        }
+        fs::rename(&tmp, self.path(&meta.id))?;
+        let branches_path = self.branches_path(&meta.id);
+        if !branches.is_empty() {
+            fs::write(&branches_path, serde_json::to_string(branches)?)?;
+        } else if branches_path.exists() {
+            fs::remove_file(&branches_path)?;
+        }
+        Ok(())
+    }
+
+    /// 读取会话，ID 可以只写开头的一部分
+    pub fn load(&self, id: &str) -> anyhow::Result<SessionRecord> {
+        let id = self.resolve(id)?;
+        let file = File::open(self.path(&id)).with_context(|| format!("无法打开会话 {}", id))?;
+        let mut lines = BufReader::new(file).lines();
+        let meta: SessionMeta = match lines.next() {
+            Some(line) => serde_json::from_str(&line?)
+                .with_context(|| format!("会话 {} 元数据解析失败", id))?,
+            None => return Err(anyhow!("会话 {} 为空", id)),
+        };
+        let mut messages = Vec::new();
+        for (i, line) in lines.enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let msg = serde_json::from_str(&line)
+                .with_context(|| format!("会话 {} 第 {} 行解析失败", id, i + 2))?;
+            messages.push(msg);
+        }
+        let branches_path = self.branches_path(&id);
+        let branches = if branches_path.is_file() {
+            serde_json::from_str(&fs::read_to_string(&branches_path)?)
+                .with_context(|| format!("会话 {} 的分支解析失败", id))?
+        } else {
+            Vec::new()
+        };
+        Ok(SessionRecord {
+            meta,
+            messages,
+            branches,
+        })
+    }
+
+    /// 按最后保存时间从新到旧列出所有会话
+    pub fn list(&self) -> anyhow::Result<Vec<SessionMeta>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut sessions = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "jsonl") {
+                continue;
+            }
+            match Self::read_meta(&path) {
+                Ok(meta) => sessions.push(meta),
+                Err(e) => warn!("跳过无法读取的会话文件 {}: {}", path.display(), e),
+            }
+        }
+        sessions.sort_by(|a, b| b.updated_at.cmp(&a.updated_at).then(b.id.cmp(&a.id)));
+        Ok(sessions)
+    }
+
+    /// 指定工作目录下最近保存的会话
+    pub fn latest(&self, cwd: &Path) -> anyhow::Result<Option<SessionRecord>> {
+        let cwd = cwd.to_string_lossy();
+        match self.list()?.into_iter().find(|meta| meta.cwd == cwd) {
+            Some(meta) => self.load(&meta.id).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn read_meta(path: &Path) -> anyhow::Result<SessionMeta> {
+        let mut line = String::new();
+        BufReader::new(File::open(path)?).read_line(&mut line)?;
+        Ok(serde_json::from_str(&line)?)
+    }
+
+    /// 把 ID 前缀解析为完整的会话 ID
+    fn resolve(&self, id: &str) -> anyhow::Result<String> {
+        if id.is_empty() || id.contains(['/', '\\']) {
+            return Err(anyhow!("无效的会话 ID: {}", id));
+        }
+        if self.path(id).is_file() {
+            return Ok(id.to_string());
+        }
+        let matches: Vec<String> = self
+            .list()?
+            .into_iter()
+            .map(|meta| meta.id)
+            .filter(|sid| sid.starts_with(id))
+            .collect();
+        match matches.len() {
+            0 => Err(anyhow!("会话 {} 不存在", id)),
+            1 => Ok(matches[0].clone()),
+            _ => Err(anyhow!("会话 ID {} 不唯一: {}", id, matches.join(", "))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::param::ToolCall;
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = std::env::temp_dir().join(format!("agent-cli-session-{}", Uuid::new_v4()));
+        let store = SessionStore::new(&dir);
+        let cwd = Path::new("/tmp/project");
+        let mut meta = SessionMeta::new(cwd);
+        let mut call = ToolCall::new();
+        call.id = "call_0".into();
+        let messages = vec![
+            ModelMessage::system("系统提示"),
+            ModelMessage::user("第一个问题\n补充说明"),
+            ModelMessage::assistant("", "", vec![call.clone()]),
+            ModelMessage::tool("结果", call),
+        ];
+        meta.update(&messages, 2, "model".into());
+        let branch = Branch {
+            id: 0,
+            fork_at: 1,
+            messages: messages[..2].to_vec(),
+            conversation_turn: 1,
+        };
+        store
+            .save(&meta, &messages, std::slice::from_ref(&branch))
+            .unwrap();
+
+        let record = store.load(&meta.id[..15]).unwrap();
+        assert_eq!(record.meta, meta);
+        assert_eq!(record.meta.title, "第一个问题");
+        assert_eq!(record.messages, messages);
+        assert_eq!(record.branches, vec![branch]);
+        assert_eq!(store.list().unwrap().len(), 1);
+        assert_eq!(store.latest(cwd).unwrap().unwrap().meta.id, meta.id);
+        assert!(store.latest(Path::new("/other")).unwrap().is_none());
+        assert!(store.load("../x").is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
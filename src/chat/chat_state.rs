@@ -1,9 +1,26 @@
-use log::info;
+use std::collections::HashSet;
+use std::path::Path;
+
+use log::{info, warn};
 use tokio_util::sync::CancellationToken;
 
+use crate::chat::chat_branch::{Branch, Branches};
+use crate::chat::chat_hooks::{Hooks, HooksConfig};
+use crate::chat::chat_loop::ToolLoopGuard;
+use crate::chat::chat_mode::ChatMode;
+use crate::chat::chat_permission::{
+    DecisionSource, GrantScope, PermissionAction, PermissionDecision, PermissionGrant,
+    PermissionPolicy,
+};
+use crate::chat::chat_todo::{self, TodoItem};
+use crate::chat::chat_window::{self, ContextPolicy};
 use crate::client::chat_client::ChatClient;
+use crate::client::tool_client::ToolClient;
+use crate::client::tool_output::OutputLimiter;
+use crate::config::{Config, ToolOutputConfig};
 use crate::mcp::McpTool;
 use crate::model::param::{ModelMessage, ToolCall};
+use crate::model::tokenizer::TokenEstimator;
 
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub enum EChatState {
@@ -17,6 +34,8 @@ pub enum EChatState {
     WaitingToolUse,
     // 等待继续对话确认
     WaitingTurnConfirm,
+    // 工具调用陷入循环或达到本轮上限，等待用户确认是否继续
+    WaitingLoopConfirm,
     // 压缩对话中
     Compressing,
 }
@@ -31,10 +50,34 @@ pub struct ChatState {
     state: EChatState,
     /// token限制
     max_tokens: u32,
-    /// 是否在工具执行前询问用户确认
-    ask_before_tool_execution: bool,
+    /// 工具调用权限策略
+    permissions: PermissionPolicy,
+    /// 本批工具调用中用户已确认执行的调用 ID
+    approved_calls: HashSet<String>,
+    /// 本批中有调用被拒绝并附带了原因，全部答复后需要让模型根据原因继续
+    rejection_feedback: bool,
     /// 对话轮次统计
     conversation_turn_count: usize,
+    /// 本地 token 估算
+    estimator: TokenEstimator,
+    /// 回退、切换时替换下来的对话分支
+    branches: Branches,
+    /// 上下文超出预算时的处理方式
+    context_policy: ContextPolicy,
+    /// 滑动窗口的 token 预算（占上限的比例）
+    window_ratio: f32,
+    /// 同一批工具调用的最大并发数
+    max_parallel_tools: usize,
+    /// 工具输出长度限制
+    output_limiter: OutputLimiter,
+    /// 生命周期钩子
+    hooks: Hooks,
+    /// 本轮对话的工具调用记录
+    tool_loop: ToolLoopGuard,
+    /// 模型通过 todo 工具记录的任务列表
+    todos: Vec<TodoItem>,
+    /// task 工具启动子代理时使用的配置，与当前对话的配置和模型相同
+    subagent_config: Option<Config>,
 }
 
 impl ChatState {
@@ -43,7 +86,8 @@ impl ChatState {
         client: ChatClient,
         context: Vec<ModelMessage>,
         max_tokens: u32,
-        ask_before_tool_execution: bool,
+        permissions: PermissionPolicy,
+        estimator: TokenEstimator,
     ) -> Self {
         Self {
             client,
@@ -51,8 +95,20 @@ impl ChatState {
             cancel_token: tokio_util::sync::CancellationToken::new(),
             state: EChatState::Idle,
             max_tokens,
-            ask_before_tool_execution,
+            permissions,
+            approved_calls: HashSet::new(),
+            rejection_feedback: false,
             conversation_turn_count: 0,
+            estimator,
+            branches: Branches::default(),
+            context_policy: ContextPolicy::default(),
+            window_ratio: 1.0,
+            max_parallel_tools: 1,
+            output_limiter: OutputLimiter::new(ToolOutputConfig::default()),
+            hooks: Hooks::default(),
+            tool_loop: ToolLoopGuard::default(),
+            todos: Vec::new(),
+            subagent_config: None,
         }
     }
 
@@ -83,17 +139,176 @@ impl ChatState {
 
     /// 检查是否有待处理的工具调用
     pub fn is_remain_tool_call(&self) -> bool {
-        if let Some(last) = self.context.last() {
-            if let Some(tools) = &last.tool_calls {
-                return !tools.is_empty();
-            }
-        }
-        false
+        !self.get_tool_calls().is_empty()
+    }
+
+    pub fn set_max_parallel_tools(&mut self, max_parallel_tools: usize) {
+        self.max_parallel_tools = max_parallel_tools;
+    }
+
+    pub fn set_tool_output(&mut self, config: ToolOutputConfig) {
+        self.output_limiter = OutputLimiter::new(config);
+    }
+
+    pub fn subagent_config(&self) -> Option<&Config> {
+        self.subagent_config.as_ref()
+    }
+
+    pub fn set_subagent_config(&mut self, config: Config) {
+        self.subagent_config = Some(config);
+    }
+
+    pub fn set_tool_loop_limits(&mut self, max_batches: usize, repeat_limit: usize) {
+        self.tool_loop = ToolLoopGuard::new(max_batches, repeat_limit);
+    }
+
+    pub fn tool_loop(&self) -> &ToolLoopGuard {
+        &self.tool_loop
+    }
+
+    /// 新的一轮对话或用户确认继续后，重新统计工具调用
+    pub fn reset_tool_loop(&mut self) {
+        self.tool_loop.reset();
+    }
+
+    /// 检查刚执行完的一批工具调用，需要暂停时返回原因
+    pub fn check_tool_loop(&mut self) -> Option<String> {
+        let index = self
+            .context
+            .iter()
+            .rposition(|msg| msg.role == "assistant")?;
+        let calls = self.context[index].tool_calls.clone()?;
+        self.tool_loop.check(&calls, &mut self.context[index + 1..])
     }
 
-    /// 检查是否需要询问用户确认工具调用
-    pub fn should_tool_confirmation(&self) -> bool {
-        self.ask_before_tool_execution
+    pub fn todos(&self) -> &[TodoItem] {
+        &self.todos
+    }
+
+    pub fn set_todos(&mut self, todos: Vec<TodoItem>) {
+        self.todos = todos;
+    }
+
+    /// 根据刚执行完的一批工具调用更新任务列表，有更新时返回新的列表
+    pub fn update_todos(
+        &mut self,
+        calls: &[ToolCall],
+        responses: &[ModelMessage],
+    ) -> Option<Vec<TodoItem>> {
+        let todos = chat_todo::latest(calls, responses)?;
+        self.todos = todos.clone();
+        Some(todos)
+    }
+
+    pub fn set_hooks(&mut self, config: HooksConfig) {
+        self.hooks = Hooks::new(config);
+    }
+
+    pub fn hooks(&self) -> &Hooks {
+        &self.hooks
+    }
+
+    /// 开启或恢复会话后调用，完整的工具输出保存到该会话的临时目录，钩子输入带上会话 ID
+    pub fn set_session_id(&mut self, id: &str) {
+        self.output_limiter.set_session(id);
+        self.hooks.set_session(id);
+    }
+
+    /// 删除本会话保存的完整工具输出
+    pub fn cleanup_tool_outputs(&self) {
+        self.output_limiter.cleanup();
+    }
+
+    /// 执行本轮工具调用的客户端
+    pub fn tool_client(&self) -> ToolClient {
+        ToolClient::new(
+            self.max_parallel_tools,
+            self.output_limiter.clone(),
+            self.permissions.clone(),
+            self.subagent_config.clone(),
+            self.client
+                .tool_definitions()
+                .iter()
+                .map(|tool| tool.name.to_string())
+                .collect(),
+        )
+    }
+
+    /// 按权限策略判断每个待处理的工具调用，用户已确认的调用视为允许
+    pub fn tool_permissions(&self) -> Vec<(ToolCall, PermissionDecision)> {
+        self.get_tool_calls()
+            .into_iter()
+            .map(|call| {
+                let decision = if self.approved_calls.contains(&call.id) {
+                    PermissionDecision {
+                        action: PermissionAction::Allow,
+                        source: DecisionSource::User,
+                    }
+                } else {
+                    self.permissions.evaluate(&call)
+                };
+                (call, decision)
+            })
+            .collect()
+    }
+
+    /// 用户确认执行本批中的一个调用
+    pub fn approve_tool_call(&mut self, id: &str) {
+        self.approved_calls.insert(id.to_string());
+    }
+
+    /// 用户拒绝本批中的一个调用，拒绝原因作为调用结果返回给模型
+    pub fn reject_tool_call(&mut self, call: ToolCall, reason: Option<String>) {
+        let content = match &reason {
+            Some(reason) => format!("用户拒绝调用，原因: {}", reason),
+            None => "用户拒绝调用".to_string(),
+        };
+        self.rejection_feedback |= reason.is_some();
+        self.add_message(ModelMessage::tool(content, call));
+    }
+
+    /// 本批工具调用都已答复，清除确认记录，返回是否有附带原因的拒绝
+    pub fn finish_tool_confirm(&mut self) -> bool {
+        self.approved_calls.clear();
+        std::mem::take(&mut self.rejection_feedback)
+    }
+
+    /// 总是允许与这个调用相同的工具或参数
+    pub fn grant_tool(
+        &mut self,
+        call: &ToolCall,
+        exact: bool,
+        scope: GrantScope,
+    ) -> anyhow::Result<()> {
+        self.permissions
+            .grant(PermissionGrant::new(call, exact), scope)
+    }
+
+    pub fn session_grants(&self) -> &[PermissionGrant] {
+        self.permissions.session_grants()
+    }
+
+    pub fn set_session_grants(&mut self, grants: Vec<PermissionGrant>) {
+        self.permissions.set_session_grants(grants);
+    }
+
+    pub fn mode(&self) -> ChatMode {
+        self.permissions.mode()
+    }
+
+    /// 切换会话模式，计划模式下只向模型提供只读的工具，并拒绝其他调用
+    pub fn set_mode(&mut self, mode: ChatMode) {
+        self.permissions.set_mode(mode);
+        self.client.set_plan_mode(mode == ChatMode::Plan);
+    }
+
+    /// 设置当前项目目录，加载该项目保存的授权
+    pub fn set_project(&mut self, cwd: &Path) {
+        self.permissions.set_project(cwd);
+    }
+
+    pub fn project(&self) -> &Path {
+        self.permissions.project()
     }
 
     /// 添加消息到上下文（支持批处理）
@@ -111,6 +326,11 @@ impl ChatState {
         self.conversation_turn_count = 0;
     }
 
+    /// 设置对话轮次计数，用于恢复会话
+    pub fn set_conversation_turn(&mut self, count: usize) {
+        self.conversation_turn_count = count;
+    }
+
     /// 获取当前对话轮次统计
     pub fn get_conversation_turn_info(&self) -> usize {
         self.conversation_turn_count
@@ -144,6 +364,54 @@ impl ChatState {
         false
     }
 
+    /// 更新token限制和估算方式，切换模型后调用
+    pub fn set_max_tokens(&mut self, max_tokens: u32, estimator: TokenEstimator) {
+        self.max_tokens = max_tokens;
+        self.estimator = estimator;
+    }
+
+    /// 设置上下文超出预算时的处理方式，ratio 为预算占 token 上限的比例
+    pub fn set_context_policy(&mut self, policy: ContextPolicy, ratio: f32) {
+        self.context_policy = policy;
+        self.window_ratio = ratio;
+    }
+
+    pub fn context_policy(&self) -> ContextPolicy {
+        self.context_policy
+    }
+
+    /// 使用滑动窗口策略时，在请求前丢弃超出预算的最早对话
+    pub fn trim_context(&mut self) {
+        if self.context_policy != ContextPolicy::SlidingWindow || self.max_tokens == 0 {
+            return;
+        }
+        let budget = (self.max_tokens as f32 * self.window_ratio) as u32;
+        let total = self.get_current_token_usage();
+        if total <= budget {
+            return;
+        }
+        let Some(mut context) = chat_window::trim(&self.context, total, budget, |msgs| {
+            self.estimator.count_messages(msgs)
+        }) else {
+            warn!(
+                "上下文用量 {} 超过预算 {}，但没有可以丢弃的消息",
+                total, budget
+            );
+            return;
+        };
+        info!(
+            "上下文用量 {} 超过预算 {}，丢弃最早的 {} 条消息",
+            total,
+            budget,
+            self.context.len() - context.len()
+        );
+        // 保留下来的消息带有裁剪前的用量，需要重新估算
+        for msg in context.iter_mut() {
+            msg.token_usage = None;
+        }
+        self.context = context;
+    }
+
     /// 获取上下文
     pub fn context(&self) -> &Vec<ModelMessage> {
         &self.context
@@ -154,33 +422,78 @@ impl ChatState {
         &mut self.context
     }
 
+    /// 回退到指定消息之前，原来的对话保存为分支，返回被丢弃的第一条消息
+    pub fn rewind(&mut self, index: usize) -> anyhow::Result<ModelMessage> {
+        self.branches
+            .rewind(&mut self.context, self.conversation_turn_count, index)
+    }
+
+    /// 切换到指定分支，当前对话保存为新的分支
+    pub fn switch_branch(&mut self, id: usize) -> anyhow::Result<()> {
+        self.conversation_turn_count =
+            self.branches
+                .switch(&mut self.context, self.conversation_turn_count, id)?;
+        Ok(())
+    }
+
+    pub fn branches(&self) -> &[Branch] {
+        self.branches.list()
+    }
+
+    /// 恢复会话时设置分支
+    pub fn set_branches(&mut self, branches: Vec<Branch>) {
+        self.branches = Branches::new(branches);
+    }
+
     /// 获取客户端
     pub fn client(&self) -> &ChatClient {
         &self.client
     }
 
+    /// 最后一条助手消息中还没有结果的工具调用，部分调用被拒绝后其余调用仍待处理
     pub fn get_tool_calls(&self) -> Vec<ToolCall> {
-        if let Some(last) = self.context().last() {
-            if let Some(tools) = &last.tool_calls {
-                tools.clone()
-            } else {
-                vec![]
+        let mut answered = HashSet::new();
+        for msg in self.context().iter().rev() {
+            if msg.role == "tool" {
+                answered.insert(msg.tool_call_id.to_string());
+                continue;
             }
-        } else {
-            vec![]
+            return match &msg.tool_calls {
+                Some(tools) if msg.role == "assistant" => tools
+                    .iter()
+                    .filter(|call| !answered.contains(&call.id))
+                    .cloned()
+                    .collect(),
+                _ => vec![],
+            };
         }
+        vec![]
     }
 
-    /// 获取当前token使用量（基于最后一条消息的token使用记录）
+    /// 获取下一次请求的token使用量
+    ///
+    /// 以最后一次模型返回的用量为准，之后新增的消息（用户输入、工具结果）在本地估算，
+    /// 还没有返回用量时整个上下文和工具定义都在本地估算
     pub fn get_current_token_usage(&self) -> u32 {
-        for ctx in self.context().iter().rev() {
+        let context = self.context();
+        for (i, ctx) in context.iter().enumerate().rev() {
             if let Some(usage) = &ctx.token_usage {
                 if usage.total_tokens > 0 {
-                    return usage.total_tokens;
+                    return usage.total_tokens + self.estimator.count_messages(&context[i + 1..]);
                 }
             }
         }
-        0
+        self.estimate_tokens(context)
+    }
+
+    /// 在本地估算一组消息连同工具定义发送时的 token 数
+    pub fn estimate_tokens(&self, messages: &[ModelMessage]) -> u32 {
+        let tools = serde_json::to_string(self.client.tool_definitions()).unwrap_or_default();
+        self.estimator.count_messages(messages) + self.estimator.count_text(&tools)
+    }
+
+    pub fn estimator(&self) -> &TokenEstimator {
+        &self.estimator
     }
 
     /// 检查是否需要自动压缩（基于token使用比例）
@@ -1,12 +1,15 @@
 use async_stream::stream;
 use futures::{Stream, StreamExt, pin_mut};
-use log::info;
+use log::{info, warn};
 
 use crate::chat::Chat;
+use crate::chat::chat_permission::PermissionAction;
+use crate::chat::chat_todo::TodoItem;
+use crate::client::tool_client::ToolClient;
 use crate::connection::TokenUsage;
 use crate::model::param::{ModelMessage, ToolCall};
 
-use super::chat_state::ChatState;
+use super::chat_state::{ChatState, EChatState};
 use super::chat_tools::ChatTools;
 
 /// 流式聊天响应类型
@@ -17,6 +20,8 @@ pub enum StreamedChatResponse {
     Reasoning(String),
     ToolResponse(ModelMessage),
     TokenUsage(TokenUsage),
+    /// todo 工具更新后的任务列表
+    Todos(Vec<TodoItem>),
     End,
 }
 
@@ -31,6 +36,7 @@ impl ChatStream {
     ) -> impl Stream<Item = Result<StreamedChatResponse, anyhow::Error>> + '_ {
         let cancel_token = chat.get_cancel_token();
         stream! {
+            chat.state.trim_context();
             let mut msg = ModelMessage::assistant("", "", vec![]);
             {
                 let stream = chat.state.client().stream_chat(chat.context().to_vec());
@@ -53,6 +59,8 @@ impl ChatStream {
                                 msg.add_think(res.think.clone());
                                 yield Ok(StreamedChatResponse::Reasoning(res.think.to_string()));
                             }
+                            // 思维链块只需保存到上下文，不用展示
+                            msg.thinking_blocks.extend(res.thinking_blocks);
                             if let Some(tools) = res.tool_calls {
                                 for tool in tools {
                                     msg.add_tool(tool.clone());
@@ -76,15 +84,16 @@ impl ChatStream {
     }
 
     /// 处理非流式聊天
-    pub fn handle_chat<'a>(
-        state: &'a mut ChatState,
-        prompt: &'a str,
-    ) -> impl Stream<Item = Result<StreamedChatResponse, anyhow::Error>> + 'a {
-        state.add_message(ModelMessage::user(prompt.to_string()));
+    pub fn handle_chat(
+        state: &mut ChatState,
+        msg: ModelMessage,
+    ) -> impl Stream<Item = Result<StreamedChatResponse, anyhow::Error>> + '_ {
+        state.add_message(msg);
         let cancel_token = state.get_cancel_token();
 
         stream! {
             loop {
+                state.trim_context();
                 let mut msg = ModelMessage::assistant("", "", vec![]);
                 {
                     let stream = state.client().chat2(state.context().to_vec());
@@ -106,6 +115,7 @@ impl ChatStream {
                                     msg.add_think(res.think.clone());
                                     yield Ok(StreamedChatResponse::Reasoning(res.think.to_string()));
                                 }
+                                msg.thinking_blocks.append(&mut res.thinking_blocks);
                                 if let Some(tools) = res.tool_calls {
                                     for tool in tools {
                                         msg.add_tool(tool.clone());
@@ -130,10 +140,25 @@ impl ChatStream {
                 }
                 state.add_message(msg.clone());
                 if msg.tool_calls.is_some() {
+                    // 有调用需要用户确认时暂停，等待确认后由 stream_rechat 继续执行
+                    if state
+                        .tool_permissions()
+                        .iter()
+                        .any(|(_, decision)| decision.action == PermissionAction::Ask)
+                    {
+                        warn!("等待工具确认");
+                        state.set_state(EChatState::WaitingToolConfirm);
+                        break;
+                    }
                     let tool_calls = msg.tool_calls.unwrap();
                     let mut tool_responses = Vec::new();
                     {
-                        let stream = ChatTools::call_tool(tool_calls, cancel_token.clone());
+                        let stream = ChatTools::call_tool(
+                            tool_calls.clone(),
+                            state.tool_client(),
+                            state.hooks().clone(),
+                            cancel_token.clone(),
+                        );
                         pin_mut!(stream);
                         while let Some(res) = stream.next().await {
                             match res {
@@ -147,9 +172,23 @@ impl ChatStream {
                             }
                         }
                     }
+                    ToolClient::sort_responses(&tool_calls, &mut tool_responses);
+                    if let Some(todos) = state.update_todos(&tool_calls, &tool_responses) {
+                        yield Ok(StreamedChatResponse::Todos(todos));
+                    }
                     for response in tool_responses {
                         state.add_message(response);
                     }
+                    // 取消后不再发起新的请求
+                    if cancel_token.is_cancelled() {
+                        break;
+                    }
+                    // 工具调用陷入循环或达到本轮上限时暂停，等待用户确认是否继续
+                    if let Some(reason) = state.check_tool_loop() {
+                        warn!("暂停工具调用: {}", reason);
+                        state.set_state(EChatState::WaitingLoopConfirm);
+                        break;
+                    }
                 }
                 else {
                     break;
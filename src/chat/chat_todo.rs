@@ -0,0 +1,130 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::chat::chat_loop;
+use crate::model::param::{ModelMessage, ToolCall};
+
+/// 记录任务列表的内置工具名
+pub const TODO_TOOL: &str = "todo";
+
+/// 任务项的状态
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TodoStatus {
+    #[default]
+    Pending,
+    InProgress,
+    #[serde(alias = "completed")]
+    Done,
+}
+
+impl fmt::Display for TodoStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TodoStatus::Pending => write!(f, "[ ]"),
+            TodoStatus::InProgress => write!(f, "[~]"),
+            TodoStatus::Done => write!(f, "[x]"),
+        }
+    }
+}
+
+/// 任务列表中的一项
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TodoItem {
+    pub content: String,
+    #[serde(default)]
+    pub status: TodoStatus,
+}
+
+#[derive(Debug, Deserialize)]
+struct TodoArgs {
+    todos: Vec<TodoItem>,
+}
+
+/// 解析 todo 工具的参数，每次调用给出完整的列表
+pub fn parse_args(arguments: &str) -> anyhow::Result<Vec<TodoItem>> {
+    let args: TodoArgs = serde_json::from_str(arguments)?;
+    if let Some(index) = args
+        .todos
+        .iter()
+        .position(|item| item.content.trim().is_empty())
+    {
+        anyhow::bail!("第 {} 项任务的内容为空", index + 1);
+    }
+    Ok(args.todos)
+}
+
+/// 已完成的数量和总数
+pub fn progress(todos: &[TodoItem]) -> (usize, usize) {
+    let done = todos
+        .iter()
+        .filter(|item| item.status == TodoStatus::Done)
+        .count();
+    (done, todos.len())
+}
+
+/// 按行列出任务，第一行是进度
+pub fn render(todos: &[TodoItem]) -> String {
+    let (done, total) = progress(todos);
+    let mut lines = vec![format!("任务进度 {}/{}", done, total)];
+    lines.extend(
+        todos
+            .iter()
+            .map(|item| format!("{} {}", item.status, item.content)),
+    );
+    lines.join("\n")
+}
+
+/// 一批工具调用中最后一个成功的 todo 调用给出的列表，被拒绝或出错的调用不算
+pub fn latest(calls: &[ToolCall], responses: &[ModelMessage]) -> Option<Vec<TodoItem>> {
+    calls
+        .iter()
+        .filter(|call| call.function.name == TODO_TOOL)
+        .filter(|call| {
+            responses.iter().any(|msg| {
+                msg.tool_call_id == call.id && chat_loop::error_message(&msg.content).is_none()
+            })
+        })
+        .filter_map(|call| parse_args(&call.function.arguments).ok())
+        .next_back()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(id: &str, arguments: &str) -> ToolCall {
+        let mut call = ToolCall::new();
+        call.id = id.into();
+        call.function.name = TODO_TOOL.into();
+        call.function.arguments = arguments.into();
+        call
+    }
+
+    #[test]
+    fn test_latest_todos() {
+        let first = call(
+            "1",
+            r#"{"todos":[{"content":"读代码","status":"done"},{"content":"修改","status":"in_progress"}]}"#,
+        );
+        let second = call("2", r#"{"todos":[{"content":"被拒绝"}]}"#);
+        let responses = vec![
+            ModelMessage::tool("ok", first.clone()),
+            ModelMessage::tool(
+                serde_json::json!({"error": true, "message": "工具调用被权限规则拒绝"}).to_string(),
+                second.clone(),
+            ),
+        ];
+        let todos = latest(&[first, second], &responses).unwrap();
+        assert_eq!(todos.len(), 2);
+        assert_eq!(todos[1].status, TodoStatus::InProgress);
+        assert_eq!(progress(&todos), (1, 2));
+        assert_eq!(render(&todos), "任务进度 1/2\n[x] 读代码\n[~] 修改");
+        assert!(parse_args(r#"{"todos":[{"content":" "}]}"#).is_err());
+        assert_eq!(
+            parse_args(r#"{"todos":[{"content":"a","status":"completed"}]}"#).unwrap()[0].status,
+            TodoStatus::Done
+        );
+    }
+}
@@ -1,9 +1,10 @@
+use crate::chat::chat_hooks::Hooks;
 use crate::chat::{Chat, StreamedChatResponse};
 use crate::client::tool_client;
 use crate::model::param::{ModelMessage, ToolCall};
 use futures::StreamExt;
 use futures::{Stream, pin_mut};
-use log::info;
+use log::{info, warn};
 
 /// Chat 工具调用处理模块
 /// 负责处理工具调用和执行
@@ -22,7 +23,12 @@ impl ChatTools {
                 // 不需要询问，直接执行工具调用
                 let mut tool_responses = Vec::new();
                 {
-                    let stream = ChatTools::call_tool(tool_calls, cancel_token.clone());
+                    let stream = ChatTools::call_tool(
+                        tool_calls.clone(),
+                        chat.state.tool_client(),
+                        chat.state.hooks().clone(),
+                        cancel_token.clone(),
+                    );
                     pin_mut!(stream);
                     while let Some(res) = stream.next().await {
                         match res {
@@ -36,6 +42,10 @@ impl ChatTools {
                         }
                     }
                 }
+                tool_client::ToolClient::sort_responses(&tool_calls, &mut tool_responses);
+                if let Some(todos) = chat.state.update_todos(&tool_calls, &tool_responses) {
+                    yield Ok(StreamedChatResponse::Todos(todos));
+                }
                 for response in tool_responses {
                     chat.state.add_message(response);
                 }
@@ -43,24 +53,47 @@ impl ChatTools {
             }
         }
     }
-    /// 调用工具并返回结果流
+    /// 并发调用工具并按完成顺序返回结果流，取消后每个未完成的调用返回一条取消的结果
+    ///
+    /// 调用前执行 pre_tool_use 钩子，被阻止的调用直接返回阻止原因；
+    /// 调用后执行 post_tool_use 钩子，钩子的说明追加到结果后面
     pub fn call_tool(
         tool_calls: Vec<ToolCall>,
+        caller: tool_client::ToolClient,
+        hooks: Hooks,
         cancel_token: tokio_util::sync::CancellationToken,
     ) -> impl Stream<Item = anyhow::Result<ModelMessage>> + 'static {
         async_stream::stream! {
             if tool_calls.is_empty() {
                 return;
             }
-            let caller = tool_client::ToolClient;
-            let stream = caller.call(tool_calls);
+            let mut allowed = Vec::new();
+            for mut call in tool_calls {
+                match hooks.pre_tool_use(&mut call).await {
+                    Some(reason) => {
+                        warn!("工具调用 {} 被钩子阻止: {}", call.function.name, reason);
+                        let error_content = serde_json::json!({
+                            "error": true,
+                            "message": format!("工具调用被阻止: {}", reason),
+                            "details": reason
+                        })
+                        .to_string();
+                        yield Ok(ModelMessage::tool(error_content, call));
+                    }
+                    None => allowed.push(call),
+                }
+            }
+            let stream = caller.call(allowed.clone(), cancel_token);
             futures::pin_mut!(stream);
             while let Some(res) = stream.next().await {
-                if cancel_token.is_cancelled() {
-                    info!("工具取消");
-                    return;
+                let Ok(mut msg) = res else {
+                    yield res;
+                    continue;
+                };
+                if let Some(call) = allowed.iter().find(|call| call.id == msg.tool_call_id) {
+                    hooks.post_tool_use(call, &mut msg).await;
                 }
-                yield res;
+                yield Ok(msg);
             }
         }
     }
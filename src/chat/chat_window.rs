@@ -0,0 +1,106 @@
+use serde::{Deserialize, Serialize};
+
+use crate::model::param::ModelMessage;
+
+/// 上下文超出预算时的处理方式
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ContextPolicy {
+    /// 按压缩策略总结较早的对话
+    #[default]
+    Compress,
+    /// 直接丢弃最早的对话，不额外请求模型
+    SlidingWindow,
+}
+
+/// 从最早的对话开始丢弃，直到用量不超过预算，没有可以丢弃的消息时返回 None
+///
+/// total 为当前用量，cost 计算一组消息的 token 数。系统提示和固定的消息始终保留。
+/// 较早的对话整轮丢弃；最后一轮保留用户消息，按模型回复逐步丢弃，
+/// 带工具调用的回复总是和对应的工具结果一起丢弃，最近一次回复始终保留
+pub fn trim(
+    context: &[ModelMessage],
+    total: u32,
+    budget: u32,
+    cost: impl Fn(&[ModelMessage]) -> u32,
+) -> Option<Vec<ModelMessage>> {
+    let head = context
+        .iter()
+        .take_while(|msg| msg.role == "system")
+        .count();
+    let last_user = (head..context.len()).rfind(|&i| context[i].role == "user");
+    let mut starts: Vec<usize> = (head..context.len())
+        .filter(|&i| match context[i].role.as_ref() {
+            "user" => true,
+            "assistant" => last_user.is_some_and(|last| i > last),
+            _ => false,
+        })
+        .collect();
+    if starts.first() != Some(&head) {
+        starts.insert(0, head);
+    }
+    starts.push(context.len());
+    // 最后一段是最近一次回复，不丢弃
+    let units = &starts[..starts.len() - 1];
+
+    let mut total = total;
+    let mut dropped = vec![false; context.len()];
+    for range in units.windows(2).map(|w| w[0]..w[1]) {
+        if total <= budget {
+            break;
+        }
+        let unit = &context[range.clone()];
+        if Some(range.start) == last_user || unit.iter().any(|msg| msg.pinned) {
+            continue;
+        }
+        total = total.saturating_sub(cost(unit));
+        dropped[range].fill(true);
+    }
+    if !dropped.contains(&true) {
+        return None;
+    }
+    Some(
+        context
+            .iter()
+            .zip(dropped)
+            .filter(|(_, dropped)| !dropped)
+            .map(|(msg, _)| msg.clone())
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::param::ToolCall;
+
+    #[test]
+    fn test_trim() {
+        let call = ToolCall::new();
+        let mut pinned = ModelMessage::user("固定");
+        pinned.pinned = true;
+        let context = vec![
+            ModelMessage::system("系统"),
+            ModelMessage::user("1"),
+            ModelMessage::assistant("a", "", vec![]),
+            pinned,
+            ModelMessage::assistant("b", "", vec![]),
+            ModelMessage::user("3"),
+            ModelMessage::assistant("", "", vec![call.clone()]),
+            ModelMessage::tool("结果", call.clone()),
+            ModelMessage::assistant("", "", vec![call.clone()]),
+            ModelMessage::tool("结果", call),
+        ];
+        let cost = |msgs: &[ModelMessage]| msgs.len() as u32;
+
+        assert_eq!(trim(&context, 10, 10, cost), None);
+        let res = trim(&context, 10, 8, cost).unwrap();
+        assert_eq!(res[..], [&context[..1], &context[3..]].concat()[..]);
+        // 固定的消息、最后一轮的用户消息和最近一次回复始终保留
+        let res = trim(&context, 10, 0, cost).unwrap();
+        assert_eq!(
+            res[..],
+            [&context[..1], &context[3..6], &context[8..]].concat()[..]
+        );
+    }
+}
@@ -1,29 +1,42 @@
+use std::sync::Arc;
+
 use async_stream::stream;
 use futures::{Stream, StreamExt};
 use log::info;
 use rmcp::model::Tool;
+use serde_json::{Value, json};
 
 use crate::{
+    chat::chat_mode,
     connection::{CommonConnectionContent, TokenUsage},
     mcp::McpTool,
     model::{
-        AgentModel, deepseek,
-        param::{ModelInputParam, ModelMessage},
+        AgentModel,
+        param::{ModelInputParam, ModelMessage, ResponseFormat, SamplingParams, ToolChoice},
+        wire,
     },
 };
 
 #[derive(Clone)]
 pub struct ChatClient {
-    pub agent: deepseek::DeepseekModel,
+    pub agent: Arc<dyn AgentModel>,
     tools: Vec<Tool>,
+    /// 默认采样参数
+    sampling: SamplingParams,
+    /// 当前轮对话的采样参数覆盖
+    turn_sampling: SamplingParams,
+    /// 计划模式下只提供只读的工具，并在系统提示词后追加计划模式的说明
+    plan_mode: bool,
 }
 
 impl ChatClient {
-    pub fn new(key: String, url: String, model: String, tools: Vec<McpTool>) -> Self {
-        let agent = deepseek::DeepseekModel::new(url, model, key);
+    pub fn new(agent: Arc<dyn AgentModel>, tools: Vec<McpTool>) -> Self {
         let mut client = Self {
             agent,
             tools: vec![],
+            sampling: SamplingParams::default(),
+            turn_sampling: SamplingParams::default(),
+            plan_mode: false,
         };
         info!("初始化工具: {:?}", tools);
         client.tools(tools);
@@ -31,7 +44,13 @@ impl ChatClient {
     }
 
     pub fn get_token_limit(&self) -> u32 {
-        self.agent.get_token_limit()
+        self.agent.capabilities().context_window
+    }
+
+    /// 替换模型后端，保留已设置的工具
+    pub fn set_agent(&mut self, agent: Arc<dyn AgentModel>) {
+        info!("切换模型 {}", agent.model_name());
+        self.agent = agent;
     }
 
     pub fn tools(&mut self, tools: Vec<McpTool>) {
@@ -41,6 +60,29 @@ impl ChatClient {
         }
     }
 
+    /// 默认采样参数
+    pub fn sampling(&self) -> &SamplingParams {
+        &self.sampling
+    }
+
+    pub fn set_sampling(&mut self, sampling: SamplingParams) {
+        self.sampling = sampling;
+    }
+
+    /// 设置当前轮对话的采样参数，覆盖默认参数中对应的字段
+    pub fn set_turn_sampling(&mut self, sampling: SamplingParams) {
+        self.turn_sampling = sampling;
+    }
+
+    pub fn set_plan_mode(&mut self, plan_mode: bool) {
+        self.plan_mode = plan_mode;
+    }
+
+    /// 已设置的工具定义
+    pub fn tool_definitions(&self) -> &[Tool] {
+        &self.tools
+    }
+
     /// 获取工具列表的引用，避免不必要的克隆
     fn get_tools_ref(&self) -> Option<&Vec<Tool>> {
         if self.tools.is_empty() {
@@ -50,21 +92,110 @@ impl ChatClient {
         }
     }
 
-    /// 构建模型输入参数
+    /// 构建模型输入参数，模型不支持工具调用时不发送工具，不支持图片时不发送图片
+    ///
+    /// 要求 json_schema 回复格式但模型不支持时，改为提供一个参数为该 Schema 的工具，
+    /// 没有其它工具时强制调用，模型的调用参数即为回复内容
     fn build_model_input(&self, messages: Vec<ModelMessage>) -> ModelInputParam {
+        let caps = self.agent.capabilities();
+        let mut tools = if caps.tools {
+            self.get_tools_ref().cloned()
+        } else {
+            None
+        };
+        let mut messages = messages;
+        if self.plan_mode {
+            tools = tools
+                .map(|tools| {
+                    tools
+                        .into_iter()
+                        .filter(chat_mode::is_visible_in_plan)
+                        .collect::<Vec<_>>()
+                })
+                .filter(|tools| !tools.is_empty());
+            if let Some(system) = messages.first_mut().filter(|msg| msg.role == "system") {
+                system.add_content(chat_mode::PLAN_PROMPT);
+            }
+        }
+        let mut sampling = self.sampling.merge(&self.turn_sampling);
+        if let Some(schema) = sampling.response_format.as_ref().and_then(|f| f.schema())
+            && !caps.json_schema
+        {
+            if caps.tools {
+                let tool = structured_output_tool(schema);
+                let tools = tools.get_or_insert_with(Vec::new);
+                if tools.is_empty() {
+                    sampling.tool_choice =
+                        Some(ToolChoice::Function(STRUCTURED_OUTPUT_TOOL.into()));
+                }
+                tools.push(tool);
+                sampling.response_format = None;
+            } else {
+                // 两者都不支持时只要求输出 JSON，由调用方校验
+                sampling.response_format = Some(ResponseFormat::JsonObject);
+            }
+        }
+        // 模型不支持图片时改为文字说明
+        let messages = if caps.vision {
+            messages
+        } else {
+            wire::strip_images(messages)
+        };
         ModelInputParam {
-            temperature: None,
-            tools: self.get_tools_ref().cloned(),
+            sampling,
+            tools,
             messages,
         }
     }
 
+    /// 当前是否通过工具调用模拟结构化输出，返回 Schema 是否被包装过
+    fn structured_output_wrapped(&self) -> Option<bool> {
+        let caps = self.agent.capabilities();
+        if caps.json_schema || !caps.tools {
+            return None;
+        }
+        let sampling = self.sampling.merge(&self.turn_sampling);
+        let schema = sampling.response_format.as_ref()?.schema()?;
+        Some(!is_object_schema(schema))
+    }
+
+    /// 当前轮对话要求的 JSON Schema
+    pub fn response_schema(&self) -> Option<Value> {
+        self.sampling
+            .merge(&self.turn_sampling)
+            .response_format
+            .and_then(|f| f.schema().cloned())
+    }
+
+    /// 不带工具和回复格式的单次请求，只返回回复文本，用于总结对话等内部任务
+    pub async fn complete(&self, messages: Vec<ModelMessage>) -> anyhow::Result<String> {
+        let mut sampling = self.sampling.clone();
+        sampling.tool_choice = None;
+        sampling.response_format = None;
+        let answer = self
+            .agent
+            .chat(ModelInputParam {
+                sampling,
+                tools: None,
+                messages,
+            })
+            .await?;
+        Ok(answer
+            .into_iter()
+            .filter_map(|ctx| match ctx {
+                CommonConnectionContent::Content(text) => Some(text),
+                _ => None,
+            })
+            .collect())
+    }
+
     pub fn chat2(
         &self,
         messages: Vec<ModelMessage>,
     ) -> impl Stream<Item = Result<ModelMessage, anyhow::Error>> + '_ {
         info!("chat2 开始，消息数量: {}", messages.len());
         let param = self.build_model_input(messages);
+        let structured = self.structured_output_wrapped();
 
         stream! {
             let answer = match self.agent.chat(param).await {
@@ -79,13 +210,19 @@ impl ChatClient {
             let mut tool_calls = Vec::new();
             let mut content = String::new();
             let mut think = String::new();
+            let mut thinking_blocks = Vec::new();
             let mut token_usage: Option<TokenUsage> = None;
 
             // 非流式请求，工具调用、回复、思维链在同一次回复里
             for ctx in answer.iter() {
                 match ctx {
                     CommonConnectionContent::ToolCall(tool) => {
-                        tool_calls.push(tool.clone());
+                        match structured {
+                            Some(wrapped) if tool.function.name == STRUCTURED_OUTPUT_TOOL => {
+                                content = unwrap_structured_output(&tool.function.arguments, wrapped);
+                            }
+                            _ => tool_calls.push(tool.clone()),
+                        }
                     }
                     CommonConnectionContent::Content(ct) => {
                         content = ct.clone();
@@ -93,6 +230,9 @@ impl ChatClient {
                     CommonConnectionContent::Reasoning(reason) => {
                         think = reason.clone();
                     }
+                    CommonConnectionContent::ThinkingBlock(block) => {
+                        thinking_blocks.push(block.clone());
+                    }
                     CommonConnectionContent::TokenUsage(usage) => {
                         info!("Token 使用情况: prompt_tokens={}, completion_tokens={}, total_tokens={}",
                             usage.prompt_tokens, usage.completion_tokens, usage.total_tokens);
@@ -103,6 +243,7 @@ impl ChatClient {
             }
 
             let mut msg = ModelMessage::assistant(content, think, tool_calls);
+            msg.thinking_blocks = thinking_blocks;
             msg.token_usage = token_usage;
             yield Ok(msg);
         }
@@ -115,10 +256,11 @@ impl ChatClient {
     ) -> impl Stream<Item = Result<ModelMessage, anyhow::Error>> + '_ {
         let agent = self.agent.clone();
         let param = self.build_model_input(messages);
+        let structured = self.structured_output_wrapped();
 
         stream! {
             info!("stream chat 开始，参数: {:?}", param);
-            let mut stream_res = Box::pin(agent.stream_chat(param).await);
+            let mut stream_res = agent.stream_chat(param).await;
 
             while let Some(res) = stream_res.next().await {
                 match res {
@@ -126,11 +268,22 @@ impl ChatClient {
                         yield Ok(ModelMessage::assistant(text, "", vec![]));
                     }
                     Ok(CommonConnectionContent::ToolCall(tool_call)) => {
-                        yield Ok(ModelMessage::assistant("", "", vec![tool_call]));
+                        match structured {
+                            Some(wrapped) if tool_call.function.name == STRUCTURED_OUTPUT_TOOL => {
+                                let content = unwrap_structured_output(&tool_call.function.arguments, wrapped);
+                                yield Ok(ModelMessage::assistant(content, "", vec![]));
+                            }
+                            _ => yield Ok(ModelMessage::assistant("", "", vec![tool_call])),
+                        }
                     }
                     Ok(CommonConnectionContent::Reasoning(reasoning)) => {
                         yield Ok(ModelMessage::assistant("", reasoning, vec![]));
                     }
+                    Ok(CommonConnectionContent::ThinkingBlock(block)) => {
+                        let mut msg = ModelMessage::assistant("", "", vec![]);
+                        msg.thinking_blocks.push(block);
+                        yield Ok(msg);
+                    }
                     Ok(CommonConnectionContent::FinishReason(reason)) => {
                         info!("流式聊天完成，原因: {}", reason);
                     }
@@ -148,3 +301,39 @@ impl ChatClient {
         }
     }
 }
+
+/// 模拟结构化输出时使用的工具名称
+const STRUCTURED_OUTPUT_TOOL: &str = "structured_output";
+
+fn is_object_schema(schema: &Value) -> bool {
+    schema.get("type").and_then(Value::as_str) == Some("object")
+}
+
+/// 结构化输出工具，工具参数必须是对象，其它类型的 Schema 包装在 result 字段中
+fn structured_output_tool(schema: &Value) -> Tool {
+    let input_schema = if is_object_schema(schema) {
+        schema.clone()
+    } else {
+        json!({
+            "type": "object",
+            "properties": {"result": schema},
+            "required": ["result"],
+        })
+    };
+    Tool::new(
+        STRUCTURED_OUTPUT_TOOL,
+        "输出最终回复。完成任务后必须调用此工具，参数即为回复内容",
+        Arc::new(input_schema.as_object().cloned().unwrap_or_default()),
+    )
+}
+
+/// 从工具参数中取出回复内容
+fn unwrap_structured_output(arguments: &str, wrapped: bool) -> String {
+    if !wrapped {
+        return arguments.to_string();
+    }
+    serde_json::from_str::<Value>(arguments)
+        .ok()
+        .and_then(|v| v.get("result").map(|r| r.to_string()))
+        .unwrap_or(arguments.to_string())
+}
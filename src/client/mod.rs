@@ -2,10 +2,12 @@ use std::io::{self, Write};
 
 use futures::{Stream, StreamExt, pin_mut};
 
-use crate::chat::StreamedChatResponse;
+use crate::chat::{Chat, StreamedChatResponse};
+use crate::model::param::{ResponseFormat, SamplingParams};
 
 pub mod chat_client;
 pub mod tool_client;
+pub mod tool_output;
 
 /// 处理流式响应并输出到标准输出
 pub async fn handle_output(
@@ -34,6 +36,35 @@ pub async fn handle_output(
     Ok(())
 }
 
+/// 结构化输出模式，不输出中间过程，结束后只把符合 Schema 的 JSON 写到标准输出
+pub async fn handle_structured_output(
+    chat: &mut Chat,
+    prompt: &str,
+    schema: serde_json::Value,
+) -> anyhow::Result<()> {
+    let sampling = SamplingParams {
+        response_format: Some(ResponseFormat::json_schema(schema)),
+        ..Default::default()
+    };
+    let mut last_err = None;
+    {
+        let stream = chat.stream_chat_with(prompt, sampling);
+        pin_mut!(stream);
+        while let Some(result) = stream.next().await {
+            // 校验失败的错误总是最后一项，中间的错误（如工具调用失败）不影响结果
+            last_err = result.err();
+        }
+    }
+    if let Some(e) = last_err {
+        return Err(e);
+    }
+    let Some(reply) = chat.context().last().filter(|msg| msg.role == "assistant") else {
+        return Err(anyhow::anyhow!("没有收到模型回复"));
+    };
+    println!("{}", reply.content);
+    Ok(())
+}
+
 /// 处理单个流式响应项
 fn handle_response_item(res: StreamedChatResponse, output: &mut String) {
     match res {
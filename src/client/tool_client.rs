@@ -1,75 +1,228 @@
 use crate::{
+    chat::{
+        chat_mode,
+        chat_permission::{PermissionAction, PermissionPolicy},
+    },
+    client::tool_output::OutputLimiter,
+    config::Config,
+    mcp::internalserver::task::{TASK_TOOL, TaskTool},
     mcp::mcp_manager,
     model::param::{ModelMessage, ToolCall},
 };
-use async_stream::stream;
-use futures::Stream;
+use futures::{Stream, StreamExt, stream};
 use log::warn;
 use serde_json::Value;
+use std::collections::HashSet;
+use tokio_util::sync::CancellationToken;
 
-pub struct ToolClient;
+/// 工具调用客户端，同一批中连续的只读调用并发执行，其他调用按顺序逐个执行
+pub struct ToolClient {
+    /// 同时执行的工具调用数量上限
+    max_parallel: usize,
+    /// 工具输出长度限制
+    limiter: OutputLimiter,
+    /// 权限策略，被拒绝的调用不会执行
+    permissions: PermissionPolicy,
+    /// task 工具启动子代理使用的配置，为空时无法执行 task 工具
+    subagent: Option<Config>,
+    /// 提供给模型的工具名称，调用其他工具时直接返回错误
+    offered: HashSet<String>,
+}
 
 impl ToolClient {
+    pub fn new(
+        max_parallel: usize,
+        limiter: OutputLimiter,
+        permissions: PermissionPolicy,
+        subagent: Option<Config>,
+        offered: HashSet<String>,
+    ) -> Self {
+        Self {
+            max_parallel: max_parallel.max(1),
+            limiter,
+            permissions,
+            subagent,
+            offered,
+        }
+    }
+
+    /// 执行一批工具调用，每个调用完成后立即返回结果，返回顺序与调用顺序无关
+    ///
+    /// 只读的调用可能并发执行，修改环境的调用等之前的调用都完成后单独执行，
+    /// 取消后未完成的调用也会各自返回一条工具响应，保证每个调用都有对应的结果
     pub fn call(
         &self,
         calls: Vec<ToolCall>,
+        cancel: CancellationToken,
     ) -> impl Stream<Item = Result<ModelMessage, anyhow::Error>> + '_ {
-        stream! {
-            // 输入验证：检查工具调用列表是否为空
-            if calls.is_empty() {
-                return;
+        stream::iter(Self::group_calls(calls))
+            .map(move |group| {
+                let cancel = cancel.clone();
+                stream::iter(group)
+                    .map(move |call| self.call_one(call, cancel.clone()))
+                    .buffer_unordered(self.max_parallel)
+            })
+            .flatten()
+            .map(Ok)
+    }
+
+    /// 按顺序分组：连续的只读调用为一组，其他调用各自一组，组之间依次执行
+    fn group_calls(calls: Vec<ToolCall>) -> Vec<Vec<ToolCall>> {
+        let mut groups: Vec<Vec<ToolCall>> = Vec::new();
+        let mut read_only_group = false;
+        for call in calls {
+            let read_only = chat_mode::is_read_only_call(&call);
+            match groups.last_mut() {
+                Some(group) if read_only && read_only_group => group.push(call),
+                _ => groups.push(vec![call]),
             }
+            read_only_group = read_only;
+        }
+        groups
+    }
+
+    /// 按工具调用的顺序（tool_call_id）排列结果
+    pub fn sort_responses(calls: &[ToolCall], responses: &mut [ModelMessage]) {
+        responses.sort_by_key(|msg| {
+            calls
+                .iter()
+                .position(|call| call.id == msg.tool_call_id)
+                .unwrap_or(usize::MAX)
+        });
+    }
+
+    /// 执行单个工具调用，出错时返回包含错误信息的工具响应，返还给模型
+    async fn call_one(&self, call: ToolCall, cancel: CancellationToken) -> ModelMessage {
+        // 验证工具名称
+        if call.function.name.is_empty() {
+            warn!("工具名称不能为空");
+            let error_content = serde_json::json!({
+                "error": true,
+                "message": "工具名称不能为空",
+                "details": "工具调用缺少名称"
+            })
+            .to_string();
+            return ModelMessage::tool(error_content, call);
+        }
+
+        // 只能调用提供给模型的工具，子代理只提供了部分工具
+        if !self.offered.contains(call.function.name.as_str()) {
+            warn!("工具 {} 没有提供给模型，拒绝调用", call.function.name);
+            let error_content = serde_json::json!({
+                "error": true,
+                "message": format!("工具 {} 不可用", call.function.name),
+                "details": "只能调用提供的工具"
+            })
+            .to_string();
+            return ModelMessage::tool(error_content, call);
+        }
 
-            // 验证每个工具调用
-            for call in calls.iter() {
-                // 验证工具名称
-                if call.function.name.is_empty() {
-                    warn!("工具名称不能为空");
-                    // 创建包含错误信息的工具响应，返还给模型
-                    let error_content = serde_json::json!({
-                        "error": true,
-                        "message": "工具名称不能为空",
-                        "details": "工具调用缺少名称"
-                    }).to_string();
-                    yield Ok(ModelMessage::tool(error_content, call.clone()));
-                    continue;
-                }
+        let decision = self.permissions.evaluate(&call);
+        if decision.action == PermissionAction::Deny {
+            warn!(
+                "工具调用 {} 被权限规则拒绝: {}",
+                call.function.name, decision
+            );
+            let error_content = serde_json::json!({
+                "error": true,
+                "message": format!("工具调用被权限规则拒绝: {}", decision),
+                "details": "不要再次尝试相同的调用"
+            })
+            .to_string();
+            return ModelMessage::tool(error_content, call);
+        }
 
-                // 解析JSON参数，如果解析失败则返回错误工具响应
-                let arguments: Value = match serde_json::from_str(&call.function.arguments) {
-                    Ok(args) => args,
-                    Err(e) => {
-                        warn!("JSON参数解析失败: {}", e);
-                        // 创建包含错误信息的工具响应，返还给模型
-                        let error_content = serde_json::json!({
-                            "error": true,
-                            "message": format!("JSON参数解析失败: {}", e),
-                            "details": e.to_string()
-                        }).to_string();
-                        yield Ok(ModelMessage::tool(error_content, call.clone()));
-                        continue;
-                    }
-                };
+        // 解析JSON参数，如果解析失败则返回错误工具响应
+        let arguments: Value = match serde_json::from_str(&call.function.arguments) {
+            Ok(args) => args,
+            Err(e) => {
+                warn!("JSON参数解析失败: {}", e);
+                let error_content = serde_json::json!({
+                    "error": true,
+                    "message": format!("JSON参数解析失败: {}", e),
+                    "details": e.to_string()
+                })
+                .to_string();
+                return ModelMessage::tool(error_content, call);
+            }
+        };
 
-                // 调用工具
-                let result = mcp_manager::McpManager::global().call_tool(&call.function.name, &arguments).await;
+        // 调用工具，子任务使用当前对话的配置和模型，由子代理自己的超时限制
+        let result = match &self.subagent {
+            Some(config) if call.function.name == TASK_TOOL => {
+                TaskTool::execute(config.clone(), &arguments, &cancel).await
+            }
+            _ => {
+                mcp_manager::McpManager::global()
+                    .call_tool(&call.function.name, &arguments, &cancel)
+                    .await
+            }
+        };
 
-                match result {
-                    Ok(s) => {
-                        yield Ok(ModelMessage::tool(s, call.clone()));
-                    }
-                    Err(e) => {
-                        // 工具调用错误也应该作为工具响应返还给模型
-                        let error_content = serde_json::json!({
-                            "error": true,
-                            "message": format!("工具调用失败: {}", e),
-                            "details": e.to_string()
-                        }).to_string();
-                        warn!("工具调用失败: {} {:?}", error_content, call);
-                        yield Ok(ModelMessage::tool(error_content, call.clone()));
-                    }
-                }
+        match result {
+            Ok(output) => self
+                .limiter
+                .apply(ModelMessage::tool(output.text, call).with_parts(output.parts)),
+            Err(e) => {
+                // 工具调用错误也应该作为工具响应返还给模型
+                let error_content = serde_json::json!({
+                    "error": true,
+                    "message": format!("工具调用失败: {}", e),
+                    "details": e.to_string()
+                })
+                .to_string();
+                warn!("工具调用失败: {} {:?}", error_content, call);
+                ModelMessage::tool(error_content, call)
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(id: &str, operation: &str) -> ToolCall {
+        let mut call = ToolCall::new();
+        call.id = id.into();
+        call.function.name = "filesystem".into();
+        call.function.arguments = format!(r#"{{"operation":"{}","path":"a"}}"#, operation);
+        call
+    }
+
+    #[test]
+    fn test_group_calls() {
+        let calls = vec![
+            call("1", "read"),
+            call("2", "list"),
+            call("3", "write"),
+            call("4", "write"),
+            call("5", "read"),
+        ];
+        let groups: Vec<Vec<String>> = ToolClient::group_calls(calls)
+            .into_iter()
+            .map(|group| group.into_iter().map(|call| call.id).collect())
+            .collect();
+        assert_eq!(
+            groups,
+            vec![vec!["1", "2"], vec!["3"], vec!["4"], vec!["5"]]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reject_tool_not_offered() {
+        let client = ToolClient::new(
+            1,
+            OutputLimiter::new(Default::default()),
+            PermissionPolicy::new(Default::default(), false),
+            None,
+            HashSet::from(["todo".to_string()]),
+        );
+        let msg = client
+            .call_one(call("1", "read"), CancellationToken::new())
+            .await;
+        let content: Value = serde_json::from_str(&msg.content).unwrap();
+        assert_eq!(content["error"], true);
+        assert_eq!(content["message"], "工具 filesystem 不可用");
+    }
+}
@@ -0,0 +1,233 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use log::{info, warn};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::config::{Config, ToolOutputConfig};
+use crate::model::param::{ModelMessage, OutputTruncation};
+
+/// 会话的工具输出目录超过这个时间没有写入时删除
+const MAX_AGE: Duration = Duration::from_secs(30 * 24 * 3600);
+
+/// 完整工具输出的保存目录，filesystem 工具可以读取这个目录下的文件
+pub fn scratch_root() -> PathBuf {
+    Config::get_standard_config_dir().join("tool-outputs")
+}
+
+/// 删除过期的会话输出目录，恢复的会话还会引用这些文件，所以不在会话结束时删除
+pub fn prune_expired() {
+    let Ok(entries) = fs::read_dir(scratch_root()) else {
+        return;
+    };
+    let now = SystemTime::now();
+    for entry in entries.flatten() {
+        let expired = entry
+            .metadata()
+            .and_then(|meta| meta.modified())
+            .is_ok_and(|time| now.duration_since(time).unwrap_or_default() > MAX_AGE);
+        if !expired {
+            continue;
+        }
+        let path = entry.path();
+        match fs::remove_dir_all(&path) {
+            Ok(()) => info!("已删除过期的工具输出目录 {}", path.display()),
+            Err(e) => warn!("删除工具输出目录 {} 失败: {}", path.display(), e),
+        }
+    }
+}
+
+/// 工具输出长度限制，超出时只在上下文中保留首尾部分，完整输出保存到会话的临时目录
+#[derive(Clone, Debug)]
+pub struct OutputLimiter {
+    config: ToolOutputConfig,
+    /// 当前会话的临时目录
+    dir: PathBuf,
+}
+
+impl OutputLimiter {
+    pub fn new(config: ToolOutputConfig) -> Self {
+        Self {
+            config,
+            dir: scratch_root().join(Uuid::new_v4().to_string()),
+        }
+    }
+
+    /// 开启或恢复会话后，完整输出保存到该会话的目录
+    pub fn set_session(&mut self, id: &str) {
+        self.dir = scratch_root().join(id);
+    }
+
+    /// 删除保存的完整输出，只用于不会保存的对话
+    pub fn cleanup(&self) {
+        if !self.dir.exists() {
+            return;
+        }
+        match fs::remove_dir_all(&self.dir) {
+            Ok(()) => info!("已删除工具输出目录 {}", self.dir.display()),
+            Err(e) => warn!("删除工具输出目录 {} 失败: {}", self.dir.display(), e),
+        }
+    }
+
+    /// 超出限制时截断工具响应，并记录截断信息
+    pub fn apply(&self, mut msg: ModelMessage) -> ModelMessage {
+        let limit = self.config.limit(&msg.name);
+        let Some((head, tail)) = truncate(&msg.content, limit) else {
+            return msg;
+        };
+        let original_chars = msg.content.chars().count();
+        let omitted = original_chars - head.chars().count() - tail.chars().count();
+        let saved = match self.save(&msg) {
+            Ok(saved) => Some(saved),
+            Err(e) => {
+                warn!("保存完整工具输出失败: {}", e);
+                None
+            }
+        };
+        info!(
+            "工具 {} 的输出共 {} 个字符，超过上限 {}，已截断",
+            msg.name, original_chars, limit
+        );
+        let note = match &saved {
+            Some((path, lines)) => format!(
+                "\n\n[输出过长，已省略中间 {} 个字符（共 {} 个字符）。完整输出已保存到 {}（共 {} 行），可以使用 filesystem 工具的 read 操作配合 offset、limit 参数按行分段查看]\n\n",
+                omitted,
+                original_chars,
+                path.display(),
+                lines
+            ),
+            None => format!(
+                "\n\n[输出过长，已省略中间 {} 个字符（共 {} 个字符）]\n\n",
+                omitted, original_chars
+            ),
+        };
+        msg.content = format!("{}{}{}", head, note, tail).into();
+        msg.truncation = Some(OutputTruncation {
+            original_chars,
+            kept_chars: original_chars - omitted,
+            path: saved.map(|(path, _)| path.to_string_lossy().to_string()),
+        });
+        msg
+    }
+
+    /// 保存完整输出，返回文件路径和行数
+    fn save(&self, msg: &ModelMessage) -> anyhow::Result<(PathBuf, usize)> {
+        fs::create_dir_all(&self.dir)?;
+        // 工具调用 ID 由模型生成，只保留可以作为文件名的字符
+        let id: String = msg
+            .tool_call_id
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+            .collect();
+        // 调用 ID 可能重复（部分模型每轮都从头编号），加上随机后缀避免覆盖之前的输出
+        let suffix = Uuid::new_v4().simple().to_string();
+        let name = if id.is_empty() {
+            suffix
+        } else {
+            format!("{}-{}", id, &suffix[..8])
+        };
+        let path = self.dir.join(format!("{}.txt", name));
+        let content = readable(&msg.content);
+        fs::write(&path, &content)?;
+        Ok((path, content.lines().count()))
+    }
+}
+
+/// 转换为便于按行查看的格式：JSON 对象按字段展开，字符串字段保留原始换行
+fn readable(content: &str) -> String {
+    let Ok(Value::Object(fields)) = serde_json::from_str::<Value>(content) else {
+        return content.to_string();
+    };
+    let mut text = String::new();
+    for (key, value) in fields {
+        let value = match value {
+            Value::String(s) => s,
+            other => serde_json::to_string_pretty(&other).unwrap_or_default(),
+        };
+        text += &format!("===== {} =====\n{}\n", key, value);
+    }
+    text
+}
+
+/// 文本超过 max_chars 个字符时返回保留的开头和结尾，各占一半，max_chars 为 0 时不限制
+fn truncate(text: &str, max_chars: usize) -> Option<(&str, &str)> {
+    if max_chars == 0 {
+        return None;
+    }
+    let total = text.chars().count();
+    if total <= max_chars {
+        return None;
+    }
+    let head_chars = max_chars / 2;
+    let tail_chars = max_chars - head_chars;
+    let head_end = text
+        .char_indices()
+        .nth(head_chars)
+        .map_or(text.len(), |(i, _)| i);
+    let tail_start = text
+        .char_indices()
+        .nth(total - tail_chars)
+        .map_or(text.len(), |(i, _)| i);
+    Some((&text[..head_end], &text[tail_start..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::param::ToolCall;
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("abcdef", 0), None);
+        assert_eq!(truncate("abcdef", 6), None);
+        assert_eq!(truncate("abcdef", 3), Some(("a", "ef")));
+        assert_eq!(truncate("一二三四五六", 4), Some(("一二", "五六")));
+    }
+
+    #[test]
+    fn test_apply() {
+        let dir = std::env::temp_dir().join(format!("agent-cli-output-{}", Uuid::new_v4()));
+        let mut config = ToolOutputConfig::default();
+        config.tools.insert("shell_command".into(), 10);
+        let limiter = OutputLimiter {
+            config,
+            dir: dir.clone(),
+        };
+        let mut call = ToolCall::new();
+        call.id = "call/1".into();
+        call.function.name = "shell_command".into();
+        let output = "0123456789".repeat(3);
+
+        let msg = limiter.apply(ModelMessage::tool("short", call.clone()));
+        assert_eq!(msg.truncation, None);
+        let msg = limiter.apply(ModelMessage::tool(output.clone(), call.clone()));
+        assert!(msg.content.starts_with("01234\n\n[输出过长"));
+        assert!(msg.content.ends_with("\n\n56789"));
+        let truncation = msg.truncation.unwrap();
+        assert_eq!((truncation.original_chars, truncation.kept_chars), (30, 10));
+        let first = truncation.path.unwrap();
+        let name = PathBuf::from(&first)
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
+        assert!(name.starts_with("call1-") && name.ends_with(".txt"));
+        assert_eq!(fs::read_to_string(&first).unwrap(), output);
+
+        let output = serde_json::json!({"exit_code": 0, "stdout": "a\nb\n".repeat(10)});
+        let msg = limiter.apply(ModelMessage::tool(output.to_string(), call));
+        let path = msg.truncation.unwrap().path.unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!(
+                "===== exit_code =====\n0\n===== stdout =====\n{}\n",
+                "a\nb\n".repeat(10)
+            )
+        );
+        assert_ne!(path, first);
+        limiter.cleanup();
+        assert!(!dir.exists());
+    }
+}
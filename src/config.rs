@@ -1,18 +1,31 @@
 use anyhow;
-use log::info;
+use log::{info, warn};
+use rmcp::ServiceExt;
 use rmcp::serde;
-use rmcp::{RoleClient, ServiceExt, service::RunningService};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
 use std::io::{self, Write};
 use std::path::PathBuf;
 
+use crate::chat::chat_compress::CompressionConfig;
+use crate::chat::chat_hooks::HooksConfig;
+use crate::chat::chat_permission::PermissionConfig;
+use crate::chat::chat_window::ContextPolicy;
+use crate::connection::retry::RetryConfig;
+use crate::mcp::mcp_server::McpConnection;
+use crate::model::catalog::CapabilityOverride;
+use crate::model::param::SamplingParams;
+use crate::model::registry::ProviderConfig;
+
 // use crate::mcp_adaptor::McpManager;
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct McpServerConfig {
     #[serde(default)]
     pub description: String,
+    /// 该服务所有工具的调用超时秒数，未配置时使用 tool_timeout 中的默认值
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
     #[serde(flatten)]
     pub transport: McpServerTransportConfig,
 }
@@ -35,13 +48,111 @@ pub enum McpServerTransportConfig {
     },
 }
 
+fn tool_timeout_secs_default() -> u64 {
+    300
+}
+
+/// 工具调用超时配置，优先级：单独配置的工具 > 所属 mcp 服务 > 默认值
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ToolTimeoutConfig {
+    /// 默认超时秒数，为 0 时不限制
+    #[serde(default = "tool_timeout_secs_default")]
+    pub default_secs: u64,
+    /// 按工具名单独配置的超时秒数
+    #[serde(default)]
+    pub tools: HashMap<String, u64>,
+}
+
+impl Default for ToolTimeoutConfig {
+    fn default() -> Self {
+        Self {
+            default_secs: tool_timeout_secs_default(),
+            tools: HashMap::new(),
+        }
+    }
+}
+
+fn tool_output_max_chars_default() -> usize {
+    20000
+}
+
+/// 工具输出长度限制，超出时保留首尾部分，完整输出保存到临时文件
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ToolOutputConfig {
+    /// 默认保留的最大字符数，为 0 时不限制
+    #[serde(default = "tool_output_max_chars_default")]
+    pub max_chars: usize,
+    /// 按工具名单独配置的最大字符数
+    #[serde(default)]
+    pub tools: HashMap<String, usize>,
+}
+
+impl ToolOutputConfig {
+    /// 工具的输出上限，为 0 时不限制
+    pub fn limit(&self, tool: &str) -> usize {
+        self.tools.get(tool).copied().unwrap_or(self.max_chars)
+    }
+}
+
+impl Default for ToolOutputConfig {
+    fn default() -> Self {
+        Self {
+            max_chars: tool_output_max_chars_default(),
+            tools: HashMap::new(),
+        }
+    }
+}
+
+fn subagent_max_tool_try_default() -> usize {
+    15
+}
+fn subagent_timeout_secs_default() -> u64 {
+    1800
+}
+
+/// task 工具启动的子代理的默认配置，调用时指定的预算不能超过这里的上限
+///
+/// 子代理使用调用它的对话当前的配置和模型，执行时间由 timeout_secs 限制，不受 tool_timeout 影响
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SubAgentConfig {
+    /// 子代理最多执行的工具调用批数
+    #[serde(default = "subagent_max_tool_try_default")]
+    pub max_tool_try: usize,
+    /// 子代理最多消耗的 token 数，为 0 时不限制
+    #[serde(default)]
+    pub max_tokens: u32,
+    /// 调用时没有指定工具时子代理可以使用的工具，为空时可以使用除 task 以外的所有工具
+    #[serde(default)]
+    pub tools: Vec<String>,
+    /// 子代理的系统提示词，调用时没有指定时使用，为空时使用内置的提示词
+    #[serde(default)]
+    pub prompt: Option<String>,
+    /// 子代理最长的执行秒数，超时后返回已有的报告，为 0 时不限制
+    #[serde(default = "subagent_timeout_secs_default")]
+    pub timeout_secs: u64,
+}
+
+impl Default for SubAgentConfig {
+    fn default() -> Self {
+        Self {
+            max_tool_try: subagent_max_tool_try_default(),
+            max_tokens: 0,
+            tools: Vec::new(),
+            prompt: None,
+            timeout_secs: subagent_timeout_secs_default(),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
 pub struct McpConfig {
     pub server: HashMap<String, McpServerConfig>,
 }
 
 impl McpServerTransportConfig {
-    pub async fn start(&self) -> anyhow::Result<RunningService<RoleClient, ()>> {
+    /// 启动并连接 mcp 服务
+    pub async fn start(&self) -> anyhow::Result<McpConnection> {
+        let mut process = None;
         let client = match self {
             McpServerTransportConfig::Streamable { url } => {
                 let transport =
@@ -63,7 +174,9 @@ impl McpServerTransportConfig {
                     .envs(envs)
                     .stdin(std::process::Stdio::piped())
                     .stdout(std::process::Stdio::piped())
-                    .stderr(std::process::Stdio::piped());
+                    .stderr(std::process::Stdio::piped())
+                    // 连接释放时结束子进程，避免取消调用后服务继续运行
+                    .kill_on_drop(true);
 
                 let mut child = cmd.spawn()?;
 
@@ -82,26 +195,37 @@ impl McpServerTransportConfig {
                 let transport = rmcp::transport::async_rw::AsyncRwTransport::new(stdout, stdin);
 
                 // 保存子进程引用以便后续管理
-                tokio::spawn(async move {
-                    let _ = child.wait().await;
-                });
+                process = Some(child);
 
                 ().serve(transport).await?
             }
         };
-        Ok(client)
+        Ok(McpConnection::new(client, process))
     }
 }
 
 fn max_tool_try_default() -> usize {
+    25
+}
+fn max_tool_repeat_default() -> usize {
     3
 }
+fn max_parallel_tools_default() -> usize {
+    4
+}
+
 fn max_context_num_default() -> usize {
     30
 }
 fn max_tokens_default() -> Option<u32> {
-    Some(64000)
+    None
 }
+/// 当前的配置文件格式版本
+const CONFIG_VERSION: u32 = 1;
+/// 旧版本写入每个配置文件的 max_tokens，当时只是默认值，不是用户设置的上限
+const LEGACY_MAX_TOKENS: u32 = 64000;
+/// 旧版本写入每个配置文件的 max_tool_try，当时没有生效，按现在的含义会在 3 批调用后就暂停
+const LEGACY_MAX_TOOL_TRY: usize = 3;
 
 fn ask_before_tool_execution_default() -> bool {
     false
@@ -115,6 +239,10 @@ fn compress_trigger_ratio_default() -> f32 {
     0.7
 }
 
+fn instruction_files_default() -> Vec<String> {
+    vec!["AGENTS.md".into(), ".agent-cli/instructions.md".into()]
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct EnvConfig {
     pub key: String,
@@ -123,30 +251,94 @@ pub struct EnvConfig {
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Config {
+    /// 配置文件格式版本，旧版本的文件没有这个字段，加载时迁移一次并写回
+    #[serde(default)]
+    pub version: u32,
     #[serde(default)]
     pub mcp: Option<McpConfig>,
+    /// 模型提供方，对应模型注册表中的名称，默认为 deepseek
+    #[serde(default)]
+    pub provider: Option<String>,
+    /// 本地提供方（local、ollama）可以不配置
+    #[serde(default)]
     pub api_key: String,
     pub url: Option<String>,
     pub model: Option<String>,
+    /// 思维链 token 预算，仅对支持扩展思考的提供方生效，未配置时不开启
+    #[serde(default)]
+    pub thinking_budget: Option<u32>,
+    /// tiktoken 格式的 BPE 词表文件路径，用于发送前计算 token 数，不配置时按字符数估算
+    #[serde(default)]
+    pub tokenizer: Option<String>,
+    /// 请求失败时的重试配置
+    #[serde(default)]
+    pub retry: RetryConfig,
+    /// 备用模型列表，主模型重试失败后按顺序尝试
+    #[serde(default)]
+    pub fallbacks: Vec<ProviderConfig>,
+    /// 一轮对话中最多执行多少批工具调用，达到后暂停，等待用户确认是否继续，为 0 时不限制
+    ///
+    /// 旧版本生成的配置文件都写入了 3，加载时视为未设置，见 LEGACY_MAX_TOOL_TRY
     #[serde(default = "max_tool_try_default")]
     pub max_tool_try: usize,
+    /// 相同参数的调用或相同的错误在一轮对话中出现多少次时提醒模型，再出现时暂停，为 0 时不检测
+    #[serde(default = "max_tool_repeat_default")]
+    pub max_tool_repeat: usize,
+    /// 模型一次返回多个工具调用时，同时执行的只读调用数量上限，为 1 时依次执行；修改环境的调用总是依次执行
+    #[serde(default = "max_parallel_tools_default")]
+    pub max_parallel_tools: usize,
+    /// 工具调用超时配置
+    #[serde(default)]
+    pub tool_timeout: ToolTimeoutConfig,
+    /// 工具输出长度限制
+    #[serde(default)]
+    pub tool_output: ToolOutputConfig,
+    /// 生命周期钩子：工具调用前后、用户提交输入、一轮对话结束时执行的命令
+    #[serde(default)]
+    pub hooks: HooksConfig,
+    /// task 工具启动的子代理的预算和可用工具
+    #[serde(default)]
+    pub subagent: SubAgentConfig,
     #[serde(default = "max_context_num_default")]
     pub max_context_num: usize,
+    /// 上下文 token 上限，为空时使用模型的上下文窗口，两者都存在时取较小值
+    ///
+    /// 旧版本生成的配置文件都写入了 64000，加载时视为未设置，见 LEGACY_MAX_TOKENS
     #[serde(default = "max_tokens_default")]
     pub max_tokens: Option<u32>,
+    /// 默认采样参数（temperature、top_p、seed、输出上限等）
+    #[serde(default)]
+    pub sampling: SamplingParams,
+    /// 模型能力覆盖，键为模型名（前缀匹配）
+    #[serde(default)]
+    pub model_capabilities: HashMap<String, CapabilityOverride>,
+    /// 没有权限规则匹配且未配置 permissions.default 时，是否在执行工具前询问
     #[serde(default = "ask_before_tool_execution_default")]
     pub ask_before_tool_execution: bool,
+    /// 工具调用权限规则，按顺序匹配，决定允许、拒绝或询问
+    #[serde(default)]
+    pub permissions: PermissionConfig,
     #[serde(default = "auto_compress_threshold_default")]
     pub auto_compress_threshold: f32,
+    /// 用量超过这个比例时才执行需要调用模型总结的压缩策略
     #[serde(default = "compress_trigger_ratio_default")]
     pub compress_trigger_ratio: f32,
+    /// 对话压缩策略
+    #[serde(default)]
+    pub compression: CompressionConfig,
+    /// 用量超过 auto_compress_threshold 时的处理方式：compress 总结压缩，sliding_window 直接丢弃最早的对话
+    #[serde(default)]
+    pub context_policy: ContextPolicy,
     pub prompt: Option<String>,
+    /// 项目说明文件名，从配置目录、仓库根目录到当前目录依次查找，内容追加到系统提示词，为空时不加载
+    #[serde(default = "instruction_files_default")]
+    pub instruction_files: Vec<String>,
     #[serde(default)]
     pub envs: Vec<EnvConfig>,
 }
 
 impl Config {
-    fn get_standard_config_dir() -> PathBuf {
+    pub fn get_standard_config_dir() -> PathBuf {
         // 获取标准应用配置目录
         #[cfg(target_os = "windows")]
         {
@@ -215,6 +407,16 @@ impl Config {
         // 读取配置文件
         let config_content = fs::read_to_string(&config_path)?;
         let mut config_file: Self = serde_json::from_str(&config_content)?;
+        if config_file.migrate_legacy_values() {
+            // 写回迁移后的配置，之后用户再设置相同的值时按新的含义处理
+            match serde_json::to_string_pretty(&config_file) {
+                Ok(json) => match fs::write(&config_path, json) {
+                    Ok(()) => info!("配置文件已迁移到版本 {}", CONFIG_VERSION),
+                    Err(e) => warn!("写回迁移后的配置文件失败: {}", e),
+                },
+                Err(e) => warn!("序列化迁移后的配置失败: {}", e),
+            }
+        }
 
         // 验证和补全配置字段
         if is_acp_mode {
@@ -245,17 +447,35 @@ impl Config {
 
         // 创建默认配置
         let config = Config {
+            version: CONFIG_VERSION,
             mcp: None,
+            provider: None,
+            thinking_budget: None,
+            tokenizer: None,
+            retry: RetryConfig::default(),
+            fallbacks: Vec::new(),
             api_key,
             url: if url.is_empty() { None } else { Some(url) },
             model: if model.is_empty() { None } else { Some(model) },
             max_tool_try: max_tool_try_default(),
+            max_tool_repeat: max_tool_repeat_default(),
+            max_parallel_tools: max_parallel_tools_default(),
+            tool_timeout: ToolTimeoutConfig::default(),
+            tool_output: ToolOutputConfig::default(),
+            hooks: HooksConfig::default(),
+            subagent: SubAgentConfig::default(),
             max_context_num: max_context_num_default(),
             max_tokens: max_tokens_default(),
+            sampling: SamplingParams::default(),
+            model_capabilities: HashMap::new(),
             ask_before_tool_execution: ask_before_tool_execution_default(),
+            permissions: PermissionConfig::default(),
             auto_compress_threshold: auto_compress_threshold_default(),
             compress_trigger_ratio: compress_trigger_ratio_default(),
+            compression: CompressionConfig::default(),
+            context_policy: ContextPolicy::default(),
             prompt: None,
+            instruction_files: instruction_files_default(),
             envs: Vec::new(),
         };
 
@@ -283,17 +503,35 @@ impl Config {
 
         // 创建默认配置（使用空字符串作为占位符）
         let config = Config {
+            version: CONFIG_VERSION,
             mcp: None,
+            provider: None,
+            thinking_budget: None,
+            tokenizer: None,
+            retry: RetryConfig::default(),
+            fallbacks: Vec::new(),
             api_key: String::new(), // 空字符串，需要由客户端提供
             url: None,
             model: None,
             max_tool_try: max_tool_try_default(),
+            max_tool_repeat: max_tool_repeat_default(),
+            max_parallel_tools: max_parallel_tools_default(),
+            tool_timeout: ToolTimeoutConfig::default(),
+            tool_output: ToolOutputConfig::default(),
+            hooks: HooksConfig::default(),
+            subagent: SubAgentConfig::default(),
             max_context_num: max_context_num_default(),
             max_tokens: max_tokens_default(),
+            sampling: SamplingParams::default(),
+            model_capabilities: HashMap::new(),
             ask_before_tool_execution: ask_before_tool_execution_default(),
+            permissions: PermissionConfig::default(),
             auto_compress_threshold: auto_compress_threshold_default(),
             compress_trigger_ratio: compress_trigger_ratio_default(),
+            compression: CompressionConfig::default(),
+            context_policy: ContextPolicy::default(),
             prompt: None,
+            instruction_files: instruction_files_default(),
             envs: Vec::new(),
         };
 
@@ -305,21 +543,37 @@ impl Config {
         Ok(config)
     }
 
-    /// 使用默认值补全配置（不询问用户）
-    fn complete_config_with_defaults(mut config: Self) -> Self {
-        // 设置默认值
-        if config.max_tool_try == 0 {
-            config.max_tool_try = max_tool_try_default();
+    /// 旧版本写入的默认值含义已经改变，只对没有版本号的旧配置迁移一次，返回是否需要写回
+    fn migrate_legacy_values(&mut self) -> bool {
+        if self.version >= CONFIG_VERSION {
+            return false;
+        }
+        self.version = CONFIG_VERSION;
+        if self.max_tokens == Some(LEGACY_MAX_TOKENS) {
+            info!(
+                "max_tokens 为旧版本的默认值 {}，使用模型的上下文窗口",
+                LEGACY_MAX_TOKENS
+            );
+            self.max_tokens = None;
+        }
+        if self.max_tool_try == LEGACY_MAX_TOOL_TRY {
+            info!(
+                "max_tool_try 为旧版本的默认值 {}，使用默认值 {}",
+                LEGACY_MAX_TOOL_TRY,
+                max_tool_try_default()
+            );
+            self.max_tool_try = max_tool_try_default();
         }
+        true
+    }
 
+    /// 使用默认值补全配置（不询问用户）
+    fn complete_config_with_defaults(mut config: Self) -> Self {
+        // 设置默认值，max_tool_try 为 0 表示不限制，保持不变
         if config.max_context_num == 0 {
             config.max_context_num = max_context_num_default();
         }
 
-        if config.max_tokens.is_none() {
-            config.max_tokens = max_tokens_default();
-        }
-
         config
     }
 
@@ -330,28 +584,18 @@ impl Config {
         let mut needs_save = false;
 
         // 验证必填字段
-        if config.api_key.is_empty() {
+        if config.api_key.is_empty() && ProviderConfig::from_config(&config).requires_api_key() {
             println!("API密钥缺失，需要重新输入");
             config.api_key = Self::prompt_user_input("请输入API密钥: ")?;
             needs_save = true;
         }
 
-        // 设置默认值
-        if config.max_tool_try == 0 {
-            config.max_tool_try = max_tool_try_default();
-            needs_save = true;
-        }
-
+        // 设置默认值，max_tool_try 为 0 表示不限制，保持不变
         if config.max_context_num == 0 {
             config.max_context_num = max_context_num_default();
             needs_save = true;
         }
 
-        if config.max_tokens.is_none() {
-            config.max_tokens = max_tokens_default();
-            needs_save = true;
-        }
-
         // 如果需要保存，更新配置文件
         if needs_save {
             let config_json = serde_json::to_string_pretty(&config)?;
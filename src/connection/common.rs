@@ -1,4 +1,4 @@
-use eventsource_client::{Client, ClientBuilder, SSE};
+use eventsource_client::{Client, ClientBuilder, ReconnectOptions, SSE};
 use futures::Stream;
 use log::{error, info, warn};
 use reqwest::header;
@@ -7,6 +7,7 @@ use std::sync::OnceLock;
 use tokio_stream::StreamExt;
 
 use crate::{
+    connection::retry::{HttpStatusError, TransportError, parse_retry_after},
     connection::{CommonConnectionContent, TokenUsage},
     model::param::ToolCall,
 };
@@ -24,27 +25,65 @@ fn get_http_client() -> &'static reqwest::Client {
     })
 }
 
+/// 将非 2xx 响应转换为 HttpStatusError，携带状态码和 Retry-After 供重试判断
+async fn check_status(
+    response: reqwest::Response,
+) -> std::result::Result<reqwest::Response, anyhow::Error> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let retry_after = parse_retry_after(
+        response
+            .headers()
+            .get(header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok()),
+    );
+    let body = response.text().await.unwrap_or_default();
+    error!("{} {:?}", status, body);
+    Err(anyhow::anyhow!(HttpStatusError {
+        status: status.as_u16(),
+        retry_after,
+        body,
+    }))
+}
+
+fn transport_error(e: reqwest::Error) -> anyhow::Error {
+    anyhow::anyhow!(TransportError(e.to_string()))
+}
+
+/// 流式请求使用的 HTTP 客户端，只限制连接超时，不限制整体耗时
+static STREAM_HTTP_CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+
+fn get_stream_http_client() -> &'static reqwest::Client {
+    STREAM_HTTP_CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .pool_max_idle_per_host(10)
+            .connect_timeout(std::time::Duration::from_secs(30))
+            .build()
+            .expect("Failed to create HTTP client")
+    })
+}
+
+/// SSE 原始事件
+#[derive(Debug, Clone)]
+pub struct SseEvent {
+    pub event: String,
+    pub data: String,
+}
+
 pub struct SseConnection;
 
 impl SseConnection {
-    pub fn stream(
+    /// 以 POST 方式建立 SSE 连接，返回未经解析的原始事件流
+    pub fn events(
         url: String,
-        key: String,
+        headers: Vec<(String, String)>,
         body: String,
-    ) -> impl Stream<Item = std::result::Result<CommonConnectionContent, anyhow::Error>> {
-        let _client = get_http_client();
-        let url_clone = url.clone();
-        let key_clone = key.clone();
-        let body_clone = body.clone();
-
+    ) -> impl Stream<Item = std::result::Result<SseEvent, anyhow::Error>> {
         async_stream::stream! {
-            // 使用 eventsource_client 解析 SSE 流
-            let mut tool_calls = Vec::new();
-
-            info!("开始流式处理");
-
             // 创建 eventsource 客户端构建器
-            let client_builder = match ClientBuilder::for_url(&url_clone) {
+            let mut client_builder = match ClientBuilder::for_url(&url) {
                 Ok(builder) => builder,
                 Err(e) => {
                     yield Err(anyhow::anyhow!("创建 SSE 客户端构建器失败: {:?}", e));
@@ -52,36 +91,104 @@ impl SseConnection {
                 }
             };
 
-            // 配置客户端 - 第一个 header
-            let client_builder = match client_builder.header("Content-Type", "application/json") {
+            // 配置请求头
+            client_builder = match client_builder.header("Content-Type", "application/json") {
                 Ok(builder) => builder,
                 Err(e) => {
                     yield Err(anyhow::anyhow!("添加 Content-Type 请求头失败: {:?}", e));
                     return;
                 }
             };
-
-            // 配置客户端 - 第二个 header
-            let client_builder = match client_builder.header("Authorization", &key_clone) {
-                Ok(builder) => builder,
-                Err(e) => {
-                    yield Err(anyhow::anyhow!("添加 Authorization 请求头失败: {:?}", e));
-                    return;
-                }
-            };
+            for (name, value) in headers.iter() {
+                client_builder = match client_builder.header(name, value) {
+                    Ok(builder) => builder,
+                    Err(e) => {
+                        yield Err(anyhow::anyhow!("添加 {} 请求头失败: {:?}", name, e));
+                        return;
+                    }
+                };
+            }
 
             // 设置 HTTP 方法为 POST
             let client_builder = client_builder.method("POST".to_string());
 
-            // 构建客户端
-            let client = client_builder.body(body_clone.clone()).build();
+            // 构建客户端，关闭自动重连，避免连接断开后重复发送请求
+            let client = client_builder
+                .reconnect(ReconnectOptions::reconnect(false).build())
+                .body(body)
+                .build();
 
             let mut stream = client.stream();
 
             while let Some(event) = stream.next().await {
                 match event {
                     Ok(SSE::Event(evt)) => {
-                        if evt.event_type == "message" || evt.event_type.is_empty() {
+                        yield Ok(SseEvent {
+                            event: evt.event_type,
+                            data: evt.data,
+                        });
+                    }
+                    Ok(SSE::Connected(_)) => {
+                        // 连接已打开，可以忽略
+                    }
+                    Ok(SSE::Comment(_)) => {
+                        // 注释事件，可以忽略
+                    }
+                    // 响应正常结束
+                    Err(eventsource_client::Error::Eof) => return,
+                    Err(eventsource_client::Error::UnexpectedResponse(resp, body)) => {
+                        let retry_after = parse_retry_after(
+                            resp.get_header_value("retry-after").ok().flatten(),
+                        );
+                        let body = match body.body_bytes().await {
+                            Ok(bytes) => String::from_utf8_lossy(&bytes).to_string(),
+                            Err(e) => e.to_string(),
+                        };
+                        error!("SSE 请求失败: {} {:?}", resp.status(), body);
+                        yield Err(anyhow::anyhow!(HttpStatusError {
+                            status: resp.status(),
+                            retry_after,
+                            body,
+                        }));
+                        return;
+                    }
+                    Err(e @ (eventsource_client::Error::TimedOut
+                    | eventsource_client::Error::HttpStream(_)
+                    | eventsource_client::Error::UnexpectedEof)) => {
+                        error!("SSE 错误: {:?}", e);
+                        yield Err(anyhow::anyhow!(TransportError(e.to_string())));
+                        return;
+                    }
+                    Err(e) => {
+                        error!("SSE 错误: {:?}", e);
+                        yield Err(anyhow::anyhow!(e.to_string()));
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    /// 解析 OpenAI 兼容格式的流式响应
+    pub fn stream(
+        url: String,
+        headers: Vec<(String, String)>,
+        body: String,
+    ) -> impl Stream<Item = std::result::Result<CommonConnectionContent, anyhow::Error>> {
+        let _client = get_http_client();
+
+        async_stream::stream! {
+            let mut tool_calls = Vec::new();
+
+            info!("开始流式处理");
+
+            let stream = Self::events(url, headers, body);
+            futures::pin_mut!(stream);
+
+            while let Some(event) = stream.next().await {
+                match event {
+                    Ok(evt) => {
+                        if evt.event == "message" || evt.event.is_empty() {
                             let data = evt.data;
 
                             // 处理结束标志
@@ -179,15 +286,8 @@ impl SseConnection {
                             }
                         }
                     }
-                    Ok(SSE::Connected(_)) => {
-                        // 连接已打开，可以忽略
-                    }
-                    Ok(SSE::Comment(_)) => {
-                        // 注释事件，可以忽略
-                    }
                     Err(e) => {
-                        error!("SSE 错误: {:?}", e);
-                        yield Err(anyhow::anyhow!(e.to_string()));
+                        yield Err(e);
                         continue;
                     }
                 }
@@ -200,42 +300,126 @@ impl SseConnection {
     }
 }
 
+/// 按行分隔的 JSON 流（NDJSON），Ollama 原生接口使用这种格式
+pub struct JsonLinesConnection;
+
+impl JsonLinesConnection {
+    /// 以 POST 方式请求，逐行解析返回的 JSON
+    pub fn stream(
+        url: String,
+        headers: Vec<(String, String)>,
+        body: String,
+    ) -> impl Stream<Item = std::result::Result<Value, anyhow::Error>> {
+        async_stream::stream! {
+            info!("请求 {}", url);
+            let mut request = get_stream_http_client()
+                .post(url)
+                .header(header::CONTENT_TYPE, "application/json");
+            for (name, value) in headers {
+                request = request.header(name, value);
+            }
+            let response = match request.body(body).send().await {
+                Ok(resp) => check_status(resp).await,
+                Err(e) => Err(transport_error(e)),
+            };
+            let mut response = match response {
+                Ok(resp) => resp,
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            };
+
+            // 数据块不一定按行切分，未完整的行留到下一次处理
+            let mut buffer: Vec<u8> = Vec::new();
+            loop {
+                let chunk = match response.chunk().await {
+                    Ok(Some(chunk)) => chunk,
+                    Ok(None) => break,
+                    Err(e) => {
+                        yield Err(transport_error(e));
+                        return;
+                    }
+                };
+                buffer.extend_from_slice(&chunk);
+                while let Some(pos) = buffer.iter().position(|b| *b == b'\n') {
+                    let line: Vec<u8> = buffer.drain(..=pos).collect();
+                    let line = String::from_utf8_lossy(&line);
+                    let line = line.trim();
+                    if line.is_empty() {
+                        continue;
+                    }
+                    match serde_json::from_str::<Value>(line) {
+                        Ok(json) => yield Ok(json),
+                        Err(e) => error!("JSON 解析错误: {:?}, 数据: {}", e, line),
+                    }
+                }
+            }
+            let line = String::from_utf8_lossy(&buffer);
+            let line = line.trim();
+            if !line.is_empty() {
+                match serde_json::from_str::<Value>(line) {
+                    Ok(json) => yield Ok(json),
+                    Err(e) => error!("JSON 解析错误: {:?}, 数据: {}", e, line),
+                }
+            }
+        }
+    }
+}
+
 pub struct DirectConnection;
 
 impl DirectConnection {
-    pub async fn request(
+    /// 发送 GET 请求并返回响应文本，非 2xx 状态视为错误
+    pub async fn get(
+        url: String,
+        headers: Vec<(String, String)>,
+    ) -> std::result::Result<String, anyhow::Error> {
+        info!("请求 {}", url);
+        let mut request = get_http_client().get(url);
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        let response = request.send().await.map_err(transport_error)?;
+        let response = check_status(response).await?;
+        response.text().await.map_err(transport_error)
+    }
+
+    /// 发送 POST 请求并返回响应文本，非 2xx 状态视为错误
+    pub async fn post(
         url: String,
-        key: String,
+        headers: Vec<(String, String)>,
         body: String,
-    ) -> std::result::Result<Vec<CommonConnectionContent>, anyhow::Error> {
+    ) -> std::result::Result<String, anyhow::Error> {
         info!("请求 {}", url);
         let client = get_http_client();
-        let response = match client
+        let mut request = client
             .post(url.clone())
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", key)
-            .body(body.clone())
-            .send()
-            .await
-        {
+            .header(header::CONTENT_TYPE, "application/json");
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        let response = match request.body(body).send().await {
             Ok(resp) => resp,
-            Err(e) => return Err(anyhow::anyhow!(e)),
+            Err(e) => return Err(transport_error(e)),
         };
 
-        if !response.status().is_success() {
-            let ret = match response.text().await {
-                Ok(text) => text,
-                Err(e) => return Err(anyhow::anyhow!(e)),
-            };
-            error!("{:?}", ret);
-            return Err(anyhow::anyhow!(ret));
-        }
+        let response = check_status(response).await?;
         info!("请求成功");
 
-        let text = match response.text().await {
-            Ok(text) => text,
-            Err(e) => return Err(anyhow::anyhow!(e)),
-        };
+        match response.text().await {
+            Ok(text) => Ok(text),
+            Err(e) => Err(transport_error(e)),
+        }
+    }
+
+    /// 请求 OpenAI 兼容格式的接口并解析响应
+    pub async fn request(
+        url: String,
+        headers: Vec<(String, String)>,
+        body: String,
+    ) -> std::result::Result<Vec<CommonConnectionContent>, anyhow::Error> {
+        let text = Self::post(url, headers, body).await?;
 
         let json: Value = match serde_json::from_str(&text) {
             Ok(json) => json,
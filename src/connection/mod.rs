@@ -1,7 +1,8 @@
-use crate::model::param::ToolCall;
+use crate::model::param::{ThinkingBlock, ToolCall};
 use serde::{Deserialize, Serialize};
 
 pub mod common;
+pub mod retry;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct TokenUsage {
@@ -17,6 +18,8 @@ pub struct TokenUsage {
 pub enum CommonConnectionContent {
     Content(String),
     Reasoning(String),
+    // 完整的思维链块，回传思维链时需要原样带回
+    ThinkingBlock(ThinkingBlock),
     ToolCall(ToolCall),
     FinishReason(String),
     TokenUsage(TokenUsage),
@@ -0,0 +1,146 @@
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+fn max_retries_default() -> u32 {
+    3
+}
+
+fn initial_delay_ms_default() -> u64 {
+    1000
+}
+
+fn max_delay_ms_default() -> u64 {
+    30000
+}
+
+/// 请求重试配置
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RetryConfig {
+    /// 同一个模型的最大重试次数，为 0 时不重试
+    #[serde(default = "max_retries_default")]
+    pub max_retries: u32,
+    /// 首次重试前的等待时间，之后每次翻倍
+    #[serde(default = "initial_delay_ms_default")]
+    pub initial_delay_ms: u64,
+    /// 单次等待时间上限
+    #[serde(default = "max_delay_ms_default")]
+    pub max_delay_ms: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: max_retries_default(),
+            initial_delay_ms: initial_delay_ms_default(),
+            max_delay_ms: max_delay_ms_default(),
+        }
+    }
+}
+
+impl RetryConfig {
+    /// 计算第 attempt 次重试（从 0 开始）前的等待时间
+    ///
+    /// 服务端返回 Retry-After 时以服务端为准，否则使用带随机抖动的指数退避，
+    /// 抖动范围为退避时间的一半到全部，避免多个客户端同时重试。两者都不超过 max_delay_ms
+    pub fn backoff(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        if let Some(retry_after) = retry_after {
+            return retry_after.min(Duration::from_millis(self.max_delay_ms));
+        }
+        let delay = self
+            .initial_delay_ms
+            .saturating_mul(1u64 << attempt.min(20))
+            .min(self.max_delay_ms);
+        let half = delay / 2;
+        Duration::from_millis(half + random_u64() % (delay - half + 1))
+    }
+}
+
+fn random_u64() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+/// 服务端返回非 2xx 状态
+#[derive(Debug)]
+pub struct HttpStatusError {
+    pub status: u16,
+    /// 服务端通过 Retry-After 要求的等待时间
+    pub retry_after: Option<Duration>,
+    pub body: String,
+}
+
+impl HttpStatusError {
+    /// 限流、超时和服务端错误可以重试，其余的客户端错误重试也不会成功
+    pub fn is_retryable(&self) -> bool {
+        matches!(self.status, 408 | 409 | 429 | 500..=599)
+    }
+}
+
+impl fmt::Display for HttpStatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "请求失败 ({}): {}", self.status, self.body)
+    }
+}
+
+impl std::error::Error for HttpStatusError {}
+
+/// 连接失败、超时、连接中断等网络层错误
+#[derive(Debug)]
+pub struct TransportError(pub String);
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "网络错误: {}", self.0)
+    }
+}
+
+impl std::error::Error for TransportError {}
+
+/// 解析 Retry-After 头，只支持秒数格式
+pub fn parse_retry_after(value: Option<&str>) -> Option<Duration> {
+    value
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .map(Duration::from_secs)
+}
+
+/// 判断错误是否值得重试
+pub fn is_retryable(e: &anyhow::Error) -> bool {
+    if let Some(e) = e.downcast_ref::<HttpStatusError>() {
+        return e.is_retryable();
+    }
+    e.downcast_ref::<TransportError>().is_some()
+}
+
+/// 获取服务端要求的等待时间
+pub fn retry_after(e: &anyhow::Error) -> Option<Duration> {
+    e.downcast_ref::<HttpStatusError>()
+        .and_then(|e| e.retry_after)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let retry = RetryConfig {
+            max_retries: 3,
+            initial_delay_ms: 1000,
+            max_delay_ms: 5000,
+        };
+        for _ in 0..20 {
+            let first = retry.backoff(0, None);
+            assert!(first >= Duration::from_millis(500) && first <= Duration::from_millis(1000));
+            // 超过上限后不再增长
+            let last = retry.backoff(10, None);
+            assert!(last >= Duration::from_millis(2500) && last <= Duration::from_millis(5000));
+        }
+        // Retry-After 优先于退避时间，但同样不超过上限
+        let retry_after = parse_retry_after(Some("3"));
+        assert_eq!(retry.backoff(0, retry_after), Duration::from_secs(3));
+        let retry_after = parse_retry_after(Some("3600"));
+        assert_eq!(retry.backoff(0, retry_after), Duration::from_secs(5));
+    }
+}
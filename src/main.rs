@@ -1,4 +1,5 @@
-use crate::client::handle_output;
+use crate::chat::{SessionRecord, SessionStore};
+use crate::client::{handle_output, handle_structured_output};
 use clap::{Parser, command};
 use log::info;
 mod acp;
@@ -98,6 +99,18 @@ struct Args {
     /// 是否使用工具（默认使用）
     #[arg(short, long, default_value = "true")]
     use_tool: Option<bool>,
+    /// 结构化输出的 JSON Schema，可以是文件路径或 JSON 字符串，指定后只输出符合 Schema 的 JSON
+    #[arg(long)]
+    schema: Option<String>,
+    /// 恢复指定 ID 的会话，可以只写 ID 开头的一部分
+    #[arg(long, conflicts_with = "continue_session")]
+    resume: Option<String>,
+    /// 继续当前目录下最近的会话
+    #[arg(short = 'c', long = "continue")]
+    continue_session: bool,
+    /// 列出保存的会话
+    #[arg(long)]
+    sessions: bool,
     /// 是否等待用户输入（默认不等待）
     #[arg(short, long, default_value = "false")]
     wait: Option<bool>,
@@ -141,6 +154,7 @@ async fn main() -> anyhow::Result<()> {
     };
 
     mcp::init().await;
+    client::tool_output::prune_expired();
 
     for env in config.envs {
         unsafe {
@@ -148,6 +162,11 @@ async fn main() -> anyhow::Result<()> {
         }
     }
 
+    if args.sessions {
+        list_sessions()?;
+        return Ok(());
+    }
+
     // 优先处理 remote 模式
     if let Some(addr) = args.remote {
         info!("Starting remote server on {}", addr);
@@ -155,11 +174,21 @@ async fn main() -> anyhow::Result<()> {
         return Ok(());
     }
 
+    // 需要恢复的会话不存在时直接退出，不要悄悄开启新会话
+    let session = match load_session(&args) {
+        Ok(session) => session,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
     // 优先处理 napcat
     #[cfg(feature = "napcat")]
     if args.napcat {
         napcat::napcat_client::NapCatClient::new(
             napcat::napcatconfig::NapCatConfig::local().unwrap(),
+            session,
         )
         .start()
         .await;
@@ -177,19 +206,33 @@ async fn main() -> anyhow::Result<()> {
     if Some(true) == args.wait {
         wait_mode(args).await;
     } else if args.prompt.is_none() {
-        let _ = tui::run().await;
+        let _ = tui::run(session).await;
     } else {
-        chat().await;
+        chat(session).await;
     }
     Ok(())
 }
 
-async fn chat() {
+async fn chat(session: Option<SessionRecord>) {
     let args = Args::parse();
     let mut chat = chat::Chat::new(config::Config::local().unwrap());
     if Some(true) == args.use_tool {
         chat = chat.tools(mcp::get_config_tools());
     }
+    open_session(&mut chat, session);
+    if let Some(schema) = &args.schema {
+        let res = match load_schema(schema) {
+            Ok(schema) => {
+                handle_structured_output(&mut chat, &args.prompt.unwrap_or_default(), schema).await
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = res {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
     if Some(true) == args.stream {
         handle_output(chat.stream_chat(&args.prompt.unwrap_or_default()))
             .await
@@ -201,6 +244,60 @@ async fn chat() {
     }
 }
 
+/// 根据 --resume / --continue 参数读取要恢复的会话
+fn load_session(args: &Args) -> anyhow::Result<Option<SessionRecord>> {
+    let store = SessionStore::local();
+    if let Some(id) = &args.resume {
+        return store.load(id).map(Some);
+    }
+    if args.continue_session {
+        let cwd = std::env::current_dir()?;
+        return match store.latest(&cwd)? {
+            Some(record) => Ok(Some(record)),
+            None => Err(anyhow::anyhow!(
+                "当前目录下没有可以继续的会话: {}",
+                cwd.display()
+            )),
+        };
+    }
+    Ok(None)
+}
+
+/// 开启会话持久化，有需要恢复的会话时恢复，否则在当前目录开启新会话
+pub fn open_session(chat: &mut chat::Chat, session: Option<SessionRecord>) {
+    match session {
+        Some(record) => chat.resume_session(record),
+        None => {
+            let cwd = std::env::current_dir().unwrap_or_default();
+            chat.start_session(&cwd);
+        }
+    }
+}
+
+/// 列出保存的会话
+fn list_sessions() -> anyhow::Result<()> {
+    let sessions = SessionStore::local().list()?;
+    if sessions.is_empty() {
+        println!("没有保存的会话");
+        return Ok(());
+    }
+    for meta in sessions {
+        println!("{}", meta.summary());
+        println!("    {}", meta.cwd);
+    }
+    Ok(())
+}
+
+/// 读取 JSON Schema，参数是已存在的文件时读取文件内容
+fn load_schema(schema: &str) -> anyhow::Result<serde_json::Value> {
+    let text = if std::path::Path::new(schema).is_file() {
+        std::fs::read_to_string(schema)?
+    } else {
+        schema.to_string()
+    };
+    serde_json::from_str(&text).map_err(|e| anyhow::anyhow!("JSON Schema 解析失败: {}", e))
+}
+
 /// 启动ACP服务器
 async fn start_acp_server(args: &Args) -> anyhow::Result<()> {
     info!("开启 acp");
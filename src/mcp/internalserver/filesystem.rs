@@ -6,6 +6,7 @@ use serde_json::{Map, Value};
 use std::fs;
 use std::path::{Path, PathBuf};
 
+use crate::client::tool_output;
 use crate::mcp::internalserver::InternalTool;
 
 #[derive(Debug)]
@@ -94,9 +95,20 @@ impl FileSystemTool {
         !Self::is_path_allowed(path)
     }
 
+    /// 检查路径是否是保存的完整工具输出，这些文件总是可以读取
+    fn is_tool_output(path: &Path) -> bool {
+        match (
+            path.canonicalize(),
+            tool_output::scratch_root().canonicalize(),
+        ) {
+            (Ok(path), Ok(root)) => path.starts_with(root),
+            _ => false,
+        }
+    }
+
     /// 读取文件内容
     fn read_file(&self, path: &Path) -> Result<String> {
-        if Self::needs_confirmation(path) {
+        if Self::needs_confirmation(path) && !Self::is_tool_output(path) {
             return Err(anyhow!(
                 "路径 '{}' 不在当前工作目录下，需要用户手动同意",
                 path.display()
@@ -262,11 +274,32 @@ impl InternalTool for FileSystemTool {
         match operation {
             "read" => {
                 let content = self.read_file(path)?;
-                let result = serde_json::json!({
-                    "success": true,
-                    "content": content,
-                    "path": path_str
-                });
+                let offset = args.get("offset").and_then(|v| v.as_u64());
+                let limit = args.get("limit").and_then(|v| v.as_u64());
+                let result = if offset.is_some() || limit.is_some() {
+                    // 按行分段读取，offset 从 1 开始
+                    let total_lines = content.lines().count();
+                    let start = offset.unwrap_or(1).max(1) as usize;
+                    let lines: Vec<&str> = content
+                        .lines()
+                        .skip(start - 1)
+                        .take(limit.map_or(usize::MAX, |l| l as usize))
+                        .collect();
+                    serde_json::json!({
+                        "success": true,
+                        "content": lines.join("\n"),
+                        "path": path_str,
+                        "start_line": start,
+                        "end_line": start + lines.len().saturating_sub(1),
+                        "total_lines": total_lines
+                    })
+                } else {
+                    serde_json::json!({
+                        "success": true,
+                        "content": content,
+                        "path": path_str
+                    })
+                };
 
                 Ok(CallToolResult {
                     content: vec![Annotated::new(
@@ -390,7 +423,7 @@ impl InternalTool for FileSystemTool {
     fn get_mcp_tool(&self) -> Tool {
         Tool {
             name: "filesystem".into(),
-            description: Some("文件系统操作工具，用于读写文件和目录。默认只能读写当前工作目录下的文件，其他路径需要用户手动同意。modify 操作使用差异格式（类似 Claude 的 SEARCH/REPLACE 格式）进行精确的文件修改。".into()),
+            description: Some("文件系统操作工具，用于读写文件和目录。默认只能读写当前工作目录下的文件，其他路径需要用户手动同意，被截断的工具输出保存的完整内容可以直接读取。modify 操作使用差异格式（类似 Claude 的 SEARCH/REPLACE 格式）进行精确的文件修改。".into()),
             input_schema: serde_json::from_str(
                 r#"
 {
@@ -416,6 +449,14 @@ impl InternalTool for FileSystemTool {
         "replacement": {
             "type": "string",
             "description": "替换的新内容（仅用于 modify 操作）。将替换 search 参数匹配到的内容"
+        },
+        "offset": {
+            "type": "integer",
+            "description": "开始读取的行号，从 1 开始（仅用于 read 操作）。与 limit 一起用于分段读取大文件"
+        },
+        "limit": {
+            "type": "integer",
+            "description": "最多读取的行数（仅用于 read 操作）"
         }
     },
     "required": ["operation", "path"]
@@ -468,6 +509,18 @@ impl InternalTool for FileSystemTool {
         "replacement": {
             "type": "string",
             "description": "替换的内容（仅用于 modify 操作）"
+        },
+        "start_line": {
+            "type": "integer",
+            "description": "读取的第一行行号（仅用于分段 read 操作）"
+        },
+        "end_line": {
+            "type": "integer",
+            "description": "读取的最后一行行号（仅用于分段 read 操作）"
+        },
+        "total_lines": {
+            "type": "integer",
+            "description": "文件总行数（仅用于分段 read 操作）"
         }
     },
     "required": ["success"]
@@ -7,6 +7,8 @@ pub mod choosetool;
 pub mod filesystem;
 pub mod getbesttool;
 pub mod shell_command;
+pub mod task;
+pub mod todo;
 
 #[async_trait]
 pub trait InternalTool: Send + Sync + Debug {
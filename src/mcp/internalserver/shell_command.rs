@@ -4,6 +4,7 @@ use log::info;
 use rmcp::model::{Annotated, CallToolResult, RawContent, RawTextContent, Tool};
 use serde_json::{Map, Value};
 use std::path::Path;
+use std::process::Stdio;
 use std::time::Duration;
 use tokio::process::Command as TokioCommand;
 use tokio::time::timeout;
@@ -13,6 +14,21 @@ use crate::mcp::internalserver::InternalTool;
 #[derive(Debug)]
 pub struct ShellCommandTool;
 
+/// 命令进程组的守卫，命令没有正常结束就被释放时（超时、调用被取消）结束整个进程组
+struct ProcessGroupGuard(Option<u32>);
+
+impl Drop for ProcessGroupGuard {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(pid) = self.0 {
+            // 命令以自己的 pid 作为进程组 id 启动
+            unsafe {
+                libc::killpg(pid as libc::pid_t, libc::SIGKILL);
+            }
+        }
+    }
+}
+
 impl ShellCommandTool {
     /// 验证命令安全性，防止危险操作
     fn validate_command(command: &str) -> Result<()> {
@@ -77,8 +93,24 @@ impl ShellCommandTool {
             command.current_dir(dir);
         }
 
+        // 超时或调用被取消时结束命令进程，unix 下连同命令启动的子进程一起结束
+        command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        #[cfg(unix)]
+        command.process_group(0);
+        let child = command
+            .spawn()
+            .map_err(|e| anyhow!("命令执行失败: {}", e))?;
+        let mut guard = ProcessGroupGuard(child.id());
+
         // 设置超时并执行命令
-        let result = timeout(Duration::from_secs(timeout_sec), command.output()).await;
+        let result = timeout(Duration::from_secs(timeout_sec), child.wait_with_output()).await;
+        if result.is_ok() {
+            guard.0 = None;
+        }
 
         match result {
             Ok(output_result) => match output_result {
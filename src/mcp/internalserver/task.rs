@@ -0,0 +1,339 @@
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use futures::{StreamExt, pin_mut};
+use log::{info, warn};
+use rmcp::model::{CallToolResult, Tool};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use tokio_util::sync::CancellationToken;
+
+use crate::chat::chat_permission::ToolCallAnswer;
+use crate::chat::{ChatBuilder, EChatState, StreamedChatResponse};
+use crate::config::Config;
+use crate::mcp::internalserver::InternalTool;
+use crate::mcp::mcp_manager::ToolOutput;
+use crate::mcp::{McpManager, McpTool};
+use crate::model::param::ModelMessage;
+
+/// 把子任务交给独立上下文的子代理，只把子代理的最终报告作为工具结果返回
+#[derive(Debug)]
+pub struct TaskTool;
+
+pub const TASK_TOOL: &str = "task";
+
+/// 子代理不能使用的工具，避免递归启动子代理
+const EXCLUDED_TOOLS: [&str; 3] = [TASK_TOOL, "get_best_tool", "choose_tool"];
+
+const PROMPT: &str = "你是一个子代理，负责独立完成主代理交给你的一个子任务。只使用提供给你的工具，不能向用户提问。\
+完成后给出简洁的最终报告：列出关键发现、相关的文件路径和行号，以及没有完成的部分。主代理只能看到这份报告，看不到你的工具调用过程。";
+
+/// 工具调用预算用完后要求子代理直接给出报告
+const FINISH_PROMPT: &str = "工具调用的预算已经用完，不要再调用工具。请根据目前获得的信息给出最终报告，并说明还有哪些没有完成。";
+
+/// 子代理中需要用户确认的调用直接拒绝
+const REJECT_REASON: &str =
+    "子任务中无法向用户确认这个调用，请换一种方法，或者在报告中说明需要用户处理";
+
+#[derive(Debug, Deserialize)]
+struct TaskArgs {
+    description: String,
+    prompt: String,
+    system_prompt: Option<String>,
+    tools: Option<Vec<String>>,
+    max_tool_try: Option<usize>,
+    max_tokens: Option<u32>,
+}
+
+/// 子代理的运行结果
+struct Report {
+    text: String,
+    batches: usize,
+    tokens: u32,
+    /// 提前结束的原因
+    stopped: Option<String>,
+}
+
+impl TaskTool {
+    /// 用调用它的对话的配置启动子代理，返回整理后的报告
+    pub async fn execute(
+        config: Config,
+        args: &Value,
+        cancel: &CancellationToken,
+    ) -> Result<ToolOutput> {
+        let args: TaskArgs = serde_json::from_value(args.clone())
+            .map_err(|e| anyhow!("task 工具参数错误: {}", e))?;
+        let description = args.description.clone();
+        let report = Self::run(config, args, cancel).await?;
+        info!(
+            "子任务 {} 结束: {} 批工具调用，{} tokens",
+            description, report.batches, report.tokens
+        );
+        let mut text = if report.text.is_empty() {
+            "[子代理没有给出报告]".to_string()
+        } else {
+            report.text
+        };
+        if let Some(reason) = report.stopped {
+            text += &format!("\n\n[子任务提前结束：{}，报告可能不完整]", reason);
+        }
+        text += &format!(
+            "\n\n[子任务 {} 执行了 {} 批工具调用，消耗 {} tokens]",
+            description, report.batches, report.tokens
+        );
+        Ok(ToolOutput {
+            text,
+            parts: Vec::new(),
+        })
+    }
+
+    async fn run(mut config: Config, args: TaskArgs, cancel: &CancellationToken) -> Result<Report> {
+        let defaults = config.subagent.clone();
+        let tools = select_tools(args.tools.as_deref().unwrap_or(&defaults.tools))?;
+        let max_tokens = budget(args.max_tokens, defaults.max_tokens);
+        config.max_tool_try = budget(args.max_tool_try, defaults.max_tool_try);
+        config.prompt = Some(
+            args.system_prompt
+                .or(defaults.prompt)
+                .unwrap_or_else(|| PROMPT.to_string()),
+        );
+        info!(
+            "启动子任务 {}: 工具 {:?}，最多 {} 批工具调用，{} tokens，{} 秒",
+            args.description,
+            tools.iter().map(McpTool::name).collect::<Vec<_>>(),
+            config.max_tool_try,
+            max_tokens,
+            defaults.timeout_secs
+        );
+        let mut chat = ChatBuilder::from_config(config)
+            .without_subagent()
+            .build()
+            .map_err(|e| anyhow!("创建子代理失败: {}", e))?
+            .tools(tools);
+
+        // 超时或主代理取消时停止子代理，已经得到的结果仍然整理成报告
+        let deadline = async {
+            match defaults.timeout_secs {
+                0 => std::future::pending().await,
+                secs => tokio::time::sleep(Duration::from_secs(secs)).await,
+            }
+        };
+        pin_mut!(deadline);
+
+        chat.submit_prompt(ModelMessage::user(args.prompt)).await?;
+        let mut tokens = 0;
+        let mut batches = 0;
+        let mut stopped = None;
+        let mut error = None;
+        let mut finishing = false;
+        let mut timed_out = false;
+        loop {
+            let chat_cancel = chat.get_cancel_token();
+            {
+                let stream = chat.stream_rechat();
+                pin_mut!(stream);
+                loop {
+                    let res = tokio::select! {
+                        res = stream.next() => match res {
+                            Some(res) => res,
+                            None => break,
+                        },
+                        _ = cancel.cancelled(), if !chat_cancel.is_cancelled() => {
+                            warn!("子任务 {} 被取消", args.description);
+                            stopped = Some("主代理取消了子任务".to_string());
+                            chat_cancel.cancel();
+                            continue;
+                        }
+                        _ = &mut deadline, if !timed_out => {
+                            warn!("子任务 {} 超时", args.description);
+                            timed_out = true;
+                            stopped = Some(format!("执行时间超过 {} 秒", defaults.timeout_secs));
+                            chat_cancel.cancel();
+                            continue;
+                        }
+                    };
+                    match res {
+                        Ok(StreamedChatResponse::TokenUsage(usage)) => {
+                            tokens += usage.total_tokens;
+                            if max_tokens > 0 && tokens >= max_tokens && !chat_cancel.is_cancelled()
+                            {
+                                warn!("子任务 {} 的 token 用量达到上限", args.description);
+                                stopped = Some(format!("消耗的 token 达到上限 {}", max_tokens));
+                                chat_cancel.cancel();
+                            }
+                        }
+                        Ok(_) => {}
+                        Err(e) => {
+                            warn!("子任务 {} 出错: {}", args.description, e);
+                            error = Some(e);
+                        }
+                    }
+                }
+            }
+            if stopped.is_some() || finishing {
+                break;
+            }
+            match chat.get_state() {
+                EChatState::WaitingToolConfirm => {
+                    chat.answer_tool_calls(ToolCallAnswer::Reject(Some(REJECT_REASON.into())))?;
+                }
+                EChatState::WaitingLoopConfirm => {
+                    // 预算用完后不再提供工具，让子代理根据已有的结果给出报告
+                    stopped = chat.tool_loop_pause();
+                    batches = chat.tool_loop_info().0;
+                    chat.confirm_tool_loop(false);
+                    chat.set_tools(Vec::new());
+                    chat.submit_prompt(ModelMessage::user(FINISH_PROMPT))
+                        .await?;
+                    finishing = true;
+                }
+                EChatState::WaitingTurnConfirm => {
+                    stopped = Some("对话轮次达到上限".into());
+                    break;
+                }
+                _ => break,
+            }
+        }
+
+        let text = chat
+            .context()
+            .iter()
+            .rev()
+            .find(|msg| msg.role == "assistant" && !msg.content.trim().is_empty())
+            .map(|msg| msg.content.to_string())
+            .unwrap_or_default();
+        chat.end_session();
+        if let Some(e) = error
+            && text.is_empty()
+        {
+            return Err(e);
+        }
+        Ok(Report {
+            text,
+            batches: batches + chat.tool_loop_info().0,
+            tokens,
+            stopped,
+        })
+    }
+}
+
+/// 子代理可以使用的工具，为空时使用除 task 以外的所有工具
+fn select_tools(names: &[String]) -> Result<Vec<McpTool>> {
+    let tools: Vec<McpTool> = McpManager::global()
+        .get_all_tools()
+        .into_iter()
+        .filter(|tool| !EXCLUDED_TOOLS.contains(&tool.name().as_str()))
+        .collect();
+    if names.is_empty() {
+        return Ok(tools);
+    }
+    if let Some(name) = names
+        .iter()
+        .find(|name| !tools.iter().any(|tool| &tool.name() == *name))
+    {
+        return Err(anyhow!(
+            "子代理不能使用工具 {}，可用的工具: {}",
+            name,
+            tools
+                .iter()
+                .map(McpTool::name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+    Ok(tools
+        .into_iter()
+        .filter(|tool| names.contains(&tool.name()))
+        .collect())
+}
+
+/// 调用时指定的预算不能超过配置的上限，上限为 0 时不限制，指定为 0 时使用上限
+fn budget<T: Ord + Copy + Default>(requested: Option<T>, limit: T) -> T {
+    match requested.filter(|requested| *requested != T::default()) {
+        Some(requested) if limit == T::default() => requested,
+        Some(requested) => requested.min(limit),
+        None => limit,
+    }
+}
+
+#[async_trait]
+impl InternalTool for TaskTool {
+    async fn call(&self, _args: Map<String, Value>) -> Result<CallToolResult> {
+        // 子代理需要调用方对话的配置和模型，由 ToolClient 调用 TaskTool::execute
+        Err(anyhow!("task 工具只能在对话中使用"))
+    }
+
+    fn get_mcp_tool(&self) -> Tool {
+        Tool {
+            name: TASK_TOOL.into(),
+            description: Some(
+                "把一个独立的子任务交给子代理完成。子代理有自己的上下文，只能使用指定的工具，完成后只返回最终报告。\
+适合需要大量搜索、阅读的探索性工作，例如查找某个函数的所有调用方，可以避免中间结果占用当前的上下文。\
+子代理看不到当前的对话，prompt 中要写清楚任务的背景、目标和需要报告的内容。"
+                    .into(),
+            ),
+            input_schema: serde_json::from_str(
+                r#"
+{
+    "type": "object",
+    "properties": {
+        "description": {
+            "type": "string",
+            "description": "子任务的简短标题"
+        },
+        "prompt": {
+            "type": "string",
+            "description": "交给子代理的完整任务说明"
+        },
+        "system_prompt": {
+            "type": "string",
+            "description": "子代理的系统提示词，默认使用内置的子代理提示词"
+        },
+        "tools": {
+            "type": "array",
+            "items": {
+                "type": "string"
+            },
+            "description": "子代理可以使用的工具名，默认使用配置的工具"
+        },
+        "max_tool_try": {
+            "type": "integer",
+            "description": "子代理最多执行的工具调用批数，不能超过配置的上限",
+            "minimum": 1
+        },
+        "max_tokens": {
+            "type": "integer",
+            "description": "子代理最多消耗的 token 数，不能超过配置的上限",
+            "minimum": 1
+        }
+    },
+    "required": ["description", "prompt"]
+}
+"#,
+            )
+            .unwrap(),
+            output_schema: None,
+            annotations: None,
+        }
+    }
+
+    fn name(&self) -> String {
+        TASK_TOOL.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget() {
+        assert_eq!(budget(None, 15), 15);
+        assert_eq!(budget(Some(5), 15), 5);
+        assert_eq!(budget(Some(50), 15), 15);
+        assert_eq!(budget(Some(50_000u32), 0), 50_000);
+        assert_eq!(budget(None, 0u32), 0);
+        assert_eq!(budget(Some(0), 15), 15);
+    }
+}
@@ -0,0 +1,89 @@
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use rmcp::model::{Annotated, CallToolResult, RawContent, RawTextContent, Tool, ToolAnnotations};
+use serde_json::{Map, Value};
+
+use crate::chat::chat_todo::{self, TODO_TOOL, TodoStatus};
+use crate::mcp::internalserver::InternalTool;
+
+/// 记录当前任务的执行计划和进度
+///
+/// 列表按会话保存在对话状态中（见 ChatState::update_todos），工具本身只校验参数并返回整理后的列表
+#[derive(Debug)]
+pub struct TodoTool;
+
+#[async_trait]
+impl InternalTool for TodoTool {
+    async fn call(&self, args: Map<String, Value>) -> Result<CallToolResult> {
+        let todos = chat_todo::parse_args(&Value::Object(args).to_string())
+            .map_err(|e| anyhow!("todo 工具参数错误: {}", e))?;
+        let mut text = chat_todo::render(&todos);
+        let in_progress = todos
+            .iter()
+            .filter(|item| item.status == TodoStatus::InProgress)
+            .count();
+        if in_progress > 1 {
+            text += &format!(
+                "\n\n注意：有 {} 项任务同时处于进行中，应该一次只进行一项",
+                in_progress
+            );
+        }
+        Ok(CallToolResult {
+            content: vec![Annotated::new(
+                RawContent::Text(RawTextContent { text }),
+                None,
+            )],
+            structured_content: None,
+            is_error: None,
+        })
+    }
+
+    fn get_mcp_tool(&self) -> Tool {
+        Tool {
+            name: TODO_TOOL.into(),
+            description: Some(
+                "记录当前任务的执行计划和进度，用户可以实时看到这个列表。\
+开始需要多个步骤的任务时先列出所有步骤，之后每开始或完成一项就再次调用，更新状态。\
+每次调用都要给出完整的列表，会替换之前的列表。同一时间只有一项处于进行中，完成后立即标记为已完成。"
+                    .into(),
+            ),
+            input_schema: serde_json::from_str(
+                r#"
+{
+    "type": "object",
+    "properties": {
+        "todos": {
+            "type": "array",
+            "description": "完整的任务列表",
+            "items": {
+                "type": "object",
+                "properties": {
+                    "content": {
+                        "type": "string",
+                        "description": "任务内容"
+                    },
+                    "status": {
+                        "type": "string",
+                        "enum": ["pending", "in_progress", "done"],
+                        "description": "pending 未开始，in_progress 进行中，done 已完成"
+                    }
+                },
+                "required": ["content", "status"]
+            }
+        }
+    },
+    "required": ["todos"]
+}
+"#,
+            )
+            .unwrap(),
+            output_schema: None,
+            // 只记录进度，不修改环境，计划模式下也可以使用
+            annotations: Some(ToolAnnotations::new().read_only(true)),
+        }
+    }
+
+    fn name(&self) -> String {
+        TODO_TOOL.into()
+    }
+}
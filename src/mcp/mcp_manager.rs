@@ -1,14 +1,22 @@
 use anyhow::Result;
 use log::{error, info, warn};
+use rmcp::model::{
+    CallToolRequest, CallToolRequestParam, CallToolResult, CancelledNotification,
+    CancelledNotificationMethod, CancelledNotificationParam, ClientRequest, ServerResult,
+};
+use rmcp::service::PeerRequestOptions;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
 
-use crate::config::McpServerTransportConfig;
+use crate::config::{McpServerTransportConfig, ToolTimeoutConfig};
 use crate::mcp::McpTool;
 use crate::mcp::internalserver::InternalTool;
-use crate::mcp::mcp_server::McpService;
+use crate::mcp::mcp_server::{McpConnection, McpService};
+use crate::model::param::ContentPart;
 
 #[derive(Serialize, Deserialize)]
 pub struct ToolDesc {
@@ -19,6 +27,10 @@ pub struct ToolDesc {
 pub struct McpManager {
     services: Arc<Mutex<HashMap<String, McpService>>>,
     tools: Arc<Mutex<HashMap<String, McpTool>>>,
+    /// 工具调用超时配置
+    timeouts: Arc<Mutex<ToolTimeoutConfig>>,
+    /// 按工具名记录所属 mcp 服务配置的超时秒数
+    server_timeouts: Arc<Mutex<HashMap<String, u64>>>,
 }
 
 impl McpManager {
@@ -28,21 +40,41 @@ impl McpManager {
         INSTANCE.get_or_init(|| McpManager {
             services: Arc::new(Mutex::new(HashMap::new())),
             tools: Arc::new(Mutex::new(HashMap::new())),
+            timeouts: Arc::new(Mutex::new(ToolTimeoutConfig::default())),
+            server_timeouts: Arc::new(Mutex::new(HashMap::new())),
         })
     }
 
+    /// 设置工具调用超时配置
+    pub fn set_timeouts(&self, timeouts: ToolTimeoutConfig) {
+        *self.timeouts.lock().unwrap() = timeouts;
+    }
+
+    /// 工具调用的超时时间，为空时不限制
+    fn timeout(&self, tool_name: &str) -> Option<Duration> {
+        let timeouts = self.timeouts.lock().unwrap();
+        let secs = timeouts
+            .tools
+            .get(tool_name)
+            .or(self.server_timeouts.lock().unwrap().get(tool_name))
+            .copied()
+            .unwrap_or(timeouts.default_secs);
+        (secs > 0).then(|| Duration::from_secs(secs))
+    }
+
     /// 添加工具服务到映射
     pub async fn add_tool_service(
         &self,
         server_name: String,
         transport: McpServerTransportConfig,
+        timeout_secs: Option<u64>,
     ) -> Result<()> {
         let mut services = self
             .services
             .lock()
             .map_err(|e| anyhow::anyhow!("Failed to lock tool services: {}", e))?;
-        let service = transport.start().await?;
-        let tools = service.list_all_tools().await?;
+        let connection = transport.start().await?;
+        let tools = connection.service.list_all_tools().await?;
         let mut self_tools = self
             .tools
             .lock()
@@ -55,9 +87,15 @@ impl McpManager {
                 self_tools.contains_key(&tool.name.to_string()),
             );
             services.insert(mcptool.name(), McpService::from_config(transport.clone()));
+            if let Some(secs) = timeout_secs {
+                self.server_timeouts
+                    .lock()
+                    .unwrap()
+                    .insert(mcptool.name(), secs);
+            }
             self_tools.insert(mcptool.name(), mcptool);
         }
-        service.cancel().await?;
+        connection.close().await;
         Ok(())
     }
 
@@ -103,6 +141,19 @@ impl McpManager {
         res
     }
 
+    pub fn get_tool(&self, tool_name: &str) -> Option<McpTool> {
+        self.tools.lock().unwrap().get(tool_name).cloned()
+    }
+
+    /// 工具所属的 mcp 服务名，内置工具为 internal
+    pub fn server_name(&self, tool_name: &str) -> Option<String> {
+        self.tools
+            .lock()
+            .unwrap()
+            .get(tool_name)
+            .map(|tool| tool.server_name().to_string())
+    }
+
     pub fn get_all_tool_desc(&self) -> Vec<ToolDesc> {
         let mut res = Vec::new();
         for (_, tool) in self.tools.lock().unwrap().iter() {
@@ -114,7 +165,16 @@ impl McpManager {
         res
     }
 
-    pub async fn call_tool(&self, tool_name: &str, param: &serde_json::Value) -> Result<String> {
+    /// 调用工具，取消或超时时立即返回错误
+    ///
+    /// 外部 mcp 服务会收到取消通知，stdio 服务的子进程随连接一起结束；
+    /// 内部工具的调用直接被丢弃
+    pub async fn call_tool(
+        &self,
+        tool_name: &str,
+        param: &serde_json::Value,
+        cancel: &CancellationToken,
+    ) -> Result<ToolOutput> {
         info!("调用工具 {} {:?}", tool_name, param);
         // 工具可能存在循环调用，services 在调用前必须先释放出来
         let service;
@@ -129,6 +189,9 @@ impl McpManager {
             }
             service = t.unwrap();
         }
+        if cancel.is_cancelled() {
+            return Err(anyhow::anyhow!("工具调用已取消"));
+        }
 
         // 创建一个参数 map
         let arguments_map = if let Value::Object(obj) = param {
@@ -137,54 +200,83 @@ impl McpManager {
             serde_json::Map::new()
         };
 
+        let timeout = self.timeout(tool_name);
         let result;
         match service {
             // 外部 mcp 工具的调用
             McpService::Common(transport) => {
-                let service = transport.start().await;
-                if service.is_err() {
-                    let e = format!("启动 mcp 服务失败 {:?}", service);
-                    error!("{}", e);
-                    return Err(anyhow::anyhow!("{}", e));
-                }
-                if !self.tools.lock().unwrap().contains_key(tool_name) {
-                    error!("找不到工具配置 {}", tool_name);
-                    return Err(anyhow::anyhow!("找不到工具配置 {}", tool_name));
-                }
                 let mcptool;
                 {
-                    mcptool = self.tools.lock().unwrap().get(tool_name).unwrap().clone();
+                    let tools = self.tools.lock().unwrap();
+                    let Some(tool) = tools.get(tool_name) else {
+                        error!("找不到工具配置 {}", tool_name);
+                        return Err(anyhow::anyhow!("找不到工具配置 {}", tool_name));
+                    };
+                    mcptool = tool.clone();
                 }
-                let service = service.unwrap();
-                result = service
-                    .call_tool(rmcp::model::CallToolRequestParam {
-                        name: std::borrow::Cow::Owned(mcptool.origin_name()),
-                        arguments: Some(arguments_map),
-                    })
-                    .await?;
-                service.cancel().await?;
+                let connection = match transport.start().await {
+                    Ok(connection) => connection,
+                    Err(e) => {
+                        let e = format!("启动 mcp 服务失败 {:?}", e);
+                        error!("{}", e);
+                        return Err(anyhow::anyhow!("{}", e));
+                    }
+                };
+                let param = CallToolRequestParam {
+                    name: std::borrow::Cow::Owned(mcptool.origin_name()),
+                    arguments: Some(arguments_map),
+                };
+                result = Self::call_remote(connection, param, cancel, timeout).await?;
             }
             // 内部定义的工具
             McpService::Internal(internal_tool) => {
-                result = internal_tool.call(arguments_map).await?;
+                result = tokio::select! {
+                    res = internal_tool.call(arguments_map) => res?,
+                    _ = cancel.cancelled() => return Err(anyhow::anyhow!("工具调用已取消")),
+                    _ = expire(timeout) => return Err(timeout_error(timeout)),
+                };
             }
         }
         info!("调用工具 {} 结果 {:?}", tool_name, result);
-        let mut res = String::new();
-        if result.content.len() <= 0 {
-            return Ok("".to_string());
-        }
+        let mut res = ToolOutput::default();
         for v in result.content.iter() {
             match &v.raw {
                 rmcp::model::RawContent::Text(raw_text_content) => {
-                    res += raw_text_content.text.as_str();
-                }
-                rmcp::model::RawContent::Image(_) => {
-                    warn!("无法处理的 mcp tool 返回类型：图片");
+                    res.text += raw_text_content.text.as_str();
                 }
-                rmcp::model::RawContent::Resource(_) => {
-                    warn!("无法处理的 mcp tool 返回类型：嵌入资源");
+                rmcp::model::RawContent::Image(image) => {
+                    res.parts.push(ContentPart::Image {
+                        mime_type: image.mime_type.clone(),
+                        data: image.data.clone(),
+                    });
                 }
+                rmcp::model::RawContent::Resource(resource) => match &resource.resource {
+                    rmcp::model::ResourceContents::TextResourceContents { text, .. } => {
+                        res.text += text.as_str();
+                    }
+                    rmcp::model::ResourceContents::BlobResourceContents {
+                        uri,
+                        mime_type,
+                        blob,
+                    } => {
+                        let mime_type = mime_type
+                            .clone()
+                            .unwrap_or("application/octet-stream".into());
+                        let part = if mime_type.starts_with("image/") {
+                            ContentPart::Image {
+                                mime_type,
+                                data: blob.clone(),
+                            }
+                        } else {
+                            ContentPart::File {
+                                name: uri.clone(),
+                                mime_type,
+                                data: blob.clone(),
+                            }
+                        };
+                        res.parts.push(part);
+                    }
+                },
                 rmcp::model::RawContent::Audio(_) => {
                     warn!("无法处理的 mcp tool 返回类型：音频");
                 }
@@ -192,4 +284,70 @@ impl McpManager {
         }
         Ok(res)
     }
+
+    /// 通过新的连接调用外部 mcp 工具，取消或超时时通知服务取消请求后关闭连接
+    async fn call_remote(
+        connection: McpConnection,
+        param: CallToolRequestParam,
+        cancel: &CancellationToken,
+        timeout: Option<Duration>,
+    ) -> Result<CallToolResult> {
+        let request = ClientRequest::CallToolRequest(CallToolRequest {
+            method: Default::default(),
+            params: param,
+            extensions: Default::default(),
+        });
+        let handle = connection
+            .service
+            .send_cancellable_request(request, PeerRequestOptions::no_options())
+            .await?;
+        let (peer, request_id) = (handle.peer.clone(), handle.id.clone());
+        let reason = tokio::select! {
+            res = handle.await_response() => {
+                connection.close().await;
+                return match res? {
+                    ServerResult::CallToolResult(result) => Ok(result),
+                    _ => Err(anyhow::anyhow!("mcp 服务返回了意外的结果")),
+                };
+            }
+            _ = cancel.cancelled() => anyhow::anyhow!("工具调用已取消"),
+            _ = expire(timeout) => timeout_error(timeout),
+        };
+        warn!("{}，通知 mcp 服务取消请求", reason);
+        let notification = CancelledNotification {
+            params: CancelledNotificationParam {
+                request_id,
+                reason: Some(reason.to_string()),
+            },
+            method: CancelledNotificationMethod,
+            extensions: Default::default(),
+        };
+        if let Err(e) = peer.send_notification(notification.into()).await {
+            warn!("发送取消通知失败: {}", e);
+        }
+        connection.close().await;
+        Err(reason)
+    }
+}
+
+/// 等待超时，没有超时限制时一直等待
+async fn expire(timeout: Option<Duration>) {
+    match timeout {
+        Some(timeout) => tokio::time::sleep(timeout).await,
+        None => std::future::pending().await,
+    }
+}
+
+fn timeout_error(timeout: Option<Duration>) -> anyhow::Error {
+    anyhow::anyhow!(
+        "工具调用超时 ({} 秒)",
+        timeout.unwrap_or_default().as_secs()
+    )
+}
+
+/// 工具调用结果，图片和文件等非文本内容放在 parts 中
+#[derive(Debug, Default)]
+pub struct ToolOutput {
+    pub text: String,
+    pub parts: Vec<ContentPart>,
 }
@@ -1,5 +1,10 @@
 use std::sync::Arc;
 
+use log::warn;
+use rmcp::RoleClient;
+use rmcp::service::RunningService;
+use tokio::process::Child;
+
 use crate::config::McpServerTransportConfig;
 use crate::mcp::internalserver::InternalTool;
 
@@ -18,3 +23,25 @@ impl McpService {
         Self::Internal(tool)
     }
 }
+
+/// 与 mcp 服务的一次连接，stdio 服务的子进程随连接一起释放
+pub struct McpConnection {
+    pub service: RunningService<RoleClient, ()>,
+    process: Option<Child>,
+}
+
+impl McpConnection {
+    pub fn new(service: RunningService<RoleClient, ()>, process: Option<Child>) -> Self {
+        Self { service, process }
+    }
+
+    /// 关闭连接，stdio 服务的子进程没有退出时直接结束
+    pub async fn close(self) {
+        if let Err(e) = self.service.cancel().await {
+            warn!("关闭 mcp 连接失败: {}", e);
+        }
+        if let Some(mut process) = self.process {
+            let _ = process.start_kill();
+        }
+    }
+}
@@ -32,6 +32,15 @@ impl McpTool {
         name
     }
 
+    /// 所属的 mcp 服务名，内置工具为 internal
+    pub fn server_name(&self) -> &str {
+        if self.server_name.is_empty() {
+            "internal"
+        } else {
+            &self.server_name
+        }
+    }
+
     pub fn origin_name(&self) -> String {
         self.tool.name.to_string()
     }
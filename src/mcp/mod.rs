@@ -11,7 +11,8 @@ use std::sync::Arc;
 use crate::{
     config,
     mcp::internalserver::{
-        InternalTool, filesystem::FileSystemTool, shell_command::ShellCommandTool,
+        InternalTool, filesystem::FileSystemTool, shell_command::ShellCommandTool, task::TaskTool,
+        todo::TodoTool,
     },
 };
 
@@ -21,12 +22,17 @@ pub async fn init() {
         warn!("没有 mcp");
     }
     let mgr = mcp_manager::McpManager::global();
+    mgr.set_timeouts(config.tool_timeout.clone());
     if let Some(mcp) = config.mcp {
         info!("{:?}", mcp);
         for server in mcp.server.iter() {
             info!("{:?}", server);
             let e = mgr
-                .add_tool_service(server.0.clone(), server.1.transport.clone())
+                .add_tool_service(
+                    server.0.clone(),
+                    server.1.transport.clone(),
+                    server.1.timeout_secs,
+                )
                 .await;
             if e.is_err() {
                 log::error!("{:?}", e);
@@ -39,6 +45,8 @@ pub async fn init() {
     }
     let _ = mgr.add_internal_tool(Arc::new(FileSystemTool));
     let _ = mgr.add_internal_tool(Arc::new(ShellCommandTool));
+    let _ = mgr.add_internal_tool(Arc::new(TaskTool));
+    let _ = mgr.add_internal_tool(Arc::new(TodoTool));
 }
 
 #[allow(unused)]
@@ -0,0 +1,618 @@
+use crate::connection::common::{DirectConnection, SseConnection};
+use crate::connection::{CommonConnectionContent, TokenUsage};
+use crate::model::param::{
+    ContentPart, ModelInputParam, ModelMessage, ThinkingBlock, ToolCall, ToolCallFunction,
+    ToolChoice,
+};
+use crate::model::registry::ProviderConfig;
+use crate::model::{AgentModel, ModelStream, wire};
+use async_trait::async_trait;
+use futures::StreamExt;
+use log::{debug, error, info};
+use rmcp::model::Tool;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+
+const DEFAULT_URL: &str = "https://api.anthropic.com";
+const DEFAULT_MODEL: &str = "claude-sonnet-4-5";
+const API_VERSION: &str = "2023-06-01";
+
+/// Anthropic Messages API 模型
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnthropicModel {
+    pub api_key: String,
+    pub url: String,
+    pub model_name: String,
+    pub temperature: f64,
+    /// 思维链预算，为空时不开启扩展思考
+    pub thinking_budget: Option<u32>,
+}
+
+/// 流式输出中正在生成的内容块
+#[derive(Debug, Default)]
+struct StreamBlock {
+    r#type: String,
+    tool: Option<ToolCall>,
+    thinking: String,
+    signature: String,
+}
+
+impl AnthropicModel {
+    pub fn new(url: String, model_name: String, api_key: String) -> Self {
+        Self {
+            api_key,
+            url,
+            model_name,
+            temperature: 0.6,
+            thinking_budget: None,
+        }
+    }
+
+    /// 从提供方配置创建，未配置的地址和模型使用 Anthropic 默认值
+    pub fn from_provider_config(config: &ProviderConfig) -> Self {
+        let mut model = Self::new(
+            config.url.clone().unwrap_or(DEFAULT_URL.into()),
+            config.model.clone().unwrap_or(DEFAULT_MODEL.into()),
+            config.api_key.clone(),
+        );
+        model.thinking_budget = config.thinking_budget;
+        model
+    }
+
+    fn endpoint(&self) -> String {
+        format!("{}/v1/messages", self.url.trim_end_matches('/'))
+    }
+
+    fn headers(&self) -> Vec<(String, String)> {
+        vec![
+            ("x-api-key".to_string(), self.api_key.clone()),
+            ("anthropic-version".to_string(), API_VERSION.to_string()),
+        ]
+    }
+
+    fn build_body(&self, param: ModelInputParam, stream: bool) -> String {
+        let (system, messages) = convert_messages(&param.messages);
+        let tools = convert_tools(param.tools.as_ref());
+        let mut body = json!({
+            "model": self.model_name,
+            "messages": messages,
+            "stream": stream,
+        });
+        if !system.is_empty() {
+            body["system"] = json!(system);
+        }
+        let sampling = param.sampling;
+        // 接口要求必须填写 max_tokens，未指定时使用模型最大输出
+        let max_output = self.capabilities().max_output;
+        let max_tokens = sampling
+            .max_tokens
+            .map_or(max_output, |m| m.min(max_output));
+        body["max_tokens"] = json!(max_tokens);
+        let thinking = self
+            .thinking_budget
+            .and_then(|budget| thinking_budget(budget, max_tokens));
+        if !tools.is_empty() {
+            body["tools"] = json!(tools);
+            // 开启思考时只允许 auto 和 none
+            if let Some(choice) = &sampling.tool_choice
+                && (thinking.is_none() || matches!(choice, ToolChoice::Auto | ToolChoice::None))
+            {
+                body["tool_choice"] = convert_tool_choice(choice);
+            }
+        }
+        if let Some(stop) = &sampling.stop {
+            body["stop_sequences"] = json!(stop);
+        }
+        match thinking {
+            // 开启思考时接口不允许修改 temperature 和 top_p
+            Some(budget) => {
+                body["thinking"] = json!({
+                    "type": "enabled",
+                    "budget_tokens": budget,
+                });
+            }
+            None => {
+                body["temperature"] = json!(sampling.temperature.unwrap_or(self.temperature));
+                if let Some(top_p) = sampling.top_p {
+                    body["top_p"] = json!(top_p);
+                }
+            }
+        }
+        body.to_string()
+    }
+}
+
+/// 接口允许的最小思考预算
+const MIN_THINKING_BUDGET: u32 = 1024;
+
+/// 实际使用的思考预算：不低于接口的最小值，并给正文留出至少 1024 个 token，
+/// max_tokens 太小放不下时返回 None，不开启思考
+fn thinking_budget(budget: u32, max_tokens: u32) -> Option<u32> {
+    let budget = budget
+        .max(MIN_THINKING_BUDGET)
+        .min(max_tokens.saturating_sub(1024));
+    (budget >= MIN_THINKING_BUDGET).then_some(budget)
+}
+
+/// 转换工具定义，Anthropic 使用 input_schema 描述参数
+fn convert_tools(tools: Option<&Vec<Tool>>) -> Vec<Value> {
+    let mut res = Vec::new();
+    if let Some(ts) = tools {
+        for tool in ts.iter() {
+            let mut schema = (*tool.input_schema).clone();
+            schema.insert("type".into(), "object".into());
+            res.push(json!({
+                "name": tool.name,
+                "description": tool.description.as_ref().map(|cow| cow.to_string()).unwrap_or_default(),
+                "input_schema": schema,
+            }));
+        }
+    }
+    res
+}
+
+/// 转换工具选择策略，Anthropic 用 any 表示必须调用工具
+fn convert_tool_choice(choice: &ToolChoice) -> Value {
+    match choice {
+        ToolChoice::Auto => json!({"type": "auto"}),
+        ToolChoice::None => json!({"type": "none"}),
+        ToolChoice::Required => json!({"type": "any"}),
+        ToolChoice::Function(name) => json!({"type": "tool", "name": name}),
+    }
+}
+
+/// 将上下文转换为 Anthropic 消息格式，返回 (system, messages)
+///
+/// 系统消息合并到顶层 system 字段，工具结果作为 user 消息的 tool_result 块，
+/// 工具结果与之后的用户消息也要合并，因为接口要求 user/assistant 交替出现
+fn convert_messages(messages: &[ModelMessage]) -> (String, Vec<Value>) {
+    let mut system: Vec<String> = Vec::new();
+    let mut res: Vec<Value> = Vec::new();
+    for msg in wire::normalize(messages).iter() {
+        let (role, blocks) = match msg.role.as_ref() {
+            "system" => {
+                system.push(msg.content.to_string());
+                continue;
+            }
+            "user" => ("user", convert_content(&msg.content, &msg.parts)),
+            "assistant" => {
+                let mut blocks = Vec::new();
+                // 思维链块按原顺序原样回传，没有签名的思维链不会被保存
+                for block in msg.thinking_blocks.iter() {
+                    blocks.push(json!(block));
+                }
+                if !msg.content.is_empty() {
+                    blocks.push(json!({"type": "text", "text": msg.content}));
+                }
+                for tool in msg.tool_calls.iter().flatten() {
+                    let input = serde_json::from_str::<Value>(&tool.function.arguments)
+                        .ok()
+                        .filter(Value::is_object)
+                        .unwrap_or(json!({}));
+                    blocks.push(json!({
+                        "type": "tool_use",
+                        "id": tool.id,
+                        "name": tool.function.name,
+                        "input": input,
+                    }));
+                }
+                ("assistant", blocks)
+            }
+            "tool" => (
+                "user",
+                vec![json!({
+                    "type": "tool_result",
+                    "tool_use_id": msg.tool_call_id,
+                    "content": convert_content(&msg.content, &msg.parts),
+                })],
+            ),
+            _ => continue,
+        };
+        if blocks.is_empty() {
+            continue;
+        }
+        if let Some(last) = res.last_mut()
+            && last["role"] == role
+        {
+            if let Some(content) = last["content"].as_array_mut() {
+                content.extend(blocks);
+            }
+            continue;
+        }
+        res.push(json!({"role": role, "content": blocks}));
+    }
+    (system.join("\n\n"), res)
+}
+
+/// 转换文本和附件为内容块，PDF 作为 document 发送，其它无法识别的文件只发送说明
+fn convert_content(text: &str, parts: &[ContentPart]) -> Vec<Value> {
+    let mut blocks = Vec::new();
+    if !text.is_empty() {
+        blocks.push(json!({"type": "text", "text": text}));
+    }
+    for part in parts.iter() {
+        let block = match part {
+            ContentPart::ImageUrl { url } => match wire::split_data_url(url) {
+                Some((mime_type, data)) => base64_block("image", mime_type, data),
+                None => json!({"type": "image", "source": {"type": "url", "url": url}}),
+            },
+            ContentPart::Image { mime_type, data } => base64_block("image", mime_type, data),
+            ContentPart::File {
+                mime_type, data, ..
+            } if mime_type == "application/pdf" => base64_block("document", mime_type, data),
+            ContentPart::File { name, data, .. } => {
+                let text = wire::file_text(name, data).unwrap_or(part.placeholder());
+                json!({"type": "text", "text": text})
+            }
+        };
+        blocks.push(block);
+    }
+    blocks
+}
+
+fn base64_block(r#type: &str, mime_type: &str, data: &str) -> Value {
+    json!({
+        "type": r#type,
+        "source": {"type": "base64", "media_type": mime_type, "data": data},
+    })
+}
+
+/// 解析 usage 字段，缓存命中的 token 也计入输入
+fn input_tokens(usage: &Value) -> u32 {
+    [
+        "input_tokens",
+        "cache_creation_input_tokens",
+        "cache_read_input_tokens",
+    ]
+    .iter()
+    .filter_map(|key| usage.get(key).and_then(Value::as_u64))
+    .sum::<u64>() as u32
+}
+
+fn output_tokens(usage: &Value) -> u32 {
+    usage
+        .get("output_tokens")
+        .and_then(Value::as_u64)
+        .unwrap_or_default() as u32
+}
+
+fn token_usage(prompt_tokens: u32, completion_tokens: u32) -> TokenUsage {
+    TokenUsage {
+        prompt_tokens,
+        completion_tokens,
+        total_tokens: prompt_tokens + completion_tokens,
+    }
+}
+
+fn error_message(json: &Value) -> String {
+    json.get("error")
+        .and_then(|e| e.get("message"))
+        .and_then(Value::as_str)
+        .map(|s| s.to_string())
+        .unwrap_or(json.to_string())
+}
+
+/// 解析非流式响应
+fn parse_response(text: &str) -> Result<Vec<CommonConnectionContent>, anyhow::Error> {
+    let json: Value = serde_json::from_str(text)?;
+    if json.get("type").and_then(Value::as_str) == Some("error") {
+        return Err(anyhow::anyhow!(error_message(&json)));
+    }
+    let Some(blocks) = json.get("content").and_then(Value::as_array) else {
+        error!("API 请求失败");
+        return Err(anyhow::anyhow!(text.to_string()));
+    };
+
+    let mut res = Vec::new();
+    let mut content = String::new();
+    let mut tool_index = 0;
+    for block in blocks.iter() {
+        match block.get("type").and_then(Value::as_str) {
+            Some("text") => {
+                content += block
+                    .get("text")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+            }
+            Some("thinking") => {
+                let thinking = block.get("thinking").and_then(Value::as_str);
+                res.push(CommonConnectionContent::Reasoning(
+                    thinking.unwrap_or_default().to_string(),
+                ));
+                if let Some(signature) = block.get("signature").and_then(Value::as_str) {
+                    res.push(CommonConnectionContent::ThinkingBlock(
+                        ThinkingBlock::Thinking {
+                            thinking: thinking.unwrap_or_default().to_string(),
+                            signature: signature.to_string(),
+                        },
+                    ));
+                }
+            }
+            Some("redacted_thinking") => {
+                if let Some(data) = block.get("data").and_then(Value::as_str) {
+                    res.push(CommonConnectionContent::ThinkingBlock(
+                        ThinkingBlock::RedactedThinking {
+                            data: data.to_string(),
+                        },
+                    ));
+                }
+            }
+            Some("tool_use") => {
+                res.push(CommonConnectionContent::ToolCall(ToolCall {
+                    index: tool_index,
+                    id: block
+                        .get("id")
+                        .and_then(Value::as_str)
+                        .unwrap_or_default()
+                        .to_string(),
+                    r#type: "function".into(),
+                    function: ToolCallFunction {
+                        name: block
+                            .get("name")
+                            .and_then(Value::as_str)
+                            .unwrap_or_default()
+                            .to_string(),
+                        arguments: block
+                            .get("input")
+                            .map(|v| v.to_string())
+                            .unwrap_or("{}".into()),
+                    },
+                }));
+                tool_index += 1;
+            }
+            _ => {}
+        }
+    }
+    res.push(CommonConnectionContent::Content(content));
+    if let Some(reason) = json.get("stop_reason").and_then(Value::as_str) {
+        res.push(CommonConnectionContent::FinishReason(reason.to_string()));
+    }
+    if let Some(usage) = json.get("usage") {
+        res.push(CommonConnectionContent::TokenUsage(token_usage(
+            input_tokens(usage),
+            output_tokens(usage),
+        )));
+    }
+    Ok(res)
+}
+
+#[async_trait]
+impl AgentModel for AnthropicModel {
+    fn get_token_limit(&self) -> u32 {
+        200000
+    }
+
+    fn model_name(&self) -> String {
+        self.model_name.clone()
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>, anyhow::Error> {
+        let text = DirectConnection::get(
+            format!("{}/v1/models", self.url.trim_end_matches('/')),
+            self.headers(),
+        )
+        .await?;
+        let json: Value = serde_json::from_str(&text)?;
+        let Some(data) = json.get("data").and_then(Value::as_array) else {
+            return Err(anyhow::anyhow!("模型列表格式错误: {}", text));
+        };
+        Ok(data
+            .iter()
+            .filter_map(|m| m.get("id").and_then(Value::as_str))
+            .map(|id| id.to_string())
+            .collect())
+    }
+
+    async fn chat(
+        &self,
+        param: ModelInputParam,
+    ) -> Result<Vec<CommonConnectionContent>, anyhow::Error> {
+        let body = self.build_body(param, false);
+        debug!("{:?}", body);
+        let text = DirectConnection::post(self.endpoint(), self.headers(), body).await?;
+        parse_response(&text)
+    }
+
+    async fn stream_chat(&self, param: ModelInputParam) -> ModelStream {
+        let body = self.build_body(param, true);
+        debug!("{:?}", body);
+        let events = SseConnection::events(self.endpoint(), self.headers(), body);
+
+        Box::pin(async_stream::stream! {
+            futures::pin_mut!(events);
+            let mut blocks: HashMap<u64, StreamBlock> = HashMap::new();
+            let mut tool_index = 0;
+            let mut prompt_tokens = 0;
+            let mut completion_tokens = 0;
+
+            info!("开始流式处理");
+            while let Some(event) = events.next().await {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(e) => {
+                        // 不重连，避免重复发送请求
+                        yield Err(e);
+                        return;
+                    }
+                };
+                let json = match serde_json::from_str::<Value>(&event.data) {
+                    Ok(json) => json,
+                    Err(e) => {
+                        error!("JSON 解析错误: {:?}, 数据: {}", e, event.data);
+                        continue;
+                    }
+                };
+                let index = json.get("index").and_then(Value::as_u64).unwrap_or_default();
+
+                match json.get("type").and_then(Value::as_str).unwrap_or_default() {
+                    "message_start" => {
+                        if let Some(usage) = json.get("message").and_then(|m| m.get("usage")) {
+                            prompt_tokens = input_tokens(usage);
+                            completion_tokens = output_tokens(usage);
+                        }
+                    }
+                    "content_block_start" => {
+                        let Some(block) = json.get("content_block") else {
+                            continue;
+                        };
+                        let r#type = block.get("type").and_then(Value::as_str).unwrap_or_default();
+                        let mut current = StreamBlock {
+                            r#type: r#type.to_string(),
+                            ..Default::default()
+                        };
+                        match r#type {
+                            "text" => {
+                                let text = block.get("text").and_then(Value::as_str).unwrap_or_default();
+                                if !text.is_empty() {
+                                    yield Ok(CommonConnectionContent::Content(text.to_string()));
+                                }
+                            }
+                            "tool_use" => {
+                                current.tool = Some(ToolCall {
+                                    index: tool_index,
+                                    id: block.get("id").and_then(Value::as_str).unwrap_or_default().to_string(),
+                                    r#type: "function".into(),
+                                    function: ToolCallFunction {
+                                        name: block.get("name").and_then(Value::as_str).unwrap_or_default().to_string(),
+                                        arguments: String::new(),
+                                    },
+                                });
+                                tool_index += 1;
+                            }
+                            "redacted_thinking" => {
+                                let data = block.get("data").and_then(Value::as_str).unwrap_or_default();
+                                yield Ok(CommonConnectionContent::ThinkingBlock(ThinkingBlock::RedactedThinking {
+                                    data: data.to_string(),
+                                }));
+                            }
+                            _ => {}
+                        }
+                        blocks.insert(index, current);
+                    }
+                    "content_block_delta" => {
+                        let Some(delta) = json.get("delta") else {
+                            continue;
+                        };
+                        match delta.get("type").and_then(Value::as_str).unwrap_or_default() {
+                            "text_delta" => {
+                                let text = delta.get("text").and_then(Value::as_str).unwrap_or_default();
+                                yield Ok(CommonConnectionContent::Content(text.to_string()));
+                            }
+                            "thinking_delta" => {
+                                let thinking = delta.get("thinking").and_then(Value::as_str).unwrap_or_default();
+                                if let Some(block) = blocks.get_mut(&index) {
+                                    block.thinking += thinking;
+                                }
+                                yield Ok(CommonConnectionContent::Reasoning(thinking.to_string()));
+                            }
+                            "signature_delta" => {
+                                if let Some(block) = blocks.get_mut(&index) {
+                                    block.signature += delta.get("signature").and_then(Value::as_str).unwrap_or_default();
+                                }
+                            }
+                            "input_json_delta" => {
+                                if let Some(tool) = blocks.get_mut(&index).and_then(|b| b.tool.as_mut()) {
+                                    tool.function.arguments += delta.get("partial_json").and_then(Value::as_str).unwrap_or_default();
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                    "content_block_stop" => {
+                        let Some(block) = blocks.remove(&index) else {
+                            continue;
+                        };
+                        if let Some(mut tool) = block.tool {
+                            // 无参数的工具调用不会有 input_json_delta
+                            if tool.function.arguments.is_empty() {
+                                tool.function.arguments = "{}".into();
+                            }
+                            yield Ok(CommonConnectionContent::ToolCall(tool));
+                        } else if block.r#type == "thinking" && !block.signature.is_empty() {
+                            yield Ok(CommonConnectionContent::ThinkingBlock(ThinkingBlock::Thinking {
+                                thinking: block.thinking,
+                                signature: block.signature,
+                            }));
+                        }
+                    }
+                    "message_delta" => {
+                        if let Some(reason) = json.get("delta").and_then(|d| d.get("stop_reason")).and_then(Value::as_str) {
+                            yield Ok(CommonConnectionContent::FinishReason(reason.to_string()));
+                        }
+                        if let Some(usage) = json.get("usage") {
+                            completion_tokens = output_tokens(usage);
+                        }
+                    }
+                    "message_stop" => {
+                        yield Ok(CommonConnectionContent::TokenUsage(token_usage(prompt_tokens, completion_tokens)));
+                        return;
+                    }
+                    "error" => {
+                        yield Err(anyhow::anyhow!(error_message(&json)));
+                        return;
+                    }
+                    _ => {}
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thinking_budget() {
+        assert_eq!(thinking_budget(8000, 32000), Some(8000));
+        // 不超过 max_tokens 减去给正文留的部分
+        assert_eq!(thinking_budget(8000, 4096), Some(3072));
+        // 低于接口最小值时提高到最小值
+        assert_eq!(thinking_budget(0, 32000), Some(1024));
+        // max_tokens 太小时不开启思考
+        assert_eq!(thinking_budget(8000, 1500), None);
+    }
+
+    #[test]
+    fn test_convert_messages() {
+        let tool = ToolCall {
+            index: 0,
+            id: "toolu_1".into(),
+            r#type: "function".into(),
+            function: ToolCallFunction {
+                name: "read_file".into(),
+                arguments: "{\"path\":\"a.rs\"}".into(),
+            },
+        };
+        let mut assistant = ModelMessage::assistant("", "先读文件", vec![tool.clone()]);
+        assistant.thinking_blocks = vec![
+            ThinkingBlock::Thinking {
+                thinking: "想一想".into(),
+                signature: "sig".into(),
+            },
+            ThinkingBlock::RedactedThinking { data: "enc".into() },
+        ];
+        let messages = vec![
+            ModelMessage::system("系统提示"),
+            ModelMessage::user("读一下 a.rs"),
+            assistant,
+            ModelMessage::tool("fn main() {}", tool.clone()),
+            ModelMessage::tool("fn main() {}", tool),
+            ModelMessage::info("忽略"),
+        ];
+        let (system, res) = convert_messages(&messages);
+        assert_eq!(system, "系统提示");
+        assert_eq!(res.len(), 3);
+        assert_eq!(res[1]["content"][0]["type"], "thinking");
+        assert_eq!(res[1]["content"][0]["thinking"], "想一想");
+        assert_eq!(res[1]["content"][0]["signature"], "sig");
+        assert_eq!(res[1]["content"][1]["type"], "redacted_thinking");
+        assert_eq!(res[1]["content"][1]["data"], "enc");
+        assert_eq!(res[1]["content"][2]["input"]["path"], "a.rs");
+        // 连续的工具结果合并到同一条 user 消息
+        assert_eq!(res[2]["role"], "user");
+        assert_eq!(res[2]["content"].as_array().unwrap().len(), 2);
+        assert_eq!(res[2]["content"][0]["tool_use_id"], "toolu_1");
+    }
+}
@@ -0,0 +1,327 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{OnceLock, RwLock};
+
+/// 未收录模型的默认最大输出 token 数
+pub const DEFAULT_MAX_OUTPUT: u32 = 8192;
+
+/// 模型价格，单位为美元每百万 token
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct ModelPricing {
+    pub input: f64,
+    pub output: f64,
+}
+
+/// 模型能力描述
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct ModelCapabilities {
+    /// 上下文窗口大小
+    pub context_window: u32,
+    /// 单次回复最大输出 token 数
+    pub max_output: u32,
+    /// 是否支持工具调用
+    pub tools: bool,
+    /// 是否输出思维链
+    pub reasoning: bool,
+    /// 是否支持图片输入
+    pub vision: bool,
+    /// 是否支持 json_schema 回复格式，不支持时通过工具调用模拟
+    #[serde(default)]
+    pub json_schema: bool,
+    pub pricing: Option<ModelPricing>,
+}
+
+impl ModelCapabilities {
+    const fn new(context_window: u32, max_output: u32) -> Self {
+        Self {
+            context_window,
+            max_output,
+            tools: true,
+            reasoning: false,
+            vision: false,
+            json_schema: false,
+            pricing: None,
+        }
+    }
+
+    const fn reasoning(mut self) -> Self {
+        self.reasoning = true;
+        self
+    }
+
+    const fn vision(mut self) -> Self {
+        self.vision = true;
+        self
+    }
+
+    const fn json_schema(mut self) -> Self {
+        self.json_schema = true;
+        self
+    }
+
+    const fn no_tools(mut self) -> Self {
+        self.tools = false;
+        self
+    }
+
+    const fn pricing(mut self, input: f64, output: f64) -> Self {
+        self.pricing = Some(ModelPricing { input, output });
+        self
+    }
+
+    /// 简短描述，用于界面展示
+    pub fn summary(&self) -> String {
+        let mut features = Vec::new();
+        if self.tools {
+            features.push("工具");
+        }
+        if self.reasoning {
+            features.push("思考");
+        }
+        if self.vision {
+            features.push("图片");
+        }
+        if self.json_schema {
+            features.push("结构化输出");
+        }
+        let mut res = format!(
+            "上下文 {} tokens，最大输出 {} tokens，支持: {}",
+            self.context_window,
+            self.max_output,
+            if features.is_empty() {
+                "无".to_string()
+            } else {
+                features.join("、")
+            }
+        );
+        if let Some(pricing) = &self.pricing {
+            res += &format!(
+                "，价格: 输入 ${}/M 输出 ${}/M",
+                pricing.input, pricing.output
+            );
+        }
+        res
+    }
+}
+
+/// 用户对模型能力的覆盖配置，只覆盖填写了的字段
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct CapabilityOverride {
+    #[serde(default)]
+    pub context_window: Option<u32>,
+    #[serde(default)]
+    pub max_output: Option<u32>,
+    #[serde(default)]
+    pub tools: Option<bool>,
+    #[serde(default)]
+    pub reasoning: Option<bool>,
+    #[serde(default)]
+    pub vision: Option<bool>,
+    #[serde(default)]
+    pub json_schema: Option<bool>,
+    #[serde(default)]
+    pub pricing: Option<ModelPricing>,
+}
+
+impl CapabilityOverride {
+    fn apply(&self, caps: &mut ModelCapabilities) {
+        if let Some(v) = self.context_window {
+            caps.context_window = v;
+        }
+        if let Some(v) = self.max_output {
+            caps.max_output = v;
+        }
+        if let Some(v) = self.tools {
+            caps.tools = v;
+        }
+        if let Some(v) = self.reasoning {
+            caps.reasoning = v;
+        }
+        if let Some(v) = self.vision {
+            caps.vision = v;
+        }
+        if let Some(v) = self.json_schema {
+            caps.json_schema = v;
+        }
+        if self.pricing.is_some() {
+            caps.pricing = self.pricing;
+        }
+    }
+}
+
+/// 内置模型能力表，按模型名前缀匹配，带日期或量化后缀的模型名也能命中
+const BUILTIN_MODELS: &[(&str, ModelCapabilities)] = &[
+    // DeepSeek
+    (
+        "deepseek-chat",
+        ModelCapabilities::new(128000, 8192).pricing(0.28, 0.42),
+    ),
+    (
+        "deepseek-reasoner",
+        ModelCapabilities::new(128000, 64000)
+            .reasoning()
+            .pricing(0.28, 0.42),
+    ),
+    // Anthropic
+    (
+        "claude-opus-4",
+        ModelCapabilities::new(200000, 32000)
+            .reasoning()
+            .vision()
+            .pricing(15.0, 75.0),
+    ),
+    (
+        "claude-opus-4-5",
+        ModelCapabilities::new(200000, 64000)
+            .reasoning()
+            .vision()
+            .pricing(5.0, 25.0),
+    ),
+    (
+        "claude-sonnet-4",
+        ModelCapabilities::new(200000, 64000)
+            .reasoning()
+            .vision()
+            .pricing(3.0, 15.0),
+    ),
+    (
+        "claude-haiku-4-5",
+        ModelCapabilities::new(200000, 64000)
+            .reasoning()
+            .vision()
+            .pricing(1.0, 5.0),
+    ),
+    (
+        "claude-3-5-haiku",
+        ModelCapabilities::new(200000, 8192)
+            .vision()
+            .pricing(0.8, 4.0),
+    ),
+    // OpenAI
+    (
+        "gpt-4o",
+        ModelCapabilities::new(128000, 16384)
+            .vision()
+            .json_schema()
+            .pricing(2.5, 10.0),
+    ),
+    (
+        "gpt-4o-mini",
+        ModelCapabilities::new(128000, 16384)
+            .vision()
+            .json_schema()
+            .pricing(0.15, 0.6),
+    ),
+    (
+        "gpt-4.1",
+        ModelCapabilities::new(1047576, 32768)
+            .vision()
+            .json_schema()
+            .pricing(2.0, 8.0),
+    ),
+    (
+        "o3",
+        ModelCapabilities::new(200000, 100000)
+            .reasoning()
+            .vision()
+            .json_schema()
+            .pricing(2.0, 8.0),
+    ),
+    (
+        "o4-mini",
+        ModelCapabilities::new(200000, 100000)
+            .reasoning()
+            .vision()
+            .json_schema()
+            .pricing(1.1, 4.4),
+    ),
+    // 本地模型，使用 Ollama 命名
+    ("qwen3", ModelCapabilities::new(40960, 8192).reasoning()),
+    ("qwen2.5-coder", ModelCapabilities::new(32768, 8192)),
+    ("llama3.1", ModelCapabilities::new(131072, 8192)),
+    ("llama3.2", ModelCapabilities::new(131072, 8192)),
+    (
+        "gemma3",
+        ModelCapabilities::new(131072, 8192).vision().no_tools(),
+    ),
+    (
+        "deepseek-r1",
+        ModelCapabilities::new(131072, 8192).reasoning().no_tools(),
+    ),
+];
+
+/// 找到与模型名匹配的最长前缀
+fn longest_prefix<'a, T>(model: &str, entries: impl Iterator<Item = (&'a str, T)>) -> Option<T> {
+    entries
+        .filter(|(name, _)| model.starts_with(name))
+        .max_by_key(|(name, _)| name.len())
+        .map(|(_, v)| v)
+}
+
+/// 模型能力目录
+///
+/// 内置常用模型的能力信息，用户可以在配置的 model_capabilities 中覆盖或补充
+pub struct ModelCatalog {
+    overrides: RwLock<HashMap<String, CapabilityOverride>>,
+}
+
+impl ModelCatalog {
+    /// 获取全局单例实例
+    pub fn global() -> &'static ModelCatalog {
+        static INSTANCE: OnceLock<ModelCatalog> = OnceLock::new();
+        INSTANCE.get_or_init(|| ModelCatalog {
+            overrides: RwLock::new(HashMap::new()),
+        })
+    }
+
+    /// 设置用户覆盖配置，会替换之前的配置
+    pub fn set_overrides(&self, overrides: HashMap<String, CapabilityOverride>) {
+        *self.overrides.write().unwrap() = overrides;
+    }
+
+    /// 查询模型能力，未收录的模型使用提供方给出的上下文大小
+    pub fn lookup(&self, model: &str, default_context: u32) -> ModelCapabilities {
+        let mut caps = longest_prefix(model, BUILTIN_MODELS.iter().map(|(n, c)| (*n, *c)))
+            .unwrap_or(ModelCapabilities::new(default_context, DEFAULT_MAX_OUTPUT));
+        let overrides = self.overrides.read().unwrap();
+        if let Some(o) = longest_prefix(model, overrides.iter().map(|(n, o)| (n.as_str(), o))) {
+            o.apply(&mut caps);
+        }
+        caps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        let catalog = ModelCatalog {
+            overrides: RwLock::new(HashMap::new()),
+        };
+        // 带日期后缀的模型名匹配最长前缀
+        let caps = catalog.lookup("claude-sonnet-4-5-20250929", 1000);
+        assert_eq!(caps.context_window, 200000);
+        assert_eq!(
+            catalog.lookup("gpt-4o-mini", 1000).pricing.unwrap().input,
+            0.15
+        );
+        // 未收录的模型使用提供方默认值
+        let caps = catalog.lookup("my-model", 4096);
+        assert_eq!(caps.context_window, 4096);
+        assert_eq!(caps.max_output, DEFAULT_MAX_OUTPUT);
+
+        catalog.set_overrides(HashMap::from([(
+            "my-model".to_string(),
+            CapabilityOverride {
+                context_window: Some(8192),
+                vision: Some(true),
+                ..Default::default()
+            },
+        )]));
+        let caps = catalog.lookup("my-model:q4", 4096);
+        assert_eq!(caps.context_window, 8192);
+        assert!(caps.vision && caps.tools);
+    }
+}
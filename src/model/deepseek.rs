@@ -1,12 +1,12 @@
 use crate::connection::{self, CommonConnectionContent};
-use crate::model::AgentModel;
-use crate::model::param::ModelInputParam;
-use futures::Stream;
+use crate::model::param::{ContentPart, ModelInputParam, ModelMessage, ToolChoice, data_url};
+use crate::model::registry::ProviderConfig;
+use crate::model::{AgentModel, ModelStream, wire};
+use async_trait::async_trait;
 use log::debug;
-use reqwest::{Client, header};
-use rmcp::model::JsonObject;
+use rmcp::model::{JsonObject, Tool};
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{Value, json};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DeepseekFunctionItem {
@@ -21,12 +21,18 @@ pub struct DeepseekFunctionInfo {
     pub parameters: JsonObject,
 }
 
+const DEFAULT_URL: &str = "https://api.deepseek.com";
+const DEFAULT_MODEL: &str = "deepseek-chat";
+const LOCAL_URL: &str = "http://127.0.0.1:8080/v1";
+/// llama.cpp 等单模型服务会忽略模型名称
+const LOCAL_MODEL: &str = "default";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DeepseekModel {
     pub api_key: String,
     pub url: String,
     pub model_name: String,
-    pub temperature: String,
+    pub temperature: f64,
 }
 
 impl DeepseekModel {
@@ -35,139 +41,297 @@ impl DeepseekModel {
             api_key,
             url,
             model_name,
-            temperature: "0.6".into(),
+            temperature: 0.6,
         }
     }
 
+    /// 从提供方配置创建，未配置的地址和模型使用 deepseek 默认值
+    pub fn from_provider_config(config: &ProviderConfig) -> Self {
+        Self::new(
+            config.url.clone().unwrap_or(DEFAULT_URL.into()),
+            config.model.clone().unwrap_or(DEFAULT_MODEL.into()),
+            config.api_key.clone(),
+        )
+    }
+
+    /// 本地 OpenAI 兼容服务（llama.cpp server、LM Studio 等），未配置时使用本机默认端口
+    pub fn from_local_config(config: &ProviderConfig) -> Self {
+        Self::new(
+            config.url.clone().unwrap_or(LOCAL_URL.into()),
+            config.model.clone().unwrap_or(LOCAL_MODEL.into()),
+            config.api_key.clone(),
+        )
+    }
+
     fn get_api_key(&self) -> String {
         format!("Bearer {}", self.api_key)
     }
 
-    #[allow(unused)]
-    async fn get_models(&self) -> Result<Vec<String>, String> {
-        let client = Client::new();
-        let response = client
-            .get(format!("{}/models", self.url))
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", self.get_api_key())
-            .body(serde_json::to_string(&serde_json::json!({})).unwrap())
-            .send()
-            .await
-            .map_err(|e| e.to_string())?;
-
-        let succ = response.status().is_success();
-        let text = response.text().await;
-        if succ {
-            let text = text.map_err(|e| e.to_string())?;
-            let js: serde_json::Value = serde_json::from_str(&text).map_err(|e| e.to_string())?;
-
-            if let Some(message) = js.get("data") {
-                let mut res: Vec<String> = Vec::new();
-                for val in message.as_array().unwrap().iter() {
-                    if let Some(id) = val.get("id") {
-                        res.push(serde_json::from_value(id.clone()).unwrap());
+    /// 本地服务通常不需要密钥，未配置时不发送认证头
+    fn auth_headers(&self) -> Vec<(String, String)> {
+        if self.api_key.is_empty() {
+            vec![]
+        } else {
+            vec![("Authorization".to_string(), self.get_api_key())]
+        }
+    }
+
+    fn build_body(&self, param: ModelInputParam, stream: bool) -> String {
+        let tools = convert_tools(param.tools.as_ref());
+        let sampling = param.sampling;
+        let mut body = json!({
+            "model": self.model_name,
+            "messages": convert_messages(&param.messages),
+            "stream": stream,
+            "temperature": sampling.temperature.unwrap_or(self.temperature),
+        });
+        if !tools.is_empty() {
+            body["tools"] = json!(tools);
+            if let Some(choice) = &sampling.tool_choice {
+                body["tool_choice"] = convert_tool_choice(choice);
+            }
+        }
+        if let Some(top_p) = sampling.top_p {
+            body["top_p"] = json!(top_p);
+        }
+        if let Some(max_tokens) = sampling.max_tokens {
+            body["max_tokens"] = json!(max_tokens);
+        }
+        if let Some(stop) = &sampling.stop {
+            body["stop"] = json!(stop);
+        }
+        if let Some(seed) = sampling.seed {
+            body["seed"] = json!(seed);
+        }
+        if let Some(penalty) = sampling.presence_penalty {
+            body["presence_penalty"] = json!(penalty);
+        }
+        if let Some(penalty) = sampling.frequency_penalty {
+            body["frequency_penalty"] = json!(penalty);
+        }
+        if let Some(format) = &sampling.response_format {
+            body["response_format"] = json!(format);
+        }
+        body.to_string()
+    }
+}
+
+/// 转换工具定义，这里补充两个字段：required type，已有 required 时保留
+fn convert_tools(tools: Option<&Vec<Tool>>) -> Vec<DeepseekFunctionItem> {
+    let mut res = Vec::new();
+    if let Some(ts) = tools {
+        for tool in ts.iter() {
+            let mut p = (*tool.input_schema).clone();
+            p.entry("required").or_insert(json!([]));
+            p.insert("type".into(), "object".into());
+            res.push(DeepseekFunctionItem {
+                r#type: "function".into(),
+                function: DeepseekFunctionInfo {
+                    name: tool.name.clone().into(),
+                    description: tool
+                        .description
+                        .as_ref()
+                        .map(|cow| cow.to_string())
+                        .unwrap_or_default(),
+                    parameters: p,
+                },
+            });
+        }
+    }
+    res
+}
+
+/// 转换为 OpenAI 格式的消息
+///
+/// 思维链不回传，deepseek-reasoner 收到 reasoning_content 会直接报错。
+/// 工具结果只能是文本，其中的图片合并到最后一个工具结果之后的一条 user 消息里，
+/// 同一批工具结果之间不能插入其他消息
+fn convert_messages(messages: &[ModelMessage]) -> Vec<Value> {
+    let mut res = Vec::new();
+    let mut attachments = Vec::new();
+    for msg in wire::normalize(messages).iter() {
+        if msg.role != "tool" {
+            flush_attachments(&mut res, &mut attachments);
+        }
+        match msg.role.as_ref() {
+            "assistant" => {
+                let mut m = json!({"role": "assistant", "content": msg.content});
+                if let Some(tools) = &msg.tool_calls {
+                    let calls: Vec<Value> = tools
+                        .iter()
+                        .map(|tool| {
+                            json!({
+                                "id": tool.id,
+                                "type": "function",
+                                "function": {
+                                    "name": tool.function.name,
+                                    "arguments": tool.function.arguments,
+                                },
+                            })
+                        })
+                        .collect();
+                    m["tool_calls"] = json!(calls);
+                }
+                res.push(m);
+            }
+            "tool" => {
+                res.push(json!({
+                    "role": "tool",
+                    "tool_call_id": msg.tool_call_id,
+                    "content": msg.content,
+                }));
+                if !msg.parts.is_empty() {
+                    let text = format!("工具 {} 返回的附件:", msg.name);
+                    if let Value::Array(items) = convert_content(&text, &msg.parts) {
+                        attachments.extend(items);
                     }
                 }
-                return Ok(res);
-            } else {
-                debug!("{:?}", text);
-                return Err(text);
             }
-        } else {
-            debug!("{:?}", text);
-            return Err(text.map_err(|e| e.to_string())?);
+            role => res
+                .push(json!({"role": role, "content": convert_content(&msg.content, &msg.parts)})),
         }
     }
+    flush_attachments(&mut res, &mut attachments);
+    res
 }
 
+/// 把缓存的工具附件作为一条 user 消息放在这批工具结果之后
+fn flush_attachments(res: &mut Vec<Value>, attachments: &mut Vec<Value>) {
+    if !attachments.is_empty() {
+        res.push(json!({"role": "user", "content": std::mem::take(attachments)}));
+    }
+}
+
+/// 有附件时 content 使用数组格式
+fn convert_content(text: &str, parts: &[ContentPart]) -> Value {
+    if parts.is_empty() {
+        return json!(text);
+    }
+    let mut content = Vec::new();
+    if !text.is_empty() {
+        content.push(json!({"type": "text", "text": text}));
+    }
+    for part in parts.iter() {
+        content.push(match part {
+            ContentPart::ImageUrl { url } => {
+                json!({"type": "image_url", "image_url": {"url": url}})
+            }
+            ContentPart::Image { mime_type, data } => {
+                json!({"type": "image_url", "image_url": {"url": data_url(mime_type, data)}})
+            }
+            ContentPart::File {
+                name,
+                mime_type,
+                data,
+            } => match wire::file_text(name, data) {
+                Some(text) => json!({"type": "text", "text": text}),
+                None => json!({
+                    "type": "file",
+                    "file": {"filename": name, "file_data": data_url(mime_type, data)},
+                }),
+            },
+        });
+    }
+    json!(content)
+}
+
+/// 转换为 OpenAI 格式的 tool_choice
+fn convert_tool_choice(choice: &ToolChoice) -> Value {
+    match choice {
+        ToolChoice::Auto => json!("auto"),
+        ToolChoice::None => json!("none"),
+        ToolChoice::Required => json!("required"),
+        ToolChoice::Function(name) => json!({"type": "function", "function": {"name": name}}),
+    }
+}
+
+#[async_trait]
 impl AgentModel for DeepseekModel {
     fn get_token_limit(&self) -> u32 {
         64000
     }
 
+    fn model_name(&self) -> String {
+        self.model_name.clone()
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>, anyhow::Error> {
+        let text = connection::common::DirectConnection::get(
+            format!("{}/models", self.url),
+            self.auth_headers(),
+        )
+        .await?;
+        let js: serde_json::Value = serde_json::from_str(&text)?;
+        let Some(data) = js.get("data").and_then(|v| v.as_array()) else {
+            debug!("{:?}", text);
+            return Err(anyhow::anyhow!("模型列表格式错误: {}", text));
+        };
+        Ok(data
+            .iter()
+            .filter_map(|val| val.get("id").and_then(|id| id.as_str()))
+            .map(|id| id.to_string())
+            .collect())
+    }
+
     async fn chat(
         &self,
         param: ModelInputParam,
     ) -> Result<Vec<CommonConnectionContent>, anyhow::Error> {
-        let messages = param.messages;
-        let mut tools = Vec::new();
-        if let Some(ts) = param.tools {
-            for tool in ts.iter() {
-                let mut p = (*tool.input_schema).clone();
-                p.insert("required".into(), json!([]));
-                p.insert("type".into(), "object".into());
-                let func = DeepseekFunctionItem {
-                    r#type: "function".into(),
-                    function: DeepseekFunctionInfo {
-                        name: tool.name.clone().into(),
-                        description: tool
-                            .description
-                            .as_ref()
-                            .map(|cow| cow.to_string())
-                            .unwrap_or_default(),
-                        parameters: p,
-                    },
-                };
-                tools.push(func);
-            }
-        }
-        let body = serde_json::to_string(&serde_json::json!({
-            "model": self.model_name,
-            "messages": messages,
-            "stream": false,
-            "tools": if !tools.is_empty() { Some(tools) } else { None },
-            "temperature": param.temperature.unwrap_or(self.temperature.parse().unwrap())
-        }))
-        .unwrap();
+        let body = self.build_body(param, false);
         debug!("{:?}", body);
         connection::common::DirectConnection::request(
             format!("{}/chat/completions", self.url),
-            self.get_api_key(),
+            self.auth_headers(),
             body,
         )
         .await
     }
 
-    async fn stream_chat(
-        &self,
-        param: ModelInputParam,
-    ) -> impl Stream<Item = Result<CommonConnectionContent, anyhow::Error>> {
-        let messages = param.messages;
-        let mut tools = Vec::new();
-        // 这里补充两个字段：required type
-        if let Some(ts) = param.tools {
-            for tool in ts.iter() {
-                let mut p = (*tool.input_schema).clone();
-                p.insert("required".into(), json!([]));
-                p.insert("type".into(), "object".into());
-                let func = DeepseekFunctionItem {
-                    r#type: "function".into(),
-                    function: DeepseekFunctionInfo {
-                        name: tool.name.clone().into(),
-                        description: tool
-                            .description
-                            .as_ref()
-                            .map(|cow| cow.to_string())
-                            .unwrap_or_default(),
-                        parameters: p,
-                    },
-                };
-                tools.push(func);
-            }
-        }
-        let body = serde_json::to_string(&serde_json::json!({
-            "model": self.model_name,
-            "messages": messages,
-            "stream": true,
-            "tools": if !tools.is_empty() {Some(tools)} else {None},
-            "temperature": param.temperature.unwrap_or(self.temperature.parse().unwrap())
-        }))
-        .unwrap();
+    async fn stream_chat(&self, param: ModelInputParam) -> ModelStream {
+        let body = self.build_body(param, true);
         debug!("{:?}", body);
-        connection::common::SseConnection::stream(
+        Box::pin(connection::common::SseConnection::stream(
             format!("{}/chat/completions", self.url),
-            self.get_api_key(),
+            self.auth_headers(),
             body,
-        )
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::param::ToolCall;
+
+    #[test]
+    fn test_tool_attachments_after_last_tool_result() {
+        let calls: Vec<ToolCall> = ["a", "b"]
+            .into_iter()
+            .map(|id| {
+                let mut call = ToolCall::new();
+                call.id = id.into();
+                call.function.name = "screenshot".into();
+                call
+            })
+            .collect();
+        let image = ContentPart::Image {
+            mime_type: "image/png".into(),
+            data: "aGk=".into(),
+        };
+        let messages = vec![
+            ModelMessage::user("截图"),
+            ModelMessage::assistant("", "", calls.clone()),
+            ModelMessage::tool("ok", calls[0].clone()).with_parts(vec![image.clone()]),
+            ModelMessage::tool("ok", calls[1].clone()).with_parts(vec![image]),
+            ModelMessage::user("继续"),
+        ];
+        let res = convert_messages(&messages);
+        let roles: Vec<&str> = res.iter().map(|m| m["role"].as_str().unwrap()).collect();
+        assert_eq!(
+            roles,
+            vec!["user", "assistant", "tool", "tool", "user", "user"]
+        );
+        // 两个工具的附件合并在一条消息中，每个附件前有说明
+        assert_eq!(res[4]["content"].as_array().unwrap().len(), 4);
     }
 }
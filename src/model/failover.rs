@@ -0,0 +1,127 @@
+use crate::connection::CommonConnectionContent;
+use crate::connection::retry::{self, RetryConfig};
+use crate::model::catalog::ModelCapabilities;
+use crate::model::param::ModelInputParam;
+use crate::model::{AgentModel, ModelStream};
+use async_trait::async_trait;
+use futures::StreamExt;
+use log::warn;
+use std::sync::Arc;
+
+/// 带重试和故障转移的模型
+///
+/// 按顺序尝试每个模型，可重试的错误先在当前模型上退避重试，
+/// 重试耗尽或遇到其它错误时切换到下一个模型。
+/// 模型信息（名称、上下文大小等）以第一个模型为准
+#[derive(Debug)]
+pub struct FailoverModel {
+    models: Vec<Arc<dyn AgentModel>>,
+    retry: RetryConfig,
+}
+
+impl FailoverModel {
+    /// models 不能为空，第一个为主模型
+    pub fn new(models: Vec<Arc<dyn AgentModel>>, retry: RetryConfig) -> Self {
+        assert!(!models.is_empty(), "FailoverModel 至少需要一个模型");
+        Self { models, retry }
+    }
+
+    fn primary(&self) -> &Arc<dyn AgentModel> {
+        &self.models[0]
+    }
+}
+
+/// 判断当前错误是否需要在同一模型上重试，需要时先等待退避时间
+async fn wait_for_retry(retry: &RetryConfig, attempt: u32, e: &anyhow::Error) -> bool {
+    if attempt >= retry.max_retries || !retry::is_retryable(e) {
+        return false;
+    }
+    let delay = retry.backoff(attempt, retry::retry_after(e));
+    warn!(
+        "请求失败，{:?} 后进行第 {} 次重试: {}",
+        delay,
+        attempt + 1,
+        e
+    );
+    tokio::time::sleep(delay).await;
+    true
+}
+
+#[async_trait]
+impl AgentModel for FailoverModel {
+    fn get_token_limit(&self) -> u32 {
+        self.primary().get_token_limit()
+    }
+
+    fn model_name(&self) -> String {
+        self.primary().model_name()
+    }
+
+    fn capabilities(&self) -> ModelCapabilities {
+        self.primary().capabilities()
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>, anyhow::Error> {
+        self.primary().list_models().await
+    }
+
+    async fn chat(
+        &self,
+        param: ModelInputParam,
+    ) -> Result<Vec<CommonConnectionContent>, anyhow::Error> {
+        let mut last_err = None;
+        for model in self.models.iter() {
+            let mut attempt = 0;
+            loop {
+                match model.chat(param.clone()).await {
+                    Ok(res) => return Ok(res),
+                    Err(e) => {
+                        if wait_for_retry(&self.retry, attempt, &e).await {
+                            attempt += 1;
+                            continue;
+                        }
+                        warn!("模型 {} 请求失败: {}", model.model_name(), e);
+                        last_err = Some(e);
+                        break;
+                    }
+                }
+            }
+        }
+        Err(last_err.unwrap_or(anyhow::anyhow!("没有可用的模型")))
+    }
+
+    async fn stream_chat(&self, param: ModelInputParam) -> ModelStream {
+        let models = self.models.clone();
+        let retry = self.retry.clone();
+        Box::pin(async_stream::stream! {
+            let mut last_err = None;
+            for model in models.iter() {
+                let mut attempt = 0;
+                loop {
+                    let mut stream = model.stream_chat(param.clone()).await;
+                    // 只有在还没有输出任何内容时才能重试，否则会重复输出
+                    match stream.next().await {
+                        Some(Ok(first)) => {
+                            yield Ok(first);
+                            while let Some(item) = stream.next().await {
+                                yield item;
+                            }
+                            return;
+                        }
+                        Some(Err(e)) => {
+                            if wait_for_retry(&retry, attempt, &e).await {
+                                attempt += 1;
+                                continue;
+                            }
+                            warn!("模型 {} 请求失败: {}", model.model_name(), e);
+                            last_err = Some(e);
+                            break;
+                        }
+                        None => return,
+                    }
+                }
+            }
+            yield Err(last_err.unwrap_or(anyhow::anyhow!("没有可用的模型")));
+        })
+    }
+}
@@ -1,20 +1,47 @@
 use crate::connection::CommonConnectionContent;
+use crate::model::catalog::{ModelCapabilities, ModelCatalog};
 ///! # model
 /// model 模块负责与模型沟通并将消息包装成模型要求的格式
+use async_trait::async_trait;
 use futures::Stream;
+use std::fmt::Debug;
+use std::pin::Pin;
+pub mod anthropic;
+pub mod catalog;
 pub mod deepseek;
+pub mod failover;
+pub mod ollama;
 pub mod param;
+pub mod registry;
+pub mod tokenizer;
+pub mod wire;
 
-pub trait AgentModel {
+/// 模型流式输出
+pub type ModelStream =
+    Pin<Box<dyn Stream<Item = Result<CommonConnectionContent, anyhow::Error>> + Send>>;
+
+/// 模型提供方接口，所有模型后端都需要实现这个 trait
+#[async_trait]
+pub trait AgentModel: Send + Sync + Debug {
     async fn chat(
         &self,
         param: param::ModelInputParam,
     ) -> Result<Vec<CommonConnectionContent>, anyhow::Error>;
-    async fn stream_chat(
-        &self,
-        param: param::ModelInputParam,
-    ) -> impl Stream<Item = Result<CommonConnectionContent, anyhow::Error>>;
+    async fn stream_chat(&self, param: param::ModelInputParam) -> ModelStream;
 
-    // 返回模型的上下文窗口大小（最大token数）
+    // 返回提供方默认的上下文窗口大小（最大token数），模型不在能力目录中时使用
     fn get_token_limit(&self) -> u32;
+
+    // 返回当前使用的模型名称
+    fn model_name(&self) -> String;
+
+    /// 查询当前模型的能力
+    fn capabilities(&self) -> ModelCapabilities {
+        ModelCatalog::global().lookup(&self.model_name(), self.get_token_limit())
+    }
+
+    /// 获取提供方可用的模型列表，不支持查询的提供方只返回当前模型
+    async fn list_models(&self) -> Result<Vec<String>, anyhow::Error> {
+        Ok(vec![self.model_name()])
+    }
 }
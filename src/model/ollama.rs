@@ -0,0 +1,351 @@
+use crate::connection::common::{DirectConnection, JsonLinesConnection};
+use crate::connection::{CommonConnectionContent, TokenUsage};
+use crate::model::catalog::{ModelCapabilities, ModelCatalog};
+use crate::model::param::{
+    ContentPart, ModelInputParam, ModelMessage, ResponseFormat, ToolCall, ToolCallFunction,
+    ToolChoice,
+};
+use crate::model::registry::ProviderConfig;
+use crate::model::{AgentModel, ModelStream, wire};
+use async_trait::async_trait;
+use futures::StreamExt;
+use log::{debug, info};
+use rmcp::model::Tool;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use uuid::Uuid;
+
+const DEFAULT_URL: &str = "http://127.0.0.1:11434";
+const DEFAULT_MODEL: &str = "qwen3";
+
+/// Ollama 原生接口模型
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OllamaModel {
+    pub url: String,
+    pub model_name: String,
+    pub temperature: f64,
+    /// 是否让支持思考的模型输出思维链
+    pub think: bool,
+    /// 配置中的上下文上限，为空时按模型上下文窗口设置 num_ctx
+    pub max_tokens: Option<u32>,
+}
+
+impl OllamaModel {
+    pub fn new(url: String, model_name: String) -> Self {
+        Self {
+            url,
+            model_name,
+            temperature: 0.6,
+            think: false,
+            max_tokens: None,
+        }
+    }
+
+    /// 从提供方配置创建，配置了思考预算时开启思考
+    pub fn from_provider_config(config: &ProviderConfig) -> Self {
+        let mut model = Self::new(
+            config.url.clone().unwrap_or(DEFAULT_URL.into()),
+            config.model.clone().unwrap_or(DEFAULT_MODEL.into()),
+        );
+        model.think = config.thinking_budget.is_some();
+        model.max_tokens = config.max_tokens;
+        model
+    }
+
+    fn endpoint(&self, path: &str) -> String {
+        format!("{}/api/{}", self.url.trim_end_matches('/'), path)
+    }
+
+    fn build_body(&self, param: ModelInputParam, stream: bool) -> String {
+        let sampling = param.sampling;
+        // 不允许调用工具时不发送工具定义
+        let tools = if sampling.tool_choice == Some(ToolChoice::None) {
+            Vec::new()
+        } else {
+            convert_tools(param.tools.as_ref())
+        };
+        // Ollama 默认的 num_ctx 很小，超出部分会被静默截断
+        let num_ctx = self
+            .max_tokens
+            .unwrap_or_else(|| self.capabilities().context_window);
+        let mut options = json!({
+            "temperature": sampling.temperature.unwrap_or(self.temperature),
+            "num_ctx": num_ctx,
+        });
+        if let Some(top_p) = sampling.top_p {
+            options["top_p"] = json!(top_p);
+        }
+        if let Some(max_tokens) = sampling.max_tokens {
+            options["num_predict"] = json!(max_tokens);
+        }
+        if let Some(stop) = &sampling.stop {
+            options["stop"] = json!(stop);
+        }
+        if let Some(seed) = sampling.seed {
+            options["seed"] = json!(seed);
+        }
+        if let Some(penalty) = sampling.presence_penalty {
+            options["presence_penalty"] = json!(penalty);
+        }
+        if let Some(penalty) = sampling.frequency_penalty {
+            options["frequency_penalty"] = json!(penalty);
+        }
+        let mut body = json!({
+            "model": self.model_name,
+            "messages": convert_messages(&param.messages),
+            "stream": stream,
+            "options": options,
+        });
+        if !tools.is_empty() {
+            body["tools"] = json!(tools);
+        }
+        match &sampling.response_format {
+            Some(ResponseFormat::JsonObject) => body["format"] = json!("json"),
+            // format 字段可以直接接受 JSON Schema
+            Some(ResponseFormat::JsonSchema { json_schema }) => {
+                body["format"] = json_schema.schema.clone()
+            }
+            _ => {}
+        }
+        if self.think {
+            body["think"] = json!(true);
+        }
+        body.to_string()
+    }
+}
+
+fn convert_tools(tools: Option<&Vec<Tool>>) -> Vec<Value> {
+    let mut res = Vec::new();
+    if let Some(ts) = tools {
+        for tool in ts.iter() {
+            let mut p = (*tool.input_schema).clone();
+            p.insert("type".into(), "object".into());
+            res.push(json!({
+                "type": "function",
+                "function": {
+                    "name": tool.name,
+                    "description": tool.description.as_ref().map(|cow| cow.to_string()).unwrap_or_default(),
+                    "parameters": p,
+                },
+            }));
+        }
+    }
+    res
+}
+
+/// 转换为 Ollama 消息格式，工具参数需要是 JSON 对象而不是字符串，思维链不回传
+fn convert_messages(messages: &[ModelMessage]) -> Vec<Value> {
+    let mut res = Vec::new();
+    for msg in wire::normalize(messages).iter() {
+        match msg.role.as_ref() {
+            "system" | "user" => {
+                res.push(convert_content(&msg.role, &msg.content, &msg.parts));
+            }
+            "assistant" => {
+                let mut m = json!({"role": "assistant", "content": msg.content});
+                if let Some(tools) = &msg.tool_calls {
+                    let calls: Vec<Value> = tools
+                        .iter()
+                        .map(|tool| {
+                            let arguments = serde_json::from_str::<Value>(&tool.function.arguments)
+                                .unwrap_or(json!({}));
+                            json!({"function": {"name": tool.function.name, "arguments": arguments}})
+                        })
+                        .collect();
+                    m["tool_calls"] = json!(calls);
+                }
+                res.push(m);
+            }
+            "tool" => {
+                let mut m = convert_content("tool", &msg.content, &msg.parts);
+                m["tool_name"] = json!(msg.name);
+                res.push(m);
+            }
+            _ => {}
+        }
+    }
+    res
+}
+
+/// 图片以 base64 放在 images 字段，图片地址和无法识别的文件只发送说明
+fn convert_content(role: &str, text: &str, parts: &[ContentPart]) -> Value {
+    let mut content = text.to_string();
+    let mut images = Vec::new();
+    for part in parts.iter() {
+        let extra = match part {
+            ContentPart::Image { data, .. } => {
+                images.push(data.clone());
+                continue;
+            }
+            ContentPart::ImageUrl { url } => match wire::split_data_url(url) {
+                Some((_, data)) => {
+                    images.push(data.to_string());
+                    continue;
+                }
+                None => part.placeholder(),
+            },
+            ContentPart::File { name, data, .. } => {
+                wire::file_text(name, data).unwrap_or(part.placeholder())
+            }
+        };
+        if !content.is_empty() {
+            content.push_str("\n\n");
+        }
+        content.push_str(&extra);
+    }
+    let mut m = json!({"role": role, "content": content});
+    if !images.is_empty() {
+        m["images"] = json!(images);
+    }
+    m
+}
+
+/// 解析单个响应块，流式和非流式格式相同
+fn parse_chunk(json: &Value, tool_index: &mut usize) -> Vec<CommonConnectionContent> {
+    let mut res = Vec::new();
+    if let Some(message) = json.get("message") {
+        if let Some(thinking) = message.get("thinking").and_then(Value::as_str)
+            && !thinking.is_empty()
+        {
+            res.push(CommonConnectionContent::Reasoning(thinking.to_string()));
+        }
+        if let Some(content) = message.get("content").and_then(Value::as_str)
+            && !content.is_empty()
+        {
+            res.push(CommonConnectionContent::Content(content.to_string()));
+        }
+        // Ollama 一次性返回完整的工具调用，并且不带 id，
+        // 每个请求的序号都从 0 开始，用 uuid 生成 id，避免和上下文中之前的调用重复
+        for call in message
+            .get("tool_calls")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+        {
+            let Some(function) = call.get("function") else {
+                continue;
+            };
+            res.push(CommonConnectionContent::ToolCall(ToolCall {
+                index: *tool_index,
+                id: format!("call_{}", Uuid::new_v4().simple()),
+                r#type: "function".into(),
+                function: ToolCallFunction {
+                    name: function
+                        .get("name")
+                        .and_then(Value::as_str)
+                        .unwrap_or_default()
+                        .to_string(),
+                    arguments: function
+                        .get("arguments")
+                        .map(|v| v.to_string())
+                        .unwrap_or("{}".into()),
+                },
+            }));
+            *tool_index += 1;
+        }
+    }
+    if json.get("done").and_then(Value::as_bool) == Some(true) {
+        if let Some(reason) = json.get("done_reason").and_then(Value::as_str) {
+            res.push(CommonConnectionContent::FinishReason(reason.to_string()));
+        }
+        let prompt_tokens = json
+            .get("prompt_eval_count")
+            .and_then(Value::as_u64)
+            .unwrap_or_default() as u32;
+        let completion_tokens = json
+            .get("eval_count")
+            .and_then(Value::as_u64)
+            .unwrap_or_default() as u32;
+        res.push(CommonConnectionContent::TokenUsage(TokenUsage {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+        }));
+    }
+    res
+}
+
+#[async_trait]
+impl AgentModel for OllamaModel {
+    fn get_token_limit(&self) -> u32 {
+        32768
+    }
+
+    fn model_name(&self) -> String {
+        self.model_name.clone()
+    }
+
+    /// Ollama 对所有模型都支持按 JSON Schema 约束输出
+    fn capabilities(&self) -> ModelCapabilities {
+        let mut caps = ModelCatalog::global().lookup(&self.model_name(), self.get_token_limit());
+        caps.json_schema = true;
+        caps
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>, anyhow::Error> {
+        let text = DirectConnection::get(self.endpoint("tags"), vec![]).await?;
+        let js: Value = serde_json::from_str(&text)?;
+        let Some(models) = js.get("models").and_then(Value::as_array) else {
+            return Err(anyhow::anyhow!("模型列表格式错误: {}", text));
+        };
+        Ok(models
+            .iter()
+            .filter_map(|m| m.get("name").and_then(Value::as_str))
+            .map(|name| name.to_string())
+            .collect())
+    }
+
+    async fn chat(
+        &self,
+        param: ModelInputParam,
+    ) -> Result<Vec<CommonConnectionContent>, anyhow::Error> {
+        let body = self.build_body(param, false);
+        debug!("{:?}", body);
+        let text = DirectConnection::post(self.endpoint("chat"), vec![], body).await?;
+        let json: Value = serde_json::from_str(&text)?;
+        if let Some(e) = json.get("error").and_then(Value::as_str) {
+            return Err(anyhow::anyhow!(e.to_string()));
+        }
+        let mut tool_index = 0;
+        let mut res = parse_chunk(&json, &mut tool_index);
+        // chat2 以 Content 作为完整回复，没有文本时也需要一个空内容
+        if !res
+            .iter()
+            .any(|c| matches!(c, CommonConnectionContent::Content(_)))
+        {
+            res.push(CommonConnectionContent::Content(String::new()));
+        }
+        Ok(res)
+    }
+
+    async fn stream_chat(&self, param: ModelInputParam) -> ModelStream {
+        let body = self.build_body(param, true);
+        debug!("{:?}", body);
+        let lines = JsonLinesConnection::stream(self.endpoint("chat"), vec![], body);
+
+        Box::pin(async_stream::stream! {
+            futures::pin_mut!(lines);
+            let mut tool_index = 0;
+            info!("开始流式处理");
+            while let Some(line) = lines.next().await {
+                let json = match line {
+                    Ok(json) => json,
+                    Err(e) => {
+                        yield Err(e);
+                        return;
+                    }
+                };
+                if let Some(e) = json.get("error").and_then(Value::as_str) {
+                    yield Err(anyhow::anyhow!(e.to_string()));
+                    return;
+                }
+                for content in parse_chunk(&json, &mut tool_index) {
+                    yield Ok(content);
+                }
+                if json.get("done").and_then(Value::as_bool) == Some(true) {
+                    return;
+                }
+            }
+        })
+    }
+}
@@ -1,6 +1,7 @@
 use crate::connection::TokenUsage;
 use rmcp::model::Tool;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::borrow::Cow;
 
 /// 辅助函数用于检查 Cow<'static, str> 是否为空
@@ -13,14 +14,91 @@ pub struct ModelMessage {
     pub role: Cow<'static, str>,
     pub content: Cow<'static, str>,
     pub think: Cow<'static, str>,
-    #[serde(skip_serializing_if = "cow_is_empty")]
+    /// 需要原样回传的思维链块（Anthropic 的 thinking 和 redacted_thinking），按返回的顺序保存
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub thinking_blocks: Vec<ThinkingBlock>,
+    #[serde(default, skip_serializing_if = "cow_is_empty")]
     pub name: Cow<'static, str>,
-    #[serde(skip_serializing_if = "cow_is_empty")]
+    #[serde(default, skip_serializing_if = "cow_is_empty")]
     pub tool_call_id: Cow<'static, str>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_calls: Option<Vec<ToolCall>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub token_usage: Option<TokenUsage>,
+    /// 文本之外的内容（图片、文件），排在 content 之后
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub parts: Vec<ContentPart>,
+    /// 固定的消息，滑动窗口裁剪上下文时不会被丢弃
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+    /// 工具输出超出长度限制被截断时的信息
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub truncation: Option<OutputTruncation>,
+}
+
+/// 工具输出的截断信息
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct OutputTruncation {
+    /// 原始输出的字符数
+    pub original_chars: usize,
+    /// 保留在上下文中的字符数
+    pub kept_chars: usize,
+    /// 完整输出的保存位置，保存失败时为空
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+/// 带签名的思维链块，回传时内容和签名都不能修改
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ThinkingBlock {
+    Thinking {
+        thinking: String,
+        signature: String,
+    },
+    /// 被加密的思维链，只有 data 可以回传
+    RedactedThinking {
+        data: String,
+    },
+}
+
+/// 消息中的非文本内容
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    /// 图片地址，可以是 http(s) 地址或 data URL
+    ImageUrl { url: String },
+    /// base64 编码的图片
+    Image { mime_type: String, data: String },
+    /// base64 编码的文件
+    File {
+        name: String,
+        mime_type: String,
+        data: String,
+    },
+}
+
+impl ContentPart {
+    pub fn is_image(&self) -> bool {
+        matches!(self, Self::ImageUrl { .. } | Self::Image { .. })
+    }
+
+    /// 模型不支持该内容时使用的文字说明
+    pub fn placeholder(&self) -> String {
+        match self {
+            Self::ImageUrl { url } if !url.starts_with("data:") => format!("[图片: {}]", url),
+            Self::ImageUrl { .. } => "[图片]".to_string(),
+            Self::Image { mime_type, .. } => format!("[图片: {}]", mime_type),
+            Self::File {
+                name, mime_type, ..
+            } => format!("[文件: {} ({})]", name, mime_type),
+        }
+    }
+}
+
+/// 组成 data URL
+pub fn data_url(mime_type: &str, data: &str) -> String {
+    format!("data:{};base64,{}", mime_type, data)
 }
 
 impl ModelMessage {
@@ -29,10 +107,14 @@ impl ModelMessage {
             role: "user".into(),
             content: content.into(),
             think: "".into(),
+            thinking_blocks: Vec::new(),
             name: "".into(),
             tool_call_id: "".into(),
             tool_calls: None,
             token_usage: None,
+            parts: Vec::new(),
+            pinned: false,
+            truncation: None,
         }
     }
 
@@ -50,10 +132,14 @@ impl ModelMessage {
             role: "assistant".into(),
             content: content.into(),
             think: think.into(),
+            thinking_blocks: Vec::new(),
             name: "".into(),
             tool_call_id: "".into(),
             tool_calls,
             token_usage: None,
+            parts: Vec::new(),
+            pinned: false,
+            truncation: None,
         }
     }
 
@@ -62,10 +148,14 @@ impl ModelMessage {
             role: "system".into(),
             content: content.into(),
             think: "".into(),
+            thinking_blocks: Vec::new(),
             name: "".into(),
             tool_call_id: "".into(),
             tool_calls: None,
             token_usage: None,
+            parts: Vec::new(),
+            pinned: false,
+            truncation: None,
         }
     }
 
@@ -74,10 +164,14 @@ impl ModelMessage {
             role: "tool".into(),
             content: content.into(),
             think: "".into(),
+            thinking_blocks: Vec::new(),
             name: tool.function.name.into(),
             tool_call_id: tool.id.into(),
             tool_calls: None,
             token_usage: None,
+            parts: Vec::new(),
+            pinned: false,
+            truncation: None,
         }
     }
 
@@ -86,10 +180,14 @@ impl ModelMessage {
             role: "system".into(),
             content: "".into(),
             think: "".into(),
+            thinking_blocks: Vec::new(),
             name: "".into(),
             tool_call_id: "".into(),
             tool_calls: None,
             token_usage: Some(token_usage),
+            parts: Vec::new(),
+            pinned: false,
+            truncation: None,
         }
     }
 
@@ -98,13 +196,23 @@ impl ModelMessage {
             role: "info".into(),
             content: content.into(),
             think: "".into(),
+            thinking_blocks: Vec::new(),
             name: "".into(),
             tool_call_id: "".into(),
             tool_calls: None,
             token_usage: None,
+            parts: Vec::new(),
+            pinned: false,
+            truncation: None,
         }
     }
 
+    /// 附加图片、文件等内容
+    pub fn with_parts(mut self, parts: Vec<ContentPart>) -> Self {
+        self.parts = parts;
+        self
+    }
+
     pub fn add_tool(&mut self, tool: ToolCall) {
         if self.tool_calls.is_none() {
             self.tool_calls = Some(vec![]);
@@ -143,9 +251,115 @@ impl ModelMessage {
     }
 }
 
+/// 工具选择策略
+///
+/// 配置中写作 "auto"、"none"、"required" 或 {"function": "工具名"}
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolChoice {
+    /// 由模型决定是否调用工具
+    Auto,
+    /// 不调用工具
+    None,
+    /// 必须调用至少一个工具
+    Required,
+    /// 必须调用指定的工具
+    Function(String),
+}
+
+/// 回复格式
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    Text,
+    /// 回复必须是合法的 JSON 对象
+    JsonObject,
+    /// 回复必须符合给定的 JSON Schema
+    JsonSchema {
+        json_schema: JsonSchemaFormat,
+    },
+}
+
+impl ResponseFormat {
+    pub fn json_schema(schema: Value) -> Self {
+        Self::JsonSchema {
+            json_schema: JsonSchemaFormat {
+                name: "response".into(),
+                schema,
+                strict: false,
+            },
+        }
+    }
+
+    /// 要求的 JSON Schema
+    pub fn schema(&self) -> Option<&Value> {
+        match self {
+            Self::JsonSchema { json_schema } => Some(&json_schema.schema),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct JsonSchemaFormat {
+    pub name: String,
+    pub schema: Value,
+    /// 严格模式要求 Schema 中所有对象都禁止额外字段
+    #[serde(default)]
+    pub strict: bool,
+}
+
+/// 采样参数，未设置的字段使用提供方默认值
+///
+/// 提供方不支持的参数会被忽略
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct SamplingParams {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    /// 单次回复最大输出 token 数，不超过模型的最大输出
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    /// 停止序列
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    /// 随机种子，配合 temperature 0 用于复现结果
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<ToolChoice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+}
+
+impl SamplingParams {
+    /// 用 overrides 中设置了的字段覆盖当前参数
+    pub fn merge(&self, overrides: &SamplingParams) -> SamplingParams {
+        let overrides = overrides.clone();
+        SamplingParams {
+            temperature: overrides.temperature.or(self.temperature),
+            top_p: overrides.top_p.or(self.top_p),
+            max_tokens: overrides.max_tokens.or(self.max_tokens),
+            stop: overrides.stop.or(self.stop.clone()),
+            seed: overrides.seed.or(self.seed),
+            presence_penalty: overrides.presence_penalty.or(self.presence_penalty),
+            frequency_penalty: overrides.frequency_penalty.or(self.frequency_penalty),
+            tool_choice: overrides.tool_choice.or(self.tool_choice.clone()),
+            response_format: overrides.response_format.or(self.response_format.clone()),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ModelInputParam {
-    pub temperature: Option<f64>,
+    #[serde(flatten)]
+    pub sampling: SamplingParams,
     pub tools: Option<Vec<Tool>>,
     pub messages: Vec<ModelMessage>,
 }
@@ -194,3 +408,33 @@ impl ToolCallFunction {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sampling_merge() {
+        let base: SamplingParams = serde_json::from_str(
+            r#"{"temperature": 0.6, "max_tokens": 1024, "tool_choice": "required"}"#,
+        )
+        .unwrap();
+        let turn: SamplingParams = serde_json::from_str(
+            r#"{"temperature": 0, "seed": 42, "tool_choice": {"function": "read_file"}}"#,
+        )
+        .unwrap();
+        let merged = base.merge(&turn);
+        assert_eq!(merged.temperature, Some(0.0));
+        assert_eq!(merged.seed, Some(42));
+        assert_eq!(merged.max_tokens, Some(1024));
+        assert_eq!(
+            merged.tool_choice,
+            Some(ToolChoice::Function("read_file".into()))
+        );
+        // 未设置的字段不会出现在请求中
+        assert_eq!(
+            serde_json::to_string(&SamplingParams::default()).unwrap(),
+            "{}"
+        );
+    }
+}
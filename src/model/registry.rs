@@ -0,0 +1,199 @@
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::config::Config;
+use crate::connection::retry::RetryConfig;
+use crate::model::AgentModel;
+use crate::model::anthropic::AnthropicModel;
+use crate::model::deepseek::DeepseekModel;
+use crate::model::failover::FailoverModel;
+use crate::model::ollama::OllamaModel;
+
+/// 未配置 provider 时使用的默认提供方
+pub const DEFAULT_PROVIDER: &str = "deepseek";
+
+/// 本地运行的提供方，不需要 API 密钥
+const LOCAL_PROVIDERS: &[&str] = &["local", "ollama"];
+
+/// 创建模型实例所需的提供方配置
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ProviderConfig {
+    /// 提供方名称，对应注册表中的名字
+    #[serde(default)]
+    pub provider: Option<String>,
+    #[serde(default)]
+    pub api_key: String,
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub model: Option<String>,
+    /// 思维链 token 预算
+    #[serde(default)]
+    pub thinking_budget: Option<u32>,
+    /// BPE 词表文件路径，用于本地计算 token 数
+    #[serde(default)]
+    pub tokenizer: Option<String>,
+    /// 配置中的上下文上限，为空时使用模型上下文窗口
+    #[serde(default)]
+    pub max_tokens: Option<u32>,
+    /// 请求失败时的重试配置
+    #[serde(default)]
+    pub retry: RetryConfig,
+    /// 主模型重试失败后依次尝试的备用模型
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fallbacks: Vec<ProviderConfig>,
+}
+
+impl ProviderConfig {
+    /// 从全局配置中提取提供方配置
+    pub fn from_config(config: &Config) -> Self {
+        let mut provider = Self {
+            provider: config.provider.clone(),
+            api_key: config.api_key.clone(),
+            url: config.url.clone(),
+            model: config.model.clone(),
+            thinking_budget: config.thinking_budget,
+            tokenizer: config.tokenizer.clone(),
+            max_tokens: config.max_tokens,
+            retry: config.retry.clone(),
+            fallbacks: vec![],
+        };
+        provider.fallbacks = config
+            .fallbacks
+            .iter()
+            .map(|fallback| fallback.inherit(&provider))
+            .collect();
+        provider
+    }
+
+    /// 把提供方配置写回全局配置，用于按运行时切换后的模型创建新的对话
+    pub fn apply_to(&self, config: &mut Config) {
+        config.provider = self.provider.clone();
+        config.api_key = self.api_key.clone();
+        config.url = self.url.clone();
+        config.model = self.model.clone();
+        config.thinking_budget = self.thinking_budget;
+        config.tokenizer = self.tokenizer.clone();
+        config.retry = self.retry.clone();
+        config.fallbacks = self.fallbacks.clone();
+    }
+
+    /// 备用模型未指定提供方时与主模型相同，同一提供方下未配置的密钥和地址沿用主模型
+    fn inherit(&self, primary: &ProviderConfig) -> Self {
+        let mut res = self.clone();
+        if res.provider.is_none() {
+            res.provider = primary.provider.clone();
+        }
+        if res.provider_name() == primary.provider_name() {
+            if res.api_key.is_empty() {
+                res.api_key = primary.api_key.clone();
+            }
+            if res.url.is_none() {
+                res.url = primary.url.clone();
+            }
+        }
+        if res.max_tokens.is_none() {
+            res.max_tokens = primary.max_tokens;
+        }
+        res.fallbacks.clear();
+        res
+    }
+
+    /// 提供方名称，未配置时回退到默认提供方
+    pub fn provider_name(&self) -> &str {
+        self.provider.as_deref().unwrap_or(DEFAULT_PROVIDER)
+    }
+
+    /// 是否需要配置 API 密钥
+    pub fn requires_api_key(&self) -> bool {
+        !LOCAL_PROVIDERS.contains(&self.provider_name())
+    }
+}
+
+/// 模型工厂，根据提供方配置创建模型实例
+pub type ProviderFactory = Arc<dyn Fn(&ProviderConfig) -> Arc<dyn AgentModel> + Send + Sync>;
+
+/// 模型提供方注册表
+///
+/// 保存提供方名称到模型工厂的映射，运行时根据配置选择后端
+pub struct ModelRegistry {
+    providers: Mutex<HashMap<String, ProviderFactory>>,
+}
+
+impl ModelRegistry {
+    /// 获取全局单例实例，首次获取时注册内置提供方
+    pub fn global() -> &'static ModelRegistry {
+        static INSTANCE: OnceLock<ModelRegistry> = OnceLock::new();
+        INSTANCE.get_or_init(|| {
+            let registry = ModelRegistry {
+                providers: Mutex::new(HashMap::new()),
+            };
+            registry.register_builtin();
+            registry
+        })
+    }
+
+    fn register_builtin(&self) {
+        self.register(
+            DEFAULT_PROVIDER,
+            Arc::new(|config| Arc::new(DeepseekModel::from_provider_config(config))),
+        );
+        self.register(
+            "local",
+            Arc::new(|config| Arc::new(DeepseekModel::from_local_config(config))),
+        );
+        self.register(
+            "ollama",
+            Arc::new(|config| Arc::new(OllamaModel::from_provider_config(config))),
+        );
+        self.register(
+            "anthropic",
+            Arc::new(|config| Arc::new(AnthropicModel::from_provider_config(config))),
+        );
+    }
+
+    /// 注册提供方，同名提供方会被覆盖
+    pub fn register(&self, name: &str, factory: ProviderFactory) {
+        info!("注册模型提供方 {}", name);
+        self.providers
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), factory);
+    }
+
+    /// 根据提供方配置创建模型实例，配置了重试或备用模型时包装为 FailoverModel
+    pub fn create(&self, config: &ProviderConfig) -> anyhow::Result<Arc<dyn AgentModel>> {
+        let primary = self.create_single(config)?;
+        if config.fallbacks.is_empty() && config.retry.max_retries == 0 {
+            return Ok(primary);
+        }
+        let mut models = vec![primary];
+        for fallback in config.fallbacks.iter() {
+            models.push(self.create_single(fallback)?);
+        }
+        Ok(Arc::new(FailoverModel::new(models, config.retry.clone())))
+    }
+
+    fn create_single(&self, config: &ProviderConfig) -> anyhow::Result<Arc<dyn AgentModel>> {
+        let name = config.provider_name();
+        // 先释放锁再拼接错误信息，provider_names 也需要加锁
+        let factory = self.providers.lock().unwrap().get(name).cloned();
+        match factory {
+            Some(factory) => Ok(factory(config)),
+            None => Err(anyhow::anyhow!(
+                "未知的模型提供方: {}，可用的提供方: {}",
+                name,
+                self.provider_names().join(", ")
+            )),
+        }
+    }
+
+    /// 获取所有已注册的提供方名称
+    pub fn provider_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.providers.lock().unwrap().keys().cloned().collect();
+        names.sort();
+        names
+    }
+}
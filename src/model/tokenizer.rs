@@ -0,0 +1,288 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use log::{info, warn};
+use std::collections::HashMap;
+use std::fs;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::model::param::{ContentPart, ModelMessage, ThinkingBlock};
+use crate::model::registry::ProviderConfig;
+use crate::model::wire;
+
+/// 每条消息的格式开销（角色、分隔符等）
+const MESSAGE_OVERHEAD: u32 = 4;
+/// 每张图片按固定 token 数估算，各家按分辨率计费，这里取常见截图的量级
+const IMAGE_TOKENS: u32 = 1500;
+/// 超过该长度的预分词片段不做字节对合并
+const LONG_PIECE_BYTES: usize = 4096;
+
+/// BPE 分词器，使用 tiktoken 格式的词表（每行为 base64 编码的 token 和排名）
+#[derive(Debug)]
+pub struct BpeTokenizer {
+    ranks: HashMap<Vec<u8>, u32>,
+}
+
+impl BpeTokenizer {
+    /// 从文件加载词表
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let mut ranks = HashMap::new();
+        for line in text.lines() {
+            let mut parts = line.split_whitespace();
+            let (Some(token), Some(rank)) = (parts.next(), parts.next()) else {
+                continue;
+            };
+            ranks.insert(STANDARD.decode(token)?, rank.parse()?);
+        }
+        if ranks.is_empty() {
+            return Err(anyhow::anyhow!("词表为空: {}", path));
+        }
+        info!("加载 BPE 词表 {}，共 {} 个 token", path, ranks.len());
+        Ok(Self { ranks })
+    }
+
+    /// 计算文本的 token 数
+    pub fn count(&self, text: &str) -> usize {
+        pretokenize(text)
+            .into_iter()
+            .map(|piece| self.count_piece(piece.as_bytes()))
+            .sum()
+    }
+
+    /// 对单个预分词片段做字节对合并，每次合并排名最小的相邻片段
+    ///
+    /// 与 tiktoken 相同，记录每个位置与后一片段合并后的排名，合并后只更新相邻两个位置
+    fn count_piece(&self, piece: &[u8]) -> usize {
+        if piece.len() <= 1 || self.ranks.contains_key(piece) {
+            return 1;
+        }
+        // 超长片段（压缩代码、长串重复字符等）合并代价过高，按每 4 字节一个 token 估算
+        if piece.len() > LONG_PIECE_BYTES {
+            return piece.len().div_ceil(4);
+        }
+        // 每个片段的起始位置和与后一片段合并后的排名，最后一个元素是结尾
+        let mut parts: Vec<(usize, u32)> = (0..=piece.len()).map(|i| (i, u32::MAX)).collect();
+        for i in 0..parts.len() {
+            let rank = self.rank_at(piece, &parts, i);
+            parts[i].1 = rank;
+        }
+        while let Some((_, i)) = parts[..parts.len() - 1]
+            .iter()
+            .enumerate()
+            .map(|(i, (_, rank))| (*rank, i))
+            .min()
+            .filter(|(rank, _)| *rank != u32::MAX)
+        {
+            parts.remove(i + 1);
+            let rank = self.rank_at(piece, &parts, i);
+            parts[i].1 = rank;
+            if i > 0 {
+                let rank = self.rank_at(piece, &parts, i - 1);
+                parts[i - 1].1 = rank;
+            }
+        }
+        parts.len() - 1
+    }
+
+    /// 第 i 个片段与后一片段合并后的排名，不能合并时为 u32::MAX
+    fn rank_at(&self, piece: &[u8], parts: &[(usize, u32)], i: usize) -> u32 {
+        match parts.get(i + 2) {
+            Some((end, _)) => self
+                .ranks
+                .get(&piece[parts[i].0..*end])
+                .copied()
+                .unwrap_or(u32::MAX),
+            None => u32::MAX,
+        }
+    }
+}
+
+fn is_punct(c: char) -> bool {
+    !c.is_whitespace() && !c.is_alphabetic() && !c.is_numeric()
+}
+
+/// 预分词，按 cl100k 的切分规则把文本切成片段
+///
+/// 依次尝试：英文缩写、（可带一个前缀符号的）字母串、最多三位数字、
+/// （可带一个前导空格的）符号串、空白
+fn pretokenize(text: &str) -> Vec<&str> {
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let byte_at = |i: usize| chars.get(i).map_or(text.len(), |(b, _)| *b);
+    let mut res = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i].1;
+        let next = chars.get(i + 1).map(|(_, c)| *c);
+        let mut j = i + 1;
+        if c == '\'' && next.is_some() {
+            let rest = text[byte_at(i + 1)..byte_at(i + 3)].to_lowercase();
+            if let Some(len) = ["re", "ve", "ll", "s", "t", "m", "d"]
+                .iter()
+                .find(|s| rest.starts_with(*s))
+                .map(|s| s.len())
+            {
+                j = i + 1 + len;
+                res.push(&text[byte_at(i)..byte_at(j)]);
+                i = j;
+                continue;
+            }
+        }
+        let prefix = !c.is_alphabetic() && !c.is_numeric() && !matches!(c, '\r' | '\n');
+        if c.is_alphabetic() || (prefix && next.is_some_and(char::is_alphabetic)) {
+            while j < chars.len() && chars[j].1.is_alphabetic() {
+                j += 1;
+            }
+        } else if c.is_numeric() {
+            while j < chars.len() && j - i < 3 && chars[j].1.is_numeric() {
+                j += 1;
+            }
+        } else if is_punct(c) || (c == ' ' && next.is_some_and(is_punct)) {
+            while j < chars.len() && is_punct(chars[j].1) {
+                j += 1;
+            }
+            while j < chars.len() && matches!(chars[j].1, '\r' | '\n') {
+                j += 1;
+            }
+        } else {
+            while j < chars.len() && chars[j].1.is_whitespace() {
+                j += 1;
+            }
+            if let Some(last) = (i..j).rev().find(|k| matches!(chars[*k].1, '\r' | '\n')) {
+                // 空白中有换行时切到最后一个换行
+                j = last + 1;
+            } else if j < chars.len() && j - i > 1 {
+                // 最后一个空格留给后面的单词
+                j -= 1;
+            }
+        }
+        res.push(&text[byte_at(i)..byte_at(j)]);
+        i = j;
+    }
+    res
+}
+
+/// token 估算器
+///
+/// 配置了 BPE 词表时精确计数，否则按提供方的经验比例估算
+#[derive(Debug, Clone)]
+pub enum TokenEstimator {
+    Bpe(Arc<BpeTokenizer>),
+    Heuristic {
+        /// 每个 ASCII 字符约等于多少 token
+        ascii: f32,
+        /// 每个非 ASCII 字符（中文等）约等于多少 token
+        other: f32,
+    },
+}
+
+impl TokenEstimator {
+    /// 根据提供方配置选择估算方式
+    pub fn for_provider(config: &ProviderConfig) -> Self {
+        if let Some(path) = &config.tokenizer {
+            match load_cached(path) {
+                Ok(bpe) => return Self::Bpe(bpe),
+                Err(e) => warn!("加载 BPE 词表 {} 失败，改用估算: {}", path, e),
+            }
+        }
+        match config.provider_name() {
+            // deepseek 官方给出的换算比例
+            "deepseek" => Self::Heuristic {
+                ascii: 0.3,
+                other: 0.6,
+            },
+            _ => Self::Heuristic {
+                ascii: 0.25,
+                other: 1.0,
+            },
+        }
+    }
+
+    /// 计算文本的 token 数
+    pub fn count_text(&self, text: &str) -> u32 {
+        match self {
+            Self::Bpe(bpe) => bpe.count(text) as u32,
+            Self::Heuristic { ascii, other } => {
+                let (a, o) = text.chars().fold((0u32, 0u32), |(a, o), c| {
+                    if c.is_ascii() { (a + 1, o) } else { (a, o + 1) }
+                });
+                (a as f32 * ascii + o as f32 * other).ceil() as u32
+            }
+        }
+    }
+
+    /// 计算一组消息发送给模型时大致占用的 token 数
+    pub fn count_messages(&self, messages: &[ModelMessage]) -> u32 {
+        messages
+            .iter()
+            .filter(|msg| !wire::is_internal(msg))
+            .map(|msg| {
+                let mut tokens = MESSAGE_OVERHEAD + self.count_text(&msg.content);
+                // 只有带签名的思维链块会回传给模型
+                for block in msg.thinking_blocks.iter() {
+                    tokens += match block {
+                        ThinkingBlock::Thinking { thinking, .. } => self.count_text(thinking),
+                        ThinkingBlock::RedactedThinking { data } => data.len() as u32 / 4,
+                    };
+                }
+                for part in msg.parts.iter() {
+                    tokens += match part {
+                        // 文件按 base64 长度粗略估算
+                        ContentPart::File { data, .. } => data.len() as u32 / 4,
+                        _ => IMAGE_TOKENS,
+                    };
+                }
+                for tool in msg.tool_calls.iter().flatten() {
+                    tokens += MESSAGE_OVERHEAD
+                        + self.count_text(&tool.function.name)
+                        + self.count_text(&tool.function.arguments);
+                }
+                tokens
+            })
+            .sum()
+    }
+}
+
+/// 词表较大，同一个文件只加载一次
+fn load_cached(path: &str) -> anyhow::Result<Arc<BpeTokenizer>> {
+    static CACHE: OnceLock<Mutex<HashMap<String, Arc<BpeTokenizer>>>> = OnceLock::new();
+    let cache = CACHE.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Some(bpe) = cache.lock().unwrap().get(path) {
+        return Ok(bpe.clone());
+    }
+    let bpe = Arc::new(BpeTokenizer::load(path)?);
+    cache.lock().unwrap().insert(path.to_string(), bpe.clone());
+    Ok(bpe)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pretokenize() {
+        assert_eq!(
+            pretokenize("Hello world, it's 12345!\n\n  ok"),
+            vec![
+                "Hello", " world", ",", " it", "'s", " ", "123", "45", "!\n\n", " ", " ok"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bpe_count() {
+        let ranks = ["a", "b", "c", "ab", "abc"]
+            .iter()
+            .enumerate()
+            .map(|(i, t)| (t.as_bytes().to_vec(), i as u32))
+            .collect();
+        let bpe = BpeTokenizer { ranks };
+        assert_eq!(bpe.count_piece(b"abc"), 1);
+        assert_eq!(bpe.count_piece(b"abcab"), 2);
+        assert_eq!(bpe.count_piece(b"cba"), 3);
+        // 超长片段按字节数估算
+        assert_eq!(
+            bpe.count_piece(&[b'a'; LONG_PIECE_BYTES + 1]),
+            LONG_PIECE_BYTES / 4 + 1
+        );
+    }
+}
@@ -0,0 +1,116 @@
+use std::borrow::Cow;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+
+use crate::model::param::{ContentPart, ModelMessage};
+
+/// 是否为只在本地使用的消息（界面提示、token 统计），这些消息不发送给模型
+pub fn is_internal(msg: &ModelMessage) -> bool {
+    match msg.role.as_ref() {
+        "info" => true,
+        "system" | "user" => msg.content.is_empty() && msg.parts.is_empty(),
+        "assistant" => msg.content.is_empty() && msg.think.is_empty() && msg.tool_calls.is_none(),
+        "tool" => false,
+        _ => true,
+    }
+}
+
+/// 将内部对话记录整理为可以发送的消息序列
+///
+/// 去掉内部消息，并合并相邻的同角色消息。工具结果各自对应一次调用，不合并；
+/// 带工具调用的回复后面必须紧跟工具结果，也不与之后的回复合并
+pub fn normalize(messages: &[ModelMessage]) -> Vec<ModelMessage> {
+    let mut res: Vec<ModelMessage> = Vec::new();
+    for msg in messages.iter().filter(|msg| !is_internal(msg)) {
+        if let Some(last) = res.last_mut()
+            && last.role == msg.role
+            && last.role != "tool"
+            && last.tool_calls.is_none()
+        {
+            last.content = join(&last.content, &msg.content);
+            last.think = join(&last.think, &msg.think);
+            last.thinking_blocks
+                .extend(msg.thinking_blocks.iter().cloned());
+            last.tool_calls = msg.tool_calls.clone();
+            last.parts.extend(msg.parts.iter().cloned());
+            continue;
+        }
+        let mut msg = msg.clone();
+        msg.token_usage = None;
+        res.push(msg);
+    }
+    res
+}
+
+/// 模型不支持图片时，把图片替换为文字说明
+pub fn strip_images(messages: Vec<ModelMessage>) -> Vec<ModelMessage> {
+    messages
+        .into_iter()
+        .map(|mut msg| {
+            if msg.parts.iter().any(ContentPart::is_image) {
+                let (images, others): (Vec<_>, Vec<_>) =
+                    msg.parts.into_iter().partition(ContentPart::is_image);
+                let text: Vec<String> = images.iter().map(ContentPart::placeholder).collect();
+                msg.content = join(&msg.content, &text.join("\n"));
+                msg.parts = others;
+            }
+            msg
+        })
+        .collect()
+}
+
+/// 拆分 data URL，返回 (mime_type, base64 数据)
+pub fn split_data_url(url: &str) -> Option<(&str, &str)> {
+    url.strip_prefix("data:")?.split_once(";base64,")
+}
+
+/// 文本文件直接以文字形式发送，返回 None 表示不是文本文件
+pub fn file_text(name: &str, data: &str) -> Option<String> {
+    let bytes = STANDARD.decode(data).ok()?;
+    let text = String::from_utf8(bytes).ok()?;
+    Some(format!("[文件: {}]\n{}", name, text))
+}
+
+fn join(a: &str, b: &str) -> Cow<'static, str> {
+    match (a.is_empty(), b.is_empty()) {
+        (_, true) => a.to_string().into(),
+        (true, false) => b.to_string().into(),
+        (false, false) => format!("{}\n\n{}", a, b).into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::TokenUsage;
+    use crate::model::param::ToolCall;
+
+    #[test]
+    fn test_normalize() {
+        let mut call = ToolCall::new();
+        call.id = "call_0".into();
+        let messages = vec![
+            ModelMessage::system("a"),
+            ModelMessage::info("界面提示"),
+            ModelMessage::token(TokenUsage {
+                prompt_tokens: 1,
+                completion_tokens: 1,
+                total_tokens: 2,
+            }),
+            ModelMessage::user("b"),
+            ModelMessage::user("c"),
+            ModelMessage::assistant("", "", vec![call.clone()]),
+            ModelMessage::tool("1", call.clone()),
+            ModelMessage::tool("2", call),
+            ModelMessage::assistant("d", "", vec![]),
+        ];
+        let res = normalize(&messages);
+        let roles: Vec<&str> = res.iter().map(|m| m.role.as_ref()).collect();
+        assert_eq!(
+            roles,
+            vec!["system", "user", "assistant", "tool", "tool", "assistant"]
+        );
+        assert_eq!(res[1].content, "b\n\nc");
+    }
+}
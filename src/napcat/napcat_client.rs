@@ -1,4 +1,6 @@
-use crate::{Args, mcp};
+use crate::chat::{EChatState, SessionRecord};
+use crate::model::param::{ContentPart, ModelMessage};
+use crate::{Args, mcp, open_session};
 use crate::{
     chat::Chat,
     client::get_output_tostring,
@@ -18,12 +20,13 @@ pub struct NapCatClient {
 }
 
 impl NapCatClient {
-    pub fn new(config: NapCatConfig) -> Self {
+    pub fn new(config: NapCatConfig, session: Option<SessionRecord>) -> Self {
         let mut chat = Chat::default();
         let args = Args::parse();
         if Some(true) == args.use_tool {
             chat = chat.tools(mcp::get_config_tools());
         }
+        open_session(&mut chat, session);
         Self { config, chat }
     }
 
@@ -53,9 +56,8 @@ impl NapCatClient {
                 onebot_v11::Event::Message(message) => match message {
                     onebot_v11::event::message::Message::PrivateMessage(private_msg) => {
                         if self.config.is_target_user(private_msg.user_id) {
-                            let prompt = get_text_msg(private_msg.message);
-                            let stream = self.chat.chat(&prompt);
-                            match get_output_tostring(stream).await {
+                            let message = get_user_msg(private_msg.message);
+                            match self.respond(message).await {
                                 Ok(response) => {
                                     let payload =
                                         ApiPayload::SendPrivateForwardMsg(SendPrivateForwardMsg {
@@ -73,9 +75,8 @@ impl NapCatClient {
                     }
                     onebot_v11::event::message::Message::GroupMessage(group_message) => {
                         if self.config.is_group_at_self(group_message.clone()) {
-                            let prompt = get_text_msg(group_message.message);
-                            let stream = self.chat.chat(&prompt);
-                            match get_output_tostring(stream).await {
+                            let message = get_user_msg(group_message.message);
+                            match self.respond(message).await {
                                 Ok(response) => {
                                     let payload =
                                         ApiPayload::SendGroupForwardMsg(SendGroupForwardMsg {
@@ -99,14 +100,73 @@ impl NapCatClient {
             }
         }
     }
+
+    /// 处理一条消息，等待工具确认时把消息作为确认的答复，都答复后继续执行
+    async fn respond(&mut self, message: ModelMessage) -> anyhow::Result<String> {
+        let response = if self.chat.get_state() == EChatState::WaitingToolConfirm {
+            match self.chat.reply_tool_confirm(&message.content) {
+                Ok(()) if self.chat.get_state() == EChatState::WaitingToolUse => {
+                    get_output_tostring(self.chat.stream_rechat()).await?
+                }
+                Ok(()) => String::new(),
+                Err(e) => e.to_string(),
+            }
+        } else if self.chat.get_state() == EChatState::WaitingLoopConfirm
+            && let Some(resume) = loop_reply(&message.content)
+        {
+            self.chat.confirm_tool_loop(resume);
+            if resume {
+                get_output_tostring(self.chat.stream_rechat()).await?
+            } else {
+                "已停止执行工具调用".to_string()
+            }
+        } else {
+            get_output_tostring(self.chat.chat_message(message)).await?
+        };
+        Ok(append_tool_confirm(&self.chat, response))
+    }
+}
+
+/// 工具调用等待确认时，在回复后面列出待确认的调用和答复方式
+fn append_tool_confirm(chat: &Chat, mut response: String) -> String {
+    if chat.get_state() == EChatState::WaitingToolConfirm {
+        response.push_str(&format!(
+            "\n\n以下工具调用需要确认，请逐个答复标记为当前的调用：\n{}",
+            chat.describe_tool_confirm()
+        ));
+    }
+    if let Some(reason) = chat.tool_loop_pause() {
+        response.push_str(&format!(
+            "\n\n{}，已暂停。回复 继续/y 继续执行，停止/n 停止，或者发送新的指示",
+            reason
+        ));
+    }
+    response.trim().to_string()
+}
+
+/// 工具调用暂停时的答复，其他内容作为新的指示
+fn loop_reply(input: &str) -> Option<bool> {
+    match input.trim().to_lowercase().as_str() {
+        "y" | "yes" | "继续" => Some(true),
+        "n" | "no" | "停止" => Some(false),
+        _ => None,
+    }
 }
 
-pub fn get_text_msg(messages: Vec<MessageSegment>) -> String {
+/// 提取消息中的文字和图片
+pub fn get_user_msg(messages: Vec<MessageSegment>) -> ModelMessage {
     let mut res = String::new();
+    let mut parts = Vec::new();
     for msg in messages {
-        if let MessageSegment::Text { data } = msg {
-            res += &data.text;
+        match msg {
+            MessageSegment::Text { data } => res += &data.text,
+            MessageSegment::Image { data } => {
+                if let Some(url) = data.url {
+                    parts.push(ContentPart::ImageUrl { url });
+                }
+            }
+            _ => {}
         }
     }
-    res
+    ModelMessage::user(res).with_parts(parts)
 }
@@ -1,18 +1,24 @@
 use chrono::{DateTime, Local};
+use log::{info, warn};
 use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
 
 pub const CHAT_PROMPT: &'static str = "```markdown
 # TODO LIST RECOMMENDED
-When starting a new task, it is recommended to create a todo list.
+When starting a task that takes several steps, create a todo list with the `todo` tool.
 
 
 
+1. Call the `todo` tool with a comprehensive list of all steps needed
 
-1. Include the task_progress parameter in your next tool call
+2. Each item has a status: pending, in_progress or done
 
-2. Create a comprehensive checklist of all steps needed
+3. Keep exactly one item in_progress, and call the tool again to mark it done as soon as it is finished
 
-3. Use markdown format: - [ ] for incomplete, - [x] for complete
+4. Every call replaces the whole list, so always pass the complete list
 
 
 
@@ -24,44 +30,42 @@ When starting a new task, it is recommended to create a todo list.
 
 	- Nothing gets forgotten or missed
 
-	- Users can see, monitor, and edit the plan
+	- Users can see and monitor the plan live
 
 
 
-**Example structure:**
+**Example steps:**
 ```
 
-- [ ] Analyze requirements
+- Analyze requirements
 
-- [ ] Set up necessary files
+- Set up necessary files
 
-- [ ] Implement main functionality
+- Implement main functionality
 
-- [ ] Handle edge cases
+- Handle edge cases
 
-- [ ] Test the implementation
+- Test the implementation
 
-- [ ] Verify results
+- Verify results
 ";
 
 /// 构建增强的系统prompt，包含当前时间和工作目录信息
-pub fn build_enhanced_prompt(base_prompt: &str) -> String {
+pub fn build_enhanced_prompt(base_prompt: &str, cwd: &Path) -> String {
     // 获取当前时间
     let now: DateTime<Local> = Local::now();
     let formatted_time = now.format("%Y-%m-%d %H:%M:%S").to_string();
 
-    // 获取当前工作目录
-    let current_dir = env::current_dir()
-        .map(|path| path.to_string_lossy().to_string())
-        .unwrap_or_else(|_| "无法获取当前目录".to_string());
-
     // 获取系统信息
     let os_info = get_system_info();
 
     // 构建增强的prompt
     format!(
         "{}\n当前时间: {}\n当前工作目录: {}\n\n{}",
-        os_info, formatted_time, current_dir, base_prompt
+        os_info,
+        formatted_time,
+        cwd.display(),
+        base_prompt
     )
 }
 
@@ -77,7 +81,142 @@ fn get_system_info() -> String {
     )
 }
 
-/// 获取默认的增强prompt
-pub fn get_default_enhanced_prompt() -> String {
-    build_enhanced_prompt(CHAT_PROMPT)
+/// 单个说明文件最多加载的字符数
+const MAX_INSTRUCTION_CHARS: usize = 20_000;
+
+/// 系统提示词的来源：配置的提示词和项目说明文件，开始对话和清空对话时按工作目录重新生成
+#[derive(Debug, Clone, Default)]
+pub struct SystemPrompt {
+    /// 配置的提示词，为空时使用 CHAT_PROMPT
+    pub base: Option<String>,
+    /// 项目说明文件名
+    pub instruction_files: Vec<String>,
+}
+
+impl SystemPrompt {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            base: config.prompt.clone(),
+            instruction_files: config.instruction_files.clone(),
+        }
+    }
+
+    /// 生成系统提示词，项目说明追加在最后
+    pub fn build(&self, cwd: &Path) -> String {
+        let mut prompt = build_enhanced_prompt(self.base.as_deref().unwrap_or(CHAT_PROMPT), cwd);
+        let files = find_instruction_files(
+            cwd,
+            &Config::get_standard_config_dir(),
+            &self.instruction_files,
+        );
+        if let Some(instructions) = load_instructions(&files) {
+            prompt.push_str("\n\n");
+            prompt.push_str(&instructions);
+        }
+        prompt
+    }
+}
+
+/// 按优先级从低到高查找说明文件：配置目录、仓库根目录，然后逐级到当前目录
+///
+/// 仓库根目录是向上第一个包含 .git 的目录，不在仓库中时只查找当前目录
+pub fn find_instruction_files(cwd: &Path, config_dir: &Path, names: &[String]) -> Vec<PathBuf> {
+    let root = cwd
+        .ancestors()
+        .find(|dir| dir.join(".git").exists())
+        .unwrap_or(cwd);
+    let mut dirs: Vec<&Path> = cwd
+        .ancestors()
+        .take_while(|dir| dir.starts_with(root))
+        .collect();
+    dirs.push(config_dir);
+    dirs.reverse();
+
+    let mut files: Vec<PathBuf> = Vec::new();
+    for dir in dirs {
+        for name in names {
+            let path = dir.join(name);
+            if !path.is_file() {
+                continue;
+            }
+            // 配置目录也可能在仓库中，同一个文件只加载一次
+            let path = path.canonicalize().unwrap_or(path);
+            if !files.contains(&path) {
+                files.push(path);
+            }
+        }
+    }
+    files
+}
+
+/// 读取说明文件并合并，后面的文件更具体，冲突时以后面的为准
+pub fn load_instructions(files: &[PathBuf]) -> Option<String> {
+    let sections: Vec<String> = files
+        .iter()
+        .filter_map(|path| match fs::read_to_string(path) {
+            Ok(content) if !content.trim().is_empty() => {
+                info!("加载项目说明 {}", path.display());
+                let content = content.trim();
+                let mut text: String = content.chars().take(MAX_INSTRUCTION_CHARS).collect();
+                if text.len() < content.len() {
+                    text.push_str("\n[内容过长，已截断]");
+                }
+                Some(format!("## {}\n{}", path.display(), text))
+            }
+            Ok(_) => None,
+            Err(e) => {
+                warn!("读取项目说明 {} 失败: {}", path.display(), e);
+                None
+            }
+        })
+        .collect();
+    if sections.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "# 项目说明\n以下说明来自用户和项目的说明文件，按优先级从低到高排列，冲突时以后面的为准。\n\n{}",
+        sections.join("\n\n")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_find_instruction_files() {
+        let tmp = env::temp_dir().join(format!("agent-cli-prompt-{}", Uuid::new_v4()));
+        let config_dir = tmp.join("config");
+        let repo = tmp.join("repo");
+        let cwd = repo.join("crates").join("core");
+        fs::create_dir_all(config_dir.clone()).unwrap();
+        fs::create_dir_all(repo.join(".git")).unwrap();
+        fs::create_dir_all(cwd.join(".agent-cli")).unwrap();
+        // 仓库之外的文件不加载
+        fs::write(tmp.join("AGENTS.md"), "outside").unwrap();
+        fs::write(config_dir.join("AGENTS.md"), "user").unwrap();
+        fs::write(repo.join("AGENTS.md"), "repo").unwrap();
+        fs::write(cwd.join("AGENTS.md"), "  ").unwrap();
+        fs::write(cwd.join(".agent-cli").join("instructions.md"), "core").unwrap();
+
+        let names = vec![
+            "AGENTS.md".to_string(),
+            ".agent-cli/instructions.md".to_string(),
+        ];
+        let files = find_instruction_files(&cwd, &config_dir, &names);
+        let contents: Vec<String> = files
+            .iter()
+            .map(|path| fs::read_to_string(path).unwrap())
+            .collect();
+        assert_eq!(contents, vec!["user", "repo", "  ", "core"]);
+
+        let merged = load_instructions(&files).unwrap();
+        let user = merged.find("\nuser").unwrap();
+        let repo_at = merged.find("\nrepo").unwrap();
+        let core = merged.find("\ncore").unwrap();
+        assert!(user < repo_at && repo_at < core);
+        assert!(find_instruction_files(&cwd, &config_dir, &[]).is_empty());
+        fs::remove_dir_all(&tmp).unwrap();
+    }
 }
@@ -21,10 +21,14 @@ pub struct ClientHandler {
 impl ClientHandler {
     /// 创建一个新的客户端处理器。
     pub fn new(ws_stream: WebSocketStream<TcpStream>, config: Config) -> Self {
+        let mut chat = Chat::new(config.clone());
+        // 每个连接一个会话
+        let cwd = std::env::current_dir().unwrap_or_default();
+        chat.start_session(&cwd);
         Self {
             ws_stream,
-            config: config.clone(),
-            chat: Chat::new(config),
+            config,
+            chat,
         }
     }
 
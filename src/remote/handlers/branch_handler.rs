@@ -0,0 +1,59 @@
+//! 处理回退和分支请求的处理器
+
+use super::base_handler::RequestHandler;
+use crate::chat::Chat;
+use crate::config::Config;
+use crate::remote::protocol::{InputType, RemoteRequest, RemoteResponse, ResponseContent};
+use log::info;
+use tokio::net::TcpStream;
+use tokio_tungstenite::WebSocketStream;
+
+/// 处理 Rewind、ListBranches、SwitchBranch 请求的处理器
+pub struct BranchHandler;
+
+#[async_trait::async_trait]
+impl RequestHandler for BranchHandler {
+    async fn handle(
+        &self,
+        request: RemoteRequest,
+        chat: &mut Chat,
+        _config: &Config,
+        _ws_stream: &mut WebSocketStream<TcpStream>,
+    ) -> RemoteResponse {
+        info!("Handling branch request: {}", request.request_id);
+
+        let result = match &request.input {
+            // 返回被丢弃的第一条消息，客户端可以修改后重新发送
+            InputType::Rewind { index } => chat.rewind(*index).map(|msg| msg.content.to_string()),
+            InputType::ListBranches => Ok(chat
+                .branches()
+                .iter()
+                .map(|branch| branch.summary())
+                .collect::<Vec<String>>()
+                .join("\n")),
+            InputType::SwitchBranch { id } => chat
+                .switch_branch(*id)
+                .map(|_| format!("已切换到分支 #{}", id)),
+            _ => Err(anyhow::anyhow!("不支持的分支请求")),
+        };
+
+        match result {
+            Ok(text) => RemoteResponse {
+                request_id: request.request_id,
+                response: ResponseContent::Text(text),
+                error: None,
+                token_usage: None,
+            },
+            Err(e) => RemoteResponse::error(&request.request_id, &e.to_string()),
+        }
+    }
+
+    fn can_handle(&self, request: &RemoteRequest) -> bool {
+        matches!(
+            &request.input,
+            InputType::Rewind { index: _ }
+                | InputType::ListBranches
+                | InputType::SwitchBranch { id: _ }
+        )
+    }
+}
@@ -4,7 +4,8 @@ use super::base_handler::RequestHandler;
 use crate::chat::Chat;
 use crate::config::Config;
 use crate::mcp;
-use crate::remote::protocol::{RemoteRequest, RemoteResponse};
+use crate::model::param::{ModelMessage, SamplingParams};
+use crate::remote::protocol::{InputType, RemoteRequest, RemoteResponse};
 use anyhow::Result;
 use log::info;
 use tokio::net::TcpStream;
@@ -24,8 +25,21 @@ impl RequestHandler for ChatHandler {
     ) -> RemoteResponse {
         info!("Handling chat request: {}", request.request_id);
 
-        // Extract text from input
-        let input_text = request.input.to_text();
+        // 图片和文件作为附件发送；修改消息时先回退到该消息之前
+        let message = match &request.input {
+            InputType::EditMessage { index, content } => {
+                match chat.edit_message(*index, content.clone()) {
+                    Ok(message) => message,
+                    Err(e) => {
+                        return RemoteResponse::error(
+                            &request.request_id,
+                            &format!("Edit error: {}", e),
+                        );
+                    }
+                }
+            }
+            input => input.to_message(),
+        };
 
         // Configure tools if requested
         let use_tools = request.use_tools.unwrap_or(true);
@@ -35,9 +49,15 @@ impl RequestHandler for ChatHandler {
             chat.set_tools(mcp::get_basic_tools());
         }
 
+        // 请求中的采样参数只对本轮对话生效
+        let sampling = request
+            .config
+            .and_then(|config| config.sampling)
+            .unwrap_or_default();
+
         // Process the chat request with WebSocket
         let result = self
-            .process_chat_with_ws(ws_stream, chat, &input_text, &request.request_id)
+            .process_chat_with_ws(ws_stream, chat, message, sampling, &request.request_id)
             .await;
 
         match result {
@@ -65,11 +85,12 @@ impl ChatHandler {
         &self,
         ws_stream: &mut WebSocketStream<TcpStream>,
         chat: &mut Chat,
-        input: &str,
+        message: ModelMessage,
+        sampling: SamplingParams,
         request_id: &str,
     ) -> Result<RemoteResponse> {
         // Use the shared function from the shared module
         use crate::remote::shared::process_streaming_chat_with_ws;
-        process_streaming_chat_with_ws(ws_stream, chat, input, request_id).await
+        process_streaming_chat_with_ws(ws_stream, chat, message, sampling, request_id).await
     }
 }
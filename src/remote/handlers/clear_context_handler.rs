@@ -24,14 +24,9 @@ impl RequestHandler for ClearContextHandler {
         // 重置对话轮次
         chat.reset_conversation_turn();
 
-        // 清理上下文（保留系统消息）
-        let system_message = chat.context().first().cloned();
+        // 清理上下文，系统消息和项目说明重新加载，之后的对话保存到新会话
         chat.clear_context();
 
-        if let Some(sys_msg) = system_message {
-            chat.add_message(sys_msg);
-        }
-
         info!("Chat context cleared successfully");
 
         RemoteResponse {
@@ -1,22 +1,28 @@
 //! 请求处理器模块
 
 mod base_handler;
+mod branch_handler;
 mod chat_handler;
 mod clear_context_handler;
 mod command_handler;
 mod instruction_handler;
 mod interrupt_handler;
+mod mode_handler;
 mod regenerate_handler;
+mod todo_handler;
 mod tool_confirmation_handler;
 mod turn_confirmation_handler;
 
 pub use base_handler::RequestHandler;
+pub use branch_handler::BranchHandler;
 pub use chat_handler::ChatHandler;
 pub use clear_context_handler::ClearContextHandler;
 pub use command_handler::CommandHandler;
 pub use instruction_handler::InstructionHandler;
 pub use interrupt_handler::InterruptHandler;
+pub use mode_handler::ModeHandler;
 pub use regenerate_handler::RegenerateHandler;
+pub use todo_handler::TodoHandler;
 pub use tool_confirmation_handler::ToolConfirmationHandler;
 pub use turn_confirmation_handler::TurnConfirmationHandler;
 
@@ -39,12 +45,12 @@ impl HandlerFactory {
             InputType::Interrupt => Some(Box::new(InterruptHandler)),
             InputType::Regenerate => Some(Box::new(RegenerateHandler)),
             InputType::ClearContext => Some(Box::new(ClearContextHandler)),
-            InputType::ToolConfirmationResponse {
-                name: _,
-                arguments: _,
-                approved: _,
-                reason: _,
-            } => Some(Box::new(ToolConfirmationHandler)),
+            InputType::Rewind { index: _ }
+            | InputType::ListBranches
+            | InputType::SwitchBranch { id: _ } => Some(Box::new(BranchHandler)),
+            InputType::ToolConfirmationResponse { .. } => Some(Box::new(ToolConfirmationHandler)),
+            InputType::SetMode { mode: _ } | InputType::ApprovePlan => Some(Box::new(ModeHandler)),
+            InputType::GetTodos => Some(Box::new(TodoHandler)),
             InputType::TurnConfirmationResponse {
                 confirmed: _,
                 reason: _,
@@ -0,0 +1,79 @@
+//! 处理 SetMode 和 ApprovePlan 请求的处理器
+
+use super::base_handler::RequestHandler;
+use crate::chat::Chat;
+use crate::config::Config;
+use crate::model::param::{ModelMessage, SamplingParams};
+use crate::remote::protocol::{InputType, RemoteRequest, RemoteResponse, ResponseContent};
+use log::info;
+use tokio::net::TcpStream;
+use tokio_tungstenite::WebSocketStream;
+
+/// 处理会话模式切换和计划批准的处理器
+pub struct ModeHandler;
+
+#[async_trait::async_trait]
+impl RequestHandler for ModeHandler {
+    async fn handle(
+        &self,
+        request: RemoteRequest,
+        chat: &mut Chat,
+        _config: &Config,
+        ws_stream: &mut WebSocketStream<TcpStream>,
+    ) -> RemoteResponse {
+        info!("Handling mode request: {}", request.request_id);
+        match &request.input {
+            InputType::SetMode { mode } => {
+                chat.set_mode(*mode);
+                RemoteResponse {
+                    request_id: request.request_id,
+                    response: ResponseContent::Text(format!(
+                        "已切换到{}：{}",
+                        mode,
+                        mode.description()
+                    )),
+                    error: None,
+                    token_usage: None,
+                }
+            }
+            InputType::ApprovePlan => {
+                let prompt = match chat.approve_plan() {
+                    Ok(prompt) => prompt,
+                    Err(e) => {
+                        return RemoteResponse::error(
+                            &request.request_id,
+                            &format!("Approve plan error: {}", e),
+                        );
+                    }
+                };
+                // 批准后按计划开始执行，和普通聊天请求一样流式返回
+                let result = crate::remote::shared::process_streaming_chat_with_ws(
+                    ws_stream,
+                    chat,
+                    ModelMessage::user(prompt),
+                    SamplingParams::default(),
+                    &request.request_id,
+                )
+                .await;
+                match result {
+                    Ok(mut response) => {
+                        response.request_id = request.request_id;
+                        response
+                    }
+                    Err(e) => RemoteResponse::error(
+                        &request.request_id,
+                        &format!("Processing error: {}", e),
+                    ),
+                }
+            }
+            _ => RemoteResponse::error(&request.request_id, "无效的请求类型"),
+        }
+    }
+
+    fn can_handle(&self, request: &RemoteRequest) -> bool {
+        matches!(
+            &request.input,
+            InputType::SetMode { .. } | InputType::ApprovePlan
+        )
+    }
+}
@@ -24,6 +24,13 @@ impl RequestHandler for RegenerateHandler {
         info!("Handling regenerate request: {}", request.request_id);
 
         if !chat.is_running() {
+            // 先丢弃上一轮的回复，原来的回复保存为分支
+            if let Err(e) = chat.prepare_regenerate(None) {
+                return RemoteResponse::error(
+                    &request.request_id,
+                    &format!("Regeneration error: {}", e),
+                );
+            }
             // 使用 stream_rechat 重新生成回复
             let mut response_chunks = Vec::new();
 
@@ -55,10 +62,22 @@ impl RequestHandler for RegenerateHandler {
                                             tool_response.content
                                         ));
                                     }
+                                    if let Some(truncation) = &tool_response.truncation {
+                                        response_chunks.push(format!(
+                                            "[Tool output truncated: {}/{} chars kept]",
+                                            truncation.kept_chars, truncation.original_chars
+                                        ));
+                                    }
                                 }
                                 StreamedChatResponse::TokenUsage(usage) => {
                                     response_chunks.push(format!("{:?}", usage));
                                 }
+                                StreamedChatResponse::Todos(todos) => {
+                                    response_chunks.push(format!(
+                                        "[Todos: {}]",
+                                        crate::chat::chat_todo::render(&todos)
+                                    ));
+                                }
                                 StreamedChatResponse::End => {
                                     // End marker, do nothing here
                                 }
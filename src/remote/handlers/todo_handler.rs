@@ -0,0 +1,37 @@
+//! 处理 GetTodos 请求的处理器
+
+use super::base_handler::RequestHandler;
+use crate::chat::Chat;
+use crate::config::Config;
+use crate::remote::protocol::{InputType, RemoteRequest, RemoteResponse, ResponseContent};
+use log::info;
+use tokio::net::TcpStream;
+use tokio_tungstenite::WebSocketStream;
+
+/// 返回当前任务列表的处理器
+pub struct TodoHandler;
+
+#[async_trait::async_trait]
+impl RequestHandler for TodoHandler {
+    async fn handle(
+        &self,
+        request: RemoteRequest,
+        chat: &mut Chat,
+        _config: &Config,
+        _ws_stream: &mut WebSocketStream<TcpStream>,
+    ) -> RemoteResponse {
+        info!("Handling get todos request: {}", request.request_id);
+        RemoteResponse {
+            request_id: request.request_id,
+            response: ResponseContent::Todos {
+                todos: chat.todos().to_vec(),
+            },
+            error: None,
+            token_usage: None,
+        }
+    }
+
+    fn can_handle(&self, request: &RemoteRequest) -> bool {
+        matches!(&request.input, InputType::GetTodos)
+    }
+}
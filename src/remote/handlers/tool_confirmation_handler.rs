@@ -1,10 +1,13 @@
 //! 处理 ToolConfirmationResponse 请求的处理器
 
+use super::TurnConfirmationHandler;
 use super::base_handler::RequestHandler;
+use crate::chat::chat_permission::{GrantScope, ToolCallAnswer};
 use crate::chat::{Chat, EChatState};
 use crate::config::Config;
-use crate::remote::protocol::{InputType, RemoteRequest, RemoteResponse};
-use log::{info, warn};
+use crate::remote::protocol::{InputType, RemoteRequest, RemoteResponse, ResponseContent};
+use crate::remote::shared::tool_confirmation_request;
+use log::info;
 use tokio::net::TcpStream;
 use tokio_tungstenite::WebSocketStream;
 
@@ -18,13 +21,16 @@ impl RequestHandler for ToolConfirmationHandler {
         request: RemoteRequest,
         chat: &mut Chat,
         _config: &Config,
-        _ws_stream: &mut WebSocketStream<TcpStream>,
+        ws_stream: &mut WebSocketStream<TcpStream>,
     ) -> RemoteResponse {
         let InputType::ToolConfirmationResponse {
             name,
-            arguments,
+            arguments: _,
             approved,
             reason,
+            tool_call_id,
+            always_allow,
+            exact,
         } = &request.input
         else {
             return RemoteResponse::error(
@@ -34,95 +40,87 @@ impl RequestHandler for ToolConfirmationHandler {
         };
 
         info!(
-            "Handling tool confirmation response: {} - tool: {}, approved: {}",
-            request.request_id, name, approved
+            "Handling tool confirmation response: {} - tool: {}, call: {:?}, approved: {}",
+            request.request_id, name, tool_call_id, approved
         );
 
-        if chat.get_state() == EChatState::WaitingToolConfirm {
-            // 验证工具名称和参数是否匹配
-            let mut validation_error = None;
-
-            // 获取等待确认的工具调用
-            if let Some(last_msg) = chat.context().last() {
-                if let Some(tool_calls) = &last_msg.tool_calls {
-                    if let Some(tool_call) = tool_calls.first() {
-                        // 验证工具名称
-                        if tool_call.function.name != *name {
-                            validation_error = Some(format!(
-                                "Tool name mismatch: expected '{}', got '{}'",
-                                tool_call.function.name, name
-                            ));
-                        } else {
-                            // 验证参数（可选，因为参数可能被序列化为字符串）
-                            // 尝试解析工具调用中的参数
-                            match serde_json::from_str::<serde_json::Value>(
-                                &tool_call.function.arguments,
-                            ) {
-                                Ok(expected_args) => {
-                                    // 简单比较JSON值是否相等
-                                    if &expected_args != arguments {
-                                        warn!("Tool arguments mismatch for tool '{}'", name);
-                                        // 这里不视为错误，因为参数格式可能不同
-                                    }
-                                }
-                                Err(e) => {
-                                    warn!("Failed to parse tool arguments for validation: {}", e);
-                                }
-                            }
-                        }
-                    } else {
-                        validation_error = Some("No tool call found in last message".to_string());
-                    }
-                } else {
-                    validation_error = Some("No tool calls found in last message".to_string());
-                }
-            } else {
-                validation_error = Some("No last message found in chat context".to_string());
-            }
+        if chat.get_state() != EChatState::WaitingToolConfirm {
+            return RemoteResponse::error(
+                &request.request_id,
+                "No pending tool confirmation found",
+            );
+        }
 
-            // 如果有验证错误，返回错误响应
-            if let Some(error_msg) = validation_error {
-                return RemoteResponse::error(
-                    &request.request_id,
-                    &format!("Tool confirmation validation failed: {}", error_msg),
-                );
-            }
+        // 验证工具名称，指定了调用 ID 时按 ID 查找，否则任意一个等待确认的调用同名即可
+        let pending = chat.pending_confirmations();
+        let matched = pending.iter().any(|(call, _)| {
+            call.function.name == *name && tool_call_id.as_ref().is_none_or(|id| call.id == *id)
+        });
+        if !matched {
+            return RemoteResponse::error(
+                &request.request_id,
+                &format!(
+                    "Tool confirmation validation failed: no pending call of tool '{}' with id {:?}",
+                    name, tool_call_id
+                ),
+            );
+        }
 
-            // 设置工具确认结果
-            if *approved {
-                chat.confirm();
-            } else {
-                chat.reject_tool_call();
-            }
+        let answer = match (approved, always_allow) {
+            (false, _) => ToolCallAnswer::Reject(reason.clone()),
+            (true, Some(scope)) => ToolCallAnswer::AlwaysAllow {
+                exact: exact.unwrap_or(*scope == GrantScope::Project),
+                scope: *scope,
+            },
+            (true, None) => ToolCallAnswer::Approve,
+        };
+        // 没有指定调用 ID 时答复所有等待确认的调用
+        let result = match tool_call_id {
+            Some(id) => chat.answer_tool_call(id, answer),
+            None => chat.answer_tool_calls(answer),
+        };
+        if let Err(e) = result {
+            return RemoteResponse::error(
+                &request.request_id,
+                &format!("Tool confirmation processing error: {}", e),
+            );
+        }
 
-            // 如果有原因，记录下来
-            if let Some(reason) = reason {
-                info!("Tool confirmation reason: {}", reason);
+        let response = match chat.get_state() {
+            // 还有调用等待确认，发送下一个确认请求
+            EChatState::WaitingToolConfirm => tool_confirmation_request(chat)
+                .map(|response| RemoteResponse {
+                    request_id: String::new(),
+                    response,
+                    error: None,
+                    token_usage: None,
+                })
+                .ok_or_else(|| anyhow::anyhow!("No pending tool confirmation found")),
+            // 都已答复，继续执行工具调用并把结果发送给模型
+            EChatState::WaitingToolUse => {
+                TurnConfirmationHandler
+                    .continue_conversation_with_ws(ws_stream, chat, &request.request_id)
+                    .await
             }
-
-            // 继续处理工具调用
-            let result: Result<RemoteResponse, ()> = Ok(RemoteResponse {
+            _ => Ok(RemoteResponse {
                 request_id: String::new(),
-                response: crate::remote::protocol::ResponseContent::Text(format!(
-                    "Tool '{}' execution was not approved by user",
-                    name
-                )),
+                response: ResponseContent::Text(
+                    "Tool execution was not approved by user".to_string(),
+                ),
                 error: None,
                 token_usage: None,
-            });
+            }),
+        };
 
-            match result {
-                Ok(mut response) => {
-                    response.request_id = request.request_id;
-                    response
-                }
-                Err(e) => RemoteResponse::error(
-                    &request.request_id,
-                    &format!("Tool confirmation processing error: {:?}", e),
-                ),
+        match response {
+            Ok(mut response) => {
+                response.request_id = request.request_id;
+                response
             }
-        } else {
-            RemoteResponse::error(&request.request_id, "No pending tool confirmation found")
+            Err(e) => RemoteResponse::error(
+                &request.request_id,
+                &format!("Tool confirmation processing error: {}", e),
+            ),
         }
     }
 
@@ -26,8 +26,11 @@ impl RequestHandler for TurnConfirmationHandler {
             request.request_id
         );
 
+        // 工具调用暂停时复用对话轮次确认协议
+        let loop_paused = chat.get_state() == crate::chat::EChatState::WaitingLoopConfirm;
+
         // 检查当前状态是否为 WaitingTurnConfirm
-        if chat.get_state() != crate::chat::EChatState::WaitingTurnConfirm {
+        if !loop_paused && chat.get_state() != crate::chat::EChatState::WaitingTurnConfirm {
             return RemoteResponse {
                 request_id: request.request_id,
                 response: crate::remote::protocol::ResponseContent::Text(
@@ -56,13 +59,29 @@ impl RequestHandler for TurnConfirmationHandler {
             }
         };
 
+        if loop_paused && !confirmed {
+            chat.confirm_tool_loop(false);
+            return RemoteResponse {
+                request_id: request.request_id,
+                response: crate::remote::protocol::ResponseContent::Text(
+                    "已停止执行工具调用".to_string(),
+                ),
+                error: None,
+                token_usage: None,
+            };
+        }
+
         if confirmed {
-            // 重置对话轮次
-            chat.reset_conversation_turn();
-            info!("对话轮次已重置");
+            if loop_paused {
+                chat.confirm_tool_loop(true);
+            } else {
+                // 重置对话轮次
+                chat.reset_conversation_turn();
+                info!("对话轮次已重置");
 
-            // 调用 confirm 函数
-            chat.confirm();
+                // 调用 confirm 函数
+                chat.confirm();
+            }
 
             // 继续处理对话（使用 stream_rechat）
             info!("继续处理对话");
@@ -106,7 +125,7 @@ impl RequestHandler for TurnConfirmationHandler {
 
 impl TurnConfirmationHandler {
     /// 继续处理对话（带WebSocket交互）
-    async fn continue_conversation_with_ws(
+    pub(super) async fn continue_conversation_with_ws(
         &self,
         ws_stream: &mut WebSocketStream<TcpStream>,
         chat: &mut Chat,
@@ -285,37 +304,23 @@ impl TurnConfirmationHandler {
         }
 
         // 发送工具确认协议
-        if chat.get_state() == crate::chat::EChatState::WaitingToolConfirm {
-            // Get the last tool call from context
-            if let Some(last_msg) = chat.context().last() {
-                if let Some(tool_calls) = &last_msg.tool_calls {
-                    if let Some(tool_call) = tool_calls.first() {
-                        // Parse arguments string to JSON value
-                        let arguments: serde_json::Value =
-                            match serde_json::from_str(&tool_call.function.arguments) {
-                                Ok(args) => args,
-                                Err(e) => {
-                                    // If parsing fails, create an empty object
-                                    log::warn!("Failed to parse tool arguments as JSON: {}", e);
-                                    serde_json::json!({})
-                                }
-                            };
-
-                        // Return a tool confirmation request
-                        return Ok(RemoteResponse {
-                            request_id: String::new(), // Will be replaced by caller
-                            response:
-                                crate::remote::protocol::ResponseContent::ToolConfirmationRequest {
-                                    name: tool_call.function.name.clone(),
-                                    arguments,
-                                    description: None,
-                                },
-                            error: None,
-                            token_usage: None,
-                        });
-                    }
-                }
-            }
+        if let Some(response) = crate::remote::shared::tool_confirmation_request(chat) {
+            return Ok(RemoteResponse {
+                request_id: String::new(), // Will be replaced by caller
+                response,
+                error: None,
+                token_usage: None,
+            });
+        }
+
+        // 发送工具调用暂停确认协议
+        if let Some(response) = crate::remote::shared::tool_loop_confirmation_request(chat) {
+            return Ok(RemoteResponse {
+                request_id: String::new(), // Will be replaced by caller
+                response,
+                error: None,
+                token_usage: None,
+            });
         }
 
         // 发送对话轮次确认协议（如果再次超过限制）
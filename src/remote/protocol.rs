@@ -6,6 +6,10 @@ use serde::{Deserialize, Serialize};
 use std::fmt;
 
 use crate::chat::StreamedChatResponse;
+use crate::chat::chat_mode::ChatMode;
+use crate::chat::chat_permission::GrantScope;
+use crate::chat::chat_todo::TodoItem;
+use crate::model::param::{ContentPart, ModelMessage, SamplingParams};
 
 /// 可以从远程客户端发送的输入类型。
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -36,6 +40,14 @@ pub enum InputType {
     Interrupt,
     /// 重新生成最后的回复
     Regenerate,
+    /// 回退到指定消息之前，原来的对话保存为分支
+    Rewind { index: usize },
+    /// 修改指定的用户消息并从这里重新生成
+    EditMessage { index: usize, content: String },
+    /// 列出对话分支
+    ListBranches,
+    /// 切换到指定分支
+    SwitchBranch { id: usize },
     /// 清理聊天上下文，重置对话轮次
     ClearContext,
     /// 工具确认响应
@@ -43,7 +55,17 @@ pub enum InputType {
         name: String,
         arguments: serde_json::Value,
         approved: bool,
+        /// 拒绝的原因，会返回给模型
         reason: Option<String>,
+        /// 答复的调用 ID，为空时答复所有等待确认的调用
+        #[serde(default)]
+        tool_call_id: Option<String>,
+        /// 同意时总是允许该工具的范围
+        #[serde(default)]
+        always_allow: Option<GrantScope>,
+        /// 总是允许时只允许参数完全相同的调用，未指定时项目范围默认只允许相同参数的调用
+        #[serde(default)]
+        exact: Option<bool>,
     },
     /// 对话轮次确认响应
     TurnConfirmationResponse {
@@ -52,9 +74,47 @@ pub enum InputType {
         /// 可选的确认原因
         reason: Option<String>,
     },
+    /// 切换会话模式
+    SetMode { mode: ChatMode },
+    /// 批准计划模式下给出的计划，切换到执行模式并开始执行
+    ApprovePlan,
+    /// 获取模型记录的任务列表
+    GetTodos,
 }
 
 impl InputType {
+    /// 转换为用户消息，图片和文件作为附件。
+    pub fn to_message(&self) -> ModelMessage {
+        let mut text = Vec::new();
+        let mut parts = Vec::new();
+        self.collect(&mut text, &mut parts);
+        ModelMessage::user(text.join("\n")).with_parts(parts)
+    }
+
+    fn collect(&self, text: &mut Vec<String>, parts: &mut Vec<ContentPart>) {
+        match self {
+            InputType::Image { data, mime_type } => parts.push(ContentPart::Image {
+                mime_type: mime_type.clone().unwrap_or("image/png".to_string()),
+                data: data.clone(),
+            }),
+            InputType::File {
+                filename,
+                content_type,
+                data,
+            } => parts.push(ContentPart::File {
+                name: filename.clone(),
+                mime_type: content_type.clone(),
+                data: data.clone(),
+            }),
+            InputType::Multi(inputs) => {
+                for input in inputs {
+                    input.collect(text, parts);
+                }
+            }
+            other => text.push(other.to_text()),
+        }
+    }
+
     /// 从输入中提取文本内容。
     /// 对于非文本输入，返回描述性字符串。
     pub fn to_text(&self) -> String {
@@ -83,12 +143,19 @@ impl InputType {
             InputType::GetCommands => "[GetCommands]".to_string(),
             InputType::Interrupt => "[Interrupt]".to_string(),
             InputType::Regenerate => "[Regenerate]".to_string(),
+            InputType::Rewind { index } => format!("[Rewind: {}]", index),
+            InputType::EditMessage { index, content } => {
+                format!("[EditMessage: {} {}]", index, content)
+            }
+            InputType::ListBranches => "[ListBranches]".to_string(),
+            InputType::SwitchBranch { id } => format!("[SwitchBranch: {}]", id),
             InputType::ClearContext => "[ClearContext]".to_string(),
             InputType::ToolConfirmationResponse {
                 name,
                 arguments,
                 approved,
                 reason,
+                ..
             } => {
                 format!(
                     "[ToolConfirmationResponse: {} with args: {}, approved: {}, reason: {}]",
@@ -105,6 +172,9 @@ impl InputType {
                     reason.as_deref().unwrap_or("none")
                 )
             }
+            InputType::SetMode { mode } => format!("[SetMode: {}]", mode.id()),
+            InputType::ApprovePlan => "[ApprovePlan]".to_string(),
+            InputType::GetTodos => "[GetTodos]".to_string(),
         }
     }
 }
@@ -141,6 +211,9 @@ pub struct RequestConfig {
     pub ask_before_tool_execution: Option<bool>,
     /// 自定义提示/指令
     pub prompt: Option<String>,
+    /// 本次请求的采样参数，覆盖配置中的默认值
+    #[serde(default)]
+    pub sampling: Option<SamplingParams>,
 }
 
 /// 从服务器到远程客户端的响应。
@@ -175,11 +248,14 @@ pub enum ResponseContent {
         name: String,
         result: serde_json::Value,
     },
-    /// 工具确认请求
+    /// 工具确认请求，name 和 arguments 是第一个等待确认的调用
     ToolConfirmationRequest {
         name: String,
         arguments: serde_json::Value,
         description: Option<String>,
+        /// 本批所有待处理的调用
+        #[serde(default)]
+        tool_calls: Vec<PendingToolCall>,
     },
     /// 工具确认响应
     ToolConfirmationResponse {
@@ -197,6 +273,8 @@ pub enum ResponseContent {
         /// 可选的请求原因
         reason: Option<String>,
     },
+    /// 模型记录的任务列表，todo 工具更新时随流式响应发送
+    Todos { todos: Vec<TodoItem> },
     /// 流式响应完成标记
     StreamComplete {
         /// 令牌使用统计信息
@@ -206,6 +284,18 @@ pub enum ResponseContent {
     },
 }
 
+/// 待处理的工具调用及权限判断结果。
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PendingToolCall {
+    pub id: String,
+    pub name: String,
+    pub arguments: serde_json::Value,
+    /// 权限判断结果的说明
+    pub decision: String,
+    /// 是否需要用户确认
+    pub needs_confirmation: bool,
+}
+
 /// 令牌使用统计信息。
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct TokenUsage {
@@ -245,6 +335,12 @@ impl RemoteResponse {
                     token_usage: None,
                 })
             }
+            StreamedChatResponse::Todos(todos) => Ok(RemoteResponse {
+                request_id,
+                response: ResponseContent::Todos { todos },
+                error: None,
+                token_usage: None,
+            }),
             _ => Err(()),
         }
     }
@@ -3,8 +3,10 @@
 use crate::chat::Chat;
 use crate::chat::EChatState;
 use crate::chat::StreamedChatResponse;
+use crate::chat::chat_permission::PermissionAction;
+use crate::model::param::{ModelMessage, SamplingParams};
 use crate::remote::protocol::{
-    InputType, RemoteRequest, RemoteResponse, ResponseContent, TokenUsage,
+    InputType, PendingToolCall, RemoteRequest, RemoteResponse, ResponseContent, TokenUsage,
 };
 use anyhow::Result;
 use futures::{SinkExt, StreamExt};
@@ -14,11 +16,53 @@ use tokio::sync::mpsc;
 use tokio_tungstenite::WebSocketStream;
 use tokio_tungstenite::tungstenite::Message;
 
+/// 等待工具确认时生成确认请求，列出本批所有待处理的调用
+pub fn tool_confirmation_request(chat: &Chat) -> Option<ResponseContent> {
+    if chat.get_state() != EChatState::WaitingToolConfirm {
+        return None;
+    }
+    let tool_calls: Vec<PendingToolCall> = chat
+        .tool_permissions()
+        .into_iter()
+        .map(|(call, decision)| PendingToolCall {
+            // Parse arguments string to JSON value
+            arguments: serde_json::from_str(&call.function.arguments).unwrap_or_else(|e| {
+                warn!("Failed to parse tool arguments as JSON: {}", e);
+                serde_json::json!({})
+            }),
+            id: call.id,
+            name: call.function.name,
+            decision: decision.to_string(),
+            needs_confirmation: decision.action == PermissionAction::Ask,
+        })
+        .collect();
+    let first = tool_calls.iter().find(|call| call.needs_confirmation)?;
+    Some(ResponseContent::ToolConfirmationRequest {
+        name: first.name.clone(),
+        arguments: first.arguments.clone(),
+        description: Some(first.decision.clone()),
+        tool_calls,
+    })
+}
+
+/// 工具调用陷入循环或达到本轮上限时生成确认请求，客户端确认后继续执行
+pub fn tool_loop_confirmation_request(chat: &Chat) -> Option<ResponseContent> {
+    let reason = chat.tool_loop_pause()?;
+    let (current_turns, max_turns) = chat.tool_loop_info();
+    info!("发送工具调用暂停确认请求: {}", reason);
+    Some(ResponseContent::TurnConfirmationRequest {
+        current_turns,
+        max_turns,
+        reason: Some(format!("{}，已暂停。是否继续执行？", reason)),
+    })
+}
+
 /// 处理流式聊天响应的共享函数
 pub async fn process_streaming_chat_with_ws(
     ws_stream: &mut WebSocketStream<TcpStream>,
     chat: &mut Chat,
-    input: &str,
+    message: ModelMessage,
+    sampling: SamplingParams,
     request_id: &str,
 ) -> Result<RemoteResponse> {
     let mut tool_errors = Vec::new();
@@ -27,13 +71,12 @@ pub async fn process_streaming_chat_with_ws(
     // 创建一个通道来接收聊天流的结果
     let (tx, mut rx) = mpsc::channel::<Result<StreamedChatResponse, anyhow::Error>>(32);
 
-    let input_clone = input.to_string();
     let mut chat_clone = chat.clone();
 
     // 创建一个单独的任务来处理聊天流
     let chat_task = tokio::spawn(async move {
         {
-            let stream = chat_clone.stream_chat(&input_clone);
+            let stream = chat_clone.stream_chat_message(message, sampling);
             futures::pin_mut!(stream);
 
             while let Some(result) = stream.next().await {
@@ -184,36 +227,23 @@ pub async fn process_streaming_chat_with_ws(
     }
 
     // 发送工具确认协议
-    if chat.get_state() == EChatState::WaitingToolConfirm {
-        // Get the last tool call from context
-        if let Some(last_msg) = chat.context().last() {
-            if let Some(tool_calls) = &last_msg.tool_calls {
-                if let Some(tool_call) = tool_calls.first() {
-                    // Parse arguments string to JSON value
-                    let arguments: serde_json::Value =
-                        match serde_json::from_str(&tool_call.function.arguments) {
-                            Ok(args) => args,
-                            Err(e) => {
-                                // If parsing fails, create an empty object
-                                warn!("Failed to parse tool arguments as JSON: {}", e);
-                                serde_json::json!({})
-                            }
-                        };
+    if let Some(response) = tool_confirmation_request(chat) {
+        return Ok(RemoteResponse {
+            request_id: String::new(), // Will be replaced by caller
+            response,
+            error: None,
+            token_usage: None,
+        });
+    }
 
-                    // Return a tool confirmation request
-                    return Ok(RemoteResponse {
-                        request_id: String::new(), // Will be replaced by caller
-                        response: ResponseContent::ToolConfirmationRequest {
-                            name: tool_call.function.name.clone(),
-                            arguments,
-                            description: None,
-                        },
-                        error: None,
-                        token_usage: None,
-                    });
-                }
-            }
-        }
+    // 发送工具调用暂停确认协议
+    if let Some(response) = tool_loop_confirmation_request(chat) {
+        return Ok(RemoteResponse {
+            request_id: String::new(), // Will be replaced by caller
+            response,
+            error: None,
+            token_usage: None,
+        });
     }
 
     // 发送对话轮次确认协议
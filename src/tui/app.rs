@@ -10,14 +10,16 @@ use tokio_util::sync::CancellationToken;
 
 use crate::{
     Args,
-    chat::Chat,
+    chat::{Chat, SessionRecord, chat_todo::TodoItem},
     mcp,
     model::param::ModelMessage,
     tui::{
         appevent::AppEvent,
         renderer::Renderer,
+        send_event,
         state_manager::StateManager,
         ui::option_dialog::OptionDialog,
+        ui::todopanel::TodoPanel,
         ui::{inputarea::InputArea, messageblock::MessageBlock},
     },
 };
@@ -29,6 +31,7 @@ pub enum ETuiEvent {
     ScrollToBottom,
     RefreshUI,
     UpdateMessage(usize, ModelMessage),
+    UpdateTodos(Vec<TodoItem>),
     Exit,
 }
 
@@ -64,6 +67,8 @@ pub struct App {
     pub commands: Vec<String>,
     /// 选项对话框
     pub option_dialog: OptionDialog,
+    /// 任务列表面板
+    pub todo_panel: TodoPanel,
 }
 
 impl App {
@@ -97,6 +102,7 @@ impl App {
             cursor_offset: 0,
             commands,
             option_dialog: OptionDialog::new(),
+            todo_panel: TodoPanel::new(),
         }
     }
 
@@ -207,6 +213,40 @@ impl App {
         }
     }
 
+    /// 开启会话持久化，恢复会话时显示之前的对话
+    pub fn open_session(&mut self, session: Option<SessionRecord>) {
+        let restored = session.as_ref().map(|record| record.meta.clone());
+        crate::open_session(&mut self.chat.lock().unwrap(), session);
+        if let Some(meta) = restored {
+            self.reload_messages();
+            self.add_info_message(&format!("已恢复会话 {} {}", meta.id, meta.title));
+        }
+    }
+
+    /// 对话记录被替换后（恢复会话、回退、切换分支），按聊天上下文重新显示消息
+    pub fn reload_messages(&mut self) {
+        self.todo_panel.todos = self.chat.lock().unwrap().todos().to_vec();
+        let messages: Vec<ModelMessage> = self
+            .chat
+            .lock()
+            .unwrap()
+            .context()
+            .iter()
+            .filter(|msg| match msg.role.as_ref() {
+                "user" | "tool" => true,
+                "assistant" => !msg.content.is_empty() || !msg.think.is_empty(),
+                _ => false,
+            })
+            .cloned()
+            .collect();
+        self.blocks.clear();
+        self.max_line = 0;
+        self.index = 0;
+        self.messages = messages;
+        self.refresh();
+        send_event(&self.event_tx, ETuiEvent::ScrollToBottom);
+    }
+
     /// 添加信息消息
     ///
     /// 添加一个信息消息到信息消息列表中，用于显示指令、提示等信息。
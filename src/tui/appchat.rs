@@ -28,30 +28,34 @@ impl AppChat {
         tx: mpsc::Sender<ETuiEvent>,
     ) {
         info!("处理聊天");
-        {
-            if selfchat.lock().unwrap().get_state() != EChatState::Idle {
+        // 获取聊天实例并克隆，共享的实例标记为运行中
+        let mut chat = {
+            let mut shared = selfchat.lock().unwrap();
+            if shared.get_state() != EChatState::Idle {
                 info!("正忙碌");
                 return;
             }
-        }
-        // 获取聊天实例并克隆
+            let chat = shared.clone();
+            shared.begin_run();
+            chat
+        };
         if !input.content.is_empty() {
-            selfchat
-                .lock()
-                .unwrap()
-                .add_message(ModelMessage::user(input.content.clone()));
-            selfchat
-                .lock()
-                .unwrap()
-                .add_message(ModelMessage::user(input.content.clone()));
             send_event(
                 &tx,
                 ETuiEvent::AddMessage(ModelMessage::user(input.content.clone())),
             );
             idx += 1;
+            // 输入被钩子阻止时不发起对话
+            if let Err(e) = chat
+                .submit_prompt(ModelMessage::user(input.content.clone()))
+                .await
+            {
+                Self::handle_stream_error(e, &tx);
+                *selfchat.lock().unwrap() = chat;
+                return;
+            }
         }
 
-        let mut chat = { selfchat.lock().unwrap().clone() };
         let stream = chat.stream_rechat();
         // 发送初始滚动信号
         send_event(&tx, ETuiEvent::ScrollToBottom);
@@ -119,6 +123,9 @@ impl AppChat {
                             error!("{:?}", e);
                         }
                     }
+                    StreamedChatResponse::Todos(todos) => {
+                        send_event(tx, ETuiEvent::UpdateTodos(todos));
+                    }
                     StreamedChatResponse::End => {
                         idx += 1;
                     }
@@ -139,12 +146,20 @@ impl AppChat {
         selfchat: Arc<Mutex<Chat>>,
         tx: mpsc::Sender<ETuiEvent>,
     ) {
-        // 检查是否正在等待对话轮次确认
-        let mut guard = { selfchat.lock().unwrap().clone() };
-        if guard.get_state() != EChatState::Idle {
-            info!("正忙碌");
-            return;
-        }
+        // 工具调用确认后处于等待执行状态，其他非空闲状态说明正在处理
+        let mut guard = {
+            let mut shared = selfchat.lock().unwrap();
+            if !matches!(
+                shared.get_state(),
+                EChatState::Idle | EChatState::WaitingToolUse
+            ) {
+                info!("正忙碌");
+                return;
+            }
+            let guard = shared.clone();
+            shared.begin_run();
+            guard
+        };
         let stream = guard.stream_rechat();
         send_event(&tx, ETuiEvent::ScrollToBottom);
         // 处理流式响应
@@ -5,9 +5,13 @@ use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
 use tokio_util::sync::CancellationToken;
 
 use crate::{
-    chat::EChatState,
+    chat::{EChatState, chat_mode::ChatMode},
+    model::param::ModelMessage,
     perf_end, perf_start,
-    tui::app::{App, ETuiEvent},
+    tui::{
+        app::{App, ETuiEvent},
+        send_event,
+    },
 };
 
 /// 事件处理器，负责处理键盘事件和事件监听
@@ -156,38 +160,47 @@ impl AppEvent {
                 app.input.hide_suggestions();
                 app.cursor_offset = 0;
 
-                // 使用block_in_place来执行阻塞操作
-                // 这会通知Tokio运行时当前线程将暂时阻塞
-                tokio::task::block_in_place(|| {
-                    // 在block_in_place中创建新的运行时
-                    // 这会在当前线程中创建一个新的运行时，而不是在Tokio工作线程中
-                    let rt = tokio::runtime::Runtime::new().unwrap();
-                    rt.block_on(async {
-                        app.execute_command(&command).await;
-                    });
-                });
+                Self::run_command(app, &command);
                 perf_end!(monitor);
                 return;
             }
         }
 
+        // 带参数的命令没有匹配的提示，按输入的内容执行
+        let content = app.input.content.clone();
+        if let Some(name) = content
+            .strip_prefix('/')
+            .and_then(|c| c.split_whitespace().next())
+            && crate::tui::global_registry().find(name).is_some()
+        {
+            app.input.clear();
+            app.cursor_offset = 0;
+            Self::run_command(app, &content);
+            perf_end!(monitor);
+            return;
+        }
+
         let mut chat = { app.chat.lock().unwrap() };
         if !chat.is_running() {
             match chat.get_state() {
                 EChatState::WaitingToolConfirm => {
-                    let res = app.input.content.to_lowercase();
+                    let res = app.input.content.clone();
                     app.input.clear();
-                    if res == "y" || res == "yes" {
-                        info!("确认工具调用");
-                        chat.confirm();
-                        // 继续执行工具调用
-                        tokio::spawn(crate::tui::appchat::AppChat::handle_tool_execution(
-                            app.messages.len(),
-                            app.chat.clone(),
-                            app.event_tx.clone(),
-                        ));
-                    } else if res == "no" || res == "n" {
-                        chat.reject_tool_call();
+                    // 每次答复第一个等待确认的调用，都答复后继续执行
+                    match chat.reply_tool_confirm(&res) {
+                        Ok(()) if chat.get_state() == EChatState::WaitingToolUse => {
+                            info!("工具调用确认完成");
+                            tokio::spawn(crate::tui::appchat::AppChat::handle_tool_execution(
+                                app.messages.len(),
+                                app.chat.clone(),
+                                app.event_tx.clone(),
+                            ));
+                        }
+                        Ok(()) => send_event(&app.event_tx, ETuiEvent::RefreshUI),
+                        Err(e) => send_event(
+                            &app.event_tx,
+                            ETuiEvent::AddMessage(ModelMessage::info(e.to_string())),
+                        ),
                     }
                 }
                 // 处理对话轮次确认
@@ -218,6 +231,28 @@ impl AppEvent {
                         chat.confirm();
                     }
                 }
+                // 工具调用暂停，可以继续、停止，或者输入新的指示
+                EChatState::WaitingLoopConfirm => {
+                    let res = app.input.content.to_lowercase();
+                    if res == "y" || res == "yes" {
+                        chat.confirm_tool_loop(true);
+                        tokio::spawn(crate::tui::appchat::AppChat::handle_tool_execution(
+                            app.messages.len(),
+                            app.chat.clone(),
+                            app.event_tx.clone(),
+                        ));
+                    } else if res == "n" || res == "no" {
+                        chat.confirm_tool_loop(false);
+                        send_event(&app.event_tx, ETuiEvent::RefreshUI);
+                    } else {
+                        tokio::spawn(crate::tui::appchat::AppChat::handle_chat(
+                            app.messages.len(),
+                            app.chat.clone(),
+                            app.input.clone(),
+                            app.event_tx.clone(),
+                        ));
+                    }
+                }
                 EChatState::Idle => {
                     info!("Idle 状态，开始对话");
                     tokio::spawn(crate::tui::appchat::AppChat::handle_chat(
@@ -236,6 +271,40 @@ impl AppEvent {
         perf_end!(monitor);
     }
 
+    /// 执行命令
+    fn run_command(app: &mut App, command: &str) {
+        // 使用block_in_place来执行阻塞操作
+        // 这会通知Tokio运行时当前线程将暂时阻塞
+        tokio::task::block_in_place(|| {
+            // 在block_in_place中创建新的运行时
+            // 这会在当前线程中创建一个新的运行时，而不是在Tokio工作线程中
+            let rt = tokio::runtime::Runtime::new().unwrap();
+            rt.block_on(async {
+                app.execute_command(command).await;
+            });
+        });
+    }
+
+    /// 处理 Shift+Tab：在执行模式和计划模式之间切换
+    pub fn handle_mode_toggle(app: &mut App) {
+        let mode = {
+            let mut chat = app.chat.lock().unwrap();
+            // 对话在副本上进行，这时切换不会影响正在进行的对话，结束后还会被覆盖
+            if chat.is_running() {
+                drop(chat);
+                app.add_system_message("对话进行中，无法切换模式");
+                return;
+            }
+            let mode = match chat.mode() {
+                ChatMode::Execute => ChatMode::Plan,
+                ChatMode::Plan => ChatMode::Execute,
+            };
+            chat.set_mode(mode);
+            mode
+        };
+        app.add_system_message(&format!("已切换到{}：{}", mode, mode.description()));
+    }
+
     /// 处理字符键：输入文本
     pub fn handle_char_key(app: &mut App, c: char) {
         let idx = app.input.get_index_by_width(app.cursor_offset);
@@ -263,6 +332,7 @@ impl AppEvent {
                         }
                         KeyCode::Delete | KeyCode::Backspace => Self::handle_delete_keys(app),
                         KeyCode::Enter => Self::handle_enter_key(app),
+                        KeyCode::BackTab => Self::handle_mode_toggle(app),
                         KeyCode::Char(c) => Self::handle_char_key(app, c),
                         _ => {}
                     }
@@ -299,6 +369,13 @@ impl AppEvent {
                     warn!("更新信息的下标有误 {}", idx);
                 }
             }
+            ETuiEvent::UpdateTodos(todos) => {
+                if let Err(e) = app.event_tx.send(ETuiEvent::RefreshUI) {
+                    error!("{:?}", e);
+                }
+                info!("UpdateTodos {:?}", todos);
+                app.todo_panel.todos = todos;
+            }
             ETuiEvent::ScrollToBottom => {
                 // 处理滚动到底部事件
                 if app.max_line > app.window_height {
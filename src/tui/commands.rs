@@ -2,6 +2,9 @@ use async_trait::async_trait;
 use log::error;
 use std::fmt::Debug;
 
+use crate::chat::{EChatState, SessionStore, chat_mode::ChatMode};
+use crate::model::param::{ModelMessage, SamplingParams};
+
 /// TUI斜杠命令trait
 ///
 /// 所有TUI斜杠命令都需要实现这个trait
@@ -79,9 +82,23 @@ pub fn init_global_registry() -> &'static CommandRegistry {
         registry.register(Box::new(ClearCommand));
         registry.register(Box::new(ExitCommand));
         registry.register(Box::new(ResetCommand));
+        registry.register(Box::new(CompressCommand));
         registry.register(Box::new(HistoryCommand));
         registry.register(Box::new(ToolsCommand));
         registry.register(Box::new(ConfigCommand));
+        registry.register(Box::new(ModelsCommand));
+        registry.register(Box::new(ModelCommand));
+        registry.register(Box::new(SamplingCommand));
+        registry.register(Box::new(SessionsCommand));
+        registry.register(Box::new(ResumeCommand));
+        registry.register(Box::new(UndoCommand));
+        registry.register(Box::new(RewindCommand));
+        registry.register(Box::new(RetryCommand));
+        registry.register(Box::new(BranchesCommand));
+        registry.register(Box::new(BranchCommand));
+        registry.register(Box::new(PinCommand));
+        registry.register(Box::new(PlanCommand));
+        registry.register(Box::new(ApproveCommand));
 
         registry
     })
@@ -134,7 +151,7 @@ impl TuiCommand for ClearCommand {
     }
 
     fn description(&self) -> &'static str {
-        "清除聊天记录"
+        "清除聊天记录，重新加载项目说明文件"
     }
 
     async fn execute(&self, app: &mut crate::tui::app::App, _args: &str) -> bool {
@@ -143,6 +160,7 @@ impl TuiCommand for ClearCommand {
         app.max_line = 0;
         app.index = 0;
         app.chat.lock().unwrap().clear_context();
+        app.todo_panel.todos.clear();
         app.add_system_message("聊天记录和信息消息已清除");
         true
     }
@@ -194,6 +212,57 @@ impl TuiCommand for ResetCommand {
     }
 }
 
+/// 压缩命令
+#[derive(Debug)]
+pub struct CompressCommand;
+
+#[async_trait]
+impl TuiCommand for CompressCommand {
+    fn name(&self) -> &'static str {
+        "compress"
+    }
+
+    fn description(&self) -> &'static str {
+        "按配置的策略压缩对话上下文"
+    }
+
+    async fn execute(&self, app: &mut crate::tui::app::App, _args: &str) -> bool {
+        let mut chat = {
+            let mut chat = app.chat.lock().unwrap();
+            if chat.get_state() != EChatState::Idle {
+                drop(chat);
+                app.add_system_message("对话进行中，无法压缩");
+                return false;
+            }
+            let copy = chat.clone();
+            // 压缩期间共享的对话保持压缩状态，阻止新的输入
+            chat.begin_compress();
+            copy
+        };
+        app.add_system_message("正在压缩对话...");
+        let selfchat = app.chat.clone();
+        let tx = app.event_tx.clone();
+        tokio::spawn(async move {
+            let before = chat.context().len();
+            let compressed = chat.compress_conversation().await;
+            let msg = if compressed {
+                format!("压缩完成，消息数 {} -> {}", before, chat.context().len())
+            } else {
+                "没有可以压缩的内容".to_string()
+            };
+            selfchat
+                .lock()
+                .unwrap()
+                .finish_compress(compressed.then_some(&chat));
+            crate::tui::send_event(
+                &tx,
+                crate::tui::app::ETuiEvent::AddMessage(ModelMessage::info(msg)),
+            );
+        });
+        true
+    }
+}
+
 /// 历史命令
 #[derive(Debug)]
 pub struct HistoryCommand;
@@ -269,3 +338,594 @@ impl TuiCommand for ConfigCommand {
         true
     }
 }
+
+/// 模型列表命令
+#[derive(Debug)]
+pub struct ModelsCommand;
+
+#[async_trait]
+impl TuiCommand for ModelsCommand {
+    fn name(&self) -> &'static str {
+        "models"
+    }
+
+    fn description(&self) -> &'static str {
+        "显示可用模型"
+    }
+
+    async fn execute(&self, app: &mut crate::tui::app::App, _args: &str) -> bool {
+        // 请求期间不持有锁
+        let agent = app.chat.lock().unwrap().agent();
+        let current = agent.model_name();
+        match agent.list_models().await {
+            Ok(models) => {
+                let list = models
+                    .iter()
+                    .map(|m| {
+                        if *m == current {
+                            format!("* {}", m)
+                        } else {
+                            format!("  {}", m)
+                        }
+                    })
+                    .collect::<Vec<String>>();
+                app.add_system_message(&format!(
+                    "可用模型 ({} 个):\n{}",
+                    list.len(),
+                    list.join("\n")
+                ));
+                true
+            }
+            Err(e) => {
+                app.add_system_message(&format!("获取模型列表失败: {}", e));
+                false
+            }
+        }
+    }
+}
+
+/// 切换模型命令
+#[derive(Debug)]
+pub struct ModelCommand;
+
+#[async_trait]
+impl TuiCommand for ModelCommand {
+    fn name(&self) -> &'static str {
+        "model"
+    }
+
+    fn description(&self) -> &'static str {
+        "切换模型，用法: /model <模型名称>"
+    }
+
+    async fn execute(&self, app: &mut crate::tui::app::App, args: &str) -> bool {
+        let model = args.trim();
+        if model.is_empty() {
+            let (current, caps) = {
+                let chat = app.chat.lock().unwrap();
+                (chat.model_name(), chat.capabilities())
+            };
+            app.add_system_message(&format!("当前模型: {}\n{}", current, caps.summary()));
+            return true;
+        }
+        let res = app.chat.lock().unwrap().set_model(model);
+        match res {
+            Ok(()) => {
+                app.add_system_message(&format!("已切换到模型: {}", model));
+                true
+            }
+            Err(e) => {
+                app.add_system_message(&format!("切换模型失败: {}", e));
+                false
+            }
+        }
+    }
+}
+
+/// 采样参数命令
+#[derive(Debug)]
+pub struct SamplingCommand;
+
+const SAMPLING_KEYS: &[&str] = &[
+    "temperature",
+    "top_p",
+    "max_tokens",
+    "stop",
+    "seed",
+    "presence_penalty",
+    "frequency_penalty",
+    "tool_choice",
+    "response_format",
+];
+
+/// 解析 key=value 形式的参数并应用到采样参数上，值按 JSON 解析，解析失败时作为字符串，null 表示清除
+fn apply_sampling_args(sampling: &SamplingParams, args: &str) -> anyhow::Result<SamplingParams> {
+    let mut value = serde_json::to_value(sampling)?;
+    for arg in args.split_whitespace() {
+        let Some((key, val)) = arg.split_once('=') else {
+            return Err(anyhow::anyhow!("参数格式错误: {}，应为 key=value", arg));
+        };
+        if !SAMPLING_KEYS.contains(&key) {
+            return Err(anyhow::anyhow!(
+                "未知的采样参数: {}，可用: {}",
+                key,
+                SAMPLING_KEYS.join(", ")
+            ));
+        }
+        value[key] = serde_json::from_str(val).unwrap_or(serde_json::Value::String(val.into()));
+    }
+    Ok(serde_json::from_value(value)?)
+}
+
+#[async_trait]
+impl TuiCommand for SamplingCommand {
+    fn name(&self) -> &'static str {
+        "sampling"
+    }
+
+    fn description(&self) -> &'static str {
+        "查看或修改采样参数，用法: /sampling [key=value ...|reset]"
+    }
+
+    async fn execute(&self, app: &mut crate::tui::app::App, args: &str) -> bool {
+        let args = args.trim();
+        let current = app.chat.lock().unwrap().sampling().clone();
+        let res = match args {
+            "" => {
+                let text = serde_json::to_string_pretty(&current).unwrap_or_default();
+                app.add_system_message(&format!("当前采样参数:\n{}", text));
+                return true;
+            }
+            "reset" => Ok(SamplingParams::default()),
+            _ => apply_sampling_args(&current, args),
+        };
+        match res {
+            Ok(sampling) => {
+                let text = serde_json::to_string(&sampling).unwrap_or_default();
+                app.chat.lock().unwrap().set_sampling(sampling);
+                app.add_system_message(&format!("采样参数已更新: {}", text));
+                true
+            }
+            Err(e) => {
+                app.add_system_message(&format!("修改采样参数失败: {}", e));
+                false
+            }
+        }
+    }
+}
+
+/// 会话列表命令
+#[derive(Debug)]
+pub struct SessionsCommand;
+
+#[async_trait]
+impl TuiCommand for SessionsCommand {
+    fn name(&self) -> &'static str {
+        "sessions"
+    }
+
+    fn description(&self) -> &'static str {
+        "列出保存的会话"
+    }
+
+    async fn execute(&self, app: &mut crate::tui::app::App, _args: &str) -> bool {
+        let current = app
+            .chat
+            .lock()
+            .unwrap()
+            .session()
+            .map(|meta| meta.id.clone());
+        match SessionStore::local().list() {
+            Ok(sessions) => {
+                let list = sessions
+                    .iter()
+                    .map(|meta| {
+                        if Some(&meta.id) == current.as_ref() {
+                            format!("* {}", meta.summary())
+                        } else {
+                            format!("  {}", meta.summary())
+                        }
+                    })
+                    .collect::<Vec<String>>();
+                app.add_system_message(&format!(
+                    "保存的会话 ({} 个):\n{}",
+                    list.len(),
+                    list.join("\n")
+                ));
+                true
+            }
+            Err(e) => {
+                app.add_system_message(&format!("读取会话列表失败: {}", e));
+                false
+            }
+        }
+    }
+}
+
+/// 恢复会话命令
+#[derive(Debug)]
+pub struct ResumeCommand;
+
+#[async_trait]
+impl TuiCommand for ResumeCommand {
+    fn name(&self) -> &'static str {
+        "resume"
+    }
+
+    fn description(&self) -> &'static str {
+        "恢复保存的会话，用法: /resume <会话ID>"
+    }
+
+    async fn execute(&self, app: &mut crate::tui::app::App, args: &str) -> bool {
+        let id = args.trim();
+        if id.is_empty() {
+            app.add_system_message("用法: /resume <会话ID>，可以用 /sessions 查看会话");
+            return false;
+        }
+        if app.chat.lock().unwrap().get_state() != EChatState::Idle {
+            app.add_system_message("对话进行中，无法切换会话");
+            return false;
+        }
+        match SessionStore::local().load(id) {
+            Ok(record) => {
+                app.open_session(Some(record));
+                true
+            }
+            Err(e) => {
+                app.add_system_message(&format!("恢复会话失败: {}", e));
+                false
+            }
+        }
+    }
+}
+
+/// 把回退得到的用户消息放回输入框，修改后回车重新发送
+fn restore_input(app: &mut crate::tui::app::App, msg: &ModelMessage) {
+    app.reload_messages();
+    if msg.role == "user" {
+        app.input.content = msg.content.to_string();
+        app.cursor_offset = app.input.get_content_width();
+        app.add_system_message("已回退，上次的输入已放回输入框，修改后回车重新发送");
+    } else {
+        app.add_system_message("已回退");
+    }
+}
+
+/// 撤销命令
+#[derive(Debug)]
+pub struct UndoCommand;
+
+#[async_trait]
+impl TuiCommand for UndoCommand {
+    fn name(&self) -> &'static str {
+        "undo"
+    }
+
+    fn description(&self) -> &'static str {
+        "撤销最后一轮对话，原来的对话保存为分支"
+    }
+
+    async fn execute(&self, app: &mut crate::tui::app::App, _args: &str) -> bool {
+        let res = app.chat.lock().unwrap().undo();
+        match res {
+            Ok(msg) => {
+                restore_input(app, &msg);
+                true
+            }
+            Err(e) => {
+                app.add_system_message(&format!("撤销失败: {}", e));
+                false
+            }
+        }
+    }
+}
+
+/// 回退命令
+#[derive(Debug)]
+pub struct RewindCommand;
+
+#[async_trait]
+impl TuiCommand for RewindCommand {
+    fn name(&self) -> &'static str {
+        "rewind"
+    }
+
+    fn description(&self) -> &'static str {
+        "回退到指定消息之前，用法: /rewind [消息序号]，不带序号时列出可以回退的位置"
+    }
+
+    async fn execute(&self, app: &mut crate::tui::app::App, args: &str) -> bool {
+        let args = args.trim();
+        if args.is_empty() {
+            let points = rewind_points(app);
+            app.add_system_message(&format!(
+                "可以回退的位置 ({} 个):\n{}",
+                points.len(),
+                points.join("\n")
+            ));
+            return true;
+        }
+        let Ok(index) = args.parse::<usize>() else {
+            app.add_system_message(&format!("无效的消息序号: {}", args));
+            return false;
+        };
+        let res = app.chat.lock().unwrap().rewind(index);
+        match res {
+            Ok(msg) => {
+                restore_input(app, &msg);
+                true
+            }
+            Err(e) => {
+                app.add_system_message(&format!("回退失败: {}", e));
+                false
+            }
+        }
+    }
+}
+
+/// 列出可以回退的消息：用户消息和模型回复
+fn rewind_points(app: &crate::tui::app::App) -> Vec<String> {
+    let chat = app.chat.lock().unwrap();
+    chat.context()
+        .iter()
+        .enumerate()
+        .filter(|(_, msg)| msg.role == "user" || msg.role == "assistant")
+        .map(|(i, msg)| {
+            let text = if msg.content.is_empty() {
+                msg.tool_calls
+                    .iter()
+                    .flatten()
+                    .map(|call| format!("[调用 {}]", call.function.name))
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            } else {
+                msg.content.lines().next().unwrap_or_default().to_string()
+            };
+            format!("  {:>3} [{}] {}", i, msg.role, text)
+        })
+        .collect()
+}
+
+/// 固定消息命令
+#[derive(Debug)]
+pub struct PinCommand;
+
+#[async_trait]
+impl TuiCommand for PinCommand {
+    fn name(&self) -> &'static str {
+        "pin"
+    }
+
+    fn description(&self) -> &'static str {
+        "固定或取消固定消息，固定的消息不会被滑动窗口丢弃，用法: /pin [消息序号]，不带序号时列出已固定的消息"
+    }
+
+    async fn execute(&self, app: &mut crate::tui::app::App, args: &str) -> bool {
+        let args = args.trim();
+        if args.is_empty() {
+            let pinned: Vec<String> = app
+                .chat
+                .lock()
+                .unwrap()
+                .context()
+                .iter()
+                .enumerate()
+                .filter(|(_, msg)| msg.pinned)
+                .map(|(i, msg)| {
+                    let text = msg.content.lines().next().unwrap_or_default();
+                    format!("  {:>3} [{}] {}", i, msg.role, text)
+                })
+                .collect();
+            app.add_system_message(&format!(
+                "已固定的消息 ({} 条):\n{}",
+                pinned.len(),
+                pinned.join("\n")
+            ));
+            return true;
+        }
+        let Ok(index) = args.parse::<usize>() else {
+            app.add_system_message(&format!("无效的消息序号: {}", args));
+            return false;
+        };
+        let res = {
+            let mut chat = app.chat.lock().unwrap();
+            let pinned = !chat.context().get(index).is_some_and(|msg| msg.pinned);
+            chat.pin_message(index, pinned).map(|_| pinned)
+        };
+        match res {
+            Ok(true) => app.add_system_message(&format!("已固定第 {} 条消息", index)),
+            Ok(false) => app.add_system_message(&format!("已取消固定第 {} 条消息", index)),
+            Err(e) => {
+                app.add_system_message(&format!("固定消息失败: {}", e));
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// 重新生成命令
+#[derive(Debug)]
+pub struct RetryCommand;
+
+#[async_trait]
+impl TuiCommand for RetryCommand {
+    fn name(&self) -> &'static str {
+        "retry"
+    }
+
+    fn description(&self) -> &'static str {
+        "重新生成最后一轮回复，用法: /retry [消息序号]，指定序号时从该消息处重新生成"
+    }
+
+    async fn execute(&self, app: &mut crate::tui::app::App, args: &str) -> bool {
+        let args = args.trim();
+        let index = if args.is_empty() {
+            None
+        } else {
+            match args.parse::<usize>() {
+                Ok(index) => Some(index),
+                Err(_) => {
+                    app.add_system_message(&format!("无效的消息序号: {}", args));
+                    return false;
+                }
+            }
+        };
+        let res = app.chat.lock().unwrap().prepare_regenerate(index);
+        if let Err(e) = res {
+            app.add_system_message(&format!("重新生成失败: {}", e));
+            return false;
+        }
+        app.reload_messages();
+        tokio::spawn(crate::tui::appchat::AppChat::handle_chat(
+            app.messages.len(),
+            app.chat.clone(),
+            crate::tui::ui::inputarea::InputArea::default(),
+            app.event_tx.clone(),
+        ));
+        true
+    }
+}
+
+/// 分支列表命令
+#[derive(Debug)]
+pub struct BranchesCommand;
+
+#[async_trait]
+impl TuiCommand for BranchesCommand {
+    fn name(&self) -> &'static str {
+        "branches"
+    }
+
+    fn description(&self) -> &'static str {
+        "列出回退、重新生成时保存下来的对话分支"
+    }
+
+    async fn execute(&self, app: &mut crate::tui::app::App, _args: &str) -> bool {
+        let list = {
+            let chat = app.chat.lock().unwrap();
+            chat.branches()
+                .iter()
+                .map(|branch| format!("  {}", branch.summary()))
+                .collect::<Vec<String>>()
+        };
+        app.add_system_message(&format!(
+            "对话分支 ({} 个):\n{}",
+            list.len(),
+            list.join("\n")
+        ));
+        true
+    }
+}
+
+/// 切换分支命令
+#[derive(Debug)]
+pub struct BranchCommand;
+
+#[async_trait]
+impl TuiCommand for BranchCommand {
+    fn name(&self) -> &'static str {
+        "branch"
+    }
+
+    fn description(&self) -> &'static str {
+        "切换到指定分支，当前对话保存为新的分支，用法: /branch <分支编号>"
+    }
+
+    async fn execute(&self, app: &mut crate::tui::app::App, args: &str) -> bool {
+        let Ok(id) = args.trim().trim_start_matches('#').parse::<usize>() else {
+            app.add_system_message("用法: /branch <分支编号>，可以用 /branches 查看分支");
+            return false;
+        };
+        let res = app.chat.lock().unwrap().switch_branch(id);
+        match res {
+            Ok(()) => {
+                app.reload_messages();
+                app.add_system_message(&format!("已切换到分支 #{}", id));
+                true
+            }
+            Err(e) => {
+                app.add_system_message(&format!("切换分支失败: {}", e));
+                false
+            }
+        }
+    }
+}
+
+/// 计划模式命令
+#[derive(Debug)]
+pub struct PlanCommand;
+
+#[async_trait]
+impl TuiCommand for PlanCommand {
+    fn name(&self) -> &'static str {
+        "plan"
+    }
+
+    fn description(&self) -> &'static str {
+        "切换只读的计划模式，用法: /plan [on|off]，也可以按 Shift+Tab 切换"
+    }
+
+    async fn execute(&self, app: &mut crate::tui::app::App, args: &str) -> bool {
+        let mode = match args.trim() {
+            "" => match app.chat.lock().unwrap().mode() {
+                ChatMode::Execute => ChatMode::Plan,
+                ChatMode::Plan => ChatMode::Execute,
+            },
+            "on" => ChatMode::Plan,
+            "off" => ChatMode::Execute,
+            other => {
+                app.add_system_message(&format!("无效的参数: {}，用法: /plan [on|off]", other));
+                return false;
+            }
+        };
+        {
+            let mut chat = app.chat.lock().unwrap();
+            if chat.is_running() {
+                drop(chat);
+                app.add_system_message("对话进行中，无法切换模式");
+                return false;
+            }
+            chat.set_mode(mode);
+        }
+        app.add_system_message(&format!("已切换到{}：{}", mode, mode.description()));
+        true
+    }
+}
+
+/// 批准计划命令
+#[derive(Debug)]
+pub struct ApproveCommand;
+
+#[async_trait]
+impl TuiCommand for ApproveCommand {
+    fn name(&self) -> &'static str {
+        "approve"
+    }
+
+    fn description(&self) -> &'static str {
+        "批准计划模式下给出的计划，切换到执行模式并开始执行"
+    }
+
+    async fn execute(&self, app: &mut crate::tui::app::App, _args: &str) -> bool {
+        let res = app.chat.lock().unwrap().approve_plan();
+        let prompt = match res {
+            Ok(prompt) => prompt,
+            Err(e) => {
+                app.add_system_message(&format!("批准计划失败: {}", e));
+                return false;
+            }
+        };
+        app.add_system_message("计划已批准，已切换到执行模式");
+        tokio::spawn(crate::tui::appchat::AppChat::handle_chat(
+            app.messages.len(),
+            app.chat.clone(),
+            crate::tui::ui::inputarea::InputArea {
+                content: prompt,
+                ..Default::default()
+            },
+            app.event_tx.clone(),
+        ));
+        true
+    }
+}
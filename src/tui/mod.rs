@@ -1,5 +1,6 @@
 use std::sync::mpsc;
 
+use crate::chat::SessionRecord;
 use crate::tui::app::{App, ETuiEvent};
 
 mod app;
@@ -15,10 +16,12 @@ mod ui;
 pub use commands::{CommandRegistry, TuiCommand, global_registry, init_global_registry};
 use log::error;
 
-pub async fn run() {
+pub async fn run(session: Option<SessionRecord>) {
     color_eyre::install().unwrap();
     let term = ratatui::init();
-    App::new().run(term).await.unwrap();
+    let mut app = App::new();
+    app.open_session(session);
+    app.run(term).await.unwrap();
     ratatui::restore();
 }
 
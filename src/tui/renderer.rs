@@ -18,6 +18,7 @@ impl Renderer {
     /// 将应用程序状态渲染到终端帧中，包括：
     /// - 消息块显示区域
     /// - 垂直滚动条
+    /// - 任务列表面板
     /// - 文本输入区域
     /// - 光标位置
     ///
@@ -38,6 +39,11 @@ impl Renderer {
         let mut input_area = area;
         input_area.y = area.height - app.input.height();
         area.height -= app.input.height();
+        // 任务列表显示在输入区域上方，最多占用一半高度
+        let mut todo_area = area;
+        todo_area.height = app.todo_panel.height().min(area.height / 2);
+        todo_area.y = area.height - todo_area.height;
+        area.height -= todo_area.height;
         app.window_height = area.height;
         // 绘制光标
         frame.set_cursor_position(Position::new(
@@ -58,6 +64,9 @@ impl Renderer {
             scroll_area,
             &mut app.vertical_scroll_state,
         );
+        if todo_area.height > 0 {
+            frame.render_widget(&app.todo_panel, todo_area);
+        }
         // 最后渲染输入
         frame.render_widget(&app.input, input_area);
 
@@ -20,16 +20,33 @@ impl StateManager {
     /// 6. 如果工具调用达到上限，显示提示消息
     /// 7. 如果正在等待工具调用确认，显示提示消息
     /// 8. 如果正在等待对话轮次确认，显示提示消息
+    /// 9. 如果工具调用陷入循环被暂停，显示暂停原因
+    /// 10. 计划模式下模型回复后，提示批准计划的方式
     pub fn refresh(app: &mut App) {
         let _perf_monitor = crate::perf_start!("StateManager::refresh", 20);
         debug!("refresh");
 
         // 提取需要的信息，然后释放锁
-        let (state, conversation_turn_info) = {
+        let (state, conversation_turn_info, permissions, loop_pause, mode) = {
             let ctx = { app.chat.lock().unwrap() };
             let conversation_turn_info = ctx.get_conversation_turn_info();
-            (ctx.get_state(), conversation_turn_info)
+            let state = ctx.get_state();
+            let loop_pause = ctx.tool_loop_pause().unwrap_or_default();
+            // 等待确认时列出每个调用匹配的权限规则
+            let permissions = if state == crate::chat::EChatState::WaitingToolConfirm {
+                ctx.describe_tool_confirm()
+            } else {
+                String::new()
+            };
+            (
+                state,
+                conversation_turn_info,
+                permissions,
+                loop_pause,
+                ctx.mode(),
+            )
         };
+        app.input.plan_mode = mode == crate::chat::chat_mode::ChatMode::Plan;
 
         // 增量更新消息块
         Self::update_blocks_incremental(app);
@@ -40,7 +57,10 @@ impl StateManager {
             EChatState::WaitingToolConfirm => {
                 Self::add_system_message_block(
                     app,
-                    "检测到工具调用，是否执行？输入 yes/y 执行，no/n 取消".into(),
+                    format!(
+                        "检测到工具调用，请逐个确认标记为当前的调用：\n{}",
+                        permissions
+                    ),
                 );
             }
             // 等待轮次确认
@@ -54,6 +74,29 @@ impl StateManager {
                     ),
                 );
             }
+            // 工具调用暂停
+            EChatState::WaitingLoopConfirm => {
+                Self::add_system_message_block(
+                    app,
+                    format!(
+                        "{}，已暂停。输入 yes/y 继续，no/n 停止，或者输入新的指示",
+                        loop_pause
+                    ),
+                );
+            }
+            // 计划模式下等待用户批准计划
+            EChatState::Idle
+                if app.input.plan_mode
+                    && app
+                        .messages
+                        .last()
+                        .is_some_and(|msg| msg.role == "assistant") =>
+            {
+                Self::add_system_message_block(
+                    app,
+                    "计划模式：输入 /approve 批准计划并开始执行，或者继续提出修改意见".to_string(),
+                );
+            }
             EChatState::Compressing => {
                 Self::add_system_message_block(app, format!("正在压缩对话中..."));
             }
@@ -12,6 +12,8 @@ pub struct InputArea {
     pub max_height: u16,
     /// 命令提示组件
     pub command_suggestions: CommandSuggestions,
+    /// 是否处于计划模式，在标题中提示
+    pub plan_mode: bool,
 }
 
 impl Default for InputArea {
@@ -20,6 +22,7 @@ impl Default for InputArea {
             content: "".into(),
             max_height: 3,
             command_suggestions: CommandSuggestions::new(),
+            plan_mode: false,
         }
     }
 }
@@ -181,8 +184,13 @@ impl Widget for &InputArea {
         }
 
         // 渲染输入区域
+        let title = if self.plan_mode {
+            "输入（计划模式，只读，Shift+Tab 切换）"
+        } else {
+            "输入"
+        };
         let block = Block::default()
-            .title("输入")
+            .title(title)
             .borders(Borders::ALL)
             .style(ratatui::style::Style::new().light_blue());
         let para = Paragraph::new(self.content.clone())
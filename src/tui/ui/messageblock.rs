@@ -330,6 +330,19 @@ impl MessageBlock {
                 usage.total_tokens
             );
         }
+        // 工具输出被截断时提示完整输出的位置
+        if let Some(truncation) = &self.message.truncation {
+            return format!(
+                "输出已截断，保留 {}/{} 个字符{}",
+                truncation.kept_chars,
+                truncation.original_chars,
+                truncation
+                    .path
+                    .as_ref()
+                    .map(|path| format!("，完整输出: {}", path))
+                    .unwrap_or_default()
+            );
+        }
         return String::new();
     }
 }
@@ -385,10 +398,14 @@ mod tests {
                 role: if i % 2 == 0 { "user".to_string().into() } else { "assistant".to_string().into() },
                 content: format!("这是第{}条消息，包含一些文本内容用于测试。这是一个较长的消息，用于测试换行和高度计算。", i).into(),
                 think: "".into(),
+                thinking_blocks: Vec::new(),
                 name: "".into(),
                 tool_call_id: "".into(),
                 tool_calls: None,
                 token_usage: None,
+                parts: Vec::new(),
+                pinned: false,
+                truncation: None,
             };
             let block = MessageBlock::new(message, 80);
             blocks.push(block);
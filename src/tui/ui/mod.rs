@@ -3,3 +3,4 @@ pub mod inputarea;
 pub mod messageblock;
 pub mod messagetext;
 pub mod option_dialog;
+pub mod todopanel;
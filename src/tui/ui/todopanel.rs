@@ -0,0 +1,78 @@
+use ratatui::{
+    style::{Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Borders, Paragraph, Widget},
+};
+
+use crate::chat::chat_todo::{self, TodoItem, TodoStatus};
+
+/// 任务列表面板，显示在输入区域上方，模型更新 todo 时实时刷新
+#[derive(Clone, Default)]
+pub struct TodoPanel {
+    pub todos: Vec<TodoItem>,
+    /// 最多显示的任务数
+    pub max_items: u16,
+}
+
+impl TodoPanel {
+    pub fn new() -> Self {
+        Self {
+            todos: Vec::new(),
+            max_items: 8,
+        }
+    }
+
+    /// 面板高度，没有任务时不显示
+    pub fn height(&self) -> u16 {
+        if self.todos.is_empty() {
+            0
+        } else {
+            (self.todos.len() as u16).min(self.max_items) + 2 // +2 用于边框
+        }
+    }
+
+    /// 任务过多时显示的起始位置：让第一个未完成的任务尽量可见
+    fn first_visible(&self) -> usize {
+        let max = self.max_items as usize;
+        if self.todos.len() <= max {
+            return 0;
+        }
+        let current = self
+            .todos
+            .iter()
+            .position(|item| item.status != TodoStatus::Done)
+            .unwrap_or(self.todos.len());
+        current.saturating_sub(1).min(self.todos.len() - max)
+    }
+}
+
+impl Widget for &TodoPanel {
+    fn render(self, area: ratatui::prelude::Rect, buf: &mut ratatui::prelude::Buffer)
+    where
+        Self: Sized,
+    {
+        let (done, total) = chat_todo::progress(&self.todos);
+        let lines: Vec<Line> = self
+            .todos
+            .iter()
+            .skip(self.first_visible())
+            .take(self.max_items as usize)
+            .map(|item| {
+                let style = match item.status {
+                    TodoStatus::Pending => Style::new().white(),
+                    TodoStatus::InProgress => Style::new().yellow().bold(),
+                    TodoStatus::Done => Style::new().dark_gray().crossed_out(),
+                };
+                Line::from(vec![
+                    Span::styled(format!("{} ", item.status), style),
+                    Span::styled(item.content.clone(), style),
+                ])
+            })
+            .collect();
+        let block = Block::default()
+            .title(format!("任务 {}/{}", done, total))
+            .borders(Borders::ALL)
+            .style(Style::new().light_blue());
+        Paragraph::new(lines).block(block).render(area, buf);
+    }
+}